@@ -0,0 +1,203 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+// Prefix length assumed when comparing subnets
+pub const DEFAULT_PREFIX_LEN: u8 = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrParseError {
+    InvalidIpv4(String),
+    InvalidMac(String),
+    InvalidCidr(String),
+}
+
+impl fmt::Display for AddrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrParseError::InvalidIpv4(s) => write!(f, "invalid IPv4 address: {s:?}"),
+            AddrParseError::InvalidMac(s) => write!(f, "invalid MAC address: {s:?}"),
+            AddrParseError::InvalidCidr(s) => write!(f, "invalid CIDR prefix: {s:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AddrParseError {}
+
+// Parses a dotted-quad IPv4 address, e.g. "192.168.1.10"
+pub fn parse_ipv4(s: &str) -> Result<Ipv4Addr, AddrParseError> {
+    s.trim().parse().map_err(|_| AddrParseError::InvalidIpv4(s.to_string()))
+}
+
+// Returns the network address of `addr` for the given prefix length
+pub fn network_of(addr: Ipv4Addr, prefix_len: u8) -> Ipv4Addr {
+    let mask = if prefix_len == 0 { 0 } else { u32::MAX << (32 - u32::from(prefix_len.min(32))) };
+    Ipv4Addr::from(u32::from(addr) & mask)
+}
+
+pub fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix_len: u8) -> bool {
+    network_of(a, prefix_len) == network_of(b, prefix_len)
+}
+
+// IPv4 network in CIDR notation, e.g. "10.1.0.0/16"
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ipv4Net {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Net {
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Result<Self, AddrParseError> {
+        if prefix_len > 32 {
+            return Err(AddrParseError::InvalidCidr(format!("{addr}/{prefix_len}")));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> Ipv4Addr { self.addr }
+
+    pub fn prefix_len(&self) -> u8 { self.prefix_len }
+
+    pub fn network(&self) -> Ipv4Addr { network_of(self.addr, self.prefix_len) }
+
+    // Same prefix with the host bits cleared, e.g. 10.1.2.3/16 -> 10.1.0.0/16
+    pub fn trunc(&self) -> Self {
+        Self { addr: self.network(), prefix_len: self.prefix_len }
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        same_subnet(self.addr, addr, self.prefix_len)
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || AddrParseError::InvalidCidr(s.to_string());
+        let (addr, prefix_len) = s.trim().split_once('/').ok_or_else(err)?;
+        let addr = parse_ipv4(addr).map_err(|_| err())?;
+        let prefix_len = prefix_len.parse().map_err(|_| err())?;
+        Self::new(addr, prefix_len).map_err(|_| err())
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl fmt::Debug for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// 48-bit Ethernet hardware address
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xFF; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+    // Destination of spanning tree BPDUs, which bridges running spanning tree consume instead of forwarding
+    pub const BRIDGE_GROUP: MacAddr = MacAddr([0x01, 0x80, 0xC2, 0x00, 0x00, 0x00]);
+
+    pub fn octets(&self) -> [u8; 6] { self.0 }
+
+    // Group address an IPv4 multicast address maps to (RFC 1112), e.g. 224.0.0.9 -> 01:00:5E:00:00:09
+    pub fn ipv4_multicast(ip: Ipv4Addr) -> Self {
+        let [_, b, c, d] = ip.octets();
+        MacAddr([0x01, 0x00, 0x5E, b & 0x7F, c, d])
+    }
+
+    // Broadcast and multicast addresses, which every port of a switch floods
+    pub fn is_group(&self) -> bool { self.0[0] & 1 == 1 }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self { MacAddr(octets) }
+}
+
+impl FromStr for MacAddr {
+    type Err = AddrParseError;
+
+    // Accepts six hex octets separated by ':' or '-', e.g. "AA:BB:CC:DD:EE:1A"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || AddrParseError::InvalidMac(s.to_string());
+        let parts: Vec<&str> = s.trim().split([':', '-']).collect();
+        if parts.len() != 6 {
+            return Err(err());
+        }
+
+        let mut octets = [0u8; 6];
+        for (octet, part) in octets.iter_mut().zip(parts) {
+            if part.len() != 2 {
+                return Err(err());
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| err())?;
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02X}:{b:02X}:{c:02X}:{d:02X}:{e:02X}:{g:02X}")
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_of_masks_host_bits() {
+        let addr = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(network_of(addr, 0), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(network_of(addr, 8), Ipv4Addr::new(10, 0, 0, 0));
+        assert_eq!(network_of(addr, 23), Ipv4Addr::new(10, 1, 2, 0));
+        assert_eq!(network_of(addr, 32), addr);
+    }
+
+    #[test]
+    fn cidr_parsing_and_containment() {
+        let net: Ipv4Net = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(net.trunc().to_string(), "10.1.0.0/16");
+        assert!(net.contains(Ipv4Addr::new(10, 1, 255, 1)));
+        assert!(!net.contains(Ipv4Addr::new(10, 2, 0, 1)));
+        assert!("0.0.0.0/0".parse::<Ipv4Net>().unwrap().contains(Ipv4Addr::new(8, 8, 8, 8)));
+        assert!("10.0.0.0/33".parse::<Ipv4Net>().is_err());
+        assert!("10.0.0.0".parse::<Ipv4Net>().is_err());
+    }
+
+    #[test]
+    fn mac_parsing_and_formatting() {
+        let mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0x0D, 0xEE, 0x1A]);
+        assert_eq!("AA:BB:CC:0D:EE:1A".parse::<MacAddr>(), Ok(mac));
+        assert_eq!("aa-bb-cc-0d-ee-1a".parse::<MacAddr>(), Ok(mac));
+        assert_eq!(" AA:BB:CC:0D:EE:1A ".parse::<MacAddr>(), Ok(mac));
+        assert_eq!(mac.to_string(), "AA:BB:CC:0D:EE:1A");
+        assert_eq!(format!("{:?}", MacAddr::BROADCAST), "FF:FF:FF:FF:FF:FF");
+        for bad in ["AA:BB:CC:DD:EE", "AA:BB:CC:DD:EE:FF:00", "AA:BB:CC:DD:EE:GG", "AA:BB:CC:D:EE:FF", "AABBCCDDEEFF", ""] {
+            assert_eq!(bad.parse::<MacAddr>(), Err(AddrParseError::InvalidMac(bad.to_string())), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn ipv4_parsing_errors_keep_the_input() {
+        assert_eq!(parse_ipv4(" 192.168.1.10 "), Ok(Ipv4Addr::new(192, 168, 1, 10)));
+        for bad in ["192.168.1", "192.168.1.256", "192.168.1.x", ""] {
+            assert_eq!(parse_ipv4(bad), Err(AddrParseError::InvalidIpv4(bad.to_string())));
+        }
+        assert_eq!(parse_ipv4("1.2.3").unwrap_err().to_string(), "invalid IPv4 address: \"1.2.3\"");
+    }
+}
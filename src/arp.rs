@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::MacAddr;
+use crate::packet::Packet;
+
+// A learned entry is trusted this long after the last confirmation, then goes stale
+pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+// Stale entries are still used, but are forgotten this long after going stale
+pub const STALE_TIME: Duration = Duration::from_secs(60);
+// Time between requests for an address that has not answered
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+// Requests sent for an address before giving up on it
+pub const MAX_REQUESTS: u32 = 3;
+// Packets held per unresolved address; the oldest are dropped beyond this
+pub const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpState {
+    Incomplete, // Request sent, no answer yet
+    Reachable,  // Answer received recently
+    Stale,      // Answer is getting old; still used, but confirmed again on use
+    Permanent,  // Configured in the topology, never ages
+}
+
+impl fmt::Display for ArpState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpState::Incomplete => write!(f, "incomplete"),
+            ArpState::Reachable => write!(f, "reachable"),
+            ArpState::Stale => write!(f, "stale"),
+            ArpState::Permanent => write!(f, "permanent"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    mac: Option<MacAddr>,
+    permanent: bool,
+    updated: Duration,  // When the address was last confirmed, or first requested
+    requests: u32,      // Requests sent while incomplete, or to confirm a stale address
+    interface: usize,   // Where requests for the address go
+    pending: Vec<Packet>,
+}
+
+// What to do for an address whose request went unanswered
+#[derive(Debug)]
+pub enum Retry {
+    Resend { interface: usize },
+    GiveUp { pending: Vec<Packet> }, // The entry is gone; these packets could not be delivered
+    Done,                            // Already resolved, or no longer wanted
+}
+
+// What to do with a packet after looking up its next hop
+#[derive(Debug)]
+pub enum Resolution {
+    Send { packet: Packet, mac: MacAddr, confirm: bool }, // Confirm: the entry went stale, so send a request now
+    Queued { request: bool }, // Request: first packet for the address, so send a request now
+}
+
+#[derive(Debug, Default)]
+pub struct ArpCache {
+    entries: HashMap<Ipv4Addr, Entry>,
+}
+
+impl ArpCache {
+    pub fn new() -> Self { Self::default() }
+
+    // Entry from configuration, which never ages
+    pub fn add_permanent(&mut self, ip: Ipv4Addr, mac: MacAddr) {
+        self.entries.insert(ip, Entry {
+            mac: Some(mac),
+            permanent: true,
+            updated: Duration::ZERO,
+            requests: 0,
+            interface: 0,
+            pending: Vec::new(),
+        });
+    }
+
+    fn state(entry: &Entry, now: Duration) -> Option<ArpState> {
+        let age = now.saturating_sub(entry.updated);
+        match entry.mac {
+            _ if entry.permanent => Some(ArpState::Permanent),
+            None => Some(ArpState::Incomplete),
+            Some(_) if age < REACHABLE_TIME => Some(ArpState::Reachable),
+            Some(_) if age < REACHABLE_TIME + STALE_TIME => Some(ArpState::Stale),
+            Some(_) => None, // Expired
+        }
+    }
+
+    // Forgets expired entries
+    fn expire(&mut self, now: Duration) {
+        self.entries.retain(|_, entry| Self::state(entry, now).is_some());
+    }
+
+    // Finds the MAC address for `ip`, or holds `packet` until a reply arrives on `interface`.
+    // A stale address is confirmed once, with requests retried like those for a new address.
+    pub fn resolve(&mut self, ip: Ipv4Addr, packet: Packet, interface: usize, now: Duration) -> Resolution {
+        self.expire(now);
+        let state = self.entries.get(&ip).and_then(|entry| Self::state(entry, now));
+        match (state, self.entries.get_mut(&ip)) {
+            (Some(ArpState::Incomplete), Some(entry)) => {
+                entry.pending.push(packet);
+                if entry.pending.len() > MAX_PENDING {
+                    entry.pending.remove(0);
+                }
+                Resolution::Queued { request: false }
+            }
+            (Some(state), Some(Entry { mac: Some(mac), requests, interface: via, .. })) => {
+                let confirm = state == ArpState::Stale && *requests == 0;
+                if confirm {
+                    *requests = 1;
+                    *via = interface;
+                }
+                Resolution::Send { packet, mac: *mac, confirm }
+            }
+            _ => {
+                self.entries.insert(ip, Entry {
+                    mac: None,
+                    permanent: false,
+                    updated: now,
+                    requests: 1,
+                    interface,
+                    pending: vec![packet],
+                });
+                Resolution::Queued { request: true }
+            }
+        }
+    }
+
+    // Records `ip` at `mac` (from a reply, or a request from that address) and returns the
+    // packets that were waiting for it. Only updates known entries unless `create` is set.
+    pub fn learn(&mut self, ip: Ipv4Addr, mac: MacAddr, now: Duration, create: bool) -> Vec<Packet> {
+        self.expire(now);
+        match self.entries.get_mut(&ip) {
+            Some(entry) if entry.permanent => Vec::new(),
+            Some(entry) => {
+                entry.mac = Some(mac);
+                entry.updated = now;
+                entry.requests = 0;
+                std::mem::take(&mut entry.pending)
+            }
+            None if create => {
+                self.entries.insert(ip, Entry {
+                    mac: Some(mac),
+                    permanent: false,
+                    updated: now,
+                    requests: 0,
+                    interface: 0,
+                    pending: Vec::new(),
+                });
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // Called when the retry timer for `ip` fires. A stale address that stops answering is
+    // forgotten, so that the next packet for it starts over with a broadcast request.
+    pub fn retry(&mut self, ip: Ipv4Addr) -> Retry {
+        let Some(entry) = self.entries.get_mut(&ip).filter(|entry| entry.mac.is_none() || entry.requests > 0) else {
+            return Retry::Done;
+        };
+        if entry.requests < MAX_REQUESTS {
+            entry.requests += 1;
+            return Retry::Resend { interface: entry.interface };
+        }
+        let pending = self.entries.remove(&ip).map(|entry| entry.pending).unwrap_or_default();
+        Retry::GiveUp { pending }
+    }
+
+    // Entries from configuration, which a saved topology writes back as configuration
+    pub fn permanent(&self) -> HashMap<Ipv4Addr, MacAddr> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.permanent)
+            .filter_map(|(ip, entry)| Some((*ip, entry.mac?)))
+            .collect()
+    }
+
+    // Entries with a known address that have not expired, learned or from configuration
+    pub fn resolved(&self, now: Duration) -> HashMap<Ipv4Addr, MacAddr> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| Self::state(entry, now).is_some())
+            .filter_map(|(ip, entry)| Some((*ip, entry.mac?)))
+            .collect()
+    }
+
+    // Table for device info windows: address, MAC address (if known) and state, sorted by address
+    pub fn describe(&self, now: Duration) -> String {
+        let mut rows: Vec<(Ipv4Addr, String)> = self.entries
+            .iter()
+            .filter_map(|(ip, entry)| {
+                let state = Self::state(entry, now)?;
+                let mac = entry.mac.map_or_else(|| "(incomplete)".to_string(), |mac| mac.to_string());
+                let pending = if entry.pending.is_empty() { String::new() } else { format!(", {} queued", entry.pending.len()) };
+                Some((*ip, format!("{ip:<15} {mac:<17} {state}{pending}")))
+            })
+            .collect();
+        rows.sort();
+        rows.into_iter().map(|(_, row)| row).collect::<Vec<_>>().join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+
+    fn packet() -> Packet {
+        Packet::new(MacAddr::ZERO, MacAddr::ZERO, Ipv4Addr::new(192, 168, 1, 10), IP, Vec::new())
+    }
+
+    fn confirms(resolution: Resolution) -> bool {
+        matches!(resolution, Resolution::Send { confirm: true, .. })
+    }
+
+    #[test]
+    fn stale_entry_is_confirmed_once_per_retry() {
+        let mut cache = ArpCache::new();
+        let mac = MacAddr::from([0xAA, 0, 0, 0, 0, 1]);
+        cache.learn(IP, mac, Duration::ZERO, true);
+        let stale = REACHABLE_TIME + Duration::from_secs(1);
+        assert!(confirms(cache.resolve(IP, packet(), 0, stale)));
+        assert!(!confirms(cache.resolve(IP, packet(), 0, stale)));
+        assert!(!confirms(cache.resolve(IP, packet(), 0, stale)));
+        for _ in 1..MAX_REQUESTS {
+            assert!(matches!(cache.retry(IP), Retry::Resend { interface: 0 }));
+        }
+        assert!(matches!(cache.retry(IP), Retry::GiveUp { .. }));
+        assert!(matches!(cache.resolve(IP, packet(), 0, stale), Resolution::Queued { request: true }));
+    }
+
+    #[test]
+    fn answered_confirmation_makes_entry_reachable() {
+        let mut cache = ArpCache::new();
+        let mac = MacAddr::from([0xAA, 0, 0, 0, 0, 1]);
+        cache.learn(IP, mac, Duration::ZERO, true);
+        let stale = REACHABLE_TIME + Duration::from_secs(1);
+        assert!(confirms(cache.resolve(IP, packet(), 0, stale)));
+        cache.learn(IP, mac, stale, false);
+        assert!(matches!(cache.retry(IP), Retry::Done));
+        assert!(cache.describe(stale).ends_with("reachable"));
+    }
+
+    #[test]
+    fn expired_entries_are_removed() {
+        let mut cache = ArpCache::new();
+        cache.learn(IP, MacAddr::from([0xAA, 0, 0, 0, 0, 1]), Duration::ZERO, true);
+        cache.add_permanent(Ipv4Addr::new(192, 168, 1, 2), MacAddr::from([0xAA, 0, 0, 0, 0, 2]));
+        cache.learn(Ipv4Addr::new(192, 168, 1, 3), MacAddr::from([0xAA, 0, 0, 0, 0, 3]), REACHABLE_TIME + STALE_TIME, true);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(!cache.entries.contains_key(&IP));
+    }
+}
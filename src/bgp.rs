@@ -0,0 +1,1116 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::Ipv4Net;
+use crate::router::RouteEntry;
+use crate::tcp::{TcpConnection, TcpEvent, TcpSegment, TcpState, ACK, SYN};
+
+pub const BGP_PORT: u16 = 179;
+pub const DEFAULT_LOCAL_PREF: u32 = 100;
+
+const MARKER: [u8; 16] = [0xFF; 16];
+const HEADER_LEN: usize = 19;
+const MAX_MESSAGE_LEN: usize = 4096;
+const VERSION: u8 = 4;
+const TYPE_OPEN: u8 = 1;
+const TYPE_UPDATE: u8 = 2;
+const TYPE_NOTIFICATION: u8 = 3;
+const TYPE_KEEPALIVE: u8 = 4;
+
+const ATTR_ORIGIN: u8 = 1;
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_NEXT_HOP: u8 = 3;
+const ATTR_MED: u8 = 4;
+const ATTR_LOCAL_PREF: u8 = 5;
+const FLAG_OPTIONAL: u8 = 0x80;
+const FLAG_TRANSITIVE: u8 = 0x40;
+const FLAG_EXTENDED_LENGTH: u8 = 0x10;
+const AS_SEQUENCE: u8 = 2;
+
+// NOTIFICATION error codes (RFC 4271 section 4.5) and the subcodes used here
+const ERROR_HEADER: u8 = 1;
+const ERROR_OPEN: u8 = 2;
+const ERROR_UPDATE: u8 = 3;
+const ERROR_HOLD_TIMER: u8 = 4;
+const ERROR_FSM: u8 = 5;
+const BAD_PEER_AS: u8 = 2;
+const UNACCEPTABLE_HOLD_TIME: u8 = 6;
+
+// Prefixes per UPDATE, which keeps messages well below the 4096 byte limit
+const PREFIXES_PER_UPDATE: usize = 64;
+// Ephemeral ports for the connections a router opens
+const FIRST_LOCAL_PORT: u16 = 49152;
+
+pub type RouterId = Ipv4Addr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BgpTimers {
+    pub hold: Duration,          // Sessions not heard from for this long are closed; the lower of the two ends' is used
+    pub keepalive: Duration,     // Between KEEPALIVEs, at most a third of the hold time
+    pub connect_retry: Duration, // Before opening a session again after it failed
+}
+
+impl BgpTimers {
+    // Time between KEEPALIVEs on a session with the given hold time
+    fn keepalive_for(&self, hold_time: Duration) -> Duration { self.keepalive.min(hold_time / 3) }
+}
+
+impl Default for BgpTimers {
+    fn default() -> Self {
+        Self {
+            hold: Duration::from_secs(90),
+            keepalive: Duration::from_secs(30),
+            connect_retry: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    Igp,
+    Egp,
+    Incomplete,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Igp => write!(f, "i"),
+            Origin::Egp => write!(f, "e"),
+            Origin::Incomplete => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathAttributes {
+    pub origin: Origin,
+    pub as_path: Vec<u16>, // Nearest AS first
+    pub next_hop: Ipv4Addr,
+    pub med: Option<u32>,        // Multi-exit discriminator, lower is better
+    pub local_pref: Option<u32>, // Only sent to iBGP peers, higher is better
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BgpMessage {
+    Open { asn: u16, hold_time: u16, router_id: RouterId },
+    // Routes to withdraw, and routes sharing one set of attributes
+    Update { withdrawn: Vec<Ipv4Net>, attributes: Option<PathAttributes>, nlri: Vec<Ipv4Net> },
+    Notification { code: u8, subcode: u8 },
+    Keepalive,
+}
+
+impl BgpMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, body) = match self {
+            BgpMessage::Open { asn, hold_time, router_id } => {
+                let mut body = vec![VERSION];
+                body.extend_from_slice(&asn.to_be_bytes());
+                body.extend_from_slice(&hold_time.to_be_bytes());
+                body.extend_from_slice(&router_id.octets());
+                body.push(0); // No optional parameters
+                (TYPE_OPEN, body)
+            }
+            BgpMessage::Update { withdrawn, attributes, nlri } => {
+                let withdrawn = encode_prefixes(withdrawn);
+                let attributes = attributes.as_ref().map_or_else(Vec::new, encode_attributes);
+                let mut body = Vec::new();
+                body.extend_from_slice(&(withdrawn.len() as u16).to_be_bytes());
+                body.extend_from_slice(&withdrawn);
+                body.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+                body.extend_from_slice(&attributes);
+                body.extend_from_slice(&encode_prefixes(nlri));
+                (TYPE_UPDATE, body)
+            }
+            BgpMessage::Notification { code, subcode } => (TYPE_NOTIFICATION, vec![*code, *subcode]),
+            BgpMessage::Keepalive => (TYPE_KEEPALIVE, Vec::new()),
+        };
+        let mut bytes = MARKER.to_vec();
+        bytes.extend_from_slice(&((HEADER_LEN + body.len()) as u16).to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // Length of the message at the start of a stream, once its header has arrived
+    fn length(stream: &[u8]) -> Option<usize> {
+        (stream.len() >= HEADER_LEN).then(|| usize::from(u16::from_be_bytes([stream[16], stream[17]])))
+    }
+
+    // One whole message; errors are the (code, subcode) of the NOTIFICATION to answer with
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, (u8, u8)> {
+        if bytes.len() < HEADER_LEN || bytes[..16] != MARKER {
+            return Err((ERROR_HEADER, 1));
+        }
+        let length = usize::from(u16::from_be_bytes([bytes[16], bytes[17]]));
+        if !(HEADER_LEN..=MAX_MESSAGE_LEN).contains(&length) || length != bytes.len() {
+            return Err((ERROR_HEADER, 2));
+        }
+        let body = &bytes[HEADER_LEN..];
+        match bytes[18] {
+            TYPE_OPEN => {
+                if body.len() < 10 || body.len() != 10 + usize::from(body[9]) {
+                    return Err((ERROR_OPEN, 0));
+                }
+                if body[0] != VERSION {
+                    return Err((ERROR_OPEN, 1));
+                }
+                Ok(BgpMessage::Open {
+                    asn: u16::from_be_bytes([body[1], body[2]]),
+                    hold_time: u16::from_be_bytes([body[3], body[4]]),
+                    router_id: Ipv4Addr::new(body[5], body[6], body[7], body[8]),
+                })
+            }
+            TYPE_UPDATE => decode_update(body).ok_or((ERROR_UPDATE, 1)),
+            TYPE_NOTIFICATION if body.len() >= 2 => Ok(BgpMessage::Notification { code: body[0], subcode: body[1] }),
+            TYPE_KEEPALIVE if body.is_empty() => Ok(BgpMessage::Keepalive),
+            TYPE_NOTIFICATION | TYPE_KEEPALIVE => Err((ERROR_HEADER, 2)),
+            _ => Err((ERROR_HEADER, 3)),
+        }
+    }
+}
+
+// Prefixes are sent as their length and as many bytes of the address as it needs
+fn encode_prefixes(prefixes: &[Ipv4Net]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for prefix in prefixes {
+        let len = prefix.prefix_len();
+        bytes.push(len);
+        bytes.extend_from_slice(&prefix.network().octets()[..usize::from(len).div_ceil(8)]);
+    }
+    bytes
+}
+
+fn decode_prefixes(mut bytes: &[u8]) -> Option<Vec<Ipv4Net>> {
+    let mut prefixes = Vec::new();
+    while let Some((&len, rest)) = bytes.split_first() {
+        let count = usize::from(len).div_ceil(8);
+        if len > 32 || rest.len() < count {
+            return None;
+        }
+        let mut octets = [0; 4];
+        octets[..count].copy_from_slice(&rest[..count]);
+        prefixes.push(Ipv4Net::new(Ipv4Addr::from(octets), len).ok()?.trunc());
+        bytes = &rest[count..];
+    }
+    Some(prefixes)
+}
+
+fn encode_attributes(attributes: &PathAttributes) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut push = |flags: u8, kind: u8, value: &[u8]| {
+        if value.len() > 255 {
+            bytes.extend_from_slice(&[flags | FLAG_EXTENDED_LENGTH, kind]);
+            bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&[flags, kind, value.len() as u8]);
+        }
+        bytes.extend_from_slice(value);
+    };
+    let origin = match attributes.origin {
+        Origin::Igp => 0,
+        Origin::Egp => 1,
+        Origin::Incomplete => 2,
+    };
+    push(FLAG_TRANSITIVE, ATTR_ORIGIN, &[origin]);
+    let mut path = Vec::new();
+    for segment in attributes.as_path.chunks(255) {
+        path.extend_from_slice(&[AS_SEQUENCE, segment.len() as u8]);
+        for asn in segment {
+            path.extend_from_slice(&asn.to_be_bytes());
+        }
+    }
+    push(FLAG_TRANSITIVE, ATTR_AS_PATH, &path);
+    push(FLAG_TRANSITIVE, ATTR_NEXT_HOP, &attributes.next_hop.octets());
+    if let Some(med) = attributes.med {
+        push(FLAG_OPTIONAL, ATTR_MED, &med.to_be_bytes());
+    }
+    if let Some(local_pref) = attributes.local_pref {
+        push(FLAG_TRANSITIVE, ATTR_LOCAL_PREF, &local_pref.to_be_bytes());
+    }
+    bytes
+}
+
+fn decode_update(body: &[u8]) -> Option<BgpMessage> {
+    let withdrawn_len = usize::from(u16::from_be_bytes([*body.first()?, *body.get(1)?]));
+    let withdrawn = decode_prefixes(body.get(2..2 + withdrawn_len)?)?;
+    let rest = &body[2 + withdrawn_len..];
+    let attributes_len = usize::from(u16::from_be_bytes([*rest.first()?, *rest.get(1)?]));
+    let mut attributes = rest.get(2..2 + attributes_len)?;
+    let nlri = decode_prefixes(&rest[2 + attributes_len..])?;
+
+    let (mut origin, mut as_path, mut next_hop, mut med, mut local_pref) = (None, None, None, None, None);
+    while !attributes.is_empty() {
+        let (flags, kind) = (attributes[0], *attributes.get(1)?);
+        let (len, start) = if flags & FLAG_EXTENDED_LENGTH != 0 {
+            (usize::from(u16::from_be_bytes([*attributes.get(2)?, *attributes.get(3)?])), 4)
+        } else {
+            (usize::from(*attributes.get(2)?), 3)
+        };
+        let value = attributes.get(start..start + len)?;
+        let u32_value = || value.try_into().ok().map(u32::from_be_bytes);
+        match kind {
+            ATTR_ORIGIN => {
+                origin = Some(match value {
+                    [0] => Origin::Igp,
+                    [1] => Origin::Egp,
+                    [2] => Origin::Incomplete,
+                    _ => return None,
+                })
+            }
+            ATTR_AS_PATH => {
+                let mut path = Vec::new();
+                let mut segments = value;
+                while let [_, count, rest @ ..] = segments {
+                    let count = usize::from(*count);
+                    let asns = rest.get(..count * 2)?;
+                    path.extend(asns.chunks_exact(2).map(|asn| u16::from_be_bytes([asn[0], asn[1]])));
+                    segments = &rest[count * 2..];
+                }
+                as_path = Some(path);
+            }
+            ATTR_NEXT_HOP => next_hop = Some(Ipv4Addr::from(u32_value()?)),
+            ATTR_MED => med = Some(u32_value()?),
+            ATTR_LOCAL_PREF => local_pref = Some(u32_value()?),
+            _ => {} // Unknown attributes are ignored
+        }
+        attributes = &attributes[start + len..];
+    }
+
+    // Routes need the three mandatory attributes
+    let attributes = if nlri.is_empty() {
+        None
+    } else {
+        Some(PathAttributes { origin: origin?, as_path: as_path?, next_hop: next_hop?, med, local_pref })
+    };
+    Some(BgpMessage::Update { withdrawn, attributes, nlri })
+}
+
+// One rule of an import or export policy. The first rule whose conditions all hold decides
+// what happens to a route; routes that match no rule are accepted unchanged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PolicyRule {
+    pub prefix: Option<Ipv4Net>, // Matches routes to this network or parts of it
+    pub as_path: Option<u16>,    // Matches routes that went through this AS
+    pub deny: bool,
+    pub local_pref: Option<u32>,
+    pub med: Option<u32>,
+    pub prepend: u8, // Extra copies of our AS number, to make a path less attractive (export only)
+}
+
+impl PolicyRule {
+    fn matches(&self, network: Ipv4Net, attributes: &PathAttributes) -> bool {
+        self.prefix.is_none_or(|prefix| prefix.contains(network.network()) && network.prefix_len() >= prefix.prefix_len())
+            && self.as_path.is_none_or(|asn| attributes.as_path.contains(&asn))
+    }
+}
+
+fn apply_policy(policy: &[PolicyRule], network: Ipv4Net, mut attributes: PathAttributes, asn: u16) -> Option<PathAttributes> {
+    let Some(rule) = policy.iter().find(|rule| rule.matches(network, &attributes)) else {
+        return Some(attributes);
+    };
+    if rule.deny {
+        return None;
+    }
+    if rule.local_pref.is_some() {
+        attributes.local_pref = rule.local_pref;
+    }
+    if rule.med.is_some() {
+        attributes.med = rule.med;
+    }
+    for _ in 0..rule.prepend {
+        attributes.as_path.insert(0, asn);
+    }
+    Some(attributes)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BgpNeighbor {
+    pub address: Ipv4Addr,
+    pub remote_as: u16, // Same as ours for iBGP
+    // Interface the neighbor is directly reached through; sessions without one are routed
+    // like other traffic, e.g. over routes from OSPF
+    pub interface: Option<usize>,
+    pub next_hop_self: bool, // Advertise ourselves as next hop to this iBGP neighbor
+    pub import: Vec<PolicyRule>,
+    pub export: Vec<PolicyRule>,
+}
+
+// BGP finite state machine (RFC 4271 section 8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Idle,    // Waiting to retry after a failure
+    Connect, // Opening the TCP connection
+    Active,  // Waiting for the neighbor to connect
+    OpenSent,
+    OpenConfirm,
+    Established,
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionState::Idle => write!(f, "Idle"),
+            SessionState::Connect => write!(f, "Connect"),
+            SessionState::Active => write!(f, "Active"),
+            SessionState::OpenSent => write!(f, "OpenSent"),
+            SessionState::OpenConfirm => write!(f, "OpenConfirm"),
+            SessionState::Established => write!(f, "Established"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    neighbor: BgpNeighbor,
+    state: SessionState,
+    connection: Option<TcpConnection>,
+    stream: Vec<u8>, // Received bytes not yet making up a whole message
+    hold_time: Duration,
+    hold_expires: Duration,
+    keepalive_at: Duration,
+    retry_at: Duration,
+    established_at: Duration,
+    peer_id: Option<RouterId>,
+    received: BTreeMap<Ipv4Net, PathAttributes>,   // Routes from the neighbor, after the import policy
+    advertised: BTreeMap<Ipv4Net, PathAttributes>, // Routes sent to it, after the export policy
+}
+
+impl Session {
+    fn is_external(&self, asn: u16) -> bool { self.neighbor.remote_as != asn }
+}
+
+// A route in the BGP table
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    attributes: PathAttributes,
+    from: Option<usize>,                // Session it was learned from, None for our own networks
+    resolved: Option<(usize, Ipv4Addr)>, // Interface and gateway toward the next hop
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BgpAction {
+    // `interface` is set for directly connected neighbors, the segment is routed otherwise
+    Send { peer: Ipv4Addr, interface: Option<usize>, segment: TcpSegment },
+    Install { network: Ipv4Net, interface: usize, next_hop: Ipv4Addr, external: bool },
+    Remove { network: Ipv4Net },
+    Log(String), // Sessions coming up and going down
+}
+
+// BGP-4 (RFC 4271) path-vector routing between autonomous systems. Each session runs over
+// a TCP connection to port 179, opened by the end with the higher router ID while the other
+// listens. Routes from established sessions pass the neighbor's import policy into the BGP
+// table, the best route to each network is installed and advertised to the other neighbors
+// through their export policies: with our AS prepended and ourselves as next hop to eBGP
+// neighbors, and never from one iBGP neighbor to another, so an AS needs a full iBGP mesh.
+#[derive(Debug, Clone)]
+pub struct Bgp {
+    asn: u16,
+    router_id: RouterId,
+    timers: BgpTimers,
+    networks: Vec<Ipv4Net>, // Announced while the routing table has a route to them
+    sessions: Vec<Session>,
+    best: BTreeMap<Ipv4Net, Candidate>,
+    next_port: u16,
+    actions: Vec<BgpAction>,
+}
+
+impl Bgp {
+    pub fn new(asn: u16, router_id: RouterId, timers: BgpTimers) -> Self {
+        Self {
+            asn,
+            router_id,
+            timers,
+            networks: Vec::new(),
+            sessions: Vec::new(),
+            best: BTreeMap::new(),
+            next_port: FIRST_LOCAL_PORT,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn asn(&self) -> u16 { self.asn }
+
+    pub fn timers(&self) -> BgpTimers { self.timers }
+
+    pub fn networks(&self) -> &[Ipv4Net] { &self.networks }
+
+    pub fn add_network(&mut self, network: Ipv4Net) {
+        if !self.networks.contains(&network.trunc()) {
+            self.networks.push(network.trunc());
+        }
+    }
+
+    pub fn neighbors(&self) -> Vec<&BgpNeighbor> { self.sessions.iter().map(|session| &session.neighbor).collect() }
+
+    pub fn add_neighbor(&mut self, neighbor: BgpNeighbor) {
+        self.sessions.push(Session {
+            neighbor,
+            state: SessionState::Idle,
+            connection: None,
+            stream: Vec::new(),
+            hold_time: self.timers.hold,
+            hold_expires: Duration::ZERO,
+            keepalive_at: Duration::ZERO,
+            retry_at: Duration::ZERO,
+            established_at: Duration::ZERO,
+            peer_id: None,
+            received: BTreeMap::new(),
+            advertised: BTreeMap::new(),
+        });
+    }
+
+    // Called every second with which interfaces have a working link and the routes from other
+    // sources (longest prefix first), which next hops are resolved over. Opens sessions, resends
+    // unacknowledged data, keeps sessions alive and closes those whose neighbor went quiet or
+    // whose interface went down, then reruns the decision process.
+    pub fn tick(&mut self, links: &[bool], routes: &[(Ipv4Net, RouteEntry)], now: Duration) -> Vec<BgpAction> {
+        for index in 0..self.sessions.len() {
+            let session = &mut self.sessions[index];
+            if session.neighbor.interface.is_some_and(|interface| !links.get(interface).copied().unwrap_or(false)) {
+                if session.state != SessionState::Idle {
+                    self.close(index, None, "interface down", now);
+                }
+                self.sessions[index].retry_at = now;
+                continue;
+            }
+
+            let events = session.connection.as_mut().map_or_else(Vec::new, |connection| connection.tick(now));
+            self.handle_tcp(index, events, now);
+            let session = &mut self.sessions[index];
+            match session.state {
+                SessionState::Idle | SessionState::Active if now >= session.retry_at => self.open(index, now),
+                SessionState::OpenSent | SessionState::OpenConfirm | SessionState::Established => {
+                    if !session.hold_time.is_zero() && now >= session.hold_expires {
+                        self.close(index, Some((ERROR_HOLD_TIMER, 0)), "hold timer expired", now);
+                    } else if session.state != SessionState::OpenSent && !session.hold_time.is_zero() && now >= session.keepalive_at {
+                        session.keepalive_at = now + self.timers.keepalive_for(session.hold_time);
+                        self.send(index, &BgpMessage::Keepalive, now);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.decide(routes, now);
+        std::mem::take(&mut self.actions)
+    }
+
+    // A TCP segment from `src_ip` to our address. Routes it brings are decided on at the next tick.
+    pub fn receive(&mut self, src_ip: Ipv4Addr, segment: &TcpSegment, now: Duration) -> Vec<BgpAction> {
+        let index = self.sessions.iter().position(|session| session.neighbor.address == src_ip);
+        let matching = index.filter(|index| self.sessions[*index].connection.as_ref().is_some_and(|connection| connection.matches(segment)));
+        match (index, matching) {
+            (_, Some(index)) => {
+                let events = self.sessions[index].connection.as_mut().map_or_else(Vec::new, |connection| connection.receive(segment, now));
+                self.handle_tcp(index, events, now);
+            }
+            // A neighbor connecting: accept if we are waiting for it, or it restarted
+            (Some(index), None) if segment.dest_port == BGP_PORT && segment.has(SYN) && !segment.has(ACK) => {
+                let session = &self.sessions[index];
+                if session.state != SessionState::Active && !self.accepts_only(index) {
+                    self.reset(src_ip, index, segment);
+                } else if let Some((connection, event)) = TcpConnection::accept(segment, self.initial_sequence(now), now) {
+                    if session.state != SessionState::Active {
+                        self.close(index, None, "neighbor reconnected", now);
+                    }
+                    let session = &mut self.sessions[index];
+                    session.connection = Some(connection);
+                    session.state = SessionState::Active;
+                    self.handle_tcp(index, vec![event], now);
+                }
+            }
+            (index, None) => self.reset(src_ip, index.unwrap_or(usize::MAX), segment),
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    // Whether the neighbor is the one to open the session
+    fn accepts_only(&self, index: usize) -> bool { self.sessions[index].neighbor.address > self.router_id }
+
+    fn open(&mut self, index: usize, now: Duration) {
+        if self.accepts_only(index) {
+            self.sessions[index].state = SessionState::Active;
+            return;
+        }
+        let port = self.next_port;
+        self.next_port = if port == u16::MAX { FIRST_LOCAL_PORT } else { port + 1 };
+        let (connection, event) = TcpConnection::connect(port, BGP_PORT, self.initial_sequence(now), now);
+        let session = &mut self.sessions[index];
+        session.connection = Some(connection);
+        session.state = SessionState::Connect;
+        session.retry_at = now + self.timers.connect_retry;
+        self.handle_tcp(index, vec![event], now);
+    }
+
+    // Initial sequence numbers vary with the clock, as RFC 793 suggests
+    fn initial_sequence(&self, now: Duration) -> u32 {
+        (now.as_micros() as u32).wrapping_mul(2_654_435_761) ^ u32::from(self.router_id)
+    }
+
+    fn reset(&mut self, src_ip: Ipv4Addr, index: usize, segment: &TcpSegment) {
+        if let Some(reset) = segment.reset_for() {
+            let interface = self.sessions.get(index).and_then(|session| session.neighbor.interface);
+            self.actions.push(BgpAction::Send { peer: src_ip, interface, segment: reset });
+        }
+    }
+
+    fn handle_tcp(&mut self, index: usize, events: Vec<TcpEvent>, now: Duration) {
+        for event in events {
+            let session = &mut self.sessions[index];
+            match event {
+                TcpEvent::Send(segment) => {
+                    self.actions.push(BgpAction::Send { peer: session.neighbor.address, interface: session.neighbor.interface, segment });
+                }
+                TcpEvent::Connected => {
+                    session.state = SessionState::OpenSent;
+                    session.hold_time = self.timers.hold;
+                    session.hold_expires = now + self.timers.hold.max(Duration::from_secs(240));
+                    let open = BgpMessage::Open { asn: self.asn, hold_time: self.timers.hold.as_secs() as u16, router_id: self.router_id };
+                    self.send(index, &open, now);
+                }
+                TcpEvent::Data(bytes) => {
+                    session.stream.extend_from_slice(&bytes);
+                    while let Some(length) = BgpMessage::length(&self.sessions[index].stream) {
+                        let session = &mut self.sessions[index];
+                        if !(HEADER_LEN..=MAX_MESSAGE_LEN).contains(&length) {
+                            self.close(index, Some((ERROR_HEADER, 2)), "bad message length", now);
+                            return;
+                        }
+                        if session.stream.len() < length {
+                            break;
+                        }
+                        let bytes: Vec<u8> = session.stream.drain(..length).collect();
+                        match BgpMessage::from_bytes(&bytes) {
+                            Ok(message) => self.handle_message(index, message, now),
+                            Err(error) => self.close(index, Some(error), "malformed message", now),
+                        }
+                        if self.sessions[index].connection.is_none() {
+                            return;
+                        }
+                    }
+                }
+                TcpEvent::Closed => {
+                    let reason = if session.state == SessionState::Connect { "connection failed" } else { "connection lost" };
+                    session.connection = None;
+                    self.close(index, None, reason, now);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, index: usize, message: BgpMessage, now: Duration) {
+        let session = &mut self.sessions[index];
+        if matches!(session.state, SessionState::OpenConfirm | SessionState::Established) {
+            session.hold_expires = now + session.hold_time;
+        }
+        match (session.state, message) {
+            (SessionState::OpenSent, BgpMessage::Open { asn, hold_time, router_id }) => {
+                if asn != session.neighbor.remote_as {
+                    self.close(index, Some((ERROR_OPEN, BAD_PEER_AS)), &format!("neighbor is in AS {asn}"), now);
+                    return;
+                }
+                if hold_time == 1 || hold_time == 2 {
+                    self.close(index, Some((ERROR_OPEN, UNACCEPTABLE_HOLD_TIME)), "unacceptable hold time", now);
+                    return;
+                }
+                session.peer_id = Some(router_id);
+                session.hold_time = session.hold_time.min(Duration::from_secs(u64::from(hold_time)));
+                session.hold_expires = now + session.hold_time;
+                session.keepalive_at = now + self.timers.keepalive_for(session.hold_time);
+                session.state = SessionState::OpenConfirm;
+                self.send(index, &BgpMessage::Keepalive, now);
+            }
+            (SessionState::OpenConfirm, BgpMessage::Keepalive) => {
+                session.state = SessionState::Established;
+                session.established_at = now;
+                let message = format!("Session with {} (AS {}) established", session.neighbor.address, session.neighbor.remote_as);
+                self.actions.push(BgpAction::Log(message));
+                // Everything we know goes out on the next decision run
+                self.sessions[index].advertised.clear();
+            }
+            (SessionState::Established, BgpMessage::Keepalive) => {}
+            (SessionState::Established, BgpMessage::Update { withdrawn, attributes, nlri }) => {
+                let external = session.is_external(self.asn);
+                for network in withdrawn {
+                    session.received.remove(&network);
+                }
+                let Some(mut attributes) = attributes else {
+                    return;
+                };
+                // Loops: a path through our own AS is dropped
+                if attributes.as_path.contains(&self.asn) {
+                    for network in nlri {
+                        session.received.remove(&network);
+                    }
+                    return;
+                }
+                if external {
+                    attributes.local_pref = None;
+                }
+                for network in nlri {
+                    match apply_policy(&session.neighbor.import, network, attributes.clone(), self.asn) {
+                        Some(accepted) => session.received.insert(network, accepted),
+                        None => session.received.remove(&network),
+                    };
+                }
+            }
+            (_, BgpMessage::Notification { code, subcode }) => {
+                if let Some(connection) = &mut session.connection {
+                    connection.close();
+                }
+                session.connection = None;
+                self.close(index, None, &format!("notification {code}/{subcode} received"), now);
+            }
+            (state, _) => {
+                self.close(index, Some((ERROR_FSM, 0)), &format!("unexpected message in state {state}"), now);
+            }
+        }
+    }
+
+    fn send(&mut self, index: usize, message: &BgpMessage, now: Duration) {
+        let event = self.sessions[index].connection.as_mut().and_then(|connection| connection.send(message.to_bytes(), now));
+        if let Some(TcpEvent::Send(segment)) = event {
+            let neighbor = &self.sessions[index].neighbor;
+            self.actions.push(BgpAction::Send { peer: neighbor.address, interface: neighbor.interface, segment });
+        }
+    }
+
+    // Ends a session, with a NOTIFICATION if there is an error to report, and forgets its routes
+    fn close(&mut self, index: usize, error: Option<(u8, u8)>, reason: &str, now: Duration) {
+        if let Some((code, subcode)) = error {
+            self.send(index, &BgpMessage::Notification { code, subcode }, now);
+        }
+        let session = &mut self.sessions[index];
+        let was_established = session.state == SessionState::Established;
+        if let Some(reset) = session.connection.as_mut().and_then(TcpConnection::close) {
+            self.actions.push(BgpAction::Send { peer: session.neighbor.address, interface: session.neighbor.interface, segment: reset });
+        }
+        session.connection = None;
+        session.stream.clear();
+        session.state = SessionState::Idle;
+        session.retry_at = now + self.timers.connect_retry;
+        session.peer_id = None;
+        session.received.clear();
+        session.advertised.clear();
+        if was_established || error.is_some() {
+            let message = format!("Session with {} (AS {}) closed: {reason}", session.neighbor.address, session.neighbor.remote_as);
+            self.actions.push(BgpAction::Log(message));
+        }
+    }
+
+    // The route to each network from our own networks and every established session
+    fn candidates(&self, routes: &[(Ipv4Net, RouteEntry)]) -> BTreeMap<Ipv4Net, Vec<Candidate>> {
+        let mut candidates: BTreeMap<Ipv4Net, Vec<Candidate>> = BTreeMap::new();
+        for network in &self.networks {
+            if routes.iter().any(|(route, _)| route == network) {
+                let attributes = PathAttributes {
+                    origin: Origin::Igp,
+                    as_path: Vec::new(),
+                    next_hop: Ipv4Addr::UNSPECIFIED,
+                    med: None,
+                    local_pref: Some(DEFAULT_LOCAL_PREF),
+                };
+                candidates.entry(*network).or_default().push(Candidate { attributes, from: None, resolved: None });
+            }
+        }
+        for (index, session) in self.sessions.iter().enumerate() {
+            if session.state != SessionState::Established {
+                continue;
+            }
+            for (network, attributes) in &session.received {
+                // Next hops are neighbors we reach directly, or resolved over the other routes
+                let next_hop = attributes.next_hop;
+                let resolved = self.sessions
+                    .iter()
+                    .find_map(|other| other.neighbor.interface.filter(|_| other.neighbor.address == next_hop))
+                    .map(|interface| (interface, next_hop))
+                    .or_else(|| {
+                        routes
+                            .iter()
+                            .find(|(route, _)| route.contains(next_hop))
+                            .map(|(_, (interface, gateway))| (*interface, gateway.unwrap_or(next_hop)))
+                    });
+                if resolved.is_some() {
+                    candidates.entry(*network).or_default().push(Candidate { attributes: attributes.clone(), from: Some(index), resolved });
+                }
+            }
+        }
+        candidates
+    }
+
+    // Best path selection (RFC 4271 section 9.1.2.2, without IGP costs): highest local
+    // preference, our own networks, shortest AS path, lowest origin, lowest MED among routes
+    // from the same neighboring AS, eBGP over iBGP, then the lowest neighbor router ID
+    fn better(&self, a: &Candidate, b: &Candidate) -> bool {
+        let local_pref = |candidate: &Candidate| candidate.attributes.local_pref.unwrap_or(DEFAULT_LOCAL_PREF);
+        if local_pref(a) != local_pref(b) {
+            return local_pref(a) > local_pref(b);
+        }
+        if a.from.is_none() != b.from.is_none() {
+            return a.from.is_none();
+        }
+        if a.attributes.as_path.len() != b.attributes.as_path.len() {
+            return a.attributes.as_path.len() < b.attributes.as_path.len();
+        }
+        if a.attributes.origin != b.attributes.origin {
+            return a.attributes.origin < b.attributes.origin;
+        }
+        if a.attributes.as_path.first() == b.attributes.as_path.first() {
+            let (med_a, med_b) = (a.attributes.med.unwrap_or(0), b.attributes.med.unwrap_or(0));
+            if med_a != med_b {
+                return med_a < med_b;
+            }
+        }
+        let session = |candidate: &Candidate| candidate.from.map(|index| &self.sessions[index]);
+        let external = |candidate: &Candidate| session(candidate).is_some_and(|session| session.is_external(self.asn));
+        if external(a) != external(b) {
+            return external(a);
+        }
+        let id = |candidate: &Candidate| session(candidate).and_then(|session| session.peer_id).unwrap_or(Ipv4Addr::UNSPECIFIED);
+        id(a) < id(b)
+    }
+
+    // Picks the best route to every network, installs the changes and updates the neighbors
+    fn decide(&mut self, routes: &[(Ipv4Net, RouteEntry)], now: Duration) {
+        let mut best: BTreeMap<Ipv4Net, Candidate> = BTreeMap::new();
+        for (network, candidates) in self.candidates(routes) {
+            let mut chosen = &candidates[0];
+            for candidate in &candidates[1..] {
+                if self.better(candidate, chosen) {
+                    chosen = candidate;
+                }
+            }
+            best.insert(network, chosen.clone());
+        }
+
+        for (network, old) in &self.best {
+            if old.from.is_some() && best.get(network).is_none_or(|new| new.from.is_none()) {
+                self.actions.push(BgpAction::Remove { network: *network });
+            }
+        }
+        for (network, new) in &best {
+            if let (Some(from), Some((interface, next_hop))) = (new.from, new.resolved)
+                && self.best.get(network).is_none_or(|old| old.resolved != new.resolved || old.from != new.from)
+            {
+                let external = self.sessions[from].is_external(self.asn);
+                self.actions.push(BgpAction::Install { network: *network, interface, next_hop, external });
+            }
+        }
+        self.best = best;
+
+        for index in 0..self.sessions.len() {
+            if self.sessions[index].state == SessionState::Established {
+                self.advertise(index, now);
+            }
+        }
+    }
+
+    // Sends a neighbor the changes to what it should hear from us
+    fn advertise(&mut self, index: usize, now: Duration) {
+        let session = &self.sessions[index];
+        let external = session.is_external(self.asn);
+        let mut desired: BTreeMap<Ipv4Net, PathAttributes> = BTreeMap::new();
+        for (network, candidate) in &self.best {
+            let learned_from = candidate.from.map(|from| &self.sessions[from]);
+            if candidate.from == Some(index) || (!external && learned_from.is_some_and(|from| !from.is_external(self.asn))) {
+                continue;
+            }
+            let mut attributes = candidate.attributes.clone();
+            if external {
+                attributes.as_path.insert(0, self.asn);
+                attributes.next_hop = self.router_id;
+                attributes.local_pref = None;
+                attributes.med = None;
+            } else {
+                attributes.local_pref = Some(attributes.local_pref.unwrap_or(DEFAULT_LOCAL_PREF));
+                if candidate.from.is_none() || session.neighbor.next_hop_self {
+                    attributes.next_hop = self.router_id;
+                }
+            }
+            if let Some(attributes) = apply_policy(&session.neighbor.export, *network, attributes, self.asn) {
+                desired.insert(*network, attributes);
+            }
+        }
+
+        let withdrawn: Vec<Ipv4Net> = session.advertised.keys().filter(|network| !desired.contains_key(network)).copied().collect();
+        let mut announced: Vec<(PathAttributes, Vec<Ipv4Net>)> = Vec::new();
+        for (network, attributes) in &desired {
+            if session.advertised.get(network) == Some(attributes) {
+                continue;
+            }
+            match announced.iter_mut().find(|(other, _)| other == attributes) {
+                Some((_, networks)) => networks.push(*network),
+                None => announced.push((attributes.clone(), vec![*network])),
+            }
+        }
+        let mut messages: Vec<BgpMessage> = withdrawn
+            .chunks(PREFIXES_PER_UPDATE)
+            .map(|chunk| BgpMessage::Update { withdrawn: chunk.to_vec(), attributes: None, nlri: Vec::new() })
+            .collect();
+        for (attributes, networks) in announced {
+            for chunk in networks.chunks(PREFIXES_PER_UPDATE) {
+                messages.push(BgpMessage::Update { withdrawn: Vec::new(), attributes: Some(attributes.clone()), nlri: chunk.to_vec() });
+            }
+        }
+        self.sessions[index].advertised = desired;
+        for message in messages {
+            self.send(index, &message, now);
+        }
+    }
+
+    // Sessions and the BGP table, best routes marked with *
+    pub fn describe(&self, interface_names: &[String], now: Duration) -> String {
+        let name = |index: usize| interface_names.get(index).map_or("?", String::as_str);
+        let mut lines = vec![format!(
+            "BGP: AS {}, router ID {}, hold {} s, keepalive {} s, networks {}",
+            self.asn,
+            self.router_id,
+            self.timers.hold.as_secs_f64(),
+            self.timers.keepalive.as_secs_f64(),
+            if self.networks.is_empty() { "-".to_string() } else { self.networks.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ") }
+        )];
+        lines.push("Neighbors:".to_string());
+        for session in &self.sessions {
+            let kind = if session.is_external(self.asn) { "eBGP" } else { "iBGP" };
+            let via = session.neighbor.interface.map_or_else(String::new, |interface| format!(" on {}", name(interface)));
+            let state = match session.state {
+                SessionState::Established => format!("Established for {:.1} s", now.saturating_sub(session.established_at).as_secs_f64()),
+                SessionState::Connect if session.connection.as_ref().is_some_and(|c| c.state() == TcpState::SynSent) => "Connect (SYN sent)".to_string(),
+                state => state.to_string(),
+            };
+            lines.push(format!(
+                "  {:<15} AS {:<6} {kind}{via}  {state}, {} received, {} advertised",
+                session.neighbor.address.to_string(),
+                session.neighbor.remote_as,
+                session.received.len(),
+                session.advertised.len()
+            ));
+        }
+        lines.push("BGP table:".to_string());
+        let mut rows = Vec::new();
+        for network in &self.networks {
+            let best = self.best.get(network).is_some_and(|best| best.from.is_none());
+            rows.push((*network, best, "0.0.0.0".to_string(), Some(DEFAULT_LOCAL_PREF), None, Origin::Igp.to_string(), "local".to_string()));
+        }
+        for session in &self.sessions {
+            for (network, attributes) in &session.received {
+                let best = self.best.get(network).is_some_and(|best| {
+                    best.from.is_some_and(|from| self.sessions[from].neighbor.address == session.neighbor.address)
+                });
+                // AS path followed by the origin code, as routers show it
+                let path = attributes.as_path.iter().map(ToString::to_string).chain([attributes.origin.to_string()]).collect::<Vec<_>>().join(" ");
+                rows.push((
+                    *network,
+                    best,
+                    attributes.next_hop.to_string(),
+                    attributes.local_pref,
+                    attributes.med,
+                    path,
+                    session.neighbor.address.to_string(),
+                ));
+            }
+        }
+        rows.sort_by_key(|row| (row.0.addr(), row.0.prefix_len(), !row.1));
+        for (network, best, next_hop, local_pref, med, path, from) in rows {
+            let optional = |value: Option<u32>| value.map_or_else(|| "-".to_string(), |value| value.to_string());
+            lines.push(format!(
+                "  {} {:<18} next hop {:<15} local pref {:<4} MED {:<4} path {path} (from {from})",
+                if best { '*' } else { ' ' },
+                network.to_string(),
+                next_hop,
+                optional(local_pref),
+                optional(med)
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::address::MacAddr;
+    use crate::packet::Packet;
+    use crate::tcp::PSH;
+
+    fn id(n: u8) -> RouterId { Ipv4Addr::new(10, 0, 0, n) }
+
+    fn net(s: &str) -> Ipv4Net { s.parse().unwrap() }
+
+    // Speakers addressed by their router IDs, delivering every segment one sends until the
+    // network is quiet. `igp` holds each speaker's other routes, `routes` what BGP installed.
+    struct Lab {
+        routers: Vec<Bgp>,
+        links: Vec<Vec<bool>>,
+        igp: Vec<Vec<(Ipv4Net, RouteEntry)>>,
+        routes: Vec<BTreeMap<Ipv4Net, (usize, Ipv4Addr, bool)>>,
+    }
+
+    impl Lab {
+        fn new(speakers: &[(u16, usize)]) -> Self {
+            Self {
+                routers: speakers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &(asn, _))| Bgp::new(asn, id(index as u8 + 1), BgpTimers::default()))
+                    .collect(),
+                links: speakers.iter().map(|&(_, interfaces)| vec![true; interfaces]).collect(),
+                igp: vec![Vec::new(); speakers.len()],
+                routes: vec![BTreeMap::new(); speakers.len()],
+            }
+        }
+
+        // A directly connected session between two (speaker, interface) pairs
+        fn peer(&mut self, (a, a_interface): (usize, usize), (b, b_interface): (usize, usize)) {
+            for (local, interface, remote) in [(a, a_interface, b), (b, b_interface, a)] {
+                let neighbor = BgpNeighbor {
+                    address: self.routers[remote].router_id,
+                    remote_as: self.routers[remote].asn,
+                    interface: Some(interface),
+                    next_hop_self: false,
+                    import: Vec::new(),
+                    export: Vec::new(),
+                };
+                self.routers[local].add_neighbor(neighbor);
+            }
+        }
+
+        fn neighbor(&mut self, router: usize, address: Ipv4Addr) -> &mut BgpNeighbor {
+            let session = self.routers[router].sessions.iter_mut().find(|session| session.neighbor.address == address);
+            &mut session.unwrap().neighbor
+        }
+
+        fn deliver(&mut self, router: usize, actions: Vec<BgpAction>, now: Duration) {
+            let mut queue = VecDeque::from([(router, actions)]);
+            while let Some((router, actions)) = queue.pop_front() {
+                for action in actions {
+                    match action {
+                        BgpAction::Send { peer, segment, .. } => {
+                            let src_ip = self.routers[router].router_id;
+                            if let Some(other) = self.routers.iter().position(|bgp| bgp.router_id == peer) {
+                                let replies = self.routers[other].receive(src_ip, &segment, now);
+                                queue.push_back((other, replies));
+                            }
+                        }
+                        BgpAction::Install { network, interface, next_hop, external } => {
+                            self.routes[router].insert(network, (interface, next_hop, external));
+                        }
+                        BgpAction::Remove { network } => {
+                            self.routes[router].remove(&network);
+                        }
+                        BgpAction::Log(_) => {}
+                    }
+                }
+            }
+        }
+
+        // Ticks every speaker once a second until `until`
+        fn run(&mut self, from: u64, until: u64) {
+            for second in from..=until {
+                let now = Duration::from_secs(second);
+                for router in 0..self.routers.len() {
+                    let actions = self.routers[router].tick(&self.links[router], &self.igp[router], now);
+                    self.deliver(router, actions, now);
+                }
+            }
+        }
+    }
+
+    // AS 100 reaches AS 400's network through AS 200 and AS 300, and has an iBGP neighbor in
+    // AS 100 too; AS 200 has the lower router ID but prepends its number twice more
+    fn diamond() -> Lab {
+        let mut lab = Lab::new(&[(100, 3), (200, 2), (300, 2), (400, 3), (100, 1)]);
+        lab.peer((0, 0), (1, 0));
+        lab.peer((0, 1), (2, 0));
+        lab.peer((1, 1), (3, 0));
+        lab.peer((2, 1), (3, 1));
+        lab.peer((0, 2), (4, 0));
+        lab.neighbor(1, id(1)).export = vec![PolicyRule { prepend: 2, ..PolicyRule::default() }];
+        lab.neighbor(0, id(5)).next_hop_self = true;
+        lab.routers[3].add_network(net("10.9.0.0/24"));
+        lab.igp[3] = vec![(net("10.9.0.0/24"), (2, None))];
+        lab
+    }
+
+    #[test]
+    fn shorter_as_path_wins() {
+        let mut lab = diamond();
+        lab.run(0, 10);
+        assert_eq!(lab.routes[0].get(&net("10.9.0.0/24")), Some(&(1, id(3), true)));
+        assert_eq!(lab.routers[0].best[&net("10.9.0.0/24")].attributes.as_path, [300, 400]);
+
+        // With AS 300 gone, the longer path is all that is left
+        lab.links[0][1] = false;
+        lab.run(11, 12);
+        assert_eq!(lab.routes[0].get(&net("10.9.0.0/24")), Some(&(0, id(2), true)));
+        assert_eq!(lab.routers[0].best[&net("10.9.0.0/24")].attributes.as_path, [200, 200, 200, 400]);
+    }
+
+    #[test]
+    fn local_preference_outranks_as_path() {
+        let mut lab = diamond();
+        lab.neighbor(0, id(2)).import = vec![PolicyRule { local_pref: Some(200), ..PolicyRule::default() }];
+        lab.run(0, 10);
+        assert_eq!(lab.routes[0].get(&net("10.9.0.0/24")), Some(&(0, id(2), true)));
+    }
+
+    #[test]
+    fn ibgp_routes_are_installed_as_internal() {
+        let mut lab = diamond();
+        lab.run(0, 10);
+        // Kept as learned over eBGP, apart from the next hop
+        assert_eq!(lab.routes[4].get(&net("10.9.0.0/24")), Some(&(0, id(1), false)));
+        let attributes = &lab.routers[4].best[&net("10.9.0.0/24")].attributes;
+        assert_eq!((attributes.as_path.as_slice(), attributes.local_pref), ([300, 400].as_slice(), Some(DEFAULT_LOCAL_PREF)));
+    }
+
+    #[test]
+    fn withdrawal_removes_the_route() {
+        let mut lab = diamond();
+        lab.run(0, 10);
+        assert!(lab.routes.iter().enumerate().all(|(router, routes)| router == 3 || routes.contains_key(&net("10.9.0.0/24"))));
+
+        // AS 400 loses its network and withdraws it
+        lab.igp[3].clear();
+        lab.run(11, 15);
+        assert!(lab.routes.iter().all(BTreeMap::is_empty));
+        assert!(lab.routers.iter().all(|bgp| bgp.best.is_empty()));
+    }
+
+    #[test]
+    fn bgp_update_over_tcp_round_trip() {
+        let update = BgpMessage::Update {
+            withdrawn: vec!["10.9.0.0/16".parse().unwrap()],
+            attributes: Some(PathAttributes {
+                origin: Origin::Igp,
+                as_path: vec![65002, 65004],
+                next_hop: Ipv4Addr::new(10, 2, 0, 1),
+                med: Some(50),
+                local_pref: None,
+            }),
+            nlri: vec!["10.4.1.0/24".parse().unwrap(), "10.128.0.0/9".parse().unwrap()],
+        };
+        let mut payload = update.to_bytes();
+        payload.extend_from_slice(&BgpMessage::Keepalive.to_bytes());
+        let segment = TcpSegment { src_port: 49152, dest_port: BGP_PORT, seq: 1000, ack: 2000, flags: PSH | ACK, window: 65535, payload };
+        let (src, dest) = (Ipv4Addr::new(10, 2, 0, 1), Ipv4Addr::new(10, 1, 2, 1));
+        let mut packet = Packet::tcp(src, dest, &segment);
+        packet.src_mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x03]);
+        packet.dest_mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x12]);
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes[23], 6);
+        let decoded = Packet::from_bytes(&bytes).unwrap().tcp_segment().unwrap();
+        assert_eq!(decoded, segment);
+
+        // The stream holds an UPDATE then a KEEPALIVE, each 19 bytes of header and its body
+        let length = usize::from(u16::from_be_bytes([decoded.payload[16], decoded.payload[17]]));
+        assert_eq!(BgpMessage::from_bytes(&decoded.payload[..length]), Ok(update));
+        assert_eq!(BgpMessage::from_bytes(&decoded.payload[length..]), Ok(BgpMessage::Keepalive));
+
+        // A segment whose checksum does not match its addresses is dropped
+        assert_eq!(TcpSegment::from_bytes(&segment.to_bytes(src, dest), dest, dest), None);
+    }
+}
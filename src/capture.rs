@@ -0,0 +1,248 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use crate::packet::Packet;
+
+pub type CaptureRef = Rc<RefCell<Capture>>;
+
+const LINKTYPE_ETHERNET: u16 = 1;
+const SNAPLEN: u32 = 65_535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug)]
+struct CapturedFrame {
+    time: Duration,
+    direction: Direction,
+    bytes: Vec<u8>,
+}
+
+// Frames seen at one or more capture points, kept until written out as PCAP or PCAPNG
+#[derive(Debug)]
+pub struct Capture {
+    name: String,
+    frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    pub fn new(name: &str) -> CaptureRef {
+        Rc::new(RefCell::new(Self { name: name.to_string(), frames: Vec::new() }))
+    }
+
+    pub fn record(&mut self, time: Duration, direction: Direction, packet: &Packet) {
+        self.frames.push(CapturedFrame { time, direction, bytes: packet.to_bytes() });
+    }
+
+    pub fn frame_count(&self) -> usize { self.frames.len() }
+
+    // Writes PCAPNG for .pcapng files and classic PCAP otherwise
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pcapng")) {
+            self.write_pcapng(&mut writer)?;
+        } else {
+            self.write_pcap(&mut writer)?;
+        }
+        writer.flush()
+    }
+
+    // Classic libpcap format with microsecond timestamps
+    pub fn write_pcap<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&0xA1B2_C3D4u32.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?; // Version 2.4
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?; // Timezone offset
+        writer.write_all(&0u32.to_le_bytes())?; // Timestamp accuracy
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&u32::from(LINKTYPE_ETHERNET).to_le_bytes())?;
+
+        for frame in &self.frames {
+            let length = frame.bytes.len() as u32;
+            writer.write_all(&(frame.time.as_secs() as u32).to_le_bytes())?;
+            writer.write_all(&frame.time.subsec_micros().to_le_bytes())?;
+            writer.write_all(&length.min(SNAPLEN).to_le_bytes())?;
+            writer.write_all(&length.to_le_bytes())?;
+            writer.write_all(&frame.bytes[..length.min(SNAPLEN) as usize])?;
+        }
+        Ok(())
+    }
+
+    // PCAPNG with one Ethernet interface named after the capture and nanosecond timestamps.
+    // Each packet carries its direction (inbound/outbound) as an epb_flags option.
+    pub fn write_pcapng<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // Section Header Block
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes()); // Section length not known
+        push_option(&mut shb, 4, b"network-simulator"); // shb_userappl
+        push_option(&mut shb, 0, &[]);
+        write_block(writer, 0x0A0D_0D0A, &shb)?;
+
+        // Interface Description Block
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&SNAPLEN.to_le_bytes());
+        push_option(&mut idb, 2, self.name.as_bytes()); // if_name
+        push_option(&mut idb, 9, &[9]); // if_tsresol: 10^-9 seconds
+        push_option(&mut idb, 0, &[]);
+        write_block(writer, 1, &idb)?;
+
+        // Enhanced Packet Blocks
+        for frame in &self.frames {
+            let nanos = frame.time.as_nanos() as u64;
+            let length = frame.bytes.len() as u32;
+            let flags: u32 = match frame.direction {
+                Direction::In => 1,
+                Direction::Out => 2,
+            };
+            let mut epb = Vec::new();
+            epb.extend_from_slice(&0u32.to_le_bytes()); // Interface ID
+            epb.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+            epb.extend_from_slice(&(nanos as u32).to_le_bytes());
+            epb.extend_from_slice(&length.to_le_bytes());
+            epb.extend_from_slice(&length.to_le_bytes());
+            epb.extend_from_slice(&frame.bytes);
+            pad(&mut epb);
+            push_option(&mut epb, 2, &flags.to_le_bytes()); // epb_flags
+            push_option(&mut epb, 0, &[]);
+            write_block(writer, 6, &epb)?;
+        }
+        Ok(())
+    }
+}
+
+// Blocks are framed by their type and total length, repeated at the end
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+// Pads to a 32-bit boundary
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::address::MacAddr;
+
+    // Two frames whose lengths are not multiples of 4, one each way
+    fn capture() -> Capture {
+        let mut capture = Capture { name: "Switch 1:2".to_string(), frames: Vec::new() };
+        for (time, direction, size) in [(Duration::from_micros(1_500_250), Direction::In, 1), (Duration::new(5, 123_456_789), Direction::Out, 7)] {
+            let packet = Packet::new(
+                MacAddr::from([0xAA, 0, 0, 0, 0, 1]),
+                MacAddr::from([0xAA, 0, 0, 0, 0, 2]),
+                Ipv4Addr::new(10, 0, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 2),
+                vec![0x5A; size],
+            );
+            capture.record(time, direction, &packet);
+        }
+        capture
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 { u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 { u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) }
+
+    // Option code and value pairs up to opt_endofopt
+    fn options(mut body: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        let mut options = Vec::new();
+        loop {
+            let (code, length) = (u16_at(body, 0), usize::from(u16_at(body, 2)));
+            if code == 0 {
+                assert_eq!(length, 0);
+                return options;
+            }
+            options.push((code, body[4..4 + length].to_vec()));
+            body = &body[4 + length.next_multiple_of(4)..];
+        }
+    }
+
+    #[test]
+    fn pcap_has_a_global_header_and_microsecond_records() {
+        let capture = capture();
+        let mut bytes = Vec::new();
+        capture.write_pcap(&mut bytes).unwrap();
+        assert_eq!(u32_at(&bytes, 0), 0xA1B2_C3D4);
+        assert_eq!((u16_at(&bytes, 4), u16_at(&bytes, 6)), (2, 4));
+        assert_eq!((u32_at(&bytes, 8), u32_at(&bytes, 12)), (0, 0));
+        assert_eq!(u32_at(&bytes, 16), 65_535);
+        assert_eq!(u32_at(&bytes, 20), 1);
+
+        let mut offset = 24;
+        for (frame, (seconds, micros)) in capture.frames.iter().zip([(1, 500_250), (5, 123_456)]) {
+            let length = frame.bytes.len();
+            assert_eq!((u32_at(&bytes, offset), u32_at(&bytes, offset + 4)), (seconds, micros));
+            assert_eq!((u32_at(&bytes, offset + 8), u32_at(&bytes, offset + 12)), (length as u32, length as u32));
+            assert_eq!(bytes[offset + 16..offset + 16 + length], frame.bytes);
+            offset += 16 + length;
+        }
+        assert_eq!(offset, bytes.len());
+    }
+
+    #[test]
+    fn pcapng_has_padded_blocks_with_nanosecond_times_and_directions() {
+        let capture = capture();
+        let mut bytes = Vec::new();
+        capture.write_pcapng(&mut bytes).unwrap();
+
+        // Every block starts and ends with its total length, a multiple of 4
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let total = u32_at(&bytes, offset + 4) as usize;
+            assert_eq!(total % 4, 0);
+            assert_eq!(u32_at(&bytes, offset + total - 4) as usize, total);
+            blocks.push((u32_at(&bytes, offset), &bytes[offset + 8..offset + total - 4]));
+            offset += total;
+        }
+        assert_eq!(offset, bytes.len());
+        let types: Vec<u32> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(types, [0x0A0D_0D0A, 1, 6, 6]);
+
+        let shb = blocks[0].1;
+        assert_eq!(u32_at(shb, 0), 0x1A2B_3C4D);
+        assert_eq!((u16_at(shb, 4), u16_at(shb, 6)), (1, 0));
+        assert_eq!(options(&shb[16..]), [(4, b"network-simulator".to_vec())]);
+
+        let idb = blocks[1].1;
+        assert_eq!(u16_at(idb, 0), 1);
+        assert_eq!(u32_at(idb, 4), 65_535);
+        assert_eq!(options(&idb[8..]), [(2, b"Switch 1:2".to_vec()), (9, vec![9])]);
+
+        for ((_, epb), (frame, (nanos, flags))) in blocks[2..].iter().zip(capture.frames.iter().zip([(1_500_250_000u64, 1u32), (5_123_456_789, 2)])) {
+            let length = frame.bytes.len();
+            assert_ne!(length % 4, 0);
+            assert_eq!(u32_at(epb, 0), 0);
+            assert_eq!((u64::from(u32_at(epb, 4)) << 32) | u64::from(u32_at(epb, 8)), nanos);
+            assert_eq!((u32_at(epb, 12), u32_at(epb, 16)), (length as u32, length as u32));
+            assert_eq!(epb[20..20 + length], frame.bytes);
+            assert!(epb[20 + length..20 + length.next_multiple_of(4)].iter().all(|&byte| byte == 0));
+            assert_eq!(options(&epb[20 + length.next_multiple_of(4)..]), [(2, flags.to_le_bytes().to_vec())]);
+        }
+    }
+}
@@ -1,70 +1,105 @@
+use std::net::Ipv4Addr;
 use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Duration;
+use crate::link::Attachment;
 use crate::packet::Packet;
+use crate::sim::{Simulator, Timer};
+
+pub type DeviceRef = Rc<RefCell<dyn Device>>;
 
 pub trait Device: std::fmt::Debug {
-    fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>>;
-    fn receive_packet(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>>;
-    fn send_packet(&mut self, dest_ip: &str, data: Vec<u8>);
-    fn get_ip_address(&self) -> String;
-    fn get_device_info(&self) -> String;
+    fn attach_link(&mut self, port: usize, attachment: Attachment);
+    fn receive_packet(&mut self, packet: Rc<Packet>, port: usize, sim: &mut Simulator);
+    fn send_packet(&mut self, dest_ip: Ipv4Addr, data: Vec<u8>, sim: &mut Simulator);
+    // Called once when the simulation starts, for devices that run protocols on their own
+    fn start(&mut self, _sim: &mut Simulator) {}
+    // Called when a timer the device set with Simulator::schedule_timer fires
+    fn handle_timer(&mut self, _timer: Timer, _sim: &mut Simulator) {}
+    fn get_ip_address(&self) -> Option<Ipv4Addr>;
+    fn get_device_info(&self, now: Duration) -> String;
 }
 
 use crate::host::Host;
 impl Device for Host {
-    fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        self.receive_arp_request(packet)
+    fn attach_link(&mut self, _port: usize, attachment: Attachment) {
+        self.attach_link(attachment);
     }
 
-    fn receive_packet(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        self.receive_packet(packet)
+    fn receive_packet(&mut self, packet: Rc<Packet>, _port: usize, sim: &mut Simulator) {
+        self.receive_packet(packet, sim);
     }
 
-    fn send_packet(&mut self, dest_ip: &str, data: Vec<u8>) {
-        self.send_packet(dest_ip, data);
+    fn send_packet(&mut self, dest_ip: Ipv4Addr, data: Vec<u8>, sim: &mut Simulator) {
+        self.send_packet(dest_ip, data, sim);
     }
 
-    fn get_ip_address(&self) -> String { self.get_ip_address() }
+    fn start(&mut self, sim: &mut Simulator) {
+        self.start(sim);
+    }
 
-    fn get_device_info(&self) -> String {
-        self.get_host_info()
+    fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        self.handle_timer(timer, sim);
+    }
+
+    // DHCP clients have no address until they get a lease
+    fn get_ip_address(&self) -> Option<Ipv4Addr> { self.has_address().then(|| self.get_ip_address()) }
+
+    fn get_device_info(&self, now: Duration) -> String {
+        self.get_host_info(now)
     }
 }
 
 use crate::router::Router;
 impl Device for Router {
-    fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        self.receive_arp_request(packet)
+    fn attach_link(&mut self, port: usize, attachment: Attachment) {
+        self.attach_link(port, attachment);
+    }
+
+    fn receive_packet(&mut self, packet: Rc<Packet>, port: usize, sim: &mut Simulator) {
+        self.forward_packet(packet, port, sim);
     }
 
-    fn receive_packet(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        self.forward_packet(packet)
+    fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
+
+    fn start(&mut self, sim: &mut Simulator) {
+        self.start(sim);
     }
 
-    fn send_packet(&mut self, dest_ip: &str, data: Vec<u8>) {}
+    fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        self.handle_timer(timer, sim);
+    }
 
-    fn get_ip_address(&self) -> String { self.get_ip_address() }
+    fn get_ip_address(&self) -> Option<Ipv4Addr> { Some(self.get_ip_address()) }
 
-    fn get_device_info(&self) -> String {
-        self.get_router_info()
+    fn get_device_info(&self, now: Duration) -> String {
+        self.get_router_info(now)
     }
 }
 
 use crate::switch::Switch;
 impl Device for Switch {
-    fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        None
+    fn attach_link(&mut self, port: usize, attachment: Attachment) {
+        self.attach_link(port, attachment);
     }
 
-    fn receive_packet(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        None
+    fn receive_packet(&mut self, packet: Rc<Packet>, port: usize, sim: &mut Simulator) {
+        self.process_packet(packet, port, sim);
     }
 
-    fn send_packet(&mut self, dest_ip: &str, data: Vec<u8>) {}
+    fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
 
-    fn get_ip_address(&self) -> String { "N/A".to_string() }
+    fn start(&mut self, sim: &mut Simulator) {
+        self.start(sim);
+    }
 
-    fn get_device_info(&self) -> String {
-        self.get_switch_info()
+    fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        self.handle_timer(timer, sim);
     }
-}
 
+    fn get_ip_address(&self) -> Option<Ipv4Addr> { None }
+
+    fn get_device_info(&self, now: Duration) -> String {
+        self.get_switch_info(now)
+    }
+}
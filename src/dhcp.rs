@@ -0,0 +1,802 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::{Ipv4Net, MacAddr};
+use crate::packet::Packet;
+use crate::udp::UdpDatagram;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+pub const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(3600);
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const FIXED_LEN: usize = 236; // Everything before the magic cookie
+const MIN_LEN: usize = 300; // Shorter messages are padded, as BOOTP relays expect (RFC 1542)
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+// How long an offered address is kept for the client it was offered to
+const OFFER_HOLD: Duration = Duration::from_secs(60);
+// Clients resend after 4 s, doubling up to 64 s (RFC 2131 section 4.1)
+const FIRST_RETRY: Duration = Duration::from_secs(4);
+const MAX_RETRY: Duration = Duration::from_secs(64);
+// REQUESTs that go unanswered before a client starts over with a DISCOVER
+const MAX_REQUESTS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+}
+
+impl DhcpMessageType {
+    fn code(&self) -> u8 {
+        match self {
+            DhcpMessageType::Discover => 1,
+            DhcpMessageType::Offer => 2,
+            DhcpMessageType::Request => 3,
+            DhcpMessageType::Decline => 4,
+            DhcpMessageType::Ack => 5,
+            DhcpMessageType::Nak => 6,
+            DhcpMessageType::Release => 7,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(DhcpMessageType::Discover),
+            2 => Some(DhcpMessageType::Offer),
+            3 => Some(DhcpMessageType::Request),
+            4 => Some(DhcpMessageType::Decline),
+            5 => Some(DhcpMessageType::Ack),
+            6 => Some(DhcpMessageType::Nak),
+            7 => Some(DhcpMessageType::Release),
+            _ => None,
+        }
+    }
+
+    // Sent by servers to clients
+    pub fn is_reply(&self) -> bool { matches!(self, DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak) }
+}
+
+impl fmt::Display for DhcpMessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DhcpMessageType::Discover => write!(f, "DISCOVER"),
+            DhcpMessageType::Offer => write!(f, "OFFER"),
+            DhcpMessageType::Request => write!(f, "REQUEST"),
+            DhcpMessageType::Decline => write!(f, "DECLINE"),
+            DhcpMessageType::Ack => write!(f, "ACK"),
+            DhcpMessageType::Nak => write!(f, "NAK"),
+            DhcpMessageType::Release => write!(f, "RELEASE"),
+        }
+    }
+}
+
+// A DHCP message (RFC 2131) with the options the simulation uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub kind: DhcpMessageType,
+    pub xid: u32,                // Transaction ID, chosen by the client
+    pub broadcast: bool,         // The client cannot receive unicast before it has an address
+    pub client_ip: Ipv4Addr,     // The client's address, when it has one
+    pub your_ip: Ipv4Addr,       // Address offered or assigned to the client
+    pub client_mac: MacAddr,
+    pub requested_ip: Option<Ipv4Addr>,
+    pub server_id: Option<Ipv4Addr>,
+    pub lease_time: Option<u32>, // Seconds
+    pub prefix_len: Option<u8>,  // Sent as a subnet mask
+    pub router: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+impl DhcpMessage {
+    fn new(kind: DhcpMessageType, xid: u32, client_mac: MacAddr) -> Self {
+        Self {
+            kind,
+            xid,
+            broadcast: false,
+            client_ip: Ipv4Addr::UNSPECIFIED,
+            your_ip: Ipv4Addr::UNSPECIFIED,
+            client_mac,
+            requested_ip: None,
+            server_id: None,
+            lease_time: None,
+            prefix_len: None,
+            router: None,
+            dns_servers: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let op = if self.kind.is_reply() { OP_REPLY } else { OP_REQUEST };
+        let mut bytes = vec![op, HTYPE_ETHERNET, 6, 0];
+        bytes.extend_from_slice(&self.xid.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]); // Seconds since the client started
+        bytes.extend_from_slice(&(if self.broadcast { FLAG_BROADCAST } else { 0 }).to_be_bytes());
+        bytes.extend_from_slice(&self.client_ip.octets());
+        bytes.extend_from_slice(&self.your_ip.octets());
+        bytes.extend_from_slice(&[0; 8]); // Next server and relay agent addresses
+        bytes.extend_from_slice(&self.client_mac.octets());
+        bytes.resize(FIXED_LEN, 0); // Rest of the hardware address, server name and boot file
+        bytes.extend_from_slice(&MAGIC_COOKIE);
+
+        let mut option = |code: u8, value: &[u8]| {
+            bytes.extend_from_slice(&[code, value.len() as u8]);
+            bytes.extend_from_slice(value);
+        };
+        option(OPTION_MESSAGE_TYPE, &[self.kind.code()]);
+        if let Some(server_id) = self.server_id {
+            option(OPTION_SERVER_ID, &server_id.octets());
+        }
+        if let Some(requested_ip) = self.requested_ip {
+            option(OPTION_REQUESTED_IP, &requested_ip.octets());
+        }
+        if let Some(lease_time) = self.lease_time {
+            option(OPTION_LEASE_TIME, &lease_time.to_be_bytes());
+        }
+        if let Some(prefix_len) = self.prefix_len {
+            let mask = if prefix_len == 0 { 0 } else { u32::MAX << (32 - u32::from(prefix_len.min(32))) };
+            option(OPTION_SUBNET_MASK, &mask.to_be_bytes());
+        }
+        if let Some(router) = self.router {
+            option(OPTION_ROUTER, &router.octets());
+        }
+        if !self.dns_servers.is_empty() {
+            let servers: Vec<u8> = self.dns_servers.iter().flat_map(|server| server.octets()).collect();
+            option(OPTION_DNS, &servers);
+        }
+        bytes.push(OPTION_END);
+        if bytes.len() < MIN_LEN {
+            bytes.resize(MIN_LEN, OPTION_PAD);
+        }
+        bytes
+    }
+
+    // None for malformed messages, other hardware types and messages without a type;
+    // unknown options are skipped
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FIXED_LEN + MAGIC_COOKIE.len()
+            || bytes[1] != HTYPE_ETHERNET
+            || bytes[2] != 6
+            || bytes[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let ipv4 = |offset: usize| Ipv4Addr::new(bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]);
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&bytes[28..34]);
+        let mut message = DhcpMessage::new(DhcpMessageType::Discover, u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), MacAddr::from(mac));
+        message.broadcast = u16::from_be_bytes([bytes[10], bytes[11]]) & FLAG_BROADCAST != 0;
+        message.client_ip = ipv4(12);
+        message.your_ip = ipv4(16);
+
+        let mut kind = None;
+        let mut options = &bytes[FIXED_LEN + 4..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..usize::from(len))?;
+            let address = || <[u8; 4]>::try_from(value.get(..4)?).ok().map(Ipv4Addr::from);
+            match code {
+                OPTION_MESSAGE_TYPE => kind = value.first().copied().and_then(DhcpMessageType::from_code),
+                OPTION_SERVER_ID => message.server_id = address(),
+                OPTION_REQUESTED_IP => message.requested_ip = address(),
+                OPTION_LEASE_TIME => message.lease_time = <[u8; 4]>::try_from(value).ok().map(u32::from_be_bytes),
+                OPTION_SUBNET_MASK => message.prefix_len = address().map(|mask| u32::from(mask).leading_ones() as u8),
+                OPTION_ROUTER => message.router = address(),
+                OPTION_DNS => message.dns_servers = value.chunks_exact(4).map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])).collect(),
+                _ => {}
+            }
+            options = &rest[usize::from(len)..];
+        }
+        message.kind = kind?;
+        // The operation has to agree with the message type
+        (message.kind.is_reply() == (bytes[0] == OP_REPLY)).then_some(message)
+    }
+}
+
+impl Packet {
+    // DHCP message between the client and server ports; replies go to the client port
+    pub fn dhcp(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, message: &DhcpMessage) -> Self {
+        let (src_port, dest_port) = if message.kind.is_reply() { (SERVER_PORT, CLIENT_PORT) } else { (CLIENT_PORT, SERVER_PORT) };
+        Packet::udp(src_ip, dest_ip, &UdpDatagram { src_port, dest_port, payload: message.to_bytes() })
+    }
+
+    // The DHCP message this packet carries, if any
+    pub fn dhcp_message(&self) -> Option<DhcpMessage> {
+        let datagram = self.udp_datagram()?;
+        if !matches!((datagram.src_port, datagram.dest_port), (CLIENT_PORT, SERVER_PORT) | (SERVER_PORT, CLIENT_PORT)) {
+            return None;
+        }
+        DhcpMessage::from_bytes(&datagram.payload)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpAction {
+    // Broadcast on the local network when `dest_ip` is 255.255.255.255
+    Send { dest_ip: Ipv4Addr, message: DhcpMessage },
+    Configure(DhcpLease), // Clients only: use the leased address and options
+    Deconfigure,          // Clients only: the lease is gone
+    Wait(Duration),       // Clients only: call tick again after this long
+    Log(String),
+}
+
+// Addresses a server hands out on one network, with the options that go with them
+#[derive(Debug, Clone, PartialEq)]
+pub struct DhcpPool {
+    pub first: Ipv4Addr,
+    pub last: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time: Duration,
+}
+
+impl DhcpPool {
+    fn contains(&self, address: Ipv4Addr) -> bool { (self.first..=self.last).contains(&address) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LeaseState {
+    Offered,
+    Bound,
+    Declined, // A client found the address in use
+}
+
+#[derive(Debug, Clone)]
+struct Lease {
+    mac: MacAddr,
+    state: LeaseState,
+    expires: Duration,
+}
+
+// DHCP server: hands out addresses from the pool of the interface a request arrives on and
+// keeps track of the leases. Expired leases are not cleaned up on a timer; their addresses
+// are simply free to be offered again.
+#[derive(Debug, Clone)]
+pub struct DhcpServer {
+    address: Ipv4Addr,             // Server identifier, which clients send renewals to
+    pools: Vec<(usize, DhcpPool)>, // Interface -> pool
+    leases: BTreeMap<Ipv4Addr, Lease>,
+}
+
+impl DhcpServer {
+    pub fn new(address: Ipv4Addr) -> Self {
+        Self { address, pools: Vec::new(), leases: BTreeMap::new() }
+    }
+
+    pub fn add_pool(&mut self, interface: usize, pool: DhcpPool) { self.pools.push((interface, pool)); }
+
+    pub fn pools(&self) -> &[(usize, DhcpPool)] { &self.pools }
+
+    // Whether `address` is free for the client with `mac`: unleased, leased to it, or expired
+    fn available(&self, address: Ipv4Addr, mac: MacAddr, now: Duration) -> bool {
+        address != self.address
+            && self.leases.get(&address).is_none_or(|lease| {
+                lease.expires <= now || (lease.mac == mac && lease.state != LeaseState::Declined)
+            })
+    }
+
+    // The lowest free address in the pool. Only leased addresses and our own can be taken, so
+    // this walks those in order rather than every address in the pool.
+    fn first_free(&self, pool: &DhcpPool, mac: MacAddr, now: Duration) -> Option<Ipv4Addr> {
+        let mut taken: BTreeSet<Ipv4Addr> = self.leases
+            .range(pool.first..=pool.last)
+            .map(|(address, _)| *address)
+            .filter(|address| !self.available(*address, mac, now))
+            .collect();
+        if pool.contains(self.address) {
+            taken.insert(self.address);
+        }
+        let mut candidate = u64::from(u32::from(pool.first));
+        for address in taken.into_iter().map(|address| u64::from(u32::from(address))) {
+            if candidate < address {
+                break;
+            }
+            candidate = address + 1;
+        }
+        (candidate <= u64::from(u32::from(pool.last))).then(|| Ipv4Addr::from(candidate as u32))
+    }
+
+    pub fn receive(&mut self, interface: usize, message: &DhcpMessage, now: Duration) -> Vec<DhcpAction> {
+        let Some(pool) = self.pools.iter().find(|(index, _)| *index == interface).map(|(_, pool)| pool.clone()) else {
+            return Vec::new();
+        };
+        let mac = message.client_mac;
+        match message.kind {
+            DhcpMessageType::Discover => {
+                // The client's current address, the one it asks for, or the first free one
+                let current = self.leases
+                    .range(pool.first..=pool.last)
+                    .find(|(address, lease)| lease.mac == mac && self.available(**address, mac, now))
+                    .map(|(address, _)| *address);
+                let requested = message.requested_ip.filter(|address| pool.contains(*address) && self.available(*address, mac, now));
+                let free = || self.first_free(&pool, mac, now);
+                let Some(address) = current.or(requested).or_else(free) else {
+                    return vec![DhcpAction::Log(format!("No free address in {}-{} for {mac}", pool.first, pool.last))];
+                };
+                let lease = self.leases.entry(address).or_insert(Lease { mac, state: LeaseState::Offered, expires: now });
+                if lease.state != LeaseState::Bound || lease.mac != mac || lease.expires <= now {
+                    *lease = Lease { mac, state: LeaseState::Offered, expires: now + OFFER_HOLD };
+                }
+                let offer = self.reply(DhcpMessageType::Offer, message, address, &pool);
+                vec![DhcpAction::Log(format!("Offering {address} to {mac}")), DhcpAction::Send { dest_ip: Ipv4Addr::BROADCAST, message: offer }]
+            }
+            DhcpMessageType::Request => {
+                // The client took another server's offer: ours is free again
+                if let Some(server_id) = message.server_id
+                    && server_id != self.address
+                {
+                    self.leases.retain(|_, lease| !(lease.mac == mac && lease.state == LeaseState::Offered));
+                    return Vec::new();
+                }
+                let address = message.requested_ip.unwrap_or(message.client_ip);
+                let renewing = !message.client_ip.is_unspecified();
+                let dest_ip = if renewing { message.client_ip } else { Ipv4Addr::BROADCAST };
+                // Without a server ID the client may be rebinding a lease from another server
+                if message.server_id.is_none() && !pool.contains(address) {
+                    return Vec::new();
+                }
+                if !pool.contains(address) || !self.available(address, mac, now) {
+                    let mut nak = DhcpMessage::new(DhcpMessageType::Nak, message.xid, mac);
+                    nak.broadcast = message.broadcast;
+                    nak.server_id = Some(self.address);
+                    return vec![
+                        DhcpAction::Log(format!("Refusing {address} to {mac}")),
+                        DhcpAction::Send { dest_ip: Ipv4Addr::BROADCAST, message: nak },
+                    ];
+                }
+                let was_bound = self.leases.get(&address).is_some_and(|lease| lease.state == LeaseState::Bound && lease.mac == mac && lease.expires > now);
+                self.leases.insert(address, Lease { mac, state: LeaseState::Bound, expires: now + pool.lease_time });
+                let ack = self.reply(DhcpMessageType::Ack, message, address, &pool);
+                let verb = if was_bound { "Renewed" } else { "Leased" };
+                vec![
+                    DhcpAction::Log(format!("{verb} {address} to {mac} for {} s", pool.lease_time.as_secs())),
+                    DhcpAction::Send { dest_ip, message: ack },
+                ]
+            }
+            DhcpMessageType::Release => {
+                let address = message.client_ip;
+                if self.leases.get(&address).is_some_and(|lease| lease.mac == mac) {
+                    self.leases.remove(&address);
+                    return vec![DhcpAction::Log(format!("{mac} released {address}"))];
+                }
+                Vec::new()
+            }
+            // Not offered again until a lease time has passed
+            DhcpMessageType::Decline => {
+                let Some(address) = message.requested_ip.filter(|address| pool.contains(*address)) else {
+                    return Vec::new();
+                };
+                self.leases.insert(address, Lease { mac, state: LeaseState::Declined, expires: now + pool.lease_time });
+                vec![DhcpAction::Log(format!("{mac} declined {address}, which is in use"))]
+            }
+            DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak => Vec::new(),
+        }
+    }
+
+    fn reply(&self, kind: DhcpMessageType, request: &DhcpMessage, address: Ipv4Addr, pool: &DhcpPool) -> DhcpMessage {
+        let mut reply = DhcpMessage::new(kind, request.xid, request.client_mac);
+        reply.broadcast = request.broadcast;
+        reply.client_ip = request.client_ip;
+        reply.your_ip = address;
+        reply.server_id = Some(self.address);
+        reply.lease_time = Some(pool.lease_time.as_secs().min(u64::from(u32::MAX)) as u32);
+        reply.prefix_len = Some(pool.prefix_len);
+        reply.router = pool.gateway;
+        reply.dns_servers = pool.dns_servers.clone();
+        reply
+    }
+
+    // Pools and leases, with the time each lease has left
+    pub fn describe(&self, interface_names: &[String], now: Duration) -> String {
+        let mut lines = vec![format!("DHCP server {}:", self.address)];
+        for (interface, pool) in &self.pools {
+            let name = interface_names.get(*interface).map_or("?", String::as_str);
+            let gateway = pool.gateway.map_or_else(|| "-".to_string(), |gateway| gateway.to_string());
+            let dns: Vec<String> = pool.dns_servers.iter().map(ToString::to_string).collect();
+            lines.push(format!(
+                "  {name}: {}-{}/{}, gateway {gateway}, DNS {}, lease {} s",
+                pool.first,
+                pool.last,
+                pool.prefix_len,
+                if dns.is_empty() { "-".to_string() } else { dns.join(", ") },
+                pool.lease_time.as_secs()
+            ));
+            for (address, lease) in self.leases.iter().filter(|(address, _)| pool.contains(**address)) {
+                let state = match lease.state {
+                    _ if lease.expires <= now => "expired".to_string(),
+                    LeaseState::Offered => "offered".to_string(),
+                    LeaseState::Bound => format!("bound, {:.1} s left", (lease.expires - now).as_secs_f64()),
+                    LeaseState::Declined => "declined".to_string(),
+                };
+                lines.push(format!("    {:<15} {}  {state}", address.to_string(), lease.mac));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// What a client got from a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub address: Ipv4Net, // With the subnet's prefix length
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub server: Ipv4Addr,
+    pub obtained: Duration,
+    pub lease_time: Duration,
+}
+
+impl DhcpLease {
+    // Renewal with the server that granted the lease starts halfway through it (T1),
+    // and with any server at seven eighths (T2)
+    fn renew_at(&self) -> Duration { self.obtained + self.lease_time / 2 }
+
+    fn rebind_at(&self) -> Duration { self.obtained + self.lease_time.mul_f64(0.875) }
+
+    fn expires_at(&self) -> Duration { self.obtained + self.lease_time }
+}
+
+// Client states (RFC 2131 figure 5), without INIT-REBOOT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientState {
+    Selecting,  // DISCOVER sent, waiting for offers
+    Requesting, // Took an offer, waiting for the ACK
+    Bound,
+    Renewing,  // Asking the server that granted the lease for more time
+    Rebinding, // Asking any server, since that one did not answer
+}
+
+impl fmt::Display for ClientState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientState::Selecting => write!(f, "Selecting"),
+            ClientState::Requesting => write!(f, "Requesting"),
+            ClientState::Bound => write!(f, "Bound"),
+            ClientState::Renewing => write!(f, "Renewing"),
+            ClientState::Rebinding => write!(f, "Rebinding"),
+        }
+    }
+}
+
+// DHCP client: finds a server with a broadcast DISCOVER, requests the first offer that
+// arrives, and keeps the lease by renewing it until a server refuses or it runs out
+#[derive(Debug, Clone)]
+pub struct DhcpClient {
+    mac: MacAddr,
+    state: ClientState,
+    xid: u32,
+    offer: Option<DhcpMessage>,
+    lease: Option<DhcpLease>,
+    retry: Duration, // Wait before the next resend
+    requests: u32,   // REQUESTs sent for the current offer
+    next_at: Duration,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddr) -> Self {
+        let [_, _, a, b, c, d] = mac.octets();
+        Self {
+            mac,
+            state: ClientState::Selecting,
+            xid: u32::from_be_bytes([a, b, c, d]),
+            offer: None,
+            lease: None,
+            retry: FIRST_RETRY,
+            requests: 0,
+            next_at: Duration::ZERO,
+        }
+    }
+
+    // Sends a DISCOVER, at the start and whenever the client has to begin again
+    pub fn start(&mut self, now: Duration) -> Vec<DhcpAction> {
+        self.state = ClientState::Selecting;
+        self.xid = self.xid.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        self.offer = None;
+        self.retry = FIRST_RETRY;
+        vec![self.discover(), self.wait(now, self.retry)]
+    }
+
+    fn discover(&self) -> DhcpAction {
+        let mut discover = DhcpMessage::new(DhcpMessageType::Discover, self.xid, self.mac);
+        discover.broadcast = true;
+        discover.requested_ip = self.lease.as_ref().map(|lease| lease.address.addr());
+        DhcpAction::Send { dest_ip: Ipv4Addr::BROADCAST, message: discover }
+    }
+
+    // REQUEST for the offer being taken, or to extend the lease: unicast to its server while
+    // renewing, broadcast otherwise
+    fn request(&self) -> Option<DhcpAction> {
+        let mut request = DhcpMessage::new(DhcpMessageType::Request, self.xid, self.mac);
+        let dest_ip = match self.state {
+            ClientState::Requesting => {
+                let offer = self.offer.as_ref()?;
+                request.broadcast = true;
+                request.requested_ip = Some(offer.your_ip);
+                request.server_id = offer.server_id;
+                Ipv4Addr::BROADCAST
+            }
+            ClientState::Renewing | ClientState::Rebinding => {
+                let lease = self.lease.as_ref()?;
+                request.client_ip = lease.address.addr();
+                if self.state == ClientState::Renewing { lease.server } else { Ipv4Addr::BROADCAST }
+            }
+            ClientState::Selecting | ClientState::Bound => return None,
+        };
+        Some(DhcpAction::Send { dest_ip, message: request })
+    }
+
+    fn wait(&mut self, now: Duration, delay: Duration) -> DhcpAction {
+        self.next_at = now + delay;
+        DhcpAction::Wait(delay)
+    }
+
+    // Resends and moves through the lease's renewal times
+    pub fn tick(&mut self, now: Duration) -> Vec<DhcpAction> {
+        if now < self.next_at {
+            return Vec::new();
+        }
+        let mut actions = Vec::new();
+        match self.state {
+            ClientState::Selecting => {
+                self.retry = (self.retry * 2).min(MAX_RETRY);
+                actions.push(self.discover());
+                actions.push(self.wait(now, self.retry));
+            }
+            ClientState::Requesting => {
+                if self.requests >= MAX_REQUESTS {
+                    actions.push(DhcpAction::Log("No answer to REQUEST, starting over".to_string()));
+                    actions.extend(self.start(now));
+                    return actions;
+                }
+                self.requests += 1;
+                self.retry = (self.retry * 2).min(MAX_RETRY);
+                actions.extend(self.request());
+                actions.push(self.wait(now, self.retry));
+            }
+            ClientState::Bound | ClientState::Renewing | ClientState::Rebinding => {
+                let Some(lease) = self.lease.clone() else {
+                    return self.start(now);
+                };
+                if now >= lease.expires_at() {
+                    self.lease = None;
+                    actions.push(DhcpAction::Log(format!("Lease of {} expired", lease.address)));
+                    actions.push(DhcpAction::Deconfigure);
+                    actions.extend(self.start(now));
+                    return actions;
+                }
+                let (state, until) = if now >= lease.rebind_at() {
+                    (ClientState::Rebinding, lease.expires_at())
+                } else {
+                    (ClientState::Renewing, lease.rebind_at())
+                };
+                if state != self.state {
+                    actions.push(DhcpAction::Log(format!("{state} lease of {}", lease.address)));
+                }
+                self.state = state;
+                actions.extend(self.request());
+                // Resend after half the time left until the next stage, but not more often than the first retry
+                let delay = (until.saturating_sub(now) / 2).max(FIRST_RETRY).min(until.saturating_sub(now));
+                actions.push(self.wait(now, delay));
+            }
+        }
+        actions
+    }
+
+    pub fn receive(&mut self, message: &DhcpMessage, now: Duration) -> Vec<DhcpAction> {
+        if !message.kind.is_reply() || message.xid != self.xid || message.client_mac != self.mac {
+            return Vec::new();
+        }
+        let mut actions = Vec::new();
+        match (self.state, message.kind) {
+            (ClientState::Selecting, DhcpMessageType::Offer) if message.server_id.is_some() => {
+                let server = message.server_id.unwrap_or(Ipv4Addr::UNSPECIFIED);
+                actions.push(DhcpAction::Log(format!("Offered {} by {server}", message.your_ip)));
+                self.offer = Some(message.clone());
+                self.state = ClientState::Requesting;
+                self.requests = 1;
+                self.retry = FIRST_RETRY;
+                actions.extend(self.request());
+                actions.push(self.wait(now, self.retry));
+            }
+            (ClientState::Requesting | ClientState::Renewing | ClientState::Rebinding, DhcpMessageType::Ack) => {
+                let server = message.server_id.or(self.lease.as_ref().map(|lease| lease.server)).unwrap_or(Ipv4Addr::UNSPECIFIED);
+                let prefix_len = message.prefix_len.or(self.offer.as_ref().and_then(|offer| offer.prefix_len)).unwrap_or(32);
+                let Ok(address) = Ipv4Net::new(message.your_ip, prefix_len) else {
+                    return actions;
+                };
+                let lease = DhcpLease {
+                    address,
+                    gateway: message.router,
+                    dns_servers: message.dns_servers.clone(),
+                    server,
+                    obtained: now,
+                    lease_time: message.lease_time.map_or(DEFAULT_LEASE_TIME, |seconds| Duration::from_secs(u64::from(seconds))),
+                };
+                let verb = if self.state == ClientState::Requesting { "Leased" } else { "Renewed" };
+                actions.push(DhcpAction::Log(format!("{verb} {address} from {server} for {} s", lease.lease_time.as_secs())));
+                actions.push(DhcpAction::Configure(lease.clone()));
+                self.state = ClientState::Bound;
+                self.offer = None;
+                let renew = lease.renew_at() - now;
+                self.lease = Some(lease);
+                actions.push(self.wait(now, renew));
+            }
+            (ClientState::Requesting | ClientState::Renewing | ClientState::Rebinding, DhcpMessageType::Nak) => {
+                actions.push(DhcpAction::Log(format!("Refused by {}, starting over", message.server_id.unwrap_or(Ipv4Addr::UNSPECIFIED))));
+                if self.lease.take().is_some() {
+                    actions.push(DhcpAction::Deconfigure);
+                }
+                actions.extend(self.start(now));
+            }
+            _ => {}
+        }
+        actions
+    }
+
+    pub fn describe(&self, now: Duration) -> String {
+        match &self.lease {
+            Some(lease) => format!(
+                "DHCP client: {}, {} from {}, {:.1} s left",
+                self.state,
+                lease.address,
+                lease.server,
+                lease.expires_at().saturating_sub(now).as_secs_f64()
+            ),
+            None => format!("DHCP client: {}, no lease", self.state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn ip(n: u8) -> Ipv4Addr { Ipv4Addr::new(10, 0, 0, n) }
+
+    fn mac(n: u8) -> MacAddr { MacAddr::from([0xAA, 0, 0, 0, 0, n]) }
+
+    // Hands out 10.0.0.1-10.0.0.3, its own address excepted, for 60 s
+    fn server() -> DhcpServer {
+        let mut server = DhcpServer::new(SERVER);
+        let pool = DhcpPool {
+            first: ip(1),
+            last: ip(3),
+            prefix_len: 24,
+            gateway: Some(SERVER),
+            dns_servers: Vec::new(),
+            lease_time: Duration::from_secs(60),
+        };
+        server.add_pool(0, pool);
+        server
+    }
+
+    fn message(kind: DhcpMessageType, client: u8, requested_ip: Option<Ipv4Addr>) -> DhcpMessage {
+        let mut message = DhcpMessage::new(kind, u32::from(client), mac(client));
+        message.broadcast = true;
+        message.requested_ip = requested_ip;
+        message.server_id = requested_ip.filter(|_| kind == DhcpMessageType::Request).map(|_| SERVER);
+        message
+    }
+
+    // The type of the reply sent and the address in it
+    fn reply(actions: &[DhcpAction]) -> Option<(DhcpMessageType, Ipv4Addr)> {
+        actions.iter().find_map(|action| match action {
+            DhcpAction::Send { message, .. } => Some((message.kind, message.your_ip)),
+            _ => None,
+        })
+    }
+
+    // DISCOVER and REQUEST, returning the address leased
+    fn lease(server: &mut DhcpServer, client: u8, now: Duration) -> Option<Ipv4Addr> {
+        let (_, offered) = reply(&server.receive(0, &message(DhcpMessageType::Discover, client, None), now))?;
+        match reply(&server.receive(0, &message(DhcpMessageType::Request, client, Some(offered)), now)) {
+            Some((DhcpMessageType::Ack, address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn exhausted_pool_offers_nothing() {
+        let mut server = server();
+        assert_eq!(lease(&mut server, 1, Duration::ZERO), Some(ip(2)));
+        assert_eq!(lease(&mut server, 2, Duration::ZERO), Some(ip(3)));
+        let actions = server.receive(0, &message(DhcpMessageType::Discover, 3, None), Duration::ZERO);
+        assert_eq!(reply(&actions), None);
+        // Until a lease runs out
+        assert_eq!(lease(&mut server, 3, Duration::from_secs(60)), Some(ip(2)));
+    }
+
+    #[test]
+    fn clients_keep_their_address() {
+        let mut server = server();
+        assert_eq!(lease(&mut server, 1, Duration::ZERO), Some(ip(2)));
+        assert_eq!(lease(&mut server, 2, Duration::ZERO), Some(ip(3)));
+        assert_eq!(lease(&mut server, 1, Duration::from_secs(30)), Some(ip(2)));
+    }
+
+    #[test]
+    fn foreign_addresses_are_refused() {
+        let mut server = server();
+        let request = message(DhcpMessageType::Request, 1, Some(ip(9)));
+        assert_eq!(reply(&server.receive(0, &request, Duration::ZERO)), Some((DhcpMessageType::Nak, Ipv4Addr::UNSPECIFIED)));
+        // So are addresses leased to another client
+        assert_eq!(lease(&mut server, 1, Duration::ZERO), Some(ip(2)));
+        let request = message(DhcpMessageType::Request, 2, Some(ip(2)));
+        assert_eq!(reply(&server.receive(0, &request, Duration::ZERO)), Some((DhcpMessageType::Nak, Ipv4Addr::UNSPECIFIED)));
+    }
+
+    #[test]
+    fn declined_addresses_are_held_for_a_lease_time() {
+        let mut server = server();
+        assert_eq!(lease(&mut server, 1, Duration::ZERO), Some(ip(2)));
+        server.receive(0, &message(DhcpMessageType::Decline, 1, Some(ip(2))), Duration::ZERO);
+        assert_eq!(lease(&mut server, 1, Duration::from_secs(1)), Some(ip(3)));
+        let actions = server.receive(0, &message(DhcpMessageType::Discover, 2, None), Duration::from_secs(1));
+        assert_eq!(reply(&actions), None);
+        assert_eq!(lease(&mut server, 2, Duration::from_secs(60)), Some(ip(2)));
+    }
+
+    #[test]
+    fn dhcp_ack_round_trip() {
+        let ack = DhcpMessage {
+            kind: DhcpMessageType::Ack,
+            xid: 0x3903_F326,
+            broadcast: true,
+            client_ip: Ipv4Addr::UNSPECIFIED,
+            your_ip: Ipv4Addr::new(10, 0, 1, 100),
+            client_mac: MacAddr::from([0xAA, 0xBB, 0xCC, 0x00, 0x01, 0x0A]),
+            requested_ip: None,
+            server_id: Some(Ipv4Addr::new(10, 0, 1, 1)),
+            lease_time: Some(60),
+            prefix_len: Some(24),
+            router: Some(Ipv4Addr::new(10, 0, 1, 1)),
+            dns_servers: vec![Ipv4Addr::new(10, 0, 2, 2), Ipv4Addr::new(10, 0, 2, 3)],
+        };
+        let mut packet = Packet::dhcp(Ipv4Addr::new(10, 0, 1, 1), Ipv4Addr::BROADCAST, &ack);
+        packet.src_mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0x00, 0x01, 0x01]);
+        packet.dest_mac = MacAddr::BROADCAST;
+        let bytes = packet.to_bytes();
+        // BOOTREPLY from port 67 to 68, with the subnet mask as option 1
+        let udp = &bytes[34..];
+        assert_eq!(&udp[..4], &[0, 67, 0, 68]);
+        assert_eq!(udp[8], 2);
+        assert!(udp.windows(6).any(|option| option == [1, 4, 255, 255, 255, 0]));
+        assert_eq!(Packet::from_bytes(&bytes).unwrap().dhcp_message(), Some(ack.clone()));
+
+        // A reply type in a BOOTREQUEST is malformed
+        let mut payload = ack.to_bytes();
+        payload[0] = 1;
+        assert_eq!(DhcpMessage::from_bytes(&payload), None);
+    }
+}
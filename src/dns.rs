@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::packet::Packet;
+use crate::udp::UdpDatagram;
+
+pub const DNS_PORT: u16 = 53;
+pub const DEFAULT_TTL: u32 = 3600;
+
+const HEADER_LEN: usize = 12;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_CNAME_CHAIN: usize = 8;
+// Compression pointers followed before a name counts as malformed
+const MAX_POINTERS: usize = 16;
+
+// Name errors are cached this long, as if every zone's SOA said so (RFC 2308)
+const NEGATIVE_TTL: Duration = Duration::from_secs(60);
+// A query is sent twice to each server, 2 s apart, before the next server is tried
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const TRIES_PER_SERVER: u32 = 2;
+// Resolver source ports are 49152 plus the query ID modulo this
+const EPHEMERAL_PORTS: u16 = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    Cname,
+    Ptr,
+    Other(u16), // Asked for, never stored
+}
+
+impl RecordType {
+    fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Ptr => 12,
+            RecordType::Other(code) => *code,
+        }
+    }
+
+    fn from_code(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            5 => RecordType::Cname,
+            12 => RecordType::Ptr,
+            code => RecordType::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Ptr => write!(f, "PTR"),
+            RecordType::Other(code) => write!(f, "TYPE{code}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Cname(String),
+    Ptr(String),
+}
+
+impl RecordData {
+    pub fn kind(&self) -> RecordType {
+        match self {
+            RecordData::A(_) => RecordType::A,
+            RecordData::Cname(_) => RecordType::Cname,
+            RecordData::Ptr(_) => RecordType::Ptr,
+        }
+    }
+}
+
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::A(address) => write!(f, "{address}"),
+            RecordData::Cname(name) | RecordData::Ptr(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub ttl: u32, // Seconds
+    pub data: RecordData,
+}
+
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} (TTL {} s)", self.name, self.data.kind(), self.data, self.ttl)
+    }
+}
+
+// Response codes the simulation produces or understands (RFC 1035 section 4.1.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    NameError, // NXDOMAIN: the name does not exist
+    NotImplemented,
+    Refused,
+}
+
+impl ResponseCode {
+    fn code(&self) -> u16 {
+        match self {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+        }
+    }
+
+    fn from_code(code: u16) -> Self {
+        match code {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
+            3 => ResponseCode::NameError,
+            4 => ResponseCode::NotImplemented,
+            5 => ResponseCode::Refused,
+            _ => ResponseCode::ServerFailure,
+        }
+    }
+}
+
+// A DNS query or response (RFC 1035) with at most one question; authority and
+// additional records are neither sent nor read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub response: bool,
+    pub authoritative: bool,
+    pub recursion_desired: bool,
+    pub rcode: ResponseCode,
+    pub question: Option<(String, RecordType)>,
+    pub answers: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    pub fn query(id: u16, name: &str, kind: RecordType) -> Self {
+        Self {
+            id,
+            response: false,
+            authoritative: false,
+            recursion_desired: true,
+            rcode: ResponseCode::NoError,
+            question: Some((normalize(name), kind)),
+            answers: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = self.rcode.code();
+        if self.response {
+            flags |= FLAG_RESPONSE;
+        }
+        if self.authoritative {
+            flags |= FLAG_AUTHORITATIVE;
+        }
+        if self.recursion_desired {
+            flags |= FLAG_RECURSION_DESIRED;
+        }
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        bytes.extend_from_slice(&flags.to_be_bytes());
+        bytes.extend_from_slice(&u16::from(self.question.is_some()).to_be_bytes());
+        bytes.extend_from_slice(&(self.answers.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0; 4]); // No authority or additional records
+        if let Some((name, kind)) = &self.question {
+            write_name(&mut bytes, name);
+            bytes.extend_from_slice(&kind.code().to_be_bytes());
+            bytes.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        for record in &self.answers {
+            write_name(&mut bytes, &record.name);
+            bytes.extend_from_slice(&record.data.kind().code().to_be_bytes());
+            bytes.extend_from_slice(&CLASS_IN.to_be_bytes());
+            bytes.extend_from_slice(&record.ttl.to_be_bytes());
+            let mut data = Vec::new();
+            match &record.data {
+                RecordData::A(address) => data.extend_from_slice(&address.octets()),
+                RecordData::Cname(name) | RecordData::Ptr(name) => write_name(&mut data, name),
+            }
+            bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&data);
+        }
+        bytes
+    }
+
+    // None for malformed messages. Answers of other types or classes are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let word = |offset: usize| -> Option<u16> { Some(u16::from_be_bytes([*bytes.get(offset)?, *bytes.get(offset + 1)?])) };
+        let flags = word(2)?;
+        let (questions, answers) = (word(4)?, word(6)?);
+        if questions > 1 || bytes.len() < HEADER_LEN {
+            return None;
+        }
+        let mut message = DnsMessage {
+            id: word(0)?,
+            response: flags & FLAG_RESPONSE != 0,
+            authoritative: flags & FLAG_AUTHORITATIVE != 0,
+            recursion_desired: flags & FLAG_RECURSION_DESIRED != 0,
+            rcode: ResponseCode::from_code(flags & 0x000F),
+            question: None,
+            answers: Vec::new(),
+        };
+        let mut offset = HEADER_LEN;
+        if questions == 1 {
+            let (name, next) = read_name(bytes, offset)?;
+            message.question = Some((name, RecordType::from_code(word(next)?)));
+            word(next + 2)?;
+            offset = next + 4;
+        }
+        for _ in 0..answers {
+            let (name, next) = read_name(bytes, offset)?;
+            let (kind, class) = (RecordType::from_code(word(next)?), word(next + 2)?);
+            let ttl = u32::from_be_bytes(bytes.get(next + 4..next + 8)?.try_into().ok()?);
+            let len = usize::from(word(next + 8)?);
+            let start = next + 10;
+            let rdata = bytes.get(start..start + len)?;
+            offset = start + len;
+            let data = match (kind, class) {
+                (RecordType::A, CLASS_IN) => RecordData::A(Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?)),
+                (RecordType::Cname, CLASS_IN) => RecordData::Cname(read_name(bytes, start)?.0),
+                (RecordType::Ptr, CLASS_IN) => RecordData::Ptr(read_name(bytes, start)?.0),
+                _ => continue,
+            };
+            message.answers.push(DnsRecord { name, ttl, data });
+        }
+        Some(message)
+    }
+}
+
+// Labels of up to 63 bytes, longer ones cut short; names are written uncompressed
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(MAX_LABEL_LEN)];
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label);
+    }
+    bytes.push(0);
+}
+
+// The name at `offset`, following compression pointers, and the offset after it
+fn read_name(bytes: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..=MAX_POINTERS {
+        loop {
+            let len = *bytes.get(offset)?;
+            match len {
+                0 => return Some((normalize(&labels.join(".")), end.unwrap_or(offset + 1))),
+                0xC0.. => {
+                    let pointer = usize::from(u16::from_be_bytes([len, *bytes.get(offset + 1)?]) & 0x3FFF);
+                    end.get_or_insert(offset + 2);
+                    offset = pointer;
+                    break;
+                }
+                0x40.. => return None,
+                _ => {
+                    let label = bytes.get(offset + 1..offset + 1 + usize::from(len))?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    offset += 1 + usize::from(len);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Names compare case-insensitively and without the root's trailing dot
+pub fn normalize(name: &str) -> String { name.trim_end_matches('.').to_ascii_lowercase() }
+
+// Whether `name` can be written as a DNS name: dot-separated labels of 1-63 bytes
+pub fn is_valid_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(|label| (1..=MAX_LABEL_LEN).contains(&label.len()))
+}
+
+// The name PTR records for an address are stored under, e.g. 10.2.0.10.in-addr.arpa
+pub fn reverse_name(address: Ipv4Addr) -> String {
+    let [a, b, c, d] = address.octets();
+    format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+}
+
+impl Packet {
+    // DNS message over UDP; queries go to port 53 and responses come from it
+    pub fn dns(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, src_port: u16, dest_port: u16, message: &DnsMessage) -> Self {
+        Packet::udp(src_ip, dest_ip, &UdpDatagram { src_port, dest_port, payload: message.to_bytes() })
+    }
+
+    // The DNS message this packet carries, if any
+    pub fn dns_message(&self) -> Option<DnsMessage> {
+        let datagram = self.udp_datagram()?;
+        let message = DnsMessage::from_bytes(&datagram.payload)?;
+        let port = if message.response { datagram.src_port } else { datagram.dest_port };
+        (port == DNS_PORT).then_some(message)
+    }
+}
+
+// Authoritative server for the records it was given, which answers queries for other
+// names with NXDOMAIN and does not recurse
+#[derive(Debug, Clone, Default)]
+pub struct DnsServer {
+    records: Vec<DnsRecord>,
+}
+
+impl DnsServer {
+    pub fn add_record(&mut self, mut record: DnsRecord) {
+        record.name = normalize(&record.name);
+        record.data = match record.data {
+            RecordData::Cname(name) => RecordData::Cname(normalize(&name)),
+            RecordData::Ptr(name) => RecordData::Ptr(normalize(&name)),
+            data => data,
+        };
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[DnsRecord] { &self.records }
+
+    // The response to a query, following CNAMEs within the zone; None for anything but a query
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        if query.response {
+            return None;
+        }
+        let mut response = DnsMessage {
+            id: query.id,
+            response: true,
+            authoritative: true,
+            recursion_desired: query.recursion_desired,
+            rcode: ResponseCode::NoError,
+            question: query.question.clone(),
+            answers: Vec::new(),
+        };
+        let Some((name, kind)) = &query.question else {
+            response.rcode = ResponseCode::FormatError;
+            return Some(response);
+        };
+        if !self.records.iter().any(|record| record.name == *name) {
+            response.rcode = ResponseCode::NameError;
+            return Some(response);
+        }
+        let mut name = name.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let matching = self.records.iter().filter(|record| record.name == name);
+            let found: Vec<&DnsRecord> = matching.clone().filter(|record| record.data.kind() == *kind).collect();
+            if !found.is_empty() || *kind == RecordType::Cname {
+                response.answers.extend(found.into_iter().cloned());
+                break;
+            }
+            let Some(alias) = matching.clone().find(|record| record.data.kind() == RecordType::Cname) else {
+                break;
+            };
+            response.answers.push(alias.clone());
+            let RecordData::Cname(target) = &alias.data else {
+                break;
+            };
+            name = target.clone();
+        }
+        Some(response)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec!["DNS Zone:".to_string()];
+        lines.extend(self.records.iter().map(|record| {
+            format!("  {:<28} {:<6} {:<7} {}", record.name, record.ttl, record.data.kind().to_string(), record.data)
+        }));
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupResult {
+    Records(Vec<DnsRecord>), // Empty if the name has no records of the type asked for
+    NoSuchName,
+    Failed(String), // No server answered, or none would
+}
+
+impl LookupResult {
+    // The address a name stands for: the A record at the end of any CNAMEs
+    pub fn address(&self) -> Option<Ipv4Addr> {
+        match self {
+            LookupResult::Records(records) => records.iter().find_map(|record| match record.data {
+                RecordData::A(address) => Some(address),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LookupResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupResult::Records(records) if records.is_empty() => write!(f, "no records"),
+            LookupResult::Records(records) => {
+                let records: Vec<String> = records.iter().map(ToString::to_string).collect();
+                write!(f, "{}", records.join(", "))
+            }
+            LookupResult::NoSuchName => write!(f, "no such name"),
+            LookupResult::Failed(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+// A name being looked up, or the outcome once it is done
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub name: String,
+    pub kind: RecordType,
+    pub started: Duration,
+    pub finished: Option<Duration>,
+    pub server: Option<Ipv4Addr>, // Who answered, None if the cache did
+    pub result: Option<LookupResult>,
+    server_index: usize,
+    tries: u32,
+    deadline: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    result: LookupResult,
+    stored: Duration,
+    expires: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsAction {
+    Send { server: Ipv4Addr, src_port: u16, message: DnsMessage },
+    Wait { id: u16, delay: Duration }, // Call timeout for the lookup after this long
+    Log(String),
+}
+
+// Stub resolver: sends each lookup to the configured servers in turn and caches the
+// answers for their TTL, and name errors for a minute
+#[derive(Debug, Clone, Default)]
+pub struct Resolver {
+    cache: HashMap<(String, RecordType), CacheEntry>,
+    lookups: HashMap<u16, Lookup>, // Query ID -> lookup
+    next_id: u16,
+}
+
+impl Resolver {
+    pub fn lookup(&self, id: u16) -> Option<&Lookup> { self.lookups.get(&id) }
+
+    pub fn clear_cache(&mut self) { self.cache.clear(); }
+
+    // Starts looking up `name` and returns the lookup's ID, which is finished at once on a cache hit
+    pub fn start(&mut self, name: &str, kind: RecordType, servers: &[Ipv4Addr], now: Duration) -> (u16, Vec<DnsAction>) {
+        let name = normalize(name);
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id;
+        let mut lookup = Lookup {
+            name: name.clone(),
+            kind,
+            started: now,
+            finished: None,
+            server: None,
+            result: None,
+            server_index: 0,
+            tries: 0,
+            deadline: now,
+        };
+        let key = (name, kind);
+        if let Some(entry) = self.cache.get(&key).filter(|entry| entry.expires > now) {
+            // Records age while they sit in the cache
+            let elapsed = (now - entry.stored).as_secs() as u32;
+            let result = match &entry.result {
+                LookupResult::Records(records) => LookupResult::Records(
+                    records.iter().map(|record| DnsRecord { ttl: record.ttl.saturating_sub(elapsed), ..record.clone() }).collect(),
+                ),
+                result => result.clone(),
+            };
+            lookup.finished = Some(now);
+            lookup.result = Some(result.clone());
+            self.lookups.insert(id, lookup);
+            return (id, vec![DnsAction::Log(format!("{} {} from cache: {result}", key.0, kind))]);
+        }
+        self.cache.remove(&key);
+        self.lookups.insert(id, lookup);
+        (id, self.send(id, servers, now))
+    }
+
+    // Sends the query to the current server, or gives up once every server was tried
+    fn send(&mut self, id: u16, servers: &[Ipv4Addr], now: Duration) -> Vec<DnsAction> {
+        let Some(lookup) = self.lookups.get_mut(&id) else {
+            return Vec::new();
+        };
+        if lookup.tries >= TRIES_PER_SERVER {
+            lookup.server_index += 1;
+            lookup.tries = 0;
+        }
+        let Some(&server) = servers.get(lookup.server_index) else {
+            let reason = if servers.is_empty() { "no DNS server configured" } else { "no answer from the DNS servers" };
+            let log = format!("{} {}: {reason}", lookup.name, lookup.kind);
+            lookup.finished = Some(now);
+            lookup.result = Some(LookupResult::Failed(reason.to_string()));
+            return vec![DnsAction::Log(log)];
+        };
+        lookup.tries += 1;
+        lookup.deadline = now + RETRY_INTERVAL;
+        vec![
+            DnsAction::Send { server, src_port: 49152 + id % EPHEMERAL_PORTS, message: DnsMessage::query(id, &lookup.name, lookup.kind) },
+            DnsAction::Wait { id, delay: RETRY_INTERVAL },
+        ]
+    }
+
+    // The server queried last did not answer in time
+    pub fn timeout(&mut self, id: u16, servers: &[Ipv4Addr], now: Duration) -> Vec<DnsAction> {
+        if self.lookups.get(&id).is_none_or(|lookup| lookup.finished.is_some() || now < lookup.deadline) {
+            return Vec::new();
+        }
+        self.send(id, servers, now)
+    }
+
+    // Takes a response from the server the query went to; others are ignored
+    pub fn receive(&mut self, src_ip: Ipv4Addr, message: &DnsMessage, servers: &[Ipv4Addr], now: Duration) -> Vec<DnsAction> {
+        let Some(lookup) = self.lookups.get_mut(&message.id) else {
+            return Vec::new();
+        };
+        if !message.response
+            || lookup.finished.is_some()
+            || servers.get(lookup.server_index) != Some(&src_ip)
+            || message.question.as_ref() != Some(&(lookup.name.clone(), lookup.kind))
+        {
+            return Vec::new();
+        }
+        let result = match message.rcode {
+            ResponseCode::NoError => LookupResult::Records(message.answers.clone()),
+            ResponseCode::NameError => LookupResult::NoSuchName,
+            // The next server may do better
+            rcode => {
+                let log = format!("{} {}: {src_ip} answered {rcode:?}", lookup.name, lookup.kind);
+                lookup.tries = TRIES_PER_SERVER;
+                let mut actions = vec![DnsAction::Log(log)];
+                actions.extend(self.send(message.id, servers, now));
+                return actions;
+            }
+        };
+        let ttl = match &result {
+            LookupResult::Records(records) => Duration::from_secs(u64::from(records.iter().map(|record| record.ttl).min().unwrap_or(0))),
+            _ => NEGATIVE_TTL,
+        };
+        lookup.finished = Some(now);
+        lookup.server = Some(src_ip);
+        lookup.result = Some(result.clone());
+        let key = (lookup.name.clone(), lookup.kind);
+        let log = format!("{} {} from {src_ip}: {result}", key.0, key.1);
+        if !ttl.is_zero() {
+            self.cache.insert(key, CacheEntry { result, stored: now, expires: now + ttl });
+        }
+        vec![DnsAction::Log(log)]
+    }
+
+    // Cache entries that have not expired, with the time they have left
+    pub fn describe(&self, now: Duration) -> String {
+        let mut entries: Vec<(&(String, RecordType), &CacheEntry)> = self.cache.iter().filter(|(_, entry)| entry.expires > now).collect();
+        entries.sort_by(|a, b| a.0.0.cmp(&b.0.0).then_with(|| a.0.1.code().cmp(&b.0.1.code())));
+        let mut lines = vec!["DNS Cache:".to_string()];
+        lines.extend(entries.into_iter().map(|((name, kind), entry)| {
+            format!("  {name} {kind}: {} ({:.1} s left)", entry.result, (entry.expires - now).as_secs_f64())
+        }));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 53);
+    const WEB: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 80);
+
+    fn zone() -> DnsServer {
+        let mut server = DnsServer::default();
+        server.add_record(DnsRecord { name: "web.lab".to_string(), ttl: 60, data: RecordData::A(WEB) });
+        server.add_record(DnsRecord { name: "www.lab".to_string(), ttl: 300, data: RecordData::Cname("web.lab".to_string()) });
+        server
+    }
+
+    // Looks a name up, answering any query from `server`; None for who answered means the cache did
+    fn resolve(resolver: &mut Resolver, server: &DnsServer, name: &str, now: Duration) -> (LookupResult, Option<Ipv4Addr>) {
+        let (id, actions) = resolver.start(name, RecordType::A, &[SERVER], now);
+        for action in actions {
+            if let DnsAction::Send { message, .. } = action {
+                let response = server.answer(&message).unwrap();
+                resolver.receive(SERVER, &response, &[SERVER], now);
+            }
+        }
+        let lookup = resolver.lookup(id).unwrap();
+        (lookup.result.clone().unwrap(), lookup.server)
+    }
+
+    #[test]
+    fn answers_are_cached_until_their_ttl_runs_out() {
+        let (mut resolver, server) = (Resolver::default(), zone());
+        let (result, from) = resolve(&mut resolver, &server, "web.lab", Duration::ZERO);
+        assert_eq!((result.address(), from), (Some(WEB), Some(SERVER)));
+
+        // Records age in the cache
+        let (result, from) = resolve(&mut resolver, &server, "WEB.lab.", Duration::from_secs(45));
+        assert_eq!(from, None);
+        assert_eq!(result, LookupResult::Records(vec![DnsRecord { name: "web.lab".to_string(), ttl: 15, data: RecordData::A(WEB) }]));
+
+        let (_, from) = resolve(&mut resolver, &server, "web.lab", Duration::from_secs(60));
+        assert_eq!(from, Some(SERVER));
+    }
+
+    #[test]
+    fn aliases_are_cached_for_the_shortest_ttl() {
+        let (mut resolver, server) = (Resolver::default(), zone());
+        let (result, _) = resolve(&mut resolver, &server, "www.lab", Duration::ZERO);
+        let LookupResult::Records(records) = &result else {
+            panic!("expected records, got {result}");
+        };
+        assert_eq!(records.iter().map(|record| record.data.kind()).collect::<Vec<_>>(), [RecordType::Cname, RecordType::A]);
+        assert_eq!(result.address(), Some(WEB));
+        assert_eq!(resolve(&mut resolver, &server, "www.lab", Duration::from_secs(59)).1, None);
+        assert_eq!(resolve(&mut resolver, &server, "www.lab", Duration::from_secs(60)).1, Some(SERVER));
+    }
+
+    #[test]
+    fn unknown_names_get_nxdomain_cached_for_a_minute() {
+        let (mut resolver, server) = (Resolver::default(), zone());
+        let query = DnsMessage::query(1, "mail.lab", RecordType::A);
+        assert_eq!(server.answer(&query).map(|response| response.rcode), Some(ResponseCode::NameError));
+
+        assert_eq!(resolve(&mut resolver, &server, "mail.lab", Duration::ZERO), (LookupResult::NoSuchName, Some(SERVER)));
+        assert_eq!(resolve(&mut resolver, &server, "mail.lab", Duration::from_secs(59)), (LookupResult::NoSuchName, None));
+        assert_eq!(resolve(&mut resolver, &server, "mail.lab", NEGATIVE_TTL).1, Some(SERVER));
+    }
+
+    #[test]
+    fn dns_response_round_trip() {
+        let mut response = DnsMessage::query(0x1234, "WWW.Lab.", RecordType::A);
+        response.response = true;
+        response.authoritative = true;
+        response.answers = vec![
+            DnsRecord { name: "www.lab".to_string(), ttl: 300, data: RecordData::Cname("web.lab".to_string()) },
+            DnsRecord { name: "web.lab".to_string(), ttl: 30, data: RecordData::A(Ipv4Addr::new(10, 0, 2, 10)) },
+        ];
+        let packet = Packet::dns(Ipv4Addr::new(10, 0, 2, 53), Ipv4Addr::new(10, 0, 1, 10), DNS_PORT, 49153, &response);
+        let decoded = Packet::from_bytes(&packet.to_bytes()).unwrap().dns_message().unwrap();
+        assert_eq!(decoded, response);
+        assert_eq!(decoded.question, Some(("www.lab".to_string(), RecordType::A)));
+
+        // Names in answers may point back at the question instead of repeating it
+        let mut bytes = DnsMessage::query(7, "web.lab", RecordType::A).to_bytes();
+        bytes[2] = 0x84; // Authoritative response
+        bytes[7] = 1; // One answer
+        bytes.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 2, 10]);
+        let decoded = DnsMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.rcode, ResponseCode::NoError);
+        assert_eq!(decoded.answers, vec![DnsRecord { name: "web.lab".to_string(), ttl: 60, data: RecordData::A(Ipv4Addr::new(10, 0, 2, 10)) }]);
+
+        // A pointer loop is malformed
+        bytes[25..27].copy_from_slice(&[0xC0, 25]);
+        assert_eq!(DnsMessage::from_bytes(&bytes), None);
+    }
+}
@@ -1,8 +1,28 @@
 use eframe::{egui, App};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::device::Device;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::device::{Device, DeviceRef};
+use crate::host::Host;
+use crate::link::Link;
+use crate::ping::PingOptions;
+use crate::sim::Simulator;
+use crate::stp::{PortRole, PortState};
+use crate::topology::{DeviceHandle, Network};
+use crate::traceroute::TracerouteOptions;
+
+// Simulated time covered by one press of the "Run" button
+const RUN_WINDOW: Duration = Duration::from_secs(1);
+
+// Devices without a position in the topology file are placed on a circle around this point
+const LAYOUT_CENTER: egui::Pos2 = egui::pos2(600.0, 400.0);
+const LAYOUT_RADIUS: f32 = 300.0;
+
+// Where "Save topology" writes unless another path is entered
+const DEFAULT_SAVE_PATH: &str = "topology.toml";
 
 #[derive(Debug, PartialEq)]
 enum NodeType {
@@ -23,6 +43,7 @@ struct Node {
 struct Edge {
     from: usize,
     to: usize,
+    link: Rc<RefCell<Link>>,
 }
 
 pub struct NetworkApp {
@@ -31,11 +52,23 @@ pub struct NetworkApp {
     next_node_id: usize,
     selected_node_info: String,
     show_selected_node_info: bool,
+    selected_node: Option<DeviceRef>, // Device shown in the "Device Info" window
     selected_host_1: Option<Rc<RefCell<dyn Device>>>,
     selected_host_2: Option<Rc<RefCell<dyn Device>>>,
     show_device_info: bool,
     device_info: String,
     pan_offset: egui::Vec2,
+    sim: Simulator,
+    selected_link: Option<Rc<RefCell<Link>>>,
+    show_link_info: bool,
+    network: Network,
+    save_path: String,
+    save_status: String,
+    ping_options: PingOptions,
+    ping: Option<(Rc<RefCell<Host>>, u16)>, // Host running the last ping started here, and the ping's id
+    show_ping: bool,
+    trace: Option<(Rc<RefCell<Host>>, u16)>, // Host running the last traceroute started here, and its id
+    show_trace: bool,
 }
 
 impl Default for NetworkApp {
@@ -46,16 +79,59 @@ impl Default for NetworkApp {
             next_node_id: 0,
             selected_node_info: String::new(),
             show_selected_node_info: false,
+            selected_node: None,
             selected_host_1: None,
             selected_host_2: None,
             show_device_info: false,
             device_info: String::new(),
             pan_offset: egui::Vec2::ZERO,
+            sim: Simulator::new(),
+            selected_link: None,
+            show_link_info: false,
+            network: Network::default(),
+            save_path: DEFAULT_SAVE_PATH.to_string(),
+            save_status: String::new(),
+            ping_options: PingOptions::default(),
+            ping: None,
+            show_ping: false,
+            trace: None,
+            show_trace: false,
         }
     }
 }
 
 impl NetworkApp {
+    // Build the app from a loaded topology, one node per device and one edge per link
+    pub fn from_network(network: Network) -> Self {
+        let mut app = Self::default();
+        let mut ids = HashMap::new();
+        let count = network.nodes.len().max(1) as f32;
+        for (index, node) in network.nodes.iter().enumerate() {
+            let [x, y] = node.position.unwrap_or_else(|| {
+                let angle = index as f32 / count * std::f32::consts::TAU;
+                [
+                    LAYOUT_CENTER.x + LAYOUT_RADIUS * angle.cos(),
+                    LAYOUT_CENTER.y + LAYOUT_RADIUS * angle.sin(),
+                ]
+            });
+            ids.insert(node.name.as_str(), app.next_node_id);
+            let device = node.device.device();
+            match node.device {
+                DeviceHandle::Host(_) => app.add_host_node(&node.name, x, y, device),
+                DeviceHandle::Switch(_) => app.add_switch_node(&node.name, x, y, device),
+                DeviceHandle::Router(_) => app.add_router_node(&node.name, x, y, device),
+            }
+        }
+        for link in &network.links {
+            if let (Some(&from), Some(&to)) = (ids.get(link.a.as_str()), ids.get(link.b.as_str())) {
+                app.add_edge(from, to, Rc::clone(&link.link));
+            }
+        }
+        network.start(&mut app.sim);
+        app.network = network;
+        app
+    }
+
     // Add a Host Node
     pub fn add_host_node(&mut self, label: &str, x: f32, y: f32, device: Rc<RefCell<dyn Device>>) {
         self.nodes.insert(
@@ -98,15 +174,15 @@ impl NetworkApp {
         self.next_node_id += 1;
     }
 
-    // Add an edge between two nodes
-    pub fn add_edge(&mut self, from: usize, to: usize) {
+    // Add an edge between two nodes, drawn for the link that connects them
+    pub fn add_edge(&mut self, from: usize, to: usize, link: Rc<RefCell<Link>>) {
         if self.nodes.contains_key(&from) && self.nodes.contains_key(&to) {
-            self.edges.push(Edge { from, to });
+            self.edges.push(Edge { from, to, link });
         }
     }
 
-    fn get_host_nodes(&self) -> Vec<(usize, String, Rc<RefCell<dyn Device>>)> {
-        let mut hosts: Vec<(usize, String, Rc<RefCell<dyn Device>>)> = self.nodes
+    fn get_host_nodes(&self) -> Vec<(usize, String, DeviceRef)> {
+        let mut hosts: Vec<(usize, String, DeviceRef)> = self.nodes
             .iter()
             .filter_map(|(&id, node)| {
                 if node.node_type == NodeType::Host {
@@ -122,16 +198,164 @@ impl NetworkApp {
         hosts
     }
 
-    // Send packet function
+    // Send packet function, the packet travels once the simulation is stepped or run
     fn send_packet(&mut self) {
         if let (Some(from_device), Some(to_device)) = (self.selected_host_1.clone(), self.selected_host_2.clone()) {
             // Borrow immutably to get IP address to avoid nested mutable borrows.
-            let dest_ip = to_device.borrow().get_ip_address();
-            from_device.borrow_mut().send_packet(&dest_ip, Vec::new());
+            let Some(dest_ip) = to_device.borrow().get_ip_address() else {
+                return;
+            };
+            self.sim.with_device(&from_device, |sim| from_device.borrow_mut().send_packet(dest_ip, Vec::new(), sim));
+            self.refresh_device_info();
+        }
+    }
+
+    // Typed handle of the source host, for operations that are not part of the Device trait
+    fn selected_source_host(&self) -> Option<(Rc<RefCell<Host>>, DeviceRef)> {
+        let from_device = self.selected_host_1.clone()?;
+        self.network.nodes.iter().find_map(|node| match &node.device {
+            DeviceHandle::Host(host) if Rc::ptr_eq(&node.device.device(), &from_device) => {
+                Some((Rc::clone(host), Rc::clone(&from_device)))
+            }
+            _ => None,
+        })
+    }
+
+    // Spanning tree role and state of the switch port at one side of a link, if it runs spanning tree
+    fn stp_port(&self, link: &Rc<RefCell<Link>>, side: usize) -> Option<(PortRole, PortState)> {
+        let (device, port) = link.borrow().end(side)?;
+        self.network.nodes.iter().find_map(|node| match &node.device {
+            DeviceHandle::Switch(switch) if Rc::ptr_eq(&node.device.device(), &device) => {
+                let switch = switch.borrow();
+                let stp = switch.spanning_tree()?;
+                Some((stp.port_role(port)?, stp.port_state(port)?))
+            }
+            _ => None,
+        })
+    }
+
+    fn selected_dest_ip(&self) -> Option<Ipv4Addr> {
+        self.selected_host_2.as_ref()?.borrow().get_ip_address()
+    }
+
+    // Start pinging the destination host from the source host; probes go out as the simulation runs
+    fn start_ping(&mut self) {
+        let (Some((host, device)), Some(dest_ip)) = (self.selected_source_host(), self.selected_dest_ip()) else {
+            return;
+        };
+        let options = self.ping_options;
+        let id = self.sim.with_device(&device, |sim| host.borrow_mut().start_ping(dest_ip, options, sim));
+        self.ping = Some((host, id));
+        self.show_ping = true;
+        self.refresh_device_info();
+    }
+
+    // Start a traceroute from the source host to the destination host
+    fn start_traceroute(&mut self) {
+        let (Some((host, device)), Some(dest_ip)) = (self.selected_source_host(), self.selected_dest_ip()) else {
+            return;
+        };
+        let id = self.sim.with_device(&device, |sim| {
+            host.borrow_mut().start_traceroute(dest_ip, TracerouteOptions::default(), sim)
+        });
+        self.trace = Some((host, id));
+        self.show_trace = true;
+        self.refresh_device_info();
+    }
+
+    // Have the source host announce its address, which also reveals duplicates
+    fn send_gratuitous_arp(&mut self) {
+        let Some((host, device)) = self.selected_source_host() else {
+            return;
+        };
+        self.sim.with_device(&device, |sim| host.borrow_mut().send_gratuitous_arp(sim));
+        self.refresh_device_info();
+    }
+
+    // Edges along the path of the last traceroute: from the source through every hop that
+    // answered, with the switches in between found by a breadth-first search
+    fn trace_path_edges(&self) -> Vec<usize> {
+        let Some((host, id)) = &self.trace else {
+            return Vec::new();
+        };
+        let Some(report) = host.borrow().traceroute_report(*id) else {
+            return Vec::new();
+        };
+        let find_node = |ip: Ipv4Addr| {
+            self.nodes
+                .iter()
+                .find(|(_, node)| node.node_type != NodeType::Switch && node.device.borrow().get_ip_address() == Some(ip))
+                .map(|(&id, _)| id)
+        };
+        let mut hops = vec![find_node(host.borrow().get_ip_address())];
+        hops.extend(report.path().into_iter().map(find_node));
+        let hops: Vec<usize> = hops.into_iter().flatten().collect();
+
+        let mut edges = Vec::new();
+        for pair in hops.windows(2) {
+            edges.extend(self.switched_path(pair[0], pair[1]));
+        }
+        edges
+    }
+
+    // Shortest run of edges from one node to another that only passes through switches
+    fn switched_path(&self, from: usize, to: usize) -> Vec<usize> {
+        let mut previous: HashMap<usize, (usize, usize)> = HashMap::new(); // Node -> (node before it, edge)
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = Vec::new();
+                let mut current = to;
+                while let Some(&(before, edge)) = previous.get(&current) {
+                    path.push(edge);
+                    current = before;
+                }
+                path.reverse();
+                return path;
+            }
+            if node != from && self.nodes.get(&node).is_none_or(|node| node.node_type != NodeType::Switch) {
+                continue;
+            }
+            for (index, edge) in self.edges.iter().enumerate() {
+                let next = if edge.from == node { edge.to } else if edge.to == node { edge.from } else { continue };
+                if next != from && !previous.contains_key(&next) {
+                    previous.insert(next, (node, index));
+                    queue.push_back(next);
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    // Write the network, with the current node positions, to the file at save_path
+    fn save_topology(&mut self) {
+        let positions = self.nodes
+            .values()
+            .map(|node| (node.label.clone(), [node.position.x, node.position.y]))
+            .collect();
+        self.save_status = match self.network.save(Path::new(&self.save_path), &positions, self.sim.now()) {
+            Ok(()) => format!("Saved to {}", self.save_path),
+            Err(e) => format!("Save failed: {e}"),
+        };
+    }
+
+    // Process the next scheduled event
+    fn step_simulation(&mut self) {
+        self.sim.step();
+        self.refresh_device_info();
+    }
+
+    // Process every event within the next RUN_WINDOW of simulated time
+    fn run_simulation(&mut self) {
+        self.sim.run_until(self.sim.now() + RUN_WINDOW);
+        self.refresh_device_info();
+    }
 
+    fn refresh_device_info(&mut self) {
+        if let (Some(from_device), Some(to_device)) = (self.selected_host_1.clone(), self.selected_host_2.clone()) {
             // Print device information
-            let info_source = from_device.borrow().get_device_info();
-            let info_dest = to_device.borrow().get_device_info();
+            let info_source = from_device.borrow().get_device_info(self.sim.now());
+            let info_dest = to_device.borrow().get_device_info(self.sim.now());
             self.device_info = format!(
                 "Source Host Info:\n{}\n\nDestination Host Info:\n{}",
                 info_source, info_dest
@@ -173,7 +397,7 @@ impl App for NetworkApp {
                             let is_selected = self
                                 .selected_host_1
                                 .as_ref()
-                                .map_or(false, |selected| Rc::ptr_eq(selected, device));
+                                .is_some_and(|selected| Rc::ptr_eq(selected, device));
                             if ui.selectable_label(is_selected, label).clicked() {
                                 self.selected_host_1 = Some(device.clone());
                             }
@@ -199,7 +423,7 @@ impl App for NetworkApp {
                             let is_selected = self
                                 .selected_host_2
                                 .as_ref()
-                                .map_or(false, |selected| Rc::ptr_eq(selected, device));
+                                .is_some_and(|selected| Rc::ptr_eq(selected, device));
                             if ui.selectable_label(is_selected, label).clicked() {
                                 self.selected_host_2 = Some(device.clone());
                             }
@@ -210,15 +434,112 @@ impl App for NetworkApp {
                 if ui.button("Send Packet").clicked() {
                     self.send_packet();
                 }
+
+                // Simulation controls
+                if ui.button("Step").clicked() {
+                    self.step_simulation();
+                }
+                if ui.button("Run").clicked() {
+                    self.run_simulation();
+                }
+                ui.label(format!(
+                    "Time: {:?} ({} pending events)",
+                    self.sim.now(),
+                    self.sim.pending_events()
+                ));
+
+                // Save the current network to a topology file
+                ui.add(egui::TextEdit::singleline(&mut self.save_path).desired_width(150.0));
+                if ui.button("Save topology").clicked() {
+                    self.save_topology();
+                }
+                ui.label(&self.save_status);
             });
 
-            // Draw edges (connections)
+            // Ping options for the selected hosts
+            ui.horizontal(|ui| {
+                ui.label("Count:");
+                ui.add(egui::DragValue::new(&mut self.ping_options.count).clamp_range(1..=1000));
+                ui.label("Interval (ms):");
+                let mut interval_ms = self.ping_options.interval.as_secs_f64() * 1e3;
+                ui.add(egui::DragValue::new(&mut interval_ms).clamp_range(0.0..=60_000.0));
+                self.ping_options.interval = Duration::from_secs_f64(interval_ms / 1e3);
+                ui.label("Size (bytes):");
+                ui.add(egui::DragValue::new(&mut self.ping_options.size).clamp_range(0..=65_000));
+                if ui.button("Ping").clicked() {
+                    self.start_ping();
+                }
+                if ui.button("Traceroute").clicked() {
+                    self.start_traceroute();
+                }
+                if ui.button("Gratuitous ARP").clicked() {
+                    self.send_gratuitous_arp();
+                }
+            });
+
+            // Draw edges (connections), links that are down are drawn in red
             let painter = ui.painter();
             for edge in &self.edges {
+                if let (Some(from), Some(to)) = (self.nodes.get(&edge.from), self.nodes.get(&edge.to)) {
+                    let color = if edge.link.borrow().is_up() {
+                        egui::Color32::from_rgb(179, 179, 179)
+                    } else {
+                        egui::Color32::from_rgb(220, 60, 60)
+                    };
+                    let from_pos = from.position + self.pan_offset;
+                    let to_pos = to.position + self.pan_offset;
+                    painter.line_segment([from_pos, to_pos], egui::Stroke::new(3.0, color));
+
+                    // Clicking the middle of an edge opens its link settings
+                    let midpoint = from_pos + (to_pos - from_pos) / 2.0;
+                    painter.circle_filled(midpoint, 4.0, color);
+                    if ui.input(|i| i.pointer.button_clicked(egui::PointerButton::Primary))
+                        && ui.input(|i| i.pointer.interact_pos())
+                            .is_some_and(|pos| pos.distance(midpoint) <= 6.0)
+                    {
+                        self.selected_link = Some(Rc::clone(&edge.link));
+                        self.show_link_info = true;
+                    }
+
+                    // Spanning tree ports: a letter for the role, colored by state, near the switch end
+                    for (side, near, far) in [(0, from_pos, to_pos), (1, to_pos, from_pos)] {
+                        let Some((role, state)) = self.stp_port(&edge.link, side) else {
+                            continue;
+                        };
+                        let color = match state {
+                            PortState::Forwarding => egui::Color32::from_rgb(60, 170, 90),
+                            PortState::Learning | PortState::Listening => egui::Color32::from_rgb(230, 160, 40),
+                            PortState::Blocking => egui::Color32::from_rgb(220, 60, 60),
+                            PortState::Disabled => egui::Color32::from_rgb(140, 140, 140),
+                        };
+                        let letter = match role {
+                            PortRole::Root => "R",
+                            PortRole::Designated => "D",
+                            PortRole::Alternate => "A",
+                            PortRole::Backup => "B",
+                            PortRole::Disabled => "-",
+                        };
+                        let marker = near + (far - near).normalized() * 28.0;
+                        painter.circle_filled(marker, 8.0, color);
+                        painter.text(
+                            marker,
+                            egui::Align2::CENTER_CENTER,
+                            letter,
+                            egui::FontId::proportional(11.0),
+                            egui::Color32::WHITE,
+                        );
+                    }
+                }
+            }
+
+            // Draw the path of the last traceroute over the edges it crosses, while its window is open
+            let trace_edges = if self.show_trace { self.trace_path_edges() } else { Vec::new() };
+            for index in trace_edges {
+                let edge = &self.edges[index];
                 if let (Some(from), Some(to)) = (self.nodes.get(&edge.from), self.nodes.get(&edge.to)) {
                     painter.line_segment(
                         [from.position + self.pan_offset, to.position + self.pan_offset],
-                        egui::Stroke::new(3.0, egui::Color32::from_rgb(179, 179, 179)),
+                        egui::Stroke::new(6.0, egui::Color32::from_rgba_unmultiplied(80, 200, 120, 180)),
                     );
                 }
             }
@@ -244,17 +565,17 @@ impl App for NetworkApp {
                 };
 
                 // If the node's device is one of the selected hosts, change its color (highlight).
-                if let Some(ref sel1) = self.selected_host_1 {
-                    if Rc::ptr_eq(sel1, &node.device) {
-                        // For example, blend in yellow.
-                        color = egui::Color32::YELLOW;
-                    }
+                if let Some(ref sel1) = self.selected_host_1
+                    && Rc::ptr_eq(sel1, &node.device)
+                {
+                    // For example, blend in yellow.
+                    color = egui::Color32::YELLOW;
                 }
-                if let Some(ref sel2) = self.selected_host_2 {
-                    if Rc::ptr_eq(sel2, &node.device) {
-                        // For example, blend in magenta.
-                        color = egui::Color32::from_rgb(255, 0, 255);
-                    }
+                if let Some(ref sel2) = self.selected_host_2
+                    && Rc::ptr_eq(sel2, &node.device)
+                {
+                    // For example, blend in magenta.
+                    color = egui::Color32::from_rgb(255, 0, 255);
                 }
 
                 // Draw circle for node
@@ -288,20 +609,92 @@ impl App for NetworkApp {
                 );
 
                 // Handle node clicks for selection
-                if ui.input(|i| i.pointer.button_clicked(egui::PointerButton::Primary)) {
-                    if rect.contains(ui.input(|i| i.pointer.interact_pos().unwrap_or_default())) {
-                        self.show_selected_node_info = true;
-                        self.selected_node_info = node.device.borrow().get_device_info();
-                    }
+                if ui.input(|i| i.pointer.button_clicked(egui::PointerButton::Primary))
+                    && rect.contains(ui.input(|i| i.pointer.interact_pos().unwrap_or_default()))
+                {
+                    self.show_selected_node_info = true;
+                    self.selected_node_info = node.device.borrow().get_device_info(self.sim.now());
+                    self.selected_node = Some(Rc::clone(&node.device));
                 }
             }
             
+            // Show a popup window with link settings if the flag is set.
+            if self.show_link_info
+                && let Some(link) = self.selected_link.clone()
+            {
+                egui::Window::new("Link Info")
+                    .open(&mut self.show_link_info)
+                    .show(ctx, |ui| {
+                        let mut link = link.borrow_mut();
+                        let mut up = link.is_up();
+                        if ui.checkbox(&mut up, "Link up").changed() {
+                            link.set_up(up);
+                        }
+
+                        let config = &mut link.config;
+                        let mut bandwidth_mbps = config.bandwidth_bps as f64 / 1e6;
+                        let mut delay_ms = config.delay.as_secs_f64() * 1e3;
+                        let mut jitter_ms = config.jitter.as_secs_f64() * 1e3;
+                        let mut loss_percent = config.loss * 100.0;
+                        egui::Grid::new("link_config").show(ui, |ui| {
+                            ui.label("Bandwidth (Mbps)");
+                            ui.add(egui::DragValue::new(&mut bandwidth_mbps).clamp_range(0.001..=100_000.0));
+                            ui.end_row();
+                            ui.label("Delay (ms)");
+                            ui.add(egui::DragValue::new(&mut delay_ms).clamp_range(0.0..=10_000.0));
+                            ui.end_row();
+                            ui.label("Jitter (ms)");
+                            ui.add(egui::DragValue::new(&mut jitter_ms).clamp_range(0.0..=10_000.0));
+                            ui.end_row();
+                            ui.label("Loss (%)");
+                            ui.add(egui::DragValue::new(&mut loss_percent).clamp_range(0.0..=100.0));
+                            ui.end_row();
+                            ui.label("Bit error rate");
+                            ui.add(egui::DragValue::new(&mut config.bit_error_rate).speed(1e-6).clamp_range(0.0..=1.0));
+                            ui.end_row();
+                            ui.label("MTU (bytes)");
+                            ui.add(egui::DragValue::new(&mut config.mtu).clamp_range(64..=65_535));
+                            ui.end_row();
+                        });
+                        config.bandwidth_bps = (bandwidth_mbps * 1e6) as u64;
+                        config.delay = Duration::from_secs_f64(delay_ms / 1e3);
+                        config.jitter = Duration::from_secs_f64(jitter_ms / 1e3);
+                        config.loss = loss_percent / 100.0;
+
+                        ui.label(format!(
+                            "Frames delivered: {}, dropped: {}",
+                            link.frames_delivered, link.frames_dropped
+                        ));
+                    });
+            }
+
             // Show a popup window with device info if the flag is set.
             if self.show_selected_node_info {
+                let now = self.sim.now();
+                let switch = self.selected_node.as_ref().and_then(|device| {
+                    self.network.nodes.iter().find_map(|node| match &node.device {
+                        DeviceHandle::Switch(switch) if Rc::ptr_eq(&node.device.device(), device) => Some(Rc::clone(switch)),
+                        _ => None,
+                    })
+                });
+                let selected_node = self.selected_node.clone();
                 egui::Window::new("Device Info")
                     .open(&mut self.show_selected_node_info)
                     .show(ctx, |ui| {
-                        ui.label("Device Information:");
+                        ui.horizontal(|ui| {
+                            ui.label("Device Information:");
+                            if ui.button("Refresh").clicked()
+                                && let Some(device) = &selected_node
+                            {
+                                self.selected_node_info = device.borrow().get_device_info(now);
+                            }
+                            if let Some(switch) = &switch
+                                && ui.button("Flush MAC table").clicked()
+                            {
+                                switch.borrow_mut().flush_mac_table();
+                                self.selected_node_info = switch.borrow().get_switch_info(now);
+                            }
+                        });
                         egui::ScrollArea::vertical().show(ui, |ui| {
                             ui.add(
                                 egui::TextEdit::multiline(&mut self.selected_node_info)
@@ -312,6 +705,34 @@ impl App for NetworkApp {
                     });
             }
 
+            // Show the statistics of the last ping, updated as the simulation runs
+            if self.show_ping
+                && let Some((host, id)) = &self.ping
+            {
+                let report = host.borrow().ping_report(*id);
+                egui::Window::new("Ping")
+                    .open(&mut self.show_ping)
+                    .show(ctx, |ui| {
+                        if let Some(report) = report {
+                            ui.monospace(report.to_string());
+                        }
+                    });
+            }
+
+            // Show the hops of the last traceroute
+            if self.show_trace
+                && let Some((host, id)) = &self.trace
+            {
+                let report = host.borrow().traceroute_report(*id);
+                egui::Window::new("Traceroute")
+                    .open(&mut self.show_trace)
+                    .show(ctx, |ui| {
+                        if let Some(report) = report {
+                            ui.monospace(report.to_string());
+                        }
+                    });
+            }
+
             // Show a popup window with host info if the flag is set.
             if self.show_device_info {
                 egui::Window::new("Host Info")
@@ -1,233 +1,713 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::rc::{Rc, Weak};
-use std::cell::RefCell;
-use crate::packet::Packet;
-use crate::switch::Switch;
+use std::net::Ipv4Addr;
+use std::rc::Rc;
+use std::time::Duration;
+use crate::address::{Ipv4Net, MacAddr};
+use crate::arp::{self, ArpCache, Resolution, Retry};
+use crate::dhcp::{DhcpAction, DhcpClient, DhcpPool, DhcpServer};
+use crate::dns::{self, DnsAction, DnsRecord, DnsServer, Lookup, RecordType, Resolver};
+use crate::icmp::{self, IcmpMessage};
+use crate::link::Attachment;
+use crate::packet::{ArpOp, Packet, PROTOCOL_EXPERIMENTAL};
+use crate::ping::{PingOptions, PingReport, PingSession};
+use crate::sim::{Simulator, Timer};
+use crate::traceroute::{ProbeAnswer, TracerouteOptions, TracerouteReport, TracerouteSession};
 
 #[derive(Debug)]
 pub struct Host {
-    arp_table: HashMap<String, String>, // IP address -> MAC address
-    routing_table: HashMap<String, Vec<(String, usize)>>, // Router IP address -> list of destination networks + hops needed
+    arp_cache: ArpCache,
+    routes: Vec<(Ipv4Net, Ipv4Addr)>, // Static routes: network -> gateway, longest prefix first
+    default_gateway: Option<Ipv4Addr>, // For destinations outside our subnet that no route covers
     incoming_packets: Vec<Rc<Packet>>,
     outgoing_packets: Vec<Rc<Packet>>,
-    ip_address: String,
-    mac_address: String,
-    pub port: usize,
-    switch: Weak<RefCell<Switch>>,
+    awaiting_reply: Vec<Ipv4Addr>, // Destinations we sent a request to and expect a response from
+    pings: HashMap<u16, PingSession>, // Echo identifier -> ping started from this host
+    traces: HashMap<u16, TracerouteSession>, // Echo identifier -> traceroute started from this host
+    next_echo_id: u16,
+    address_conflicts: Vec<MacAddr>, // Other devices seen using our IP address
+    ip_address: Ipv4Addr,
+    subnet: Ipv4Net, // Addresses reached directly rather than through a gateway
+    dns_servers: Vec<Ipv4Addr>, // Queried in order by the resolver
+    resolver: Resolver,
+    dns_server: Option<DnsServer>, // Set when the host serves a DNS zone
+    dhcp_client: Option<DhcpClient>, // Set when the host gets its address from DHCP
+    dhcp_server: Option<DhcpServer>,
+    mac_address: MacAddr,
+    link: Option<Attachment>,
 }
 
 impl Host {
-    pub fn new(ip_address: String, mac_address: String, port: usize, switch: Weak<RefCell<Switch>>) -> Self {
+    // `address` is the interface address with the subnet's prefix length, e.g. 192.168.1.10/24
+    pub fn new(address: Ipv4Net, mac_address: MacAddr) -> Self {
         Self {
-            arp_table: HashMap::new(),
-            routing_table: HashMap::new(),
+            arp_cache: ArpCache::new(),
+            routes: Vec::new(),
+            default_gateway: None,
             incoming_packets: Vec::new(),
             outgoing_packets: Vec::new(),
-            ip_address,
+            awaiting_reply: Vec::new(),
+            pings: HashMap::new(),
+            traces: HashMap::new(),
+            next_echo_id: 1,
+            address_conflicts: Vec::new(),
+            ip_address: address.addr(),
+            subnet: address.trunc(),
+            dns_servers: Vec::new(),
+            resolver: Resolver::default(),
+            dns_server: None,
+            dhcp_client: None,
+            dhcp_server: None,
             mac_address,
-            port, // Temporary port, will be assigned later
-            switch,
+            link: None, // Attached once the host is connected to a switch
         }
     }
 
-    pub fn assign_port(&mut self, port: usize) { self.port = port; }
+    pub fn attach_link(&mut self, attachment: Attachment) { self.link = Some(attachment); }
 
-    pub fn populate_routing_table(&mut self, router_ip: String, vector: Vec<(String, usize)>) {
-        self.routing_table.insert(router_ip, vector);
+    pub fn is_connected(&self) -> bool { self.link.is_some() }
+
+    pub fn set_default_gateway(&mut self, gateway: Option<Ipv4Addr>) { self.default_gateway = gateway; }
+
+    pub fn default_gateway(&self) -> Option<Ipv4Addr> { self.default_gateway }
+
+    // Adds or replaces the route to a network
+    pub fn add_route(&mut self, network: Ipv4Net, gateway: Ipv4Addr) {
+        let network = network.trunc();
+        self.routes.retain(|(net, _)| *net != network);
+        self.routes.push((network, gateway));
+        self.routes.sort_by_key(|(net, _)| (Reverse(net.prefix_len()), net.addr()));
+    }
+
+    pub fn routes(&self) -> &[(Ipv4Net, Ipv4Addr)] { &self.routes }
+
+    pub fn get_ip_address(&self) -> Ipv4Addr { self.ip_address }
+
+    // Whether the host has an address, which DHCP clients lack until they get a lease
+    pub fn has_address(&self) -> bool { !self.ip_address.is_unspecified() }
+
+    pub fn set_dns_servers(&mut self, servers: Vec<Ipv4Addr>) { self.dns_servers = servers; }
+
+    pub fn dns_servers(&self) -> &[Ipv4Addr] { &self.dns_servers }
+
+    // Serves the record from port 53, along with any others added
+    pub fn add_dns_record(&mut self, record: DnsRecord) {
+        self.dns_server.get_or_insert_with(DnsServer::default).add_record(record);
+    }
+
+    pub fn dns_server(&self) -> Option<&DnsServer> { self.dns_server.as_ref() }
+
+    // Starts resolving `name` and returns the lookup's id; see `lookup` for the outcome
+    pub fn start_lookup(&mut self, name: &str, kind: RecordType, sim: &mut Simulator) -> u16 {
+        let (id, actions) = self.resolver.start(name, kind, &self.dns_servers, sim.now());
+        self.apply_dns(actions, sim);
+        id
+    }
+
+    pub fn lookup(&self, id: u16) -> Option<&Lookup> { self.resolver.lookup(id) }
+
+    fn apply_dns(&mut self, actions: Vec<DnsAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                DnsAction::Send { server, src_port, message } => {
+                    self.transmit_ip(Packet::dns(self.ip_address, server, src_port, dns::DNS_PORT, &message), sim);
+                }
+                DnsAction::Wait { id, delay } => sim.schedule_timer(delay, Timer::DnsTimeout { id }),
+                DnsAction::Log(message) => {
+                    println!("==============================================");
+                    println!("HOST: {}", self.ip_address);
+                    println!("DNS: {message} at {:?}", sim.now());
+                    println!("==============================================\n");
+                }
+            }
+        }
+    }
+
+    // The host starts without an address and leases one once the simulation starts
+    pub fn enable_dhcp_client(&mut self) {
+        self.configure(Ipv4Net::new(Ipv4Addr::UNSPECIFIED, 32).expect("/32 is a valid prefix"), None, Vec::new());
+        self.dhcp_client = Some(DhcpClient::new(self.mac_address));
+    }
+
+    pub fn dhcp_client(&self) -> Option<&DhcpClient> { self.dhcp_client.as_ref() }
+
+    // Hands out addresses from `pool` to the host's network, answering as the host's own address
+    pub fn add_dhcp_pool(&mut self, pool: DhcpPool) {
+        let address = self.ip_address;
+        self.dhcp_server.get_or_insert_with(|| DhcpServer::new(address)).add_pool(0, pool);
+    }
+
+    pub fn dhcp_server(&self) -> Option<&DhcpServer> { self.dhcp_server.as_ref() }
+
+    fn configure(&mut self, address: Ipv4Net, gateway: Option<Ipv4Addr>, dns_servers: Vec<Ipv4Addr>) {
+        self.ip_address = address.addr();
+        self.subnet = address.trunc();
+        self.default_gateway = gateway;
+        self.dns_servers = dns_servers;
     }
 
-    pub fn get_ip_address(&self) -> String { self.ip_address.clone() }
+    pub fn subnet(&self) -> Ipv4Net { self.subnet }
 
-    // Returns an Option<String> that contains the MAC address if successful.
-    pub fn send_arp_request(&mut self, dest_ip: &str) -> Option<String> {
+    pub fn get_mac_address(&self) -> MacAddr { self.mac_address }
+
+    // ARP entries from configuration; learned entries come and go
+    pub fn static_arp_entries(&self) -> HashMap<Ipv4Addr, MacAddr> { self.arp_cache.permanent() }
+
+    // Every address the ARP cache knows at `now`
+    pub fn arp_entries(&self, now: Duration) -> HashMap<Ipv4Addr, MacAddr> { self.arp_cache.resolved(now) }
+
+    pub fn add_arp_entry(&mut self, ip: Ipv4Addr, mac: MacAddr) { self.arp_cache.add_permanent(ip, mac); }
+
+    pub fn address_conflicts(&self) -> &[MacAddr] { &self.address_conflicts }
+
+    // (packets sent, packets received)
+    pub fn packet_counts(&self) -> (usize, usize) { (self.outgoing_packets.len(), self.incoming_packets.len()) }
+
+    // Broadcasts a request for the MAC address of `target_ip`
+    fn send_arp_request(&mut self, target_ip: Ipv4Addr, sim: &mut Simulator) {
         println!("==============================================");
         println!("HOST: {}", self.ip_address);
-        println!("Sending ARP request for {}", dest_ip);
+        println!("Sending ARP request for {} at {:?}", target_ip, sim.now());
         println!("==============================================\n");
-        let request = Packet::new(
-            &self.mac_address,
-            "UNKNOWN",
-            &self.ip_address,
-            dest_ip,
-            Vec::new(),
-            true
+        let request = Packet::arp_request(
+            self.mac_address,
+            MacAddr::BROADCAST,
+            self.ip_address,
+            target_ip
         );
+        self.send_frame(Rc::new(request), sim);
+    }
 
-        let switch_rc = self.switch.upgrade();
-        if switch_rc.is_none() {
-            println!("Switch not available");
-            return None;
+    // Announces our own address, which also detects other devices using it: they answer,
+    // or warn about us themselves
+    pub fn send_gratuitous_arp(&mut self, sim: &mut Simulator) {
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Sending gratuitous ARP at {:?}", sim.now());
+        println!("==============================================\n");
+        let announcement = Packet::arp_request(
+            self.mac_address,
+            MacAddr::BROADCAST,
+            self.ip_address,
+            self.ip_address
+        );
+        self.send_frame(Rc::new(announcement), sim);
+    }
+
+    fn send_arp_reply(&mut self, target_ip: Ipv4Addr, target_mac: MacAddr, sim: &mut Simulator) {
+        let reply = Packet::arp_reply(
+            self.mac_address,
+            target_mac,
+            self.ip_address,
+            target_ip
+        );
+        self.send_frame(Rc::new(reply), sim);
+    }
+
+    // Requests are usually broadcast and replies unicast. Any ARP message from a known address refreshes
+    // its entry; one addressed to us also creates the entry and releases packets waiting for it.
+    fn receive_arp(&mut self, packet: Rc<Packet>, sim: &mut Simulator) {
+        let is_request = packet.arp.operation == ArpOp::Request;
+        if self.has_address() && packet.src_ip == self.ip_address && packet.src_mac != self.mac_address {
+            if !self.address_conflicts.contains(&packet.src_mac) {
+                self.address_conflicts.push(packet.src_mac);
+            }
+            println!("==============================================");
+            println!("HOST: {}", self.ip_address);
+            println!("WARNING: duplicate address {} also used by {}", self.ip_address, packet.src_mac);
+            println!("==============================================\n");
+            // Defend the address so that the other device sees the conflict too
+            if is_request {
+                self.send_arp_reply(packet.src_ip, packet.src_mac, sim);
+            }
+            return;
         }
-        let binding = switch_rc.unwrap();
-        let mut switch = binding.borrow_mut();
 
-        let response = switch.process_arp_request(Rc::new(request), self.port);
-        if let Some(ref resp) = response {
-            self.arp_table.insert(dest_ip.to_string(), resp.src_mac.clone());
+        let for_us = self.has_address() && packet.dest_ip == self.ip_address && packet.src_ip != self.ip_address;
+        let pending = self.arp_cache.learn(packet.src_ip, packet.src_mac, sim.now(), for_us);
+        if for_us {
             println!("==============================================");
             println!("HOST: {}", self.ip_address);
-            println!("Received ARP response from {}", resp.src_ip);
-            println!("ARP Table: {:#?}", self.arp_table);
+            println!("Received ARP {} from {}", if is_request { "request" } else { "reply" }, packet.src_ip);
             println!("==============================================\n");
-            Some(resp.src_mac.clone())
-        } else {
-            None
+        }
+        for waiting in pending {
+            self.transmit_frame(waiting, packet.src_mac, sim);
+        }
+        if for_us && is_request {
+            self.send_arp_reply(packet.src_ip, packet.src_mac, sim);
         }
     }
 
-    pub fn send_packet(&mut self, dest_ip: &str, data: Vec<u8>) {
-        // Check if they are in the same subnet, else find the router that can forward it
-        let hop_dest_ip = if self.ip_address.get(..9) == dest_ip.get(..9) {
-            dest_ip.to_string()
-        } else {
-            let mut found: Option<(String, usize)> = None;
-            for (router_ip, networks) in &self.routing_table {
-                for (net, metric) in networks {
-                    if dest_ip.get(..9) == net.get(..9) {
-                        if found.is_none() || *metric < found.as_ref().unwrap().1 {
-                            found = Some((router_ip.clone(), *metric));
-                        }
+    pub fn start(&mut self, sim: &mut Simulator) {
+        if let Some(client) = self.dhcp_client.as_mut() {
+            let actions = client.start(sim.now());
+            self.apply_dhcp(actions, sim);
+        }
+    }
+
+    fn apply_dhcp(&mut self, actions: Vec<DhcpAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                DhcpAction::Send { dest_ip, message } => {
+                    let packet = Packet::dhcp(self.ip_address, dest_ip, &message);
+                    if dest_ip == Ipv4Addr::BROADCAST {
+                        self.transmit_frame(packet, MacAddr::BROADCAST, sim);
+                    } else {
+                        self.transmit_ip(packet, sim);
                     }
                 }
+                DhcpAction::Configure(lease) => {
+                    // The cache may hold answers from servers that no longer apply
+                    if lease.dns_servers != self.dns_servers {
+                        self.resolver.clear_cache();
+                    }
+                    let changed = lease.address.addr() != self.ip_address;
+                    self.configure(lease.address, lease.gateway, lease.dns_servers);
+                    // Tell the network about the new address, which also shows whether someone else has it
+                    if changed {
+                        self.send_gratuitous_arp(sim);
+                    }
+                }
+                DhcpAction::Deconfigure => {
+                    self.configure(Ipv4Net::new(Ipv4Addr::UNSPECIFIED, 32).expect("/32 is a valid prefix"), None, Vec::new());
+                }
+                DhcpAction::Wait(delay) => sim.schedule_timer(delay, Timer::DhcpClient),
+                DhcpAction::Log(message) => {
+                    println!("==============================================");
+                    println!("HOST: {}", self.ip_address);
+                    println!("DHCP: {message} at {:?}", sim.now());
+                    println!("==============================================\n");
+                }
             }
-            if let Some((router_ip, _)) = found {
-                router_ip
-            } else {
-                println!("No route to {}", dest_ip);
-                return;
-            }
+        }
+    }
+
+    // Next hop toward `dest_ip`, for everything the host sends: the destination itself if it is
+    // in our subnet, else the gateway of the longest matching route, else the default gateway
+    fn next_hop(&self, dest_ip: Ipv4Addr) -> Option<Ipv4Addr> {
+        if self.subnet.contains(dest_ip) {
+            return Some(dest_ip);
+        }
+        self.routes
+            .iter()
+            .find(|(network, _)| network.contains(dest_ip))
+            .map(|(_, gateway)| *gateway)
+            .or(self.default_gateway)
+    }
+
+    // Sends an IP packet toward its next hop, holding it until ARP resolves the next hop's MAC
+    // address if needed. Returns false if it could not be sent.
+    fn transmit_ip(&mut self, packet: Packet, sim: &mut Simulator) -> bool {
+        let Some(hop_ip) = self.next_hop(packet.dest_ip) else {
+            println!("No route to {}", packet.dest_ip);
+            return false;
         };
+        if self.link.is_none() {
+            println!("Switch not available");
+            return false;
+        }
 
-        // Check the ARP table if the destination MAC address exists, else send an ARP request
-        let hop_dest_mac = match self.arp_table.get(&hop_dest_ip) {
-            Some(mac) => mac.clone(),
-            None => match self.send_arp_request(&hop_dest_ip) {
-                Some(mac) => mac,
-                None => {
-                    println!("No route to {}", dest_ip);
-                    return;
+        match self.arp_cache.resolve(hop_ip, packet, 0, sim.now()) {
+            Resolution::Send { packet, mac, confirm } => {
+                if confirm {
+                    self.send_arp_request(hop_ip, sim);
+                    sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip: hop_ip });
                 }
-            },
-        };
+                self.transmit_frame(packet, mac, sim);
+            }
+            Resolution::Queued { request } => {
+                if request {
+                    self.send_arp_request(hop_ip, sim);
+                    sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip: hop_ip });
+                }
+            }
+        }
+        true
+    }
 
-        let request = Rc::new(Packet::new(
-            &self.mac_address,
-            &hop_dest_mac,
-            &self.ip_address,
-            dest_ip,
-            data,
-            false
-        ));
+    // Fills in the Ethernet addresses of an IP packet and puts it on the wire
+    fn transmit_frame(&mut self, mut packet: Packet, hop_dest_mac: MacAddr, sim: &mut Simulator) {
+        packet.src_mac = self.mac_address;
+        packet.dest_mac = hop_dest_mac;
+        let packet = Rc::new(packet);
 
-        let switch_rc = self.switch.upgrade();
-        if switch_rc.is_none() {
-            println!("Switch not available");
-            return;
+        // Clone so that we maintain ownership of the packet
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Sending packet for {} at {:?}", packet.dest_ip, sim.now());
+        println!("==============================================\n");
+        self.outgoing_packets.push(Rc::clone(&packet));
+        self.send_frame(packet, sim);
+    }
+
+    fn send_frame(&self, packet: Rc<Packet>, sim: &mut Simulator) {
+        match &self.link {
+            Some(link) => link.transmit(packet, sim),
+            None => println!("Switch not available"),
         }
-        let binding = switch_rc.unwrap();
-        let mut switch = binding.borrow_mut();
+    }
 
-        // Clone so that we maintain ownership of the packet
+    // No ARP reply came for `ip`: retry, or give up on the packets waiting for it
+    fn retry_arp(&mut self, ip: Ipv4Addr, sim: &mut Simulator) {
+        match self.arp_cache.retry(ip) {
+            Retry::Resend { .. } => {
+                self.send_arp_request(ip, sim);
+                sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip });
+            }
+            Retry::GiveUp { pending } => {
+                println!("==============================================");
+                println!("HOST: {}", self.ip_address);
+                println!("No ARP reply from {ip}, dropping {} packet(s)", pending.len());
+                println!("==============================================\n");
+                for packet in pending {
+                    let message = IcmpMessage::destination_unreachable(icmp::HOST_UNREACHABLE, &packet);
+                    self.handle_icmp_error(&message, self.ip_address, sim);
+                }
+            }
+            Retry::Done => {}
+        }
+    }
+
+    pub fn send_packet(&mut self, dest_ip: Ipv4Addr, data: Vec<u8>, sim: &mut Simulator) {
+        let request = Packet::new(
+            self.mac_address,
+            MacAddr::ZERO,
+            self.ip_address,
+            dest_ip,
+            data
+        );
+        if self.transmit_ip(request, sim) {
+            self.awaiting_reply.push(dest_ip);
+        }
+    }
+
+    // Starts pinging `dest_ip` and returns the ping's id; the probes after the first are sent on timers
+    pub fn start_ping(&mut self, dest_ip: Ipv4Addr, options: PingOptions, sim: &mut Simulator) -> u16 {
+        let id = self.next_echo_id();
+        self.pings.insert(id, PingSession::new(dest_ip, options));
         println!("==============================================");
         println!("HOST: {}", self.ip_address);
-        println!("Sending packet for {}", dest_ip);
+        println!("Pinging {dest_ip} with {} bytes of data", options.size);
         println!("==============================================\n");
-        self.outgoing_packets.push(Rc::clone(&request));
-        if let Some(response) = switch.process_packet(Rc::clone(&request), self.port) {
-            println!("==============================================");
-            println!("HOST: {}", self.ip_address);
-            println!("Received response: {response:#?}");
-            println!("==============================================\n");
-            self.incoming_packets.push(response);
+        self.send_probe(id, sim);
+        id
+    }
+
+    // Identifier for a new ping or traceroute, so that echo replies can be told apart
+    fn next_echo_id(&mut self) -> u16 {
+        let id = self.next_echo_id;
+        self.next_echo_id = self.next_echo_id.wrapping_add(1);
+        id
+    }
+
+    pub fn ping_report(&self, id: u16) -> Option<PingReport> {
+        self.pings.get(&id).map(PingSession::report)
+    }
+
+    // Sends the next echo request of a ping and sets the timer for what comes after it
+    fn send_probe(&mut self, id: u16, sim: &mut Simulator) {
+        let Some(session) = self.pings.get(&id) else {
+            return;
+        };
+        let (dest_ip, options) = (session.dest_ip, session.options);
+        let Some(seq) = session.next_seq() else {
+            self.finish_ping(id);
+            return;
+        };
+
+        let data = (0..options.size).map(|i| i as u8).collect();
+        let request = Packet::icmp(self.ip_address, dest_ip, &IcmpMessage::EchoRequest { id, seq, data });
+        let sent = self.transmit_ip(request, sim);
+
+        let Some(session) = self.pings.get_mut(&id) else {
+            return;
+        };
+        session.record_sent(seq, sim.now());
+        if !sent {
+            session.record_error(seq, format!("No route to {dest_ip}"));
+        }
+        if session.next_seq().is_some() {
+            sim.schedule_timer(options.interval, Timer::PingProbe { id });
+        } else {
+            sim.schedule_timer(options.timeout, Timer::PingTimeout { id });
         }
     }
 
-    pub fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        // If the ARP request is intended for this host, return the MAC value
-        if packet.dest_ip == self.ip_address {
-            self.arp_table.insert(packet.src_ip.clone(), packet.src_mac.clone());
+    fn finish_ping(&mut self, id: u16) {
+        if let Some(session) = self.pings.get_mut(&id) {
+            session.finished = true;
             println!("==============================================");
             println!("HOST: {}", self.ip_address);
-            println!("Received ARP request from {}", packet.src_ip);
-            println!("ARP Table: {:#?}", self.arp_table);
+            println!("{}", session.report());
             println!("==============================================\n");
-            return Some(Rc::new(Packet::new(
-                &self.mac_address,
-                &packet.src_mac,
-                &self.ip_address,
-                &packet.src_ip,
-                Vec::new(),
-                true,
-            )));
         }
-        None
     }
 
-    pub fn receive_packet(&mut self, request: Rc<Packet>) -> Option<Rc<Packet>> {
-        // Make sure the packet is intended for this host
-        if request.dest_ip != self.ip_address {
-            return None;
+    // Starts a traceroute to `dest_ip` and returns its id; each hop is probed once the previous one is done
+    pub fn start_traceroute(&mut self, dest_ip: Ipv4Addr, options: TracerouteOptions, sim: &mut Simulator) -> u16 {
+        let id = self.next_echo_id();
+        self.traces.insert(id, TracerouteSession::new(dest_ip, options));
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Traceroute to {dest_ip}, {} hops max", options.max_hops);
+        println!("==============================================\n");
+        self.send_trace_hop(id, sim);
+        id
+    }
+
+    pub fn traceroute_report(&self, id: u16) -> Option<TracerouteReport> {
+        self.traces.get(&id).map(TracerouteSession::report)
+    }
+
+    // Sends the echo requests for the next TTL and sets the timer to give up on them
+    fn send_trace_hop(&mut self, id: u16, sim: &mut Simulator) {
+        let Some(session) = self.traces.get_mut(&id) else {
+            return;
+        };
+        let (dest_ip, timeout) = (session.dest_ip, session.options.timeout);
+        let (ttl, seqs) = session.start_hop(sim.now());
+        for seq in seqs {
+            let mut request = Packet::icmp(self.ip_address, dest_ip, &IcmpMessage::EchoRequest { id, seq, data: Vec::new() });
+            request.header.ttl = ttl;
+            self.transmit_ip(request, sim);
         }
+        sim.schedule_timer(timeout, Timer::TracerouteHop { id, ttl });
+    }
 
-        // Add to list of incoming packets
-        self.incoming_packets.push(Rc::clone(&request));
+    // Moves on to the next hop, or ends the traceroute if the destination answered or hops ran out
+    fn advance_trace(&mut self, id: u16, sim: &mut Simulator) {
+        let Some(session) = self.traces.get_mut(&id) else {
+            return;
+        };
+        if session.has_next_hop() {
+            self.send_trace_hop(id, sim);
+            return;
+        }
+        session.finished = true;
         println!("==============================================");
         println!("HOST: {}", self.ip_address);
-        println!("Received packet: {request:#?}");
+        println!("{}", session.report());
         println!("==============================================\n");
+    }
 
-        // Determine next hop for response using the same logic
-        let hop_dest_ip = if self.ip_address.get(..9) == request.src_ip.get(..9) {
-            Some((request.src_ip.to_string(), 0))
-        } else {
-            let mut found: Option<(String, usize)> = None;
-            for (router_ip, networks) in &self.routing_table {
-                for (net, metric) in networks {
-                    if request.src_ip.get(..9) == net.get(..9) {
-                        if found.is_none() || *metric < found.as_ref().unwrap().1 {
-                            found = Some((router_ip.clone(), *metric));
-                        }
-                    }
+    pub fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        match timer {
+            Timer::ArpRetry { ip } => self.retry_arp(ip, sim),
+            Timer::PingProbe { id } => self.send_probe(id, sim),
+            Timer::PingTimeout { id } => self.finish_ping(id),
+            Timer::TracerouteHop { id, ttl } => {
+                // Hops that were fully answered already moved on
+                if self.traces.get(&id).is_some_and(|session| !session.finished && session.current_ttl() == ttl) {
+                    self.advance_trace(id, sim);
+                }
+            }
+            Timer::DnsTimeout { id } => {
+                let actions = self.resolver.timeout(id, &self.dns_servers, sim.now());
+                self.apply_dns(actions, sim);
+            }
+            Timer::DhcpClient => {
+                if let Some(client) = self.dhcp_client.as_mut() {
+                    let actions = client.tick(sim.now());
+                    self.apply_dhcp(actions, sim);
                 }
             }
-            found
+            // Protocols only switches and routers run
+            Timer::StpHello
+            | Timer::StpForwardDelay { .. }
+            | Timer::RipUpdate
+            | Timer::RipTriggered
+            | Timer::RipExpire { .. }
+            | Timer::OspfHello
+            | Timer::OspfRetransmit
+            | Timer::BgpTick => {}
+        }
+    }
+
+    // Puts a prebuilt frame on the wire as is, e.g. one decoded from captured bytes
+    pub fn inject_frame(&mut self, packet: Rc<Packet>, sim: &mut Simulator) {
+        let Some(link) = self.link.clone() else {
+            println!("Switch not available");
+            return;
         };
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Injecting frame for {} at {:?}", packet.dest_ip, sim.now());
+        println!("==============================================\n");
+        self.outgoing_packets.push(Rc::clone(&packet));
+        link.transmit(packet, sim);
+    }
 
-        let hop_dest_mac = match hop_dest_ip {
-            Some((ref ip, _)) => match self.arp_table.get(ip) {
-                Some(mac) => mac.clone(),
-                None => match self.send_arp_request(ip) {
-                    Some(mac) => mac,
-                    None => {
-                        println!("No route to {}", request.src_ip);
-                        return None;
-                    }
-                },
-            },
-            None => {
-                println!("No route to {}", request.src_ip);
-                return None;
+    // ICMP errors end the exchange with the destination of the packet they quote.
+    // `from` is whoever reported the error, which is us if ARP gave up.
+    fn handle_icmp_error(&mut self, message: &IcmpMessage, from: Ipv4Addr, sim: &mut Simulator) {
+        if let Some(dest_ip) = message.original_destination()
+            && let Some(index) = self.awaiting_reply.iter().position(|ip| *ip == dest_ip)
+        {
+            self.awaiting_reply.remove(index);
+        }
+        if let Some((id, seq)) = message.original_echo() {
+            if let Some(session) = self.pings.get_mut(&id) {
+                session.record_error(seq, format!("{message} from {from}"));
             }
-        };
+            let answer = match message {
+                IcmpMessage::TimeExceeded { .. } => ProbeAnswer::TimeExceeded,
+                _ => ProbeAnswer::Unreachable(message.to_string()),
+            };
+            if let Some(session) = self.traces.get_mut(&id)
+                && session.record_answer(seq, from, answer, sim.now())
+            {
+                self.advance_trace(id, sim);
+            }
+        }
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Received ICMP {message} from {from} at {:?}", sim.now());
+        println!("==============================================\n");
+    }
 
-        let response = Rc::new(Packet::new(
-            &self.mac_address,
-            &hop_dest_mac,
-            &self.ip_address,
-            &request.src_ip,
-            request.data.clone(),
-            false
-        ));
+    pub fn receive_packet(&mut self, request: Rc<Packet>, sim: &mut Simulator) {
+        // Like a network card, drop unicast frames for other stations that a switch flooded here
+        if request.dest_mac != self.mac_address && !request.dest_mac.is_group() {
+            return;
+        }
+        // Hosts have no VLAN interfaces; tagged frames only belong on trunk ports
+        if request.vlan.is_some() {
+            return;
+        }
 
-        // Clone so that we maintain ownership of the packet
-        self.outgoing_packets.push(Rc::clone(&response));
-        Some(response)
+        if request.is_arp {
+            self.receive_arp(request, sim);
+            return;
+        }
+
+        // Make sure the packet is intended for this host
+        let is_broadcast = request.dest_ip == Ipv4Addr::BROADCAST;
+        if request.dest_ip != self.ip_address && !is_broadcast {
+            return;
+        }
+
+        // Add to list of incoming packets
+        self.incoming_packets.push(Rc::clone(&request));
+
+        // DHCP replies are for our client and requests for our server, if we run them
+        if let Some(message) = request.dhcp_message() {
+            let now = sim.now();
+            let actions = if message.kind.is_reply() {
+                self.dhcp_client.as_mut().map(|client| client.receive(&message, now))
+            } else {
+                self.dhcp_server.as_mut().map(|server| server.receive(0, &message, now))
+            };
+            self.apply_dhcp(actions.unwrap_or_default(), sim);
+            return;
+        }
+
+        // DNS responses are for our resolver and queries for our zone, if we serve one
+        if let Some(message) = request.dns_message()
+            && (message.response || self.dns_server.is_some())
+        {
+            if message.response {
+                let actions = self.resolver.receive(request.src_ip, &message, &self.dns_servers, sim.now());
+                self.apply_dns(actions, sim);
+            } else if let Some(response) = self.dns_server.as_ref().and_then(|server| server.answer(&message))
+                && let Some(datagram) = request.udp_datagram()
+                && !is_broadcast
+            {
+                let response = Packet::dns(self.ip_address, request.src_ip, dns::DNS_PORT, datagram.src_port, &response);
+                self.transmit_ip(response, sim);
+            }
+            return;
+        }
+
+        let message = request.icmp_message();
+
+        // Echo replies belong to one of our pings or traceroutes
+        if let Some(IcmpMessage::EchoReply { id, seq, .. }) = message {
+            if let Some(session) = self.traces.get_mut(&id) {
+                if session.record_answer(seq, request.src_ip, ProbeAnswer::Reached, sim.now()) {
+                    self.advance_trace(id, sim);
+                }
+                return;
+            }
+            let rtt = self.pings.get_mut(&id).and_then(|session| session.record_reply(seq, sim.now()));
+            println!("==============================================");
+            println!("HOST: {}", self.ip_address);
+            match rtt {
+                Some(rtt) => println!("Reply from {}: seq={seq} time={:.3} ms", request.src_ip, rtt.as_secs_f64() * 1e3),
+                None => println!("Ignoring unexpected or late echo reply from {}", request.src_ip),
+            }
+            println!("==============================================\n");
+            return;
+        }
+
+        if let Some(message) = message.as_ref().filter(|message| message.is_error()) {
+            self.handle_icmp_error(message, request.src_ip, sim);
+            return;
+        }
+
+        // A response to one of our own requests ends the exchange
+        if let Some(index) = self.awaiting_reply.iter().position(|ip| *ip == request.src_ip) {
+            self.awaiting_reply.remove(index);
+            println!("==============================================");
+            println!("HOST: {}", self.ip_address);
+            println!("Received response at {:?}: {request:#?}", sim.now());
+            println!("==============================================\n");
+            return;
+        }
+
+        println!("==============================================");
+        println!("HOST: {}", self.ip_address);
+        println!("Received packet at {:?}: {request:#?}", sim.now());
+        println!("==============================================\n");
+
+        // Echo requests get an echo reply and experimental payloads are echoed back as is, each as a
+        // new packet routed on its own. Other protocols expect no answer, and neither do broadcasts.
+        if is_broadcast {
+            return;
+        }
+        let response = match message {
+            Some(IcmpMessage::EchoRequest { id, seq, data }) => {
+                Packet::icmp(self.ip_address, request.src_ip, &IcmpMessage::EchoReply { id, seq, data })
+            }
+            None if !request.is_arp && request.header.protocol == PROTOCOL_EXPERIMENTAL => {
+                Packet::new(MacAddr::ZERO, MacAddr::ZERO, self.ip_address, request.src_ip, request.data.clone())
+            }
+            _ => return,
+        };
+        self.transmit_ip(response, sim);
     }
 
-    pub fn get_host_info(&self) -> String {
+    pub fn get_host_info(&self, now: Duration) -> String {
+        let conflicts = if self.address_conflicts.is_empty() {
+            String::new()
+        } else {
+            let macs: Vec<String> = self.address_conflicts.iter().map(ToString::to_string).collect();
+            format!("Address also used by: {}\n", macs.join(", "))
+        };
+        let mut routes = vec![format!("{:<18} direct", self.subnet.to_string())];
+        routes.extend(self.routes.iter().map(|(network, gateway)| format!("{:<18} {gateway}", network.to_string())));
+        if let Some(gateway) = self.default_gateway {
+            routes.push(format!("{:<18} {gateway}", "0.0.0.0/0"));
+        }
+        let dns = if self.dns_servers.is_empty() {
+            String::new()
+        } else {
+            let servers: Vec<String> = self.dns_servers.iter().map(ToString::to_string).collect();
+            format!("DNS Servers: {}\n", servers.join(", "))
+        };
+        let dhcp: String = [
+            self.dhcp_client.as_ref().map(|client| client.describe(now)),
+            self.dhcp_server.as_ref().map(|server| server.describe(&["eth0".to_string()], now)),
+            self.dns_server.as_ref().map(DnsServer::describe),
+            Some(self.resolver.describe(now)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|text| text + "\n")
+        .collect();
         format!(
-            "======================================\nHOST: {}\nARP Table: {:#?}\nOutgoing Packets: {:#?}\nIncoming Packets: {:#?}\n======================================\n",
-            self.ip_address, self.arp_table, self.outgoing_packets, self.incoming_packets
+            "======================================\nHOST: {}/{}\n{}{}{}Routing Table:\n{}\nARP Table:\n{}\nOutgoing Packets: {:#?}\nIncoming Packets: {:#?}\n======================================\n",
+            self.ip_address, self.subnet.prefix_len(), conflicts, dns, dhcp, routes.join("\n"), self.arp_cache.describe(now), self.outgoing_packets, self.incoming_packets
         )
     }
 }
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use crate::address::MacAddr;
+use crate::packet::{internet_checksum, Packet};
+
+pub const PROTOCOL_ICMP: u8 = 1;
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_DESTINATION_UNREACHABLE: u8 = 3;
+const TYPE_ECHO_REQUEST: u8 = 8;
+const TYPE_TIME_EXCEEDED: u8 = 11;
+
+// Destination Unreachable codes
+pub const NET_UNREACHABLE: u8 = 0;
+pub const HOST_UNREACHABLE: u8 = 1;
+pub const PROTOCOL_UNREACHABLE: u8 = 2;
+pub const PORT_UNREACHABLE: u8 = 3;
+
+// Bytes of the offending datagram quoted in error messages: its IP header plus 8 bytes of payload
+const QUOTE_LEN: usize = 20 + 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcmpMessage {
+    EchoReply { id: u16, seq: u16, data: Vec<u8> },
+    DestinationUnreachable { code: u8, original: Vec<u8> },
+    EchoRequest { id: u16, seq: u16, data: Vec<u8> },
+    // TTL reached zero in transit (code 0)
+    TimeExceeded { original: Vec<u8> },
+}
+
+impl IcmpMessage {
+    // Error about `packet`, quoting the start of it so the sender can match it up
+    pub fn time_exceeded(packet: &Packet) -> Self {
+        IcmpMessage::TimeExceeded { original: quote(packet) }
+    }
+
+    pub fn destination_unreachable(code: u8, packet: &Packet) -> Self {
+        IcmpMessage::DestinationUnreachable { code, original: quote(packet) }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (icmp_type, code, word, rest) = match self {
+            IcmpMessage::EchoReply { id, seq, data } => (TYPE_ECHO_REPLY, 0, echo_word(*id, *seq), data),
+            IcmpMessage::DestinationUnreachable { code, original } => (TYPE_DESTINATION_UNREACHABLE, *code, [0; 4], original),
+            IcmpMessage::EchoRequest { id, seq, data } => (TYPE_ECHO_REQUEST, 0, echo_word(*id, *seq), data),
+            IcmpMessage::TimeExceeded { original } => (TYPE_TIME_EXCEEDED, 0, [0; 4], original),
+        };
+        let mut bytes = vec![icmp_type, code, 0, 0];
+        bytes.extend_from_slice(&word);
+        bytes.extend_from_slice(rest);
+        let checksum = internet_checksum(&bytes);
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // None for unknown types or messages with a bad checksum
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 8 || internet_checksum(bytes) != 0 {
+            return None;
+        }
+        let id = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let seq = u16::from_be_bytes([bytes[6], bytes[7]]);
+        let rest = bytes[8..].to_vec();
+        match (bytes[0], bytes[1]) {
+            (TYPE_ECHO_REPLY, 0) => Some(IcmpMessage::EchoReply { id, seq, data: rest }),
+            (TYPE_DESTINATION_UNREACHABLE, code) => Some(IcmpMessage::DestinationUnreachable { code, original: rest }),
+            (TYPE_ECHO_REQUEST, 0) => Some(IcmpMessage::EchoRequest { id, seq, data: rest }),
+            (TYPE_TIME_EXCEEDED, 0) => Some(IcmpMessage::TimeExceeded { original: rest }),
+            _ => None,
+        }
+    }
+
+    // ICMP errors are never sent in response to other ICMP errors
+    pub fn is_error(&self) -> bool {
+        matches!(self, IcmpMessage::DestinationUnreachable { .. } | IcmpMessage::TimeExceeded { .. })
+    }
+
+    // Start of the datagram an error message is about
+    fn quoted(&self) -> Option<&[u8]> {
+        match self {
+            IcmpMessage::DestinationUnreachable { original, .. } | IcmpMessage::TimeExceeded { original } => Some(original),
+            _ => None,
+        }
+    }
+
+    // Destination address of the datagram an error message quotes
+    pub fn original_destination(&self) -> Option<Ipv4Addr> {
+        let dest: [u8; 4] = self.quoted()?.get(16..20)?.try_into().ok()?;
+        Some(dest.into())
+    }
+
+    // (id, seq) of the echo request an error message quotes
+    pub fn original_echo(&self) -> Option<(u16, u16)> {
+        let quoted = self.quoted()?;
+        if *quoted.get(9)? != PROTOCOL_ICMP {
+            return None;
+        }
+        let icmp = quoted.get(usize::from(quoted[0] & 0xF) * 4..)?;
+        if *icmp.first()? != TYPE_ECHO_REQUEST || icmp.len() < 8 {
+            return None;
+        }
+        Some((u16::from_be_bytes([icmp[4], icmp[5]]), u16::from_be_bytes([icmp[6], icmp[7]])))
+    }
+}
+
+impl fmt::Display for IcmpMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcmpMessage::EchoReply { id, seq, .. } => write!(f, "Echo Reply (id {id}, seq {seq})"),
+            IcmpMessage::DestinationUnreachable { code, .. } => match *code {
+                NET_UNREACHABLE => write!(f, "Destination Net Unreachable"),
+                HOST_UNREACHABLE => write!(f, "Destination Host Unreachable"),
+                PROTOCOL_UNREACHABLE => write!(f, "Destination Protocol Unreachable"),
+                PORT_UNREACHABLE => write!(f, "Destination Port Unreachable"),
+                code => write!(f, "Destination Unreachable (code {code})"),
+            },
+            IcmpMessage::EchoRequest { id, seq, .. } => write!(f, "Echo Request (id {id}, seq {seq})"),
+            IcmpMessage::TimeExceeded { .. } => write!(f, "Time Exceeded"),
+        }
+    }
+}
+
+impl Packet {
+    // ICMP message carried in an IPv4 packet; MAC addresses are filled in when it is routed
+    pub fn icmp(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, message: &IcmpMessage) -> Self {
+        let mut packet = Packet::new(
+            MacAddr::ZERO,
+            MacAddr::ZERO,
+            src_ip,
+            dest_ip,
+            message.to_bytes(),
+        );
+        packet.header.protocol = PROTOCOL_ICMP;
+        packet
+    }
+
+    // The ICMP message this packet carries, if any
+    pub fn icmp_message(&self) -> Option<IcmpMessage> {
+        if self.is_arp || self.header.protocol != PROTOCOL_ICMP {
+            return None;
+        }
+        IcmpMessage::from_bytes(&self.data)
+    }
+}
+
+fn echo_word(id: u16, seq: u16) -> [u8; 4] {
+    let [id_hi, id_lo] = id.to_be_bytes();
+    let [seq_hi, seq_lo] = seq.to_be_bytes();
+    [id_hi, id_lo, seq_hi, seq_lo]
+}
+
+fn quote(packet: &Packet) -> Vec<u8> {
+    let bytes = packet.to_bytes();
+    let ip = &bytes[packet.ethernet_header_len()..];
+    ip[..ip.len().min(QUOTE_LEN)].to_vec()
+}
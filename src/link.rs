@@ -0,0 +1,260 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+use crate::device::{Device, DeviceRef};
+use crate::packet::Packet;
+use crate::sim::{Event, Simulator};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    pub bandwidth_bps: u64,
+    pub delay: Duration,      // Propagation delay
+    pub jitter: Duration,     // Extra random delay, up to this amount
+    pub loss: f64,            // Probability that a frame is lost
+    pub bit_error_rate: f64,  // Probability that any single bit is flipped
+    pub mtu: usize,           // Largest frame (in bytes) the link carries
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            bandwidth_bps: 1_000_000_000,
+            delay: Duration::from_millis(1),
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            bit_error_rate: 0.0,
+            mtu: 1518,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LinkEnd {
+    device: Weak<RefCell<dyn Device>>,
+    port: usize,
+}
+
+// Point-to-point connection between two device ports
+#[derive(Debug)]
+pub struct Link {
+    pub config: LinkConfig,
+    up: bool,
+    outages: u64, // Times the link went down; frames sent before an outage never arrive
+    ends: [LinkEnd; 2],
+    busy_until: [Duration; 2], // When each direction finishes transmitting its queued frames
+    pub frames_delivered: usize, // Counted on arrival
+    pub frames_dropped: usize,
+}
+
+// One side of a link as seen from the device that owns the port
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub link: Rc<RefCell<Link>>,
+    pub side: usize,
+}
+
+impl Link {
+    // Creates a link between `a_port` on `a` and `b_port` on `b`, attaching it to both devices
+    pub fn connect(a: &DeviceRef, a_port: usize, b: &DeviceRef, b_port: usize, config: LinkConfig) -> Rc<RefCell<Link>> {
+        let link = Rc::new(RefCell::new(Link {
+            config,
+            up: true,
+            outages: 0,
+            ends: [
+                LinkEnd { device: Rc::downgrade(a), port: a_port },
+                LinkEnd { device: Rc::downgrade(b), port: b_port },
+            ],
+            busy_until: [Duration::ZERO; 2],
+            frames_delivered: 0,
+            frames_dropped: 0,
+        }));
+        a.borrow_mut().attach_link(a_port, Attachment { link: Rc::clone(&link), side: 0 });
+        b.borrow_mut().attach_link(b_port, Attachment { link: Rc::clone(&link), side: 1 });
+        link
+    }
+
+    pub fn is_up(&self) -> bool { self.up }
+
+    pub fn set_up(&mut self, up: bool) {
+        if self.up && !up {
+            self.outages += 1;
+        }
+        self.up = up;
+    }
+
+    // Whether a frame sent after `outages` outages is still on the wire
+    pub fn carries(&self, outages: u64) -> bool { self.up && self.outages == outages }
+
+    // Device and port at the given side of the link
+    pub fn end(&self, side: usize) -> Option<(DeviceRef, usize)> {
+        let end = &self.ends[side];
+        end.device.upgrade().map(|device| (device, end.port))
+    }
+}
+
+impl Attachment {
+    // Puts a frame on the wire, scheduling its arrival at the other side.
+    // Frames queue behind earlier frames sent in the same direction.
+    pub fn transmit(&self, packet: Rc<Packet>, sim: &mut Simulator) {
+        let mut link = self.link.borrow_mut();
+        if !link.up {
+            link.frames_dropped += 1;
+            println!("Link down, dropping packet for {}", packet.dest_ip);
+            return;
+        }
+
+        let size = packet.size();
+        if size > link.config.mtu {
+            link.frames_dropped += 1;
+            println!("Packet of {} bytes exceeds MTU of {}, dropping", size, link.config.mtu);
+            return;
+        }
+
+        let bits = (size * 8) as u64;
+        let serialization = Duration::from_secs_f64(bits as f64 / link.config.bandwidth_bps as f64);
+        let start = sim.now().max(link.busy_until[self.side]);
+        link.busy_until[self.side] = start + serialization;
+
+        if sim.random() < link.config.loss {
+            link.frames_dropped += 1;
+            println!("Packet for {} lost on link", packet.dest_ip);
+            return;
+        }
+
+        // A single flipped bit fails the receiver's frame check, so the frame is discarded
+        let intact = (1.0 - link.config.bit_error_rate).powi(bits as i32);
+        if sim.random() >= intact {
+            link.frames_dropped += 1;
+            println!("Packet for {} corrupted on link", packet.dest_ip);
+            return;
+        }
+
+        let jitter = link.config.jitter.mul_f64(sim.random());
+        let arrival = start + serialization + link.config.delay + jitter;
+        let outages = link.outages;
+        drop(link);
+
+        sim.schedule_at(arrival, Event::Deliver {
+            link: Rc::clone(&self.link),
+            side: 1 - self.side,
+            packet,
+            outages,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::address::MacAddr;
+    use crate::sim::Timer;
+
+    // Remembers when frames arrive
+    #[derive(Debug, Default)]
+    struct Probe {
+        link: Option<Attachment>,
+        arrivals: Vec<Duration>,
+    }
+
+    impl Device for Probe {
+        fn attach_link(&mut self, _port: usize, attachment: Attachment) { self.link = Some(attachment); }
+        fn receive_packet(&mut self, _packet: Rc<Packet>, _port: usize, sim: &mut Simulator) { self.arrivals.push(sim.now()); }
+        fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
+        fn handle_timer(&mut self, _timer: Timer, _sim: &mut Simulator) {}
+        fn get_ip_address(&self) -> Option<Ipv4Addr> { None }
+        fn get_device_info(&self, _now: Duration) -> String { String::new() }
+    }
+
+    // 1 Mb/s with 10 ms of delay, so a 125-byte frame takes 1 ms to send and 11 ms to arrive
+    const SLOW: LinkConfig = LinkConfig {
+        bandwidth_bps: 1_000_000,
+        delay: Duration::from_millis(10),
+        jitter: Duration::ZERO,
+        loss: 0.0,
+        bit_error_rate: 0.0,
+        mtu: 1518,
+    };
+
+    fn pair(config: LinkConfig) -> ([Rc<RefCell<Probe>>; 2], Rc<RefCell<Link>>) {
+        let (a, b) = (Rc::new(RefCell::new(Probe::default())), Rc::new(RefCell::new(Probe::default())));
+        let (a_ref, b_ref): (DeviceRef, DeviceRef) = (a.clone(), b.clone());
+        let link = Link::connect(&a_ref, 0, &b_ref, 0, config);
+        ([a, b], link)
+    }
+
+    fn frame(size: usize) -> Rc<Packet> {
+        let packet = Packet::new(MacAddr::ZERO, MacAddr::BROADCAST, Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST, vec![0; size - 34]);
+        assert_eq!(packet.size(), size);
+        Rc::new(packet)
+    }
+
+    fn send(probe: &RefCell<Probe>, packet: Rc<Packet>, sim: &mut Simulator) {
+        let attachment = probe.borrow().link.clone().unwrap();
+        attachment.transmit(packet, sim);
+    }
+
+    fn millis(times: &[Duration]) -> Vec<f64> { times.iter().map(|time| time.as_secs_f64() * 1e3).collect() }
+
+    #[test]
+    fn frames_take_serialization_and_propagation_delay() {
+        let mut sim = Simulator::new();
+        let ([a, b], link) = pair(SLOW);
+        send(&a, frame(125), &mut sim);
+        while sim.step() {}
+        assert_eq!(millis(&b.borrow().arrivals), [11.0]);
+        assert_eq!(link.borrow().frames_delivered, 1);
+    }
+
+    #[test]
+    fn back_to_back_frames_queue_behind_each_other() {
+        let mut sim = Simulator::new();
+        let ([a, b], _link) = pair(SLOW);
+        for _ in 0..3 {
+            send(&a, frame(125), &mut sim);
+        }
+        // The other direction has a queue of its own
+        send(&b, frame(250), &mut sim);
+        while sim.step() {}
+        assert_eq!(millis(&b.borrow().arrivals), [11.0, 12.0, 13.0]);
+        assert_eq!(millis(&a.borrow().arrivals), [12.0]);
+    }
+
+    #[test]
+    fn frames_over_the_mtu_are_dropped() {
+        let mut sim = Simulator::new();
+        let ([a, b], link) = pair(LinkConfig { mtu: 124, ..SLOW });
+        send(&a, frame(125), &mut sim);
+        send(&a, frame(124), &mut sim);
+        while sim.step() {}
+        // The dropped frame does not hold up the next one
+        assert_eq!(millis(&b.borrow().arrivals), [10.992]);
+        assert_eq!((link.borrow().frames_delivered, link.borrow().frames_dropped), (1, 1));
+    }
+
+    #[test]
+    fn certain_loss_drops_every_frame() {
+        let mut sim = Simulator::new();
+        let ([a, b], link) = pair(LinkConfig { loss: 1.0, ..SLOW });
+        for _ in 0..100 {
+            send(&a, frame(125), &mut sim);
+        }
+        assert_eq!(sim.pending_events(), 0);
+        assert!(b.borrow().arrivals.is_empty());
+        assert_eq!((link.borrow().frames_delivered, link.borrow().frames_dropped), (0, 100));
+    }
+
+    #[test]
+    fn frames_in_flight_are_lost_when_the_link_goes_down() {
+        let mut sim = Simulator::new();
+        let ([a, b], link) = pair(SLOW);
+        send(&a, frame(125), &mut sim);
+        sim.run_until(Duration::from_millis(5));
+        link.borrow_mut().set_up(false);
+        send(&a, frame(125), &mut sim);
+        link.borrow_mut().set_up(true);
+        while sim.step() {}
+        assert!(b.borrow().arrivals.is_empty());
+        assert_eq!((link.borrow().frames_delivered, link.borrow().frames_dropped), (0, 2));
+    }
+}
@@ -0,0 +1,1342 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::{Ipv4Net, MacAddr};
+use crate::packet::{internet_checksum, Packet};
+
+pub const PROTOCOL_OSPF: u8 = 89;
+// Every OSPF router on a link listens to the first group, designated routers also to the second
+pub const ALL_SPF_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 5);
+pub const ALL_D_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 6);
+// Router priority when not configured; 0 never becomes designated router
+pub const DEFAULT_PRIORITY: u8 = 1;
+// Interface cost is this divided by the link bandwidth, at least 1
+const REFERENCE_BANDWIDTH_BPS: u64 = 100_000_000;
+// Age of LSAs that are being removed from every database
+const MAX_AGE: u16 = 3600;
+const INITIAL_SEQUENCE: u32 = 0x8000_0001;
+
+const VERSION: u8 = 2;
+const TYPE_HELLO: u8 = 1;
+const TYPE_UPDATE: u8 = 4;
+const TYPE_ACK: u8 = 5;
+const HEADER_LEN: usize = 24;
+const LSA_HEADER_LEN: usize = 20;
+const OPTIONS: u8 = 0x02; // E bit
+const LSA_ROUTER: u8 = 1;
+const LSA_NETWORK: u8 = 2;
+const LINK_TRANSIT: u8 = 2;
+const LINK_STUB: u8 = 3;
+const LSAS_PER_UPDATE: usize = 16;
+
+// Routers are identified by their address
+pub type RouterId = Ipv4Addr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OspfTimers {
+    pub hello: Duration,      // Between hellos on every interface
+    pub dead: Duration,       // Neighbors not heard from for this long are gone; also the wait before the first election
+    pub retransmit: Duration, // Between resends of LSAs a neighbor has not acknowledged
+}
+
+impl Default for OspfTimers {
+    fn default() -> Self {
+        Self {
+            hello: Duration::from_secs(10),
+            dead: Duration::from_secs(40),
+            retransmit: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LsaKind {
+    Router,  // Links of one router
+    Network, // Routers on a segment, sent by its designated router
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LsaKey {
+    pub kind: LsaKind,
+    pub id: Ipv4Addr, // Router ID for router LSAs, the DR's interface ID for network LSAs
+    pub advertising: RouterId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterLink {
+    // Segment with a designated router, which is known by the DR and its interface ID
+    Transit { dr: RouterId, interface_id: u32, cost: u16 },
+    // Network reached through the router
+    Stub { network: Ipv4Net, cost: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LsaBody {
+    Router(Vec<RouterLink>),
+    Network(Vec<RouterId>), // Attached routers, the DR included
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lsa {
+    pub key: LsaKey,
+    pub sequence: u32,
+    pub age: u16, // 0, or MAX_AGE while the LSA is being removed
+    pub body: LsaBody,
+}
+
+// What acknowledgements and comparisons need of an LSA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsaHeader {
+    pub key: LsaKey,
+    pub sequence: u32,
+    pub age: u16,
+    pub checksum: u16,
+}
+
+impl Lsa {
+    pub fn header(&self) -> LsaHeader {
+        let bytes = self.to_bytes();
+        LsaHeader { key: self.key, sequence: self.sequence, age: self.age, checksum: u16::from_be_bytes([bytes[16], bytes[17]]) }
+    }
+
+    fn is_flushed(&self) -> bool { self.age >= MAX_AGE }
+
+    // Higher sequence numbers are newer; of two equal ones, the instance being flushed is
+    fn instance(&self) -> (i32, bool) { (self.sequence as i32, self.is_flushed()) }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, body) = match &self.body {
+            LsaBody::Router(links) => {
+                let mut body = vec![0, 0];
+                body.extend_from_slice(&(links.len() as u16).to_be_bytes());
+                for link in links {
+                    let (id, data, kind, cost) = match *link {
+                        RouterLink::Transit { dr, interface_id, cost } => (dr, interface_id, LINK_TRANSIT, cost),
+                        RouterLink::Stub { network, cost } => (network.network(), prefix_mask(network.prefix_len()), LINK_STUB, cost),
+                    };
+                    body.extend_from_slice(&id.octets());
+                    body.extend_from_slice(&data.to_be_bytes());
+                    body.extend_from_slice(&[kind, 0]);
+                    body.extend_from_slice(&cost.to_be_bytes());
+                }
+                (LSA_ROUTER, body)
+            }
+            LsaBody::Network(routers) => {
+                let mut body = vec![0; 4]; // Segments have no prefix of their own
+                for router in routers {
+                    body.extend_from_slice(&router.octets());
+                }
+                (LSA_NETWORK, body)
+            }
+        };
+        let mut bytes = Vec::with_capacity(LSA_HEADER_LEN + body.len());
+        bytes.extend_from_slice(&self.age.to_be_bytes());
+        bytes.extend_from_slice(&[OPTIONS, kind]);
+        bytes.extend_from_slice(&self.key.id.octets());
+        bytes.extend_from_slice(&self.key.advertising.octets());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&((LSA_HEADER_LEN + body.len()) as u16).to_be_bytes());
+        bytes.extend_from_slice(&body);
+        // The checksum skips the age, which changes in transit
+        let checksum = fletcher_checksum(&bytes[2..], 14);
+        bytes[16..18].copy_from_slice(&checksum);
+        bytes
+    }
+
+    // None for unknown types, bad checksums and malformed LSAs
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < LSA_HEADER_LEN || !fletcher_valid(&bytes[2..]) {
+            return None;
+        }
+        let key = |kind| LsaKey { kind, id: ipv4_at(bytes, 4), advertising: ipv4_at(bytes, 8) };
+        let body = &bytes[LSA_HEADER_LEN..];
+        let (key, body) = match bytes[3] {
+            LSA_ROUTER if body.len() >= 4 => {
+                let count = usize::from(u16::from_be_bytes([body[2], body[3]]));
+                let links = body[4..].chunks_exact(12).take(count);
+                if links.len() != count {
+                    return None;
+                }
+                let links = links
+                    .map(|link| {
+                        let cost = u16::from_be_bytes([link[10], link[11]]);
+                        let data = u32::from_be_bytes([link[4], link[5], link[6], link[7]]);
+                        match link[8] {
+                            LINK_TRANSIT => Some(RouterLink::Transit { dr: ipv4_at(link, 0), interface_id: data, cost }),
+                            LINK_STUB => {
+                                let network = Ipv4Net::new(ipv4_at(link, 0), data.leading_ones() as u8).ok()?;
+                                Some(RouterLink::Stub { network, cost })
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                (key(LsaKind::Router), LsaBody::Router(links))
+            }
+            LSA_NETWORK if body.len() >= 4 => {
+                let routers = body[4..].chunks_exact(4).map(|id| ipv4_at(id, 0)).collect();
+                (key(LsaKind::Network), LsaBody::Network(routers))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            key,
+            sequence: u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            age: u16::from_be_bytes([bytes[0], bytes[1]]).min(MAX_AGE),
+            body,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    // Sent where OSPFv2 has the network mask: interfaces have no addresses of their own, so
+    // segments are known by their DR's interface ID, as in OSPFv3
+    pub interface_id: u32,
+    pub priority: u8,
+    pub hello_interval: u16, // Seconds
+    pub dead_interval: u32,  // Seconds
+    pub dr: RouterId,        // 0.0.0.0 if none
+    pub bdr: RouterId,
+    pub neighbors: Vec<RouterId>, // Routers heard from on the link
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OspfPacket {
+    Hello(Hello),
+    Update(Vec<Lsa>),
+    Ack(Vec<LsaHeader>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OspfMessage {
+    pub router_id: RouterId,
+    pub packet: OspfPacket,
+}
+
+impl OspfMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, body) = match &self.packet {
+            OspfPacket::Hello(hello) => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&hello.interface_id.to_be_bytes());
+                body.extend_from_slice(&hello.hello_interval.to_be_bytes());
+                body.extend_from_slice(&[OPTIONS, hello.priority]);
+                body.extend_from_slice(&hello.dead_interval.to_be_bytes());
+                body.extend_from_slice(&hello.dr.octets());
+                body.extend_from_slice(&hello.bdr.octets());
+                for neighbor in &hello.neighbors {
+                    body.extend_from_slice(&neighbor.octets());
+                }
+                (TYPE_HELLO, body)
+            }
+            OspfPacket::Update(lsas) => {
+                let mut body = (lsas.len() as u32).to_be_bytes().to_vec();
+                for lsa in lsas {
+                    body.extend_from_slice(&lsa.to_bytes());
+                }
+                (TYPE_UPDATE, body)
+            }
+            OspfPacket::Ack(headers) => {
+                let mut body = Vec::new();
+                for header in headers {
+                    let kind = match header.key.kind {
+                        LsaKind::Router => LSA_ROUTER,
+                        LsaKind::Network => LSA_NETWORK,
+                    };
+                    body.extend_from_slice(&header.age.to_be_bytes());
+                    body.extend_from_slice(&[OPTIONS, kind]);
+                    body.extend_from_slice(&header.key.id.octets());
+                    body.extend_from_slice(&header.key.advertising.octets());
+                    body.extend_from_slice(&header.sequence.to_be_bytes());
+                    body.extend_from_slice(&header.checksum.to_be_bytes());
+                    body.extend_from_slice(&(LSA_HEADER_LEN as u16).to_be_bytes());
+                }
+                (TYPE_ACK, body)
+            }
+        };
+        let mut bytes = vec![VERSION, kind];
+        bytes.extend_from_slice(&((HEADER_LEN + body.len()) as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.router_id.octets());
+        bytes.extend_from_slice(&[0; 4]);  // Backbone area
+        bytes.extend_from_slice(&[0; 12]); // Checksum, no authentication
+        bytes.extend_from_slice(&body);
+        let checksum = internet_checksum(&bytes);
+        bytes[12..14].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // None for other versions, areas or packet types, bad checksums and malformed packets.
+    // LSAs that cannot be decoded are left out of updates.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0] != VERSION || bytes[8..12] != [0; 4] {
+            return None;
+        }
+        let length = usize::from(u16::from_be_bytes([bytes[2], bytes[3]]));
+        if length < HEADER_LEN || length > bytes.len() || internet_checksum(&bytes[..length]) != 0 {
+            return None;
+        }
+        let body = &bytes[HEADER_LEN..length];
+        let packet = match bytes[1] {
+            TYPE_HELLO if body.len() >= 20 => OspfPacket::Hello(Hello {
+                interface_id: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+                hello_interval: u16::from_be_bytes([body[4], body[5]]),
+                priority: body[7],
+                dead_interval: u32::from_be_bytes([body[8], body[9], body[10], body[11]]),
+                dr: ipv4_at(body, 12),
+                bdr: ipv4_at(body, 16),
+                neighbors: body[20..].chunks_exact(4).map(|id| ipv4_at(id, 0)).collect(),
+            }),
+            TYPE_UPDATE if body.len() >= 4 => {
+                let count = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                let mut lsas = Vec::new();
+                let mut rest = &body[4..];
+                for _ in 0..count {
+                    let length = usize::from(u16::from_be_bytes([*rest.get(18)?, *rest.get(19)?]));
+                    if length < LSA_HEADER_LEN || length > rest.len() {
+                        return None;
+                    }
+                    lsas.extend(Lsa::from_bytes(&rest[..length]));
+                    rest = &rest[length..];
+                }
+                OspfPacket::Update(lsas)
+            }
+            TYPE_ACK => OspfPacket::Ack(
+                body.chunks_exact(LSA_HEADER_LEN)
+                    .filter_map(|header| {
+                        let kind = match header[3] {
+                            LSA_ROUTER => LsaKind::Router,
+                            LSA_NETWORK => LsaKind::Network,
+                            _ => return None,
+                        };
+                        Some(LsaHeader {
+                            key: LsaKey { kind, id: ipv4_at(header, 4), advertising: ipv4_at(header, 8) },
+                            sequence: u32::from_be_bytes([header[12], header[13], header[14], header[15]]),
+                            age: u16::from_be_bytes([header[0], header[1]]).min(MAX_AGE),
+                            checksum: u16::from_be_bytes([header[16], header[17]]),
+                        })
+                    })
+                    .collect(),
+            ),
+            _ => return None,
+        };
+        Some(Self { router_id: ipv4_at(bytes, 4), packet })
+    }
+}
+
+impl Packet {
+    // OSPF packet for the routers on one link
+    pub fn ospf(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, message: &OspfMessage) -> Self {
+        let mut packet = Packet::new(MacAddr::ZERO, MacAddr::ZERO, src_ip, dest_ip, message.to_bytes());
+        packet.header.protocol = PROTOCOL_OSPF;
+        packet.header.ttl = 1;
+        packet
+    }
+
+    // The OSPF packet this packet carries, if any
+    pub fn ospf_message(&self) -> Option<OspfMessage> {
+        if self.is_arp || self.header.protocol != PROTOCOL_OSPF {
+            return None;
+        }
+        OspfMessage::from_bytes(&self.data)
+    }
+}
+
+// Fletcher checksum of an LSA (from its options field) with the checksum at `offset` (RFC 905 annex B)
+fn fletcher_checksum(bytes: &[u8], offset: usize) -> [u8; 2] {
+    let (mut c0, mut c1) = (0i64, 0i64);
+    for (index, byte) in bytes.iter().enumerate() {
+        let byte = if index == offset || index == offset + 1 { 0 } else { i64::from(*byte) };
+        c0 = (c0 + byte) % 255;
+        c1 = (c1 + c0) % 255;
+    }
+    let mut x = ((bytes.len() - offset - 1) as i64 * c0 - c1) % 255;
+    if x <= 0 {
+        x += 255;
+    }
+    let mut y = 510 - c0 - x;
+    if y > 255 {
+        y -= 255;
+    }
+    [x as u8, y as u8]
+}
+
+fn fletcher_valid(bytes: &[u8]) -> bool {
+    let (mut c0, mut c1) = (0u32, 0u32);
+    for byte in bytes {
+        c0 = (c0 + u32::from(*byte)) % 255;
+        c1 = (c1 + c0) % 255;
+    }
+    c0 == 0 && c1 == 0
+}
+
+fn prefix_mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        len => u32::MAX << (32 - u32::from(len.min(32))),
+    }
+}
+
+fn ipv4_at(bytes: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3])
+}
+
+fn optional(id: RouterId) -> Option<RouterId> { (!id.is_unspecified()).then_some(id) }
+
+fn seconds(time: Duration) -> u32 { time.as_secs_f64().round() as u32 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceState {
+    Down,
+    Waiting, // Listening for an existing DR before the first election
+    DrOther,
+    Backup,
+    Dr,
+}
+
+impl fmt::Display for InterfaceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceState::Down => write!(f, "down"),
+            InterfaceState::Waiting => write!(f, "waiting"),
+            InterfaceState::DrOther => write!(f, "DROther"),
+            InterfaceState::Backup => write!(f, "BDR"),
+            InterfaceState::Dr => write!(f, "DR"),
+        }
+    }
+}
+
+// Databases are exchanged as soon as two routers decide to become adjacent, so the
+// ExStart/Exchange/Loading states of OSPF pass in one step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NeighborState {
+    Init,   // Heard from, but it has not heard from us yet
+    TwoWay, // Both directions work
+    Full,   // Adjacent: databases are synchronized and LSAs are flooded between the two
+}
+
+impl fmt::Display for NeighborState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NeighborState::Init => write!(f, "Init"),
+            NeighborState::TwoWay => write!(f, "2-Way"),
+            NeighborState::Full => write!(f, "Full"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Neighbor {
+    state: NeighborState,
+    mac: MacAddr,
+    interface_id: u32,
+    priority: u8,
+    dr: Option<RouterId>,  // As the neighbor declares them
+    bdr: Option<RouterId>,
+    dead_at: Duration,
+    retransmit: Vec<LsaKey>, // LSAs sent to it that it has not acknowledged
+}
+
+#[derive(Debug, Clone)]
+struct Interface {
+    state: InterfaceState,
+    cost: u16,
+    configured_cost: Option<u16>, // Overrides the cost from the link bandwidth
+    priority: u8,
+    passive: bool, // Advertised, but no hellos are sent or accepted
+    dr: Option<(RouterId, u32)>, // Designated router and its interface ID
+    bdr: Option<RouterId>,
+    wait_until: Duration,
+    neighbors: BTreeMap<RouterId, Neighbor>,
+}
+
+impl Interface {
+    fn is_dr_or_backup(&self) -> bool { matches!(self.state, InterfaceState::Dr | InterfaceState::Backup) }
+}
+
+// Vertex of the shortest-path tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Vertex {
+    Router(RouterId),
+    Network { dr: RouterId, interface_id: u32 },
+}
+
+impl fmt::Display for Vertex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vertex::Router(id) => write!(f, "router {id}"),
+            Vertex::Network { dr, interface_id } => write!(f, "segment {dr}#{interface_id}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TreeNode {
+    vertex: Vertex,
+    cost: u32,
+    parent: Option<Vertex>,
+    first_hop: Option<(usize, Option<Ipv4Addr>)>, // Outgoing interface and next hop, None for the root
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OspfAction {
+    Send { interface: usize, dest_ip: Ipv4Addr, dest_mac: MacAddr, message: OspfMessage },
+    Install { network: Ipv4Net, interface: usize, next_hop: Ipv4Addr },
+    Remove { network: Ipv4Net },
+}
+
+// OSPFv2-style link-state routing in a single area: hellos and neighbor discovery, DR/BDR
+// election on every link, reliable flooding of router and network LSAs into the link-state
+// database, and a shortest-path tree from which the routes to the routers' networks are taken.
+// Every link is treated as a broadcast segment.
+#[derive(Debug, Clone)]
+pub struct Ospf {
+    router_id: RouterId,
+    timers: OspfTimers,
+    interfaces: Vec<Interface>,
+    connected: Vec<(Ipv4Net, usize)>, // Directly connected networks, advertised as stub links
+    lsdb: BTreeMap<LsaKey, Lsa>,
+    changed: bool,                    // The database changed since the last SPF run
+    tree: Vec<TreeNode>,              // In the order vertices were added
+    routes: BTreeMap<Ipv4Net, (u32, usize, Ipv4Addr)>, // Network -> (cost, interface, next hop)
+    actions: Vec<OspfAction>,
+}
+
+impl Ospf {
+    pub fn new(router_id: RouterId, timers: OspfTimers, interface_count: usize) -> Self {
+        let interface = Interface {
+            state: InterfaceState::Down,
+            cost: 1,
+            configured_cost: None,
+            priority: DEFAULT_PRIORITY,
+            passive: false,
+            dr: None,
+            bdr: None,
+            wait_until: Duration::ZERO,
+            neighbors: BTreeMap::new(),
+        };
+        Self {
+            router_id,
+            timers,
+            interfaces: vec![interface; interface_count],
+            connected: Vec::new(),
+            lsdb: BTreeMap::new(),
+            changed: false,
+            tree: Vec::new(),
+            routes: BTreeMap::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn timers(&self) -> OspfTimers { self.timers }
+
+    pub fn configured_cost(&self, interface: usize) -> Option<u16> {
+        self.interfaces.get(interface).and_then(|iface| iface.configured_cost)
+    }
+
+    pub fn set_cost(&mut self, interface: usize, cost: Option<u16>) {
+        if let Some(iface) = self.interfaces.get_mut(interface) {
+            iface.configured_cost = cost.map(|cost| cost.max(1));
+        }
+    }
+
+    pub fn priority(&self, interface: usize) -> u8 {
+        self.interfaces.get(interface).map_or(DEFAULT_PRIORITY, |iface| iface.priority)
+    }
+
+    pub fn set_priority(&mut self, interface: usize, priority: u8) {
+        if let Some(iface) = self.interfaces.get_mut(interface) {
+            iface.priority = priority;
+        }
+    }
+
+    pub fn is_passive(&self, interface: usize) -> bool { self.interfaces.get(interface).is_some_and(|iface| iface.passive) }
+
+    pub fn set_passive(&mut self, interface: usize, passive: bool) {
+        if let Some(iface) = self.interfaces.get_mut(interface) {
+            iface.passive = passive;
+        }
+    }
+
+    // Called every hello interval with the bandwidth of each interface's link (None while it has
+    // no working link) and the directly connected networks (network, interface). Brings interfaces
+    // up and down, drops neighbors that went quiet, ends the wait before the first election and
+    // sends hellos.
+    pub fn tick(&mut self, links: &[Option<u64>], connected: &[(Ipv4Net, usize)], now: Duration) -> Vec<OspfAction> {
+        self.connected = connected.to_vec();
+        for index in 0..self.interfaces.len() {
+            let dead_interval = self.timers.dead;
+            let iface = &mut self.interfaces[index];
+            let mut elect = false;
+            match links.get(index).copied().flatten() {
+                None => {
+                    if iface.state != InterfaceState::Down {
+                        iface.state = InterfaceState::Down;
+                        iface.neighbors.clear();
+                        iface.dr = None;
+                        iface.bdr = None;
+                    }
+                    continue;
+                }
+                Some(bandwidth_bps) => {
+                    let cost = (REFERENCE_BANDWIDTH_BPS / bandwidth_bps.max(1)).clamp(1, u64::from(u16::MAX)) as u16;
+                    iface.cost = iface.configured_cost.unwrap_or(cost);
+                    if iface.state == InterfaceState::Down {
+                        iface.state = InterfaceState::Waiting;
+                        iface.wait_until = if iface.passive { now } else { now + dead_interval };
+                    }
+                }
+            }
+
+            let before = iface.neighbors.len();
+            iface.neighbors.retain(|_, neighbor| neighbor.dead_at > now);
+            if iface.neighbors.len() != before && iface.state != InterfaceState::Waiting {
+                elect = true;
+            }
+            if iface.state == InterfaceState::Waiting && iface.wait_until <= now {
+                elect = true;
+            }
+            if elect {
+                self.elect(index);
+            }
+            self.send_hello(index);
+        }
+        self.originate();
+        std::mem::take(&mut self.actions)
+    }
+
+    // `dest_ip` tells multicasts to all routers from those to designated routers only
+    pub fn receive(&mut self, interface: usize, src_mac: MacAddr, dest_ip: Ipv4Addr, message: OspfMessage, now: Duration) -> Vec<OspfAction> {
+        let Some(iface) = self.interfaces.get(interface) else {
+            return Vec::new();
+        };
+        if message.router_id == self.router_id
+            || iface.passive
+            || iface.state == InterfaceState::Down
+            || (dest_ip == ALL_D_ROUTERS && !iface.is_dr_or_backup())
+        {
+            return Vec::new();
+        }
+        match message.packet {
+            OspfPacket::Hello(hello) => self.receive_hello(interface, src_mac, message.router_id, hello, now),
+            OspfPacket::Update(lsas) => self.receive_update(interface, message.router_id, lsas),
+            OspfPacket::Ack(headers) => self.receive_ack(interface, message.router_id, &headers),
+        }
+        self.originate();
+        std::mem::take(&mut self.actions)
+    }
+
+    // Resends the LSAs each adjacent neighbor has not acknowledged yet
+    pub fn retransmit(&mut self) -> Vec<OspfAction> {
+        for index in 0..self.interfaces.len() {
+            let pending: Vec<(RouterId, MacAddr, Vec<Lsa>)> = self.interfaces[index].neighbors
+                .iter()
+                .filter(|(_, neighbor)| neighbor.state == NeighborState::Full && !neighbor.retransmit.is_empty())
+                .map(|(id, neighbor)| {
+                    (*id, neighbor.mac, neighbor.retransmit.iter().filter_map(|key| self.lsdb.get(key).cloned()).collect())
+                })
+                .collect();
+            for (id, mac, lsas) in pending {
+                self.send_update(index, id, mac, lsas);
+            }
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    fn receive_hello(&mut self, index: usize, src_mac: MacAddr, id: RouterId, hello: Hello, now: Duration) {
+        if u32::from(hello.hello_interval) != seconds(self.timers.hello) || hello.dead_interval != seconds(self.timers.dead) {
+            return;
+        }
+        let dead_at = now + self.timers.dead;
+        let router_id = self.router_id;
+        let iface = &mut self.interfaces[index];
+        let is_new = !iface.neighbors.contains_key(&id);
+        let neighbor = iface.neighbors.entry(id).or_insert_with(|| Neighbor {
+            state: NeighborState::Init,
+            mac: src_mac,
+            interface_id: hello.interface_id,
+            priority: hello.priority,
+            dr: None,
+            bdr: None,
+            dead_at,
+            retransmit: Vec::new(),
+        });
+        let declared = (hello.priority, optional(hello.dr), optional(hello.bdr));
+        let changed = declared != (neighbor.priority, neighbor.dr, neighbor.bdr);
+        (neighbor.priority, neighbor.dr, neighbor.bdr) = declared;
+        neighbor.mac = src_mac;
+        neighbor.interface_id = hello.interface_id;
+        neighbor.dead_at = dead_at;
+
+        let mut elect = changed && neighbor.state >= NeighborState::TwoWay;
+        if hello.neighbors.contains(&router_id) {
+            if neighbor.state == NeighborState::Init {
+                neighbor.state = NeighborState::TwoWay;
+                elect = true;
+            }
+        } else if neighbor.state >= NeighborState::TwoWay {
+            neighbor.state = NeighborState::Init;
+            neighbor.retransmit.clear();
+            elect = true;
+        }
+
+        // A neighbor that already acts as backup, or as DR without a backup, ends the wait early
+        if iface.state == InterfaceState::Waiting {
+            let backup_seen = optional(hello.bdr) == Some(id) || (optional(hello.dr) == Some(id) && optional(hello.bdr).is_none());
+            elect = backup_seen;
+        }
+        if elect {
+            self.elect(index);
+        }
+        if is_new {
+            // Answer at once, so the neighbor sees us without waiting a hello interval
+            self.send_hello(index);
+        }
+    }
+
+    fn receive_update(&mut self, index: usize, from: RouterId, lsas: Vec<Lsa>) {
+        let Some(neighbor) = self.interfaces[index].neighbors.get(&from).filter(|neighbor| neighbor.state == NeighborState::Full) else {
+            return;
+        };
+        let mac = neighbor.mac;
+        let mut acks = Vec::new();
+        for lsa in lsas {
+            match self.lsdb.get(&lsa.key).map(|current| current.instance().cmp(&lsa.instance())) {
+                // Nothing to remove
+                None if lsa.is_flushed() => acks.push(lsa.header()),
+                None | Some(Ordering::Less) => {
+                    for iface in &mut self.interfaces {
+                        for neighbor in iface.neighbors.values_mut() {
+                            neighbor.retransmit.retain(|key| *key != lsa.key);
+                        }
+                    }
+                    acks.push(lsa.header());
+                    self.flood(&lsa, Some((index, from)));
+                    self.lsdb.insert(lsa.key, lsa);
+                    self.changed = true;
+                }
+                Some(Ordering::Equal) => {
+                    // The same LSA coming back acknowledges the one we sent
+                    let retransmit = &mut self.interfaces[index].neighbors.get_mut(&from).unwrap().retransmit;
+                    if retransmit.contains(&lsa.key) {
+                        retransmit.retain(|key| *key != lsa.key);
+                    } else {
+                        acks.push(lsa.header());
+                    }
+                }
+                // The neighbor is behind: send it our copy
+                Some(Ordering::Greater) => {
+                    let current = self.lsdb[&lsa.key].clone();
+                    self.send_update(index, from, mac, vec![current]);
+                }
+            }
+        }
+        if !acks.is_empty() {
+            self.send(index, from, mac, OspfPacket::Ack(acks));
+        }
+    }
+
+    fn receive_ack(&mut self, index: usize, from: RouterId, headers: &[LsaHeader]) {
+        let Some(neighbor) = self.interfaces[index].neighbors.get_mut(&from) else {
+            return;
+        };
+        for header in headers {
+            let acknowledged = self.lsdb.get(&header.key).is_some_and(|lsa| lsa.sequence == header.sequence && lsa.age == header.age);
+            if acknowledged {
+                neighbor.retransmit.retain(|key| *key != header.key);
+            }
+        }
+    }
+
+    // Designated and backup designated router election (RFC 2328 section 9.4), then the
+    // neighbors to be adjacent with: every neighbor for the DR and BDR, only those two otherwise
+    fn elect(&mut self, index: usize) {
+        let router_id = self.router_id;
+        for _ in 0..3 {
+            let iface = &self.interfaces[index];
+            // (priority, router ID, declares itself DR, declares itself BDR, interface ID)
+            let mut candidates: Vec<(u8, RouterId, bool, bool, u32)> = iface.neighbors
+                .iter()
+                .filter(|(_, neighbor)| neighbor.state >= NeighborState::TwoWay && neighbor.priority > 0)
+                .map(|(id, neighbor)| (neighbor.priority, *id, neighbor.dr == Some(*id), neighbor.bdr == Some(*id), neighbor.interface_id))
+                .collect();
+            if iface.priority > 0 {
+                let declares_dr = iface.dr.is_some_and(|(dr, _)| dr == router_id);
+                candidates.push((iface.priority, router_id, declares_dr, iface.bdr == Some(router_id), index as u32));
+            }
+            // The backup is chosen among the routers not claiming to be DR, those claiming to
+            // be backup first; without a DR claim the new backup becomes DR
+            let bdr = candidates
+                .iter()
+                .filter(|candidate| !candidate.2)
+                .max_by_key(|candidate| (candidate.3, candidate.0, candidate.1));
+            let dr = candidates
+                .iter()
+                .filter(|candidate| candidate.2)
+                .max_by_key(|candidate| (candidate.0, candidate.1))
+                .or(bdr)
+                .map(|candidate| (candidate.1, candidate.4));
+            let bdr = bdr.map(|candidate| candidate.1);
+
+            // Taking up or giving up a role changes what we claim, so the election runs again
+            let iface = &mut self.interfaces[index];
+            let roles = |dr: Option<(RouterId, u32)>, bdr: Option<RouterId>| (dr.is_some_and(|(dr, _)| dr == router_id), bdr == Some(router_id));
+            let was = roles(iface.dr, iface.bdr);
+            iface.dr = dr;
+            iface.bdr = bdr;
+            if was == roles(dr, bdr) {
+                break;
+            }
+        }
+        let iface = &mut self.interfaces[index];
+        iface.state = if iface.dr.is_some_and(|(dr, _)| dr == router_id) {
+            InterfaceState::Dr
+        } else if iface.bdr == Some(router_id) {
+            InterfaceState::Backup
+        } else {
+            InterfaceState::DrOther
+        };
+
+        let iface = &mut self.interfaces[index];
+        let dr_or_backup = iface.is_dr_or_backup();
+        let (dr, bdr) = (iface.dr.map(|(dr, _)| dr), iface.bdr);
+        let mut adjacent = Vec::new();
+        for (id, neighbor) in &mut iface.neighbors {
+            let should = dr_or_backup || Some(*id) == dr || Some(*id) == bdr;
+            match neighbor.state {
+                NeighborState::TwoWay if should => {
+                    neighbor.state = NeighborState::Full;
+                    adjacent.push((*id, neighbor.mac));
+                }
+                NeighborState::Full if !should => {
+                    neighbor.state = NeighborState::TwoWay;
+                    neighbor.retransmit.clear();
+                }
+                _ => {}
+            }
+        }
+        // Database synchronization: send the new neighbor everything we know
+        for (id, mac) in adjacent {
+            let lsas: Vec<Lsa> = self.lsdb.values().filter(|lsa| !lsa.is_flushed()).cloned().collect();
+            if let Some(neighbor) = self.interfaces[index].neighbors.get_mut(&id) {
+                neighbor.retransmit = lsas.iter().map(|lsa| lsa.key).collect();
+            }
+            self.send_update(index, id, mac, lsas);
+        }
+    }
+
+    // Sends an LSA to the adjacent neighbors on every interface but the one it came from,
+    // unless the DR there takes care of it (RFC 2328 section 13.3)
+    fn flood(&mut self, lsa: &Lsa, from: Option<(usize, RouterId)>) {
+        for index in 0..self.interfaces.len() {
+            let iface = &self.interfaces[index];
+            if iface.passive {
+                continue;
+            }
+            let recipients: Vec<RouterId> = iface.neighbors
+                .iter()
+                .filter(|(id, neighbor)| neighbor.state == NeighborState::Full && from != Some((index, **id)))
+                .map(|(id, _)| *id)
+                .collect();
+            if recipients.is_empty() {
+                continue;
+            }
+            if let Some((from_index, from_id)) = from
+                && from_index == index
+                && (iface.dr.is_some_and(|(dr, _)| dr == from_id) || iface.bdr == Some(from_id) || iface.state == InterfaceState::Backup)
+            {
+                continue;
+            }
+            let dest_ip = if iface.is_dr_or_backup() { ALL_SPF_ROUTERS } else { ALL_D_ROUTERS };
+            let iface = &mut self.interfaces[index];
+            for id in recipients {
+                let retransmit = &mut iface.neighbors.get_mut(&id).unwrap().retransmit;
+                if !retransmit.contains(&lsa.key) {
+                    retransmit.push(lsa.key);
+                }
+            }
+            let message = OspfMessage { router_id: self.router_id, packet: OspfPacket::Update(vec![lsa.clone()]) };
+            self.actions.push(OspfAction::Send { interface: index, dest_ip, dest_mac: MacAddr::ipv4_multicast(dest_ip), message });
+        }
+    }
+
+    // Brings our own router LSA and the network LSAs of the links we are DR on up to date,
+    // then recomputes the routes if anything in the database changed
+    fn originate(&mut self) {
+        let links = self.router_links();
+        self.update_own(LsaKey { kind: LsaKind::Router, id: self.router_id, advertising: self.router_id }, Some(LsaBody::Router(links)));
+        for index in 0..self.interfaces.len() {
+            let iface = &self.interfaces[index];
+            let attached: Vec<RouterId> = iface.neighbors
+                .iter()
+                .filter(|(_, neighbor)| neighbor.state == NeighborState::Full)
+                .map(|(id, _)| *id)
+                .collect();
+            let body = (iface.state == InterfaceState::Dr && !attached.is_empty()).then(|| {
+                let mut routers = vec![self.router_id];
+                routers.extend(attached);
+                routers.sort();
+                LsaBody::Network(routers)
+            });
+            let key = LsaKey { kind: LsaKind::Network, id: Ipv4Addr::from(index as u32), advertising: self.router_id };
+            self.update_own(key, body);
+        }
+        if self.changed {
+            self.changed = false;
+            self.run_spf();
+        }
+    }
+
+    // Originates a new instance of one of our LSAs if its contents changed, or flushes it
+    fn update_own(&mut self, key: LsaKey, body: Option<LsaBody>) {
+        let current = self.lsdb.get(&key);
+        let lsa = match (current, body) {
+            (None, None) => return,
+            (Some(current), None) if current.is_flushed() => return,
+            (Some(current), None) => Lsa { key, sequence: current.sequence.wrapping_add(1), age: MAX_AGE, body: current.body.clone() },
+            (Some(current), Some(body)) if !current.is_flushed() && current.body == body => return,
+            (current, Some(body)) => Lsa {
+                key,
+                sequence: current.map_or(INITIAL_SEQUENCE, |current| current.sequence.wrapping_add(1)),
+                age: 0,
+                body,
+            },
+        };
+        for iface in &mut self.interfaces {
+            for neighbor in iface.neighbors.values_mut() {
+                neighbor.retransmit.retain(|other| *other != key);
+            }
+        }
+        self.flood(&lsa, None);
+        self.lsdb.insert(key, lsa);
+        self.changed = true;
+    }
+
+    fn router_links(&self) -> Vec<RouterLink> {
+        let mut links = Vec::new();
+        for iface in &self.interfaces {
+            let Some((dr, interface_id)) = iface.dr.filter(|_| !iface.passive && iface.state != InterfaceState::Down) else {
+                continue;
+            };
+            let adjacent = if dr == self.router_id {
+                iface.neighbors.values().any(|neighbor| neighbor.state == NeighborState::Full)
+            } else {
+                iface.neighbors.get(&dr).is_some_and(|neighbor| neighbor.state == NeighborState::Full)
+            };
+            if adjacent {
+                links.push(RouterLink::Transit { dr, interface_id, cost: iface.cost });
+            }
+        }
+        for &(network, index) in &self.connected {
+            if let Some(iface) = self.interfaces.get(index).filter(|iface| iface.state != InterfaceState::Down) {
+                links.push(RouterLink::Stub { network, cost: iface.cost });
+            }
+        }
+        links
+    }
+
+    fn valid_lsa(&self, kind: LsaKind, id: Ipv4Addr, advertising: RouterId) -> Option<&LsaBody> {
+        self.lsdb.get(&LsaKey { kind, id, advertising }).filter(|lsa| !lsa.is_flushed()).map(|lsa| &lsa.body)
+    }
+
+    // Neighbors of a vertex in the graph the database describes, counting only links both ends report
+    fn edges(&self, vertex: Vertex) -> Vec<(Vertex, u32)> {
+        match vertex {
+            Vertex::Router(id) => {
+                let Some(LsaBody::Router(links)) = self.valid_lsa(LsaKind::Router, id, id) else {
+                    return Vec::new();
+                };
+                links
+                    .iter()
+                    .filter_map(|link| match *link {
+                        RouterLink::Transit { dr, interface_id, cost } => {
+                            let Some(LsaBody::Network(routers)) = self.valid_lsa(LsaKind::Network, Ipv4Addr::from(interface_id), dr) else {
+                                return None;
+                            };
+                            routers.contains(&id).then_some((Vertex::Network { dr, interface_id }, u32::from(cost)))
+                        }
+                        RouterLink::Stub { .. } => None,
+                    })
+                    .collect()
+            }
+            Vertex::Network { dr, interface_id } => {
+                let Some(LsaBody::Network(routers)) = self.valid_lsa(LsaKind::Network, Ipv4Addr::from(interface_id), dr) else {
+                    return Vec::new();
+                };
+                routers
+                    .iter()
+                    .filter(|router| {
+                        matches!(self.valid_lsa(LsaKind::Router, **router, **router), Some(LsaBody::Router(links))
+                            if links.iter().any(|link| matches!(link, RouterLink::Transit { dr: d, interface_id: i, .. } if *d == dr && *i == interface_id)))
+                    })
+                    .map(|router| (Vertex::Router(*router), 0))
+                    .collect()
+            }
+        }
+    }
+
+    // Dijkstra from this router, then routes to the stub networks of every router reached
+    fn run_spf(&mut self) {
+        let root = Vertex::Router(self.router_id);
+        let mut tentative: BTreeMap<Vertex, TreeNode> = BTreeMap::new();
+        let mut done: BTreeSet<Vertex> = BTreeSet::new();
+        let mut queue = BinaryHeap::new();
+        let mut tree = Vec::new();
+        tentative.insert(root, TreeNode { vertex: root, cost: 0, parent: None, first_hop: None });
+        queue.push(Reverse((0, root)));
+
+        while let Some(Reverse((cost, vertex))) = queue.pop() {
+            if !done.insert(vertex) {
+                continue;
+            }
+            let node = tentative[&vertex];
+            tree.push(node);
+            for (next, link_cost) in self.edges(vertex) {
+                if done.contains(&next) {
+                    continue;
+                }
+                let first_hop = match (node.first_hop, next) {
+                    // A segment this router is on: reached directly through its interface
+                    (None, Vertex::Network { dr, interface_id }) => self.interfaces
+                        .iter()
+                        .position(|iface| iface.state != InterfaceState::Down && iface.dr == Some((dr, interface_id)))
+                        .map(|index| (index, None)),
+                    // A router on such a segment is the next hop
+                    (Some((index, None)), Vertex::Router(id)) => Some((index, Some(id))),
+                    (first_hop, _) => first_hop,
+                };
+                let Some(first_hop) = first_hop else {
+                    continue;
+                };
+                let cost = cost + link_cost;
+                if tentative.get(&next).is_none_or(|other| cost < other.cost) {
+                    tentative.insert(next, TreeNode { vertex: next, cost, parent: Some(vertex), first_hop: Some(first_hop) });
+                    queue.push(Reverse((cost, next)));
+                }
+            }
+        }
+
+        let mut routes: BTreeMap<Ipv4Net, (u32, usize, Ipv4Addr)> = BTreeMap::new();
+        for node in &tree {
+            let (Vertex::Router(id), Some((interface, Some(next_hop)))) = (node.vertex, node.first_hop) else {
+                continue;
+            };
+            let Some(LsaBody::Router(links)) = self.valid_lsa(LsaKind::Router, id, id) else {
+                continue;
+            };
+            for link in links {
+                if let RouterLink::Stub { network, cost } = *link {
+                    let cost = node.cost + u32::from(cost);
+                    if routes.get(&network).is_none_or(|(other, _, _)| cost < *other) {
+                        routes.insert(network, (cost, interface, next_hop));
+                    }
+                }
+            }
+        }
+
+        for network in self.routes.keys() {
+            if !routes.contains_key(network) {
+                self.actions.push(OspfAction::Remove { network: *network });
+            }
+        }
+        for (network, route) in &routes {
+            if self.routes.get(network) != Some(route) {
+                let (_, interface, next_hop) = *route;
+                self.actions.push(OspfAction::Install { network: *network, interface, next_hop });
+            }
+        }
+        self.routes = routes;
+        self.tree = tree;
+    }
+
+    fn send_hello(&mut self, index: usize) {
+        let iface = &self.interfaces[index];
+        if iface.passive || iface.state == InterfaceState::Down {
+            return;
+        }
+        let hello = Hello {
+            interface_id: index as u32,
+            priority: iface.priority,
+            hello_interval: seconds(self.timers.hello) as u16,
+            dead_interval: seconds(self.timers.dead),
+            dr: iface.dr.map_or(Ipv4Addr::UNSPECIFIED, |(dr, _)| dr),
+            bdr: iface.bdr.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            neighbors: iface.neighbors.keys().copied().collect(),
+        };
+        let message = OspfMessage { router_id: self.router_id, packet: OspfPacket::Hello(hello) };
+        let dest_mac = MacAddr::ipv4_multicast(ALL_SPF_ROUTERS);
+        self.actions.push(OspfAction::Send { interface: index, dest_ip: ALL_SPF_ROUTERS, dest_mac, message });
+    }
+
+    fn send_update(&mut self, index: usize, to: RouterId, mac: MacAddr, lsas: Vec<Lsa>) {
+        for chunk in lsas.chunks(LSAS_PER_UPDATE) {
+            self.send(index, to, mac, OspfPacket::Update(chunk.to_vec()));
+        }
+    }
+
+    fn send(&mut self, index: usize, to: RouterId, mac: MacAddr, packet: OspfPacket) {
+        let message = OspfMessage { router_id: self.router_id, packet };
+        self.actions.push(OspfAction::Send { interface: index, dest_ip: to, dest_mac: mac, message });
+    }
+
+    // Interfaces, neighbors, the link-state database and the shortest-path tree
+    pub fn describe(&self, interface_names: &[String], now: Duration) -> String {
+        let name = |index: usize| interface_names.get(index).map_or("?", String::as_str);
+        let id_or_dash = |id: Option<RouterId>| id.map_or_else(|| "-".to_string(), |id| id.to_string());
+        let mut lines = vec![format!(
+            "OSPF: router ID {}, hello {} s, dead {} s, retransmit {} s",
+            self.router_id,
+            self.timers.hello.as_secs_f64(),
+            self.timers.dead.as_secs_f64(),
+            self.timers.retransmit.as_secs_f64()
+        )];
+        lines.push("Interfaces:".to_string());
+        for (index, iface) in self.interfaces.iter().enumerate() {
+            let passive = if iface.passive { " passive" } else { "" };
+            lines.push(format!(
+                "  {:<8} {:<8} cost {:<5} priority {:<3} DR {:<15} BDR {}{passive}",
+                name(index),
+                iface.state.to_string(),
+                iface.cost,
+                iface.priority,
+                id_or_dash(iface.dr.map(|(dr, _)| dr)),
+                id_or_dash(iface.bdr)
+            ));
+        }
+        lines.push("Neighbors:".to_string());
+        for (index, iface) in self.interfaces.iter().enumerate() {
+            for (id, neighbor) in &iface.neighbors {
+                lines.push(format!(
+                    "  {:<15} {:<8} {:<6} priority {:<3} dead in {:.1} s, {} to retransmit",
+                    id.to_string(),
+                    name(index),
+                    neighbor.state.to_string(),
+                    neighbor.priority,
+                    neighbor.dead_at.saturating_sub(now).as_secs_f64(),
+                    neighbor.retransmit.len()
+                ));
+            }
+        }
+        lines.push("Link-state database:".to_string());
+        for lsa in self.lsdb.values() {
+            let flushed = if lsa.is_flushed() { " (flushed)" } else { "" };
+            let contents = match &lsa.body {
+                LsaBody::Router(links) => links
+                    .iter()
+                    .map(|link| match link {
+                        RouterLink::Transit { dr, interface_id, cost } => format!("segment {dr}#{interface_id} cost {cost}"),
+                        RouterLink::Stub { network, cost } => format!("{network} cost {cost}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                LsaBody::Network(routers) => routers.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+            };
+            let what = match lsa.key.kind {
+                LsaKind::Router => format!("router  {}", lsa.key.advertising),
+                LsaKind::Network => format!("network {}#{}", lsa.key.advertising, u32::from(lsa.key.id)),
+            };
+            lines.push(format!("  {what:<26} seq {:#010x}{flushed}: {contents}", lsa.sequence));
+        }
+        lines.push("SPF tree:".to_string());
+        for node in &self.tree {
+            let via = match node.first_hop {
+                None => "(root)".to_string(),
+                Some((index, None)) => format!("direct on {}", name(index)),
+                Some((index, Some(next_hop))) => format!("via {next_hop} on {}", name(index)),
+            };
+            let parent = node.parent.map_or_else(String::new, |parent| format!(", parent {parent}"));
+            lines.push(format!("  {:<30} cost {:<5} {via}{parent}", node.vertex.to_string(), node.cost));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    const FAST_ETHERNET: Option<u64> = Some(100_000_000);
+
+    fn id(n: u8) -> RouterId { Ipv4Addr::new(10, 0, 0, n) }
+
+    fn net(s: &str) -> Ipv4Net { s.parse().unwrap() }
+
+    // Routers joined by broadcast segments of (router, interface) pairs, delivering every
+    // message a router sends until the network is quiet
+    struct Lab {
+        routers: Vec<Ospf>,
+        links: Vec<Vec<Option<u64>>>,
+        connected: Vec<Vec<(Ipv4Net, usize)>>,
+        segments: Vec<Vec<(usize, usize)>>,
+        routes: Vec<BTreeMap<Ipv4Net, (usize, Ipv4Addr)>>,
+    }
+
+    impl Lab {
+        fn new(interface_counts: &[usize], segments: &[&[(usize, usize)]]) -> Self {
+            Self {
+                routers: (0..interface_counts.len())
+                    .map(|index| Ospf::new(id(index as u8 + 1), OspfTimers::default(), interface_counts[index]))
+                    .collect(),
+                links: interface_counts.iter().map(|&count| vec![FAST_ETHERNET; count]).collect(),
+                connected: vec![Vec::new(); interface_counts.len()],
+                segments: segments.iter().map(|segment| segment.to_vec()).collect(),
+                routes: vec![BTreeMap::new(); interface_counts.len()],
+            }
+        }
+
+        fn mac(router: usize, interface: usize) -> MacAddr { MacAddr::from([0xAA, 0, 0, 0, router as u8, interface as u8]) }
+
+        fn deliver(&mut self, router: usize, actions: Vec<OspfAction>, now: Duration) {
+            let mut queue = VecDeque::from([(router, actions)]);
+            while let Some((router, actions)) = queue.pop_front() {
+                for action in actions {
+                    match action {
+                        OspfAction::Send { interface, dest_ip, message, .. } => {
+                            let Some(segment) = self.segments.iter().find(|segment| segment.contains(&(router, interface))) else {
+                                continue;
+                            };
+                            for &(other, other_interface) in segment {
+                                let multicast = dest_ip == ALL_SPF_ROUTERS || dest_ip == ALL_D_ROUTERS;
+                                if other != router && (multicast || dest_ip == self.routers[other].router_id) {
+                                    let src_mac = Self::mac(router, interface);
+                                    let replies = self.routers[other].receive(other_interface, src_mac, dest_ip, message.clone(), now);
+                                    queue.push_back((other, replies));
+                                }
+                            }
+                        }
+                        OspfAction::Install { network, interface, next_hop } => {
+                            self.routes[router].insert(network, (interface, next_hop));
+                        }
+                        OspfAction::Remove { network } => {
+                            self.routes[router].remove(&network);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Runs every router's hello timer until `until`, answering acknowledgements on the way
+        fn run(&mut self, from: u64, until: u64) {
+            for second in (from..=until).step_by(5) {
+                let now = Duration::from_secs(second);
+                for router in 0..self.routers.len() {
+                    let actions = if second % 10 == 0 {
+                        self.routers[router].tick(&self.links[router], &self.connected[router], now)
+                    } else {
+                        self.routers[router].retransmit()
+                    };
+                    self.deliver(router, actions, now);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn highest_router_ids_become_dr_and_backup() {
+        let mut lab = Lab::new(&[1, 1, 1], &[&[(0, 0), (1, 0), (2, 0)]]);
+        lab.run(0, 60);
+        let states: Vec<InterfaceState> = lab.routers.iter().map(|ospf| ospf.interfaces[0].state).collect();
+        assert_eq!(states, [InterfaceState::DrOther, InterfaceState::Backup, InterfaceState::Dr]);
+        for ospf in &lab.routers {
+            assert_eq!(ospf.interfaces[0].dr, Some((id(3), 0)));
+            assert_eq!(ospf.interfaces[0].bdr, Some(id(2)));
+        }
+        // Others are only adjacent with the DR and backup
+        let neighbors = &lab.routers[0].interfaces[0].neighbors;
+        assert_eq!(neighbors[&id(2)].state, NeighborState::Full);
+        assert_eq!(neighbors[&id(3)].state, NeighborState::Full);
+        assert_eq!(lab.routers[1].interfaces[0].neighbors[&id(1)].state, NeighborState::Full);
+    }
+
+    #[test]
+    fn priority_outranks_router_id() {
+        let mut lab = Lab::new(&[1, 1, 1], &[&[(0, 0), (1, 0), (2, 0)]]);
+        lab.routers[0].set_priority(0, 10);
+        lab.routers[2].set_priority(0, 0);
+        lab.run(0, 60);
+        assert_eq!(lab.routers[0].interfaces[0].state, InterfaceState::Dr);
+        assert_eq!(lab.routers[1].interfaces[0].state, InterfaceState::Backup);
+        assert_eq!(lab.routers[2].interfaces[0].state, InterfaceState::DrOther);
+    }
+
+    // Router 1 reaches router 2's network directly at cost 10, or through router 3 at cost 1 + 1
+    fn triangle() -> Lab {
+        let mut lab = Lab::new(&[2, 3, 2], &[&[(0, 0), (1, 0)], &[(0, 1), (2, 0)], &[(1, 1), (2, 1)]]);
+        lab.routers[0].set_cost(0, Some(10));
+        lab.connected[1] = vec![(net("10.9.0.0/24"), 2)];
+        lab
+    }
+
+    #[test]
+    fn lsas_are_flooded_and_acknowledged() {
+        let mut lab = triangle();
+        lab.run(0, 60);
+        for ospf in &lab.routers[1..] {
+            assert_eq!(ospf.lsdb, lab.routers[0].lsdb);
+        }
+        // Two router LSAs and one network LSA per segment
+        assert_eq!(lab.routers[0].lsdb.len(), 3 + 3);
+        let unacknowledged = lab.routers
+            .iter()
+            .flat_map(|ospf| ospf.interfaces.iter())
+            .flat_map(|iface| iface.neighbors.values())
+            .map(|neighbor| neighbor.retransmit.len())
+            .sum::<usize>();
+        assert_eq!(unacknowledged, 0);
+    }
+
+    #[test]
+    fn spf_prefers_the_cheaper_path() {
+        let mut lab = triangle();
+        lab.run(0, 60);
+        assert_eq!(lab.routes[0].get(&net("10.9.0.0/24")), Some(&(1, id(3))));
+        assert_eq!(lab.routes[2].get(&net("10.9.0.0/24")), Some(&(1, id(2))));
+
+        // Without router 3's link, the expensive direct path is all that is left
+        lab.links[0][1] = None;
+        lab.run(70, 70);
+        assert_eq!(lab.routes[0].get(&net("10.9.0.0/24")), Some(&(0, id(2))));
+    }
+
+    #[test]
+    fn ospf_update_round_trip() {
+        let router_id = Ipv4Addr::new(10, 0, 2, 1);
+        let router = Lsa {
+            key: LsaKey { kind: LsaKind::Router, id: router_id, advertising: router_id },
+            sequence: 0x8000_0002,
+            age: 0,
+            body: LsaBody::Router(vec![
+                RouterLink::Transit { dr: router_id, interface_id: 1, cost: 10 },
+                RouterLink::Stub { network: "10.0.2.0/24".parse().unwrap(), cost: 1 },
+            ]),
+        };
+        let network = Lsa {
+            key: LsaKey { kind: LsaKind::Network, id: Ipv4Addr::from(1), advertising: router_id },
+            sequence: 0x8000_0001,
+            age: 0,
+            body: LsaBody::Network(vec![Ipv4Addr::new(10, 0, 1, 1), router_id]),
+        };
+        let message = OspfMessage { router_id, packet: OspfPacket::Update(vec![router, network.clone()]) };
+        let mut packet = Packet::ospf(router_id, ALL_SPF_ROUTERS, &message);
+        packet.src_mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x12]);
+        packet.dest_mac = MacAddr::ipv4_multicast(ALL_SPF_ROUTERS);
+        let bytes = packet.to_bytes();
+        assert_eq!(&bytes[..6], &[0x01, 0x00, 0x5E, 0x00, 0x00, 0x05]);
+        assert_eq!(bytes[23], 89);
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.ospf_message(), Some(message.clone()));
+
+        // A flipped bit in the first LSA fails its own checksum even with the packet's fixed up
+        let mut corrupted = decoded;
+        corrupted.data[24 + 4 + 22] ^= 1;
+        corrupted.data[12..14].copy_from_slice(&[0, 0]);
+        let checksum = internet_checksum(&corrupted.data);
+        corrupted.data[12..14].copy_from_slice(&checksum.to_be_bytes());
+        let expected = OspfMessage { router_id, packet: OspfPacket::Update(vec![network]) };
+        assert_eq!(corrupted.ospf_message(), Some(expected));
+    }
+}
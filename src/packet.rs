@@ -1,40 +1,500 @@
-#[derive(Debug, Clone)]
+use std::fmt;
+use std::net::Ipv4Addr;
+use crate::address::MacAddr;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_VLAN: u16 = 0x8100; // IEEE 802.1Q tag
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const LLC_STP: [u8; 3] = [0x42, 0x42, 0x03]; // DSAP, SSAP and control of spanning tree BPDUs
+const MAX_LENGTH_FIELD: u16 = 1500;           // Larger values in the EtherType position are EtherTypes
+const ARP_LEN: usize = 28;
+const IPV4_HEADER_LEN: usize = 20;
+const DEFAULT_TTL: u8 = 64;
+pub const PROTOCOL_EXPERIMENTAL: u8 = 253; // RFC 3692, payloads without a real protocol header
+
+// IPv4 header fields that are not derived from the rest of the packet.
+// Version, IHL, total length and checksum are computed when encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub tos: u8,
+    pub identification: u16,
+    pub flags: u8,            // 3 bits: reserved, don't fragment, more fragments
+    pub fragment_offset: u16, // 13 bits, in units of 8 bytes
+    pub ttl: u8,
+    pub protocol: u8,
+}
+
+impl Default for Ipv4Header {
+    fn default() -> Self {
+        Self {
+            tos: 0,
+            identification: 0,
+            flags: 0,
+            fragment_offset: 0,
+            ttl: DEFAULT_TTL,
+            protocol: PROTOCOL_EXPERIMENTAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOp {
+    Request,
+    Reply,
+}
+
+// ARP fields that are not derived from the rest of the packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpHeader {
+    pub operation: ArpOp,
+    pub target_mac: MacAddr, // Zero in requests, which do not know it yet
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Packet {
-    pub src_mac: String,
-    pub dest_mac: String,
-    pub src_ip: String,
-    pub dest_ip: String,
+    pub src_mac: MacAddr,
+    pub dest_mac: MacAddr,
+    pub src_ip: Ipv4Addr,
+    pub dest_ip: Ipv4Addr,
     pub data: Vec<u8>,
     pub is_arp: bool,
+    pub header: Ipv4Header, // Unused for ARP
+    pub arp: ArpHeader,     // Unused unless is_arp
+    pub vlan: Option<u16>,  // 802.1Q VLAN ID, None for untagged frames
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated { needed: usize, available: usize },
+    UnsupportedEtherType(u16),
+    UnsupportedArp,        // Not Ethernet/IPv4 ARP, or an unknown operation
+    UnsupportedVersion(u8),
+    InvalidHeaderLength(u8),
+    InvalidTotalLength(u16),
+    BadChecksum { expected: u16, found: u16 },
 }
 
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, available } => {
+                write!(f, "frame truncated: needed {needed} bytes, got {available}")
+            }
+            DecodeError::UnsupportedEtherType(ether_type) => write!(f, "unsupported EtherType 0x{ether_type:04X}"),
+            DecodeError::UnsupportedArp => write!(f, "unsupported ARP hardware, protocol or operation"),
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported IP version {version}"),
+            DecodeError::InvalidHeaderLength(ihl) => write!(f, "invalid IPv4 header length {ihl}"),
+            DecodeError::InvalidTotalLength(length) => write!(f, "invalid IPv4 total length {length}"),
+            DecodeError::BadChecksum { expected, found } => {
+                write!(f, "bad IPv4 header checksum 0x{found:04X}, expected 0x{expected:04X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 impl Packet {
+    // IPv4 datagram, or a BPDU when sent to the bridge group address
     pub fn new(
-        src_mac: &str,
-        dest_mac: &str,
-        src_ip: &str,
-        dest_ip: &str,
-        data: Vec<u8>,
-        is_arp: bool
+        src_mac: MacAddr,
+        dest_mac: MacAddr,
+        src_ip: Ipv4Addr,
+        dest_ip: Ipv4Addr,
+        data: Vec<u8>
     ) -> Self {
         Self {
-            src_mac: src_mac.to_string(),
-            dest_mac: dest_mac.to_string(),
-            src_ip: src_ip.to_string(),
-            dest_ip: dest_ip.to_string(),
+            src_mac,
+            dest_mac,
+            src_ip,
+            dest_ip,
             data,
-            is_arp,
+            is_arp: false,
+            header: Ipv4Header::default(),
+            arp: ArpHeader { operation: ArpOp::Request, target_mac: MacAddr::ZERO },
+            vlan: None,
+        }
+    }
+
+    // ARP request for the MAC address of `target_ip`, broadcast or sent straight to a cached address
+    pub fn arp_request(src_mac: MacAddr, dest_mac: MacAddr, src_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        Self::arp(src_mac, dest_mac, src_ip, target_ip, ArpHeader { operation: ArpOp::Request, target_mac: MacAddr::ZERO })
+    }
+
+    // ARP reply giving our address to the device at `dest_mac` and `dest_ip`
+    pub fn arp_reply(src_mac: MacAddr, dest_mac: MacAddr, src_ip: Ipv4Addr, dest_ip: Ipv4Addr) -> Self {
+        Self::arp(src_mac, dest_mac, src_ip, dest_ip, ArpHeader { operation: ArpOp::Reply, target_mac: dest_mac })
+    }
+
+    fn arp(src_mac: MacAddr, dest_mac: MacAddr, src_ip: Ipv4Addr, dest_ip: Ipv4Addr, arp: ArpHeader) -> Self {
+        Self { is_arp: true, arp, ..Self::new(src_mac, dest_mac, src_ip, dest_ip, Vec::new()) }
+    }
+
+    // Ethernet header length, including the 802.1Q tag if the frame has one
+    pub fn ethernet_header_len(&self) -> usize {
+        ETHERNET_HEADER_LEN + if self.vlan.is_some() { VLAN_TAG_LEN } else { 0 }
+    }
+
+    // Copy of the frame with the VLAN tag replaced, or removed with None
+    pub fn with_vlan(&self, vlan: Option<u16>) -> Self {
+        Self { vlan, ..self.clone() }
+    }
+
+    // Spanning tree BPDUs travel in 802.3 frames with an LLC header instead of Ethernet II,
+    // and the data holds the BPDU. They are told apart by their destination address.
+    pub fn is_bpdu(&self) -> bool {
+        !self.is_arp && self.dest_mac == MacAddr::BRIDGE_GROUP
+    }
+
+    // Size of the frame on the wire in bytes (Ethernet header plus ARP, IPv4 or LLC header and payload)
+    pub fn size(&self) -> usize {
+        let l3 = if self.is_arp {
+            ARP_LEN
+        } else if self.is_bpdu() {
+            LLC_STP.len() + self.data.len()
+        } else {
+            IPV4_HEADER_LEN + self.data.len()
+        };
+        self.ethernet_header_len() + l3
+    }
+
+    // Ethernet II frame carrying the ARP message or IPv4 datagram, as it would appear on the wire
+    // (without preamble, padding or FCS, like a capture shows it)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(&self.dest_mac.octets());
+        bytes.extend_from_slice(&self.src_mac.octets());
+        if let Some(vlan) = self.vlan {
+            // Priority and drop eligible bits are left at zero
+            bytes.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            bytes.extend_from_slice(&(vlan & 0x0FFF).to_be_bytes());
+        }
+
+        if self.is_arp {
+            let operation: u16 = match self.arp.operation {
+                ArpOp::Request => 1,
+                ArpOp::Reply => 2,
+            };
+            bytes.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+            bytes.extend_from_slice(&1u16.to_be_bytes()); // Hardware type: Ethernet
+            bytes.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            bytes.extend_from_slice(&[6, 4]); // Hardware and protocol address lengths
+            bytes.extend_from_slice(&operation.to_be_bytes());
+            bytes.extend_from_slice(&self.src_mac.octets());
+            bytes.extend_from_slice(&self.src_ip.octets());
+            bytes.extend_from_slice(&self.arp.target_mac.octets());
+            bytes.extend_from_slice(&self.dest_ip.octets());
+            return bytes;
+        }
+
+        if self.is_bpdu() {
+            bytes.extend_from_slice(&((LLC_STP.len() + self.data.len()) as u16).to_be_bytes());
+            bytes.extend_from_slice(&LLC_STP);
+            bytes.extend_from_slice(&self.data);
+            return bytes;
+        }
+
+        bytes.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let header_start = bytes.len();
+        let total_length = (IPV4_HEADER_LEN + self.data.len()) as u16;
+        let flags_fragment = (u16::from(self.header.flags & 0x7) << 13) | (self.header.fragment_offset & 0x1FFF);
+        bytes.extend_from_slice(&[0x45, self.header.tos]); // Version 4, 5 word header
+        bytes.extend_from_slice(&total_length.to_be_bytes());
+        bytes.extend_from_slice(&self.header.identification.to_be_bytes());
+        bytes.extend_from_slice(&flags_fragment.to_be_bytes());
+        bytes.extend_from_slice(&[self.header.ttl, self.header.protocol, 0, 0]);
+        bytes.extend_from_slice(&self.src_ip.octets());
+        bytes.extend_from_slice(&self.dest_ip.octets());
+        let checksum = internet_checksum(&bytes[header_start..]);
+        bytes[header_start + 10..header_start + 12].copy_from_slice(&checksum.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    // Parses an Ethernet II frame with an ARP message or IPv4 datagram, optionally 802.1Q tagged.
+    // Trailing bytes (e.g. Ethernet padding) are ignored, and IPv4 options are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        need(bytes, ETHERNET_HEADER_LEN)?;
+        if u16_at(bytes, 12) == ETHERTYPE_VLAN {
+            need(bytes, ETHERNET_HEADER_LEN + VLAN_TAG_LEN)?;
+            let vlan = u16_at(bytes, 14) & 0x0FFF;
+            let mut untagged = bytes[..12].to_vec();
+            untagged.extend_from_slice(&bytes[16..]);
+            let mut packet = Self::from_bytes(&untagged)?;
+            if packet.vlan.is_some() {
+                // Stacked (Q-in-Q) tags are not modelled
+                return Err(DecodeError::UnsupportedEtherType(ETHERTYPE_VLAN));
+            }
+            packet.vlan = Some(vlan);
+            return Ok(packet);
+        }
+        let dest_mac = mac_at(bytes, 0);
+        let src_mac = mac_at(bytes, 6);
+        let ether_type = u16_at(bytes, 12);
+        let l3 = &bytes[ETHERNET_HEADER_LEN..];
+
+        match ether_type {
+            // 802.3 length field: only spanning tree BPDUs are understood
+            length if length <= MAX_LENGTH_FIELD => {
+                need(l3, usize::from(length))?;
+                if usize::from(length) < LLC_STP.len() || l3[..LLC_STP.len()] != LLC_STP || dest_mac != MacAddr::BRIDGE_GROUP {
+                    return Err(DecodeError::UnsupportedEtherType(length));
+                }
+                let data = l3[LLC_STP.len()..usize::from(length)].to_vec();
+                Ok(Self::new(src_mac, dest_mac, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, data))
+            }
+            ETHERTYPE_ARP => {
+                need(l3, ARP_LEN)?;
+                let operation = match u16_at(l3, 6) {
+                    1 => ArpOp::Request,
+                    2 => ArpOp::Reply,
+                    _ => return Err(DecodeError::UnsupportedArp),
+                };
+                if u16_at(l3, 0) != 1 || u16_at(l3, 2) != ETHERTYPE_IPV4 || l3[4] != 6 || l3[5] != 4 {
+                    return Err(DecodeError::UnsupportedArp);
+                }
+                let arp = ArpHeader { operation, target_mac: mac_at(l3, 18) };
+                Ok(Self::arp(src_mac, dest_mac, ipv4_at(l3, 14), ipv4_at(l3, 24), arp))
+            }
+            ETHERTYPE_IPV4 => {
+                need(l3, IPV4_HEADER_LEN)?;
+                let version = l3[0] >> 4;
+                if version != 4 {
+                    return Err(DecodeError::UnsupportedVersion(version));
+                }
+                let ihl = l3[0] & 0xF;
+                let header_len = usize::from(ihl) * 4;
+                if ihl < 5 {
+                    return Err(DecodeError::InvalidHeaderLength(ihl));
+                }
+                need(l3, header_len)?;
+                let total_length = u16_at(l3, 2);
+                if usize::from(total_length) < header_len {
+                    return Err(DecodeError::InvalidTotalLength(total_length));
+                }
+                need(l3, usize::from(total_length))?;
+
+                // A valid header sums to zero, including its own checksum
+                if internet_checksum(&l3[..header_len]) != 0 {
+                    let mut header = l3[..header_len].to_vec();
+                    header[10..12].fill(0);
+                    return Err(DecodeError::BadChecksum {
+                        expected: internet_checksum(&header),
+                        found: u16_at(l3, 10),
+                    });
+                }
+
+                let flags_fragment = u16_at(l3, 6);
+                let mut packet = Self::new(
+                    src_mac,
+                    dest_mac,
+                    ipv4_at(l3, 12),
+                    ipv4_at(l3, 16),
+                    l3[header_len..usize::from(total_length)].to_vec(),
+                );
+                packet.header = Ipv4Header {
+                    tos: l3[1],
+                    identification: u16_at(l3, 4),
+                    flags: (flags_fragment >> 13) as u8,
+                    fragment_offset: flags_fragment & 0x1FFF,
+                    ttl: l3[8],
+                    protocol: l3[9],
+                };
+                Ok(packet)
+            }
+            other => Err(DecodeError::UnsupportedEtherType(other)),
         }
     }
 
-    pub fn rebuild_L3(&self, src_mac: String, dest_mac: String) -> Self {
+    pub fn rebuild_l3(&self, src_mac: MacAddr, dest_mac: MacAddr) -> Self {
         Self {
             src_mac,
             dest_mac,
-            src_ip: self.src_ip.clone(),
-            dest_ip: self.dest_ip.clone(),
+            src_ip: self.src_ip,
+            dest_ip: self.dest_ip,
             data: self.data.clone(),
             is_arp: self.is_arp,
+            header: self.header,
+            arp: self.arp,
+            vlan: None, // Tagged again by the port it leaves through
         }
     }
 }
+
+// RFC 1071 ones' complement sum of 16-bit words
+pub fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u64 = bytes
+        .chunks(2)
+        .map(|chunk| u64::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn need(bytes: &[u8], needed: usize) -> Result<(), DecodeError> {
+    if bytes.len() < needed {
+        return Err(DecodeError::Truncated { needed, available: bytes.len() });
+    }
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn mac_at(bytes: &[u8], offset: usize) -> MacAddr {
+    let mut octets = [0; 6];
+    octets.copy_from_slice(&bytes[offset..offset + 6]);
+    MacAddr::from(octets)
+}
+
+fn ipv4_at(bytes: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(s: &str) -> MacAddr { s.parse().unwrap() }
+
+    fn ipv4_packet() -> Packet {
+        Packet::new(
+            mac("AA:BB:CC:DD:01:0A"),
+            mac("AA:BB:CC:DD:EE:11"),
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(192, 168, 5, 18),
+            b"hello, network".to_vec(),
+        )
+    }
+
+    #[test]
+    fn ipv4_round_trip() {
+        let mut packet = ipv4_packet();
+        packet.header = Ipv4Header {
+            tos: 0xB8,
+            identification: 0x1C46,
+            flags: 0b010,
+            fragment_offset: 0x123,
+            ttl: 17,
+            protocol: 17,
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), packet.size());
+        assert_eq!(Packet::from_bytes(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn ipv4_header_layout() {
+        let bytes = ipv4_packet().to_bytes();
+        assert_eq!(&bytes[12..14], &[0x08, 0x00]);
+        let header = &bytes[14..34];
+        assert_eq!(header[0], 0x45);
+        assert_eq!(u16_at(header, 2), 20 + 14);
+        assert_eq!(header[8], DEFAULT_TTL);
+        assert_eq!(&header[12..16], &[192, 168, 1, 10]);
+        assert_eq!(&header[16..20], &[192, 168, 5, 18]);
+        assert_eq!(internet_checksum(header), 0);
+    }
+
+    #[test]
+    fn checksum_matches_known_header() {
+        // Example header with checksum 0xB861
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0x00, 0x00, 0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7,
+        ];
+        assert_eq!(internet_checksum(&header), 0xB861);
+    }
+
+    #[test]
+    fn arp_request_round_trip() {
+        let request = Packet::arp_request(
+            mac("AA:BB:CC:DD:01:0A"),
+            MacAddr::BROADCAST,
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(192, 168, 1, 1),
+        );
+        let bytes = request.to_bytes();
+        assert_eq!(bytes.len(), 42);
+        assert_eq!(u16_at(&bytes, 20), 1); // Operation: request
+        assert_eq!(&bytes[32..38], &[0; 6]); // Target MAC unknown
+        assert_eq!(Packet::from_bytes(&bytes), Ok(request));
+    }
+
+    #[test]
+    fn arp_reply_round_trip() {
+        let reply = Packet::arp_reply(
+            mac("AA:BB:CC:DD:EE:11"),
+            mac("AA:BB:CC:DD:01:0A"),
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 10),
+        );
+        let bytes = reply.to_bytes();
+        assert_eq!(u16_at(&bytes, 20), 2); // Operation: reply
+        assert_eq!(Packet::from_bytes(&bytes), Ok(reply));
+    }
+
+    #[test]
+    fn unicast_arp_request_round_trip() {
+        // A request sent straight to a cached address to confirm it is still there
+        let request = Packet::arp_request(
+            mac("AA:BB:CC:DD:01:0A"),
+            mac("AA:BB:CC:DD:EE:11"),
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(192, 168, 1, 1),
+        );
+        let bytes = request.to_bytes();
+        assert_eq!(u16_at(&bytes, 20), 1); // Operation: request
+        assert_eq!(Packet::from_bytes(&bytes), Ok(request));
+    }
+
+    #[test]
+    fn ethernet_padding_is_ignored() {
+        let packet = ipv4_packet();
+        let mut bytes = packet.to_bytes();
+        bytes.resize(bytes.len() + 10, 0);
+        assert_eq!(Packet::from_bytes(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn corrupted_header_is_rejected() {
+        let mut bytes = ipv4_packet().to_bytes();
+        bytes[22] ^= 0x01; // Flip a bit in the TTL
+        assert!(matches!(Packet::from_bytes(&bytes), Err(DecodeError::BadChecksum { .. })));
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let bytes = ipv4_packet().to_bytes();
+        assert!(matches!(Packet::from_bytes(&bytes[..10]), Err(DecodeError::Truncated { .. })));
+        assert!(matches!(Packet::from_bytes(&bytes[..30]), Err(DecodeError::Truncated { .. })));
+        assert!(matches!(Packet::from_bytes(&bytes[..bytes.len() - 1]), Err(DecodeError::Truncated { .. })));
+    }
+
+    #[test]
+    fn bpdu_frame_round_trip() {
+        let bpdu = Packet::new(
+            mac("02:00:00:00:00:01"),
+            MacAddr::BRIDGE_GROUP,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            vec![0, 0, 0, 0x80],
+        );
+        let bytes = bpdu.to_bytes();
+        assert_eq!(bytes.len(), bpdu.size());
+        assert_eq!(&bytes[12..17], &[0x00, 0x07, 0x42, 0x42, 0x03]);
+        assert_eq!(Packet::from_bytes(&bytes), Ok(bpdu));
+    }
+
+    #[test]
+    fn unknown_ether_type_is_rejected() {
+        let mut bytes = ipv4_packet().to_bytes();
+        bytes[12..14].copy_from_slice(&0x86DDu16.to_be_bytes());
+        assert_eq!(Packet::from_bytes(&bytes), Err(DecodeError::UnsupportedEtherType(0x86DD)));
+    }
+}
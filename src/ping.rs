@@ -0,0 +1,201 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingOptions {
+    pub count: usize,
+    pub interval: Duration, // Between echo requests
+    pub size: usize,        // Payload bytes per echo request
+    pub timeout: Duration,  // How long to wait for each reply
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            interval: Duration::from_secs(1),
+            size: 56,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub seq: u16,
+    pub rtt_ms: Option<f64>,       // None if no reply came back in time
+    pub error: Option<String>,     // ICMP error received instead of a reply
+}
+
+// Summary of a ping, like the last lines of the ping command's output
+#[derive(Debug, Clone, Serialize)]
+pub struct PingReport {
+    pub destination: String,
+    pub transmitted: usize,
+    pub received: usize,
+    pub loss_percent: f64,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+    pub finished: bool,
+    pub probes: Vec<ProbeResult>,
+}
+
+// State of one ping run by a host, identified by its ICMP echo identifier
+#[derive(Debug, Clone)]
+pub struct PingSession {
+    pub dest_ip: Ipv4Addr,
+    pub options: PingOptions,
+    sent: Vec<Duration>, // Send time of each probe, seq numbers start at 1
+    results: Vec<ProbeResult>,
+    pub finished: bool,
+}
+
+impl PingSession {
+    pub fn new(dest_ip: Ipv4Addr, options: PingOptions) -> Self {
+        Self {
+            dest_ip,
+            options,
+            sent: Vec::new(),
+            results: Vec::new(),
+            finished: false,
+        }
+    }
+
+    // Sequence number for the next probe, None once all probes are out
+    pub fn next_seq(&self) -> Option<u16> {
+        (self.sent.len() < self.options.count).then(|| self.sent.len() as u16 + 1)
+    }
+
+    pub fn record_sent(&mut self, seq: u16, time: Duration) {
+        self.sent.push(time);
+        self.results.push(ProbeResult { seq, rtt_ms: None, error: None });
+    }
+
+    // Returns the round-trip time, or None for unknown, duplicate or late replies
+    pub fn record_reply(&mut self, seq: u16, now: Duration) -> Option<Duration> {
+        let index = usize::from(seq).checked_sub(1)?;
+        let sent = *self.sent.get(index)?;
+        let result = &mut self.results[index];
+        let rtt = now.checked_sub(sent)?;
+        if result.rtt_ms.is_some() || result.error.is_some() || rtt > self.options.timeout {
+            return None;
+        }
+        result.rtt_ms = Some(rtt.as_secs_f64() * 1e3);
+        Some(rtt)
+    }
+
+    pub fn record_error(&mut self, seq: u16, error: String) {
+        if let Some(result) = usize::from(seq).checked_sub(1).and_then(|index| self.results.get_mut(index))
+            && result.rtt_ms.is_none()
+        {
+            result.error = Some(error);
+        }
+    }
+
+    pub fn report(&self) -> PingReport {
+        let rtts: Vec<f64> = self.results.iter().filter_map(|result| result.rtt_ms).collect();
+        let transmitted = self.results.len();
+        let received = rtts.len();
+        PingReport {
+            destination: self.dest_ip.to_string(),
+            transmitted,
+            received,
+            loss_percent: if transmitted == 0 { 0.0 } else { 100.0 * (transmitted - received) as f64 / transmitted as f64 },
+            rtt_min_ms: rtts.iter().copied().reduce(f64::min),
+            rtt_avg_ms: (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / received as f64),
+            rtt_max_ms: rtts.iter().copied().reduce(f64::max),
+            finished: self.finished,
+            probes: self.results.clone(),
+        }
+    }
+}
+
+impl fmt::Display for PingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "PING {}", self.destination)?;
+        for probe in &self.probes {
+            match (&probe.rtt_ms, &probe.error) {
+                (Some(rtt), _) => writeln!(f, "  seq={} time={rtt:.3} ms", probe.seq)?,
+                (None, Some(error)) => writeln!(f, "  seq={} {error}", probe.seq)?,
+                (None, None) if self.finished => writeln!(f, "  seq={} timeout", probe.seq)?,
+                (None, None) => writeln!(f, "  seq={} waiting", probe.seq)?,
+            }
+        }
+        write!(
+            f,
+            "{} packets transmitted, {} received, {:.1}% packet loss",
+            self.transmitted, self.received, self.loss_percent
+        )?;
+        if let (Some(min), Some(avg), Some(max)) = (self.rtt_min_ms, self.rtt_avg_ms, self.rtt_max_ms) {
+            write!(f, "\nrtt min/avg/max = {min:.3}/{avg:.3}/{max:.3} ms")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::address::MacAddr;
+    use crate::device::DeviceRef;
+    use crate::host::Host;
+    use crate::link::{Link, LinkConfig};
+    use crate::sim::Simulator;
+
+    #[test]
+    fn lost_probes_count_as_loss_and_not_in_the_rtts() {
+        let mut sim = Simulator::new();
+        let (a_mac, b_mac) = (MacAddr::from([0xAA, 0, 0, 0, 0, 1]), MacAddr::from([0xAA, 0, 0, 0, 0, 2]));
+        let (a_ip, b_ip) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let a = Rc::new(RefCell::new(Host::new("10.0.0.1/24".parse().unwrap(), a_mac)));
+        let b = Rc::new(RefCell::new(Host::new("10.0.0.2/24".parse().unwrap(), b_mac)));
+        a.borrow_mut().add_arp_entry(b_ip, b_mac);
+        b.borrow_mut().add_arp_entry(a_ip, a_mac);
+        let (a_ref, b_ref): (DeviceRef, DeviceRef) = (a.clone(), b.clone());
+        let config = LinkConfig { loss: 0.3, delay: Duration::from_millis(5), ..LinkConfig::default() };
+        let link = Link::connect(&a_ref, 0, &b_ref, 0, config);
+
+        let options = PingOptions { count: 20, interval: Duration::from_millis(100), ..PingOptions::default() };
+        let id = sim.with_device(&a_ref, |sim| a.borrow_mut().start_ping(b_ip, options, sim));
+        while sim.step() {}
+        let report = a.borrow().ping_report(id).unwrap();
+
+        // Each lost probe is one dropped frame, the request or its reply
+        let dropped = link.borrow().frames_dropped;
+        assert!(report.finished);
+        assert_eq!(report.transmitted, 20);
+        assert!(dropped > 0 && dropped < 20, "{dropped} dropped");
+        assert_eq!(report.received, 20 - dropped);
+        assert_eq!(report.loss_percent, 100.0 * dropped as f64 / 20.0);
+        assert_eq!(report.probes.iter().filter(|probe| probe.rtt_ms.is_some()).count(), report.received);
+
+        // Replies come back after two link delays and a little serialization
+        let rtts: Vec<f64> = report.probes.iter().filter_map(|probe| probe.rtt_ms).collect();
+        assert!(rtts.iter().all(|rtt| (10.0..10.01).contains(rtt)), "{rtts:?}");
+        assert_eq!(report.rtt_min_ms, rtts.iter().copied().reduce(f64::min));
+        assert_eq!(report.rtt_max_ms, rtts.iter().copied().reduce(f64::max));
+        assert_eq!(report.rtt_avg_ms, Some(rtts.iter().sum::<f64>() / rtts.len() as f64));
+    }
+
+    #[test]
+    fn late_and_duplicate_replies_are_not_counted() {
+        let mut session = PingSession::new(Ipv4Addr::new(10, 0, 0, 2), PingOptions::default());
+        for seq in 1..=3 {
+            session.record_sent(seq, Duration::from_secs(u64::from(seq)));
+        }
+        assert_eq!(session.record_reply(1, Duration::from_millis(1_020)), Some(Duration::from_millis(20)));
+        assert_eq!(session.record_reply(1, Duration::from_millis(1_030)), None);
+        assert_eq!(session.record_reply(2, Duration::from_millis(3_500)), None); // After the 1 s timeout
+        session.record_error(3, "Destination Host Unreachable".to_string());
+        assert_eq!(session.record_reply(4, Duration::from_secs(4)), None);
+        let report = session.report();
+        assert_eq!((report.transmitted, report.received), (3, 1));
+        assert_eq!(report.rtt_avg_ms, Some(20.0));
+        assert_eq!(report.probes[2].error.as_deref(), Some("Destination Host Unreachable"));
+    }
+}
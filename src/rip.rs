@@ -0,0 +1,566 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::Ipv4Net;
+use crate::packet::Packet;
+use crate::udp::UdpDatagram;
+
+pub const RIP_PORT: u16 = 520;
+// Group address RIPv2 updates are sent to (RFC 2453)
+pub const RIP_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 9);
+// Metric of unreachable networks
+pub const INFINITY: u32 = 16;
+// Metric of directly connected networks, and what every router on the way adds
+const HOP_COST: u32 = 1;
+
+const COMMAND_REQUEST: u8 = 1;
+const COMMAND_RESPONSE: u8 = 2;
+const VERSION: u8 = 2;
+const AFI_IPV4: u16 = 2;
+const HEADER_LEN: usize = 4;
+const ENTRY_LEN: usize = 20;
+const MAX_ENTRIES: usize = 25; // Per message
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RipTimers {
+    pub update: Duration,  // Between regular updates with the whole table
+    pub timeout: Duration, // How long a learned route lasts without being refreshed
+    pub garbage: Duration, // How long an unreachable route is still advertised before it is deleted
+}
+
+impl Default for RipTimers {
+    fn default() -> Self {
+        Self {
+            update: Duration::from_secs(30),
+            timeout: Duration::from_secs(180),
+            garbage: Duration::from_secs(120),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RipEntry {
+    pub network: Ipv4Net,
+    pub next_hop: Ipv4Addr, // 0.0.0.0 means through the sender
+    pub metric: u32,
+    pub tag: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RipMessage {
+    // Asks for the listed networks, or for the whole table if there are none
+    Request(Vec<RipEntry>),
+    Response(Vec<RipEntry>),
+}
+
+impl RipMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (command, entries) = match self {
+            RipMessage::Request(entries) => (COMMAND_REQUEST, entries),
+            RipMessage::Response(entries) => (COMMAND_RESPONSE, entries),
+        };
+        let mut bytes = vec![command, VERSION, 0, 0];
+        if entries.is_empty() && command == COMMAND_REQUEST {
+            // Whole table: a single entry with address family 0 and metric infinity
+            bytes.extend_from_slice(&[0; ENTRY_LEN - 4]);
+            bytes.extend_from_slice(&INFINITY.to_be_bytes());
+        }
+        for entry in entries {
+            let mask = match entry.network.prefix_len() {
+                0 => 0,
+                len => u32::MAX << (32 - u32::from(len)),
+            };
+            bytes.extend_from_slice(&AFI_IPV4.to_be_bytes());
+            bytes.extend_from_slice(&entry.tag.to_be_bytes());
+            bytes.extend_from_slice(&entry.network.network().octets());
+            bytes.extend_from_slice(&mask.to_be_bytes());
+            bytes.extend_from_slice(&entry.next_hop.octets());
+            bytes.extend_from_slice(&entry.metric.to_be_bytes());
+        }
+        bytes
+    }
+
+    // None for other versions and malformed messages. Entries for other address families
+    // (e.g. authentication) and with non-contiguous masks are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[1] != VERSION || !(bytes.len() - HEADER_LEN).is_multiple_of(ENTRY_LEN) {
+            return None;
+        }
+        let raw: Vec<&[u8]> = bytes[HEADER_LEN..].chunks(ENTRY_LEN).collect();
+        let u32_at = |entry: &[u8], offset: usize| {
+            u32::from_be_bytes([entry[offset], entry[offset + 1], entry[offset + 2], entry[offset + 3]])
+        };
+        let whole_table = raw.len() == 1 && raw[0][..2] == [0, 0] && u32_at(raw[0], 16) == INFINITY;
+        let entries = raw
+            .iter()
+            .filter(|entry| u16::from_be_bytes([entry[0], entry[1]]) == AFI_IPV4)
+            .filter_map(|entry| {
+                let mask = u32_at(entry, 8);
+                let prefix_len = mask.leading_ones();
+                if prefix_len + mask.trailing_zeros() < 32 {
+                    return None;
+                }
+                let network = Ipv4Net::new(Ipv4Addr::from(u32_at(entry, 4)), prefix_len as u8).ok()?.trunc();
+                Some(RipEntry {
+                    network,
+                    next_hop: Ipv4Addr::from(u32_at(entry, 12)),
+                    metric: u32_at(entry, 16),
+                    tag: u16::from_be_bytes([entry[2], entry[3]]),
+                })
+            })
+            .collect();
+        match bytes[0] {
+            COMMAND_REQUEST if whole_table => Some(RipMessage::Request(Vec::new())),
+            COMMAND_REQUEST => Some(RipMessage::Request(entries)),
+            COMMAND_RESPONSE => Some(RipMessage::Response(entries)),
+            _ => None,
+        }
+    }
+}
+
+impl Packet {
+    // RIP message from router port to router port; it never goes further than the next router
+    pub fn rip(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, message: &RipMessage) -> Self {
+        let datagram = UdpDatagram { src_port: RIP_PORT, dest_port: RIP_PORT, payload: message.to_bytes() };
+        let mut packet = Packet::udp(src_ip, dest_ip, &datagram);
+        packet.header.ttl = 1;
+        packet
+    }
+
+    // The RIP message this packet carries, if any
+    pub fn rip_message(&self) -> Option<RipMessage> {
+        let datagram = self.udp_datagram()?;
+        if datagram.dest_port != RIP_PORT {
+            return None;
+        }
+        RipMessage::from_bytes(&datagram.payload)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RipAction {
+    Send { interface: usize, message: RipMessage }, // Multicast to RIP_GROUP out of the interface
+    Install { network: Ipv4Net, interface: usize, next_hop: Ipv4Addr },
+    Remove { network: Ipv4Net },
+    TriggerUpdate,                          // Call triggered_update after a short random delay
+    Expire { network: Ipv4Net, after: Duration }, // Call expire for the network after this long
+}
+
+#[derive(Debug, Clone)]
+struct RipRoute {
+    interface: usize,
+    next_hop: Option<Ipv4Addr>, // None for directly connected networks
+    metric: u32,
+    timeout: Option<Duration>,  // When a learned route expires unless it is heard again
+    garbage: Option<Duration>,  // When an unreachable route is deleted
+    timer: Option<Duration>,    // When the Expire action already asked for fires
+    changed: bool,              // Goes in the next triggered update
+}
+
+impl RipRoute {
+    fn learned(interface: usize, next_hop: Ipv4Addr, metric: u32, timeout: Duration) -> Self {
+        Self { interface, next_hop: Some(next_hop), metric, timeout: Some(timeout), garbage: None, timer: None, changed: true }
+    }
+
+    // The next time something happens to the route by itself
+    fn deadline(&self) -> Option<Duration> { self.garbage.or(self.timeout) }
+}
+
+// RIPv2 (RFC 2453) state of one router: the distance-vector database, periodic and triggered
+// updates with split horizon and poison reverse, and route timeout and garbage collection.
+// The router installs the reachable routes in its routing table.
+#[derive(Debug, Clone)]
+pub struct Rip {
+    timers: RipTimers,
+    passive: Vec<usize>, // Interfaces that listen to updates but do not send any
+    up: Vec<bool>,       // Interfaces with a working link, as of the last update
+    routes: BTreeMap<Ipv4Net, RipRoute>,
+    triggered_pending: bool,
+    actions: Vec<RipAction>,
+}
+
+impl Rip {
+    pub fn new(timers: RipTimers) -> Self {
+        Self {
+            timers,
+            passive: Vec::new(),
+            up: Vec::new(),
+            routes: BTreeMap::new(),
+            triggered_pending: false,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn timers(&self) -> RipTimers { self.timers }
+
+    pub fn is_passive(&self, interface: usize) -> bool { self.passive.contains(&interface) }
+
+    pub fn set_passive(&mut self, interface: usize, passive: bool) {
+        self.passive.retain(|&other| other != interface);
+        if passive {
+            self.passive.push(interface);
+        }
+    }
+
+    // Each update is given which interfaces have a working link and the directly connected
+    // networks (network, interface). Starting asks the neighbors for their tables.
+    pub fn start(&mut self, up: &[bool], connected: &[(Ipv4Net, usize)], now: Duration) -> Vec<RipAction> {
+        self.refresh(up, connected, now);
+        for interface in self.sending_interfaces() {
+            self.actions.push(RipAction::Send { interface, message: RipMessage::Request(Vec::new()) });
+        }
+        self.send_table(false);
+        std::mem::take(&mut self.actions)
+    }
+
+    pub fn periodic_update(&mut self, up: &[bool], connected: &[(Ipv4Net, usize)], now: Duration) -> Vec<RipAction> {
+        self.refresh(up, connected, now);
+        self.send_table(false);
+        std::mem::take(&mut self.actions)
+    }
+
+    // Sends only the routes that changed since the last update
+    pub fn triggered_update(&mut self, up: &[bool], connected: &[(Ipv4Net, usize)], now: Duration) -> Vec<RipAction> {
+        self.refresh(up, connected, now);
+        self.triggered_pending = false;
+        self.send_table(true);
+        std::mem::take(&mut self.actions)
+    }
+
+    pub fn receive_response(&mut self, interface: usize, sender: Ipv4Addr, entries: &[RipEntry], now: Duration) -> Vec<RipAction> {
+        for entry in entries {
+            if entry.metric == 0 || entry.metric > INFINITY {
+                continue;
+            }
+            let metric = (entry.metric + HOP_COST).min(INFINITY);
+            let next_hop = if entry.next_hop.is_unspecified() { sender } else { entry.next_hop };
+            self.consider(entry.network, interface, next_hop, metric, now);
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    // Answers a request: the whole table as a regular update on that interface would carry it,
+    // or the metrics of the networks asked about
+    pub fn answer(&self, interface: usize, request: &[RipEntry]) -> Vec<RipMessage> {
+        let entries: Vec<RipEntry> = if request.is_empty() {
+            self.entries_for(interface, false)
+        } else {
+            request
+                .iter()
+                .map(|entry| RipEntry {
+                    metric: self.routes.get(&entry.network).map_or(INFINITY, |route| route.metric),
+                    ..*entry
+                })
+                .collect()
+        };
+        entries.chunks(MAX_ENTRIES).map(|chunk| RipMessage::Response(chunk.to_vec())).collect()
+    }
+
+    // Times out a learned route that was not refreshed, or deletes an unreachable one.
+    // A route refreshed since the timer was set gets a new timer for its new deadline.
+    pub fn expire(&mut self, network: Ipv4Net, now: Duration) -> Vec<RipAction> {
+        if let Some(route) = self.routes.get_mut(&network) {
+            if route.timer.is_some_and(|timer| timer <= now) {
+                route.timer = None;
+            }
+            if route.garbage.is_some_and(|garbage| garbage <= now) {
+                self.routes.remove(&network);
+            } else if route.timeout.is_some_and(|timeout| timeout <= now) {
+                self.invalidate(network, now);
+            } else {
+                self.arm(network, now);
+            }
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    // Route to `network` through `next_hop` on `interface` heard from a neighbor
+    fn consider(&mut self, network: Ipv4Net, interface: usize, next_hop: Ipv4Addr, metric: u32, now: Duration) {
+        let timeout = now + self.timers.timeout;
+        let Some(route) = self.routes.get_mut(&network) else {
+            if metric < INFINITY {
+                self.routes.insert(network, RipRoute::learned(interface, next_hop, metric, timeout));
+                self.install(network, interface, next_hop, now);
+            }
+            return;
+        };
+
+        if route.next_hop == Some(next_hop) && route.interface == interface {
+            // Same neighbor: believe it, whether the route got better or worse
+            if metric >= INFINITY {
+                if route.metric < INFINITY {
+                    self.invalidate(network, now);
+                }
+                return;
+            }
+            // The pending timer finds the later deadline when it fires
+            route.timeout = Some(timeout);
+            route.garbage = None;
+            let changed = route.metric != metric;
+            route.metric = metric;
+            if changed {
+                route.changed = true;
+                self.install(network, interface, next_hop, now);
+            } else {
+                self.arm(network, now);
+            }
+        } else if metric < route.metric {
+            *route = RipRoute { timer: route.timer, ..RipRoute::learned(interface, next_hop, metric, timeout) };
+            self.install(network, interface, next_hop, now);
+        }
+    }
+
+    fn install(&mut self, network: Ipv4Net, interface: usize, next_hop: Ipv4Addr, now: Duration) {
+        self.actions.push(RipAction::Install { network, interface, next_hop });
+        self.arm(network, now);
+        self.mark_changed();
+    }
+
+    // Asks for an Expire action at the route's deadline, unless one already comes by then
+    fn arm(&mut self, network: Ipv4Net, now: Duration) {
+        let Some(route) = self.routes.get_mut(&network) else {
+            return;
+        };
+        let Some(deadline) = route.deadline() else {
+            return;
+        };
+        if route.timer.is_some_and(|timer| timer <= deadline) {
+            return;
+        }
+        route.timer = Some(deadline);
+        self.actions.push(RipAction::Expire { network, after: deadline.saturating_sub(now) });
+    }
+
+    // Makes a route unreachable: it is advertised with metric infinity until it is deleted
+    fn invalidate(&mut self, network: Ipv4Net, now: Duration) {
+        let Some(route) = self.routes.get_mut(&network) else {
+            return;
+        };
+        route.metric = INFINITY;
+        route.timeout = None;
+        route.garbage = Some(now + self.timers.garbage);
+        route.changed = true;
+        if route.next_hop.is_some() {
+            self.actions.push(RipAction::Remove { network });
+        }
+        self.arm(network, now);
+        self.mark_changed();
+    }
+
+    fn mark_changed(&mut self) {
+        if !self.triggered_pending {
+            self.triggered_pending = true;
+            self.actions.push(RipAction::TriggerUpdate);
+        }
+    }
+
+    // Picks up links that went up or down and directly connected networks
+    fn refresh(&mut self, up: &[bool], connected: &[(Ipv4Net, usize)], now: Duration) {
+        self.up = up.to_vec();
+        for &(network, interface) in connected {
+            if !self.is_up(interface) {
+                continue;
+            }
+            let current = self.routes.get(&network).is_some_and(|route| {
+                route.next_hop.is_none() && route.interface == interface && route.metric == HOP_COST
+            });
+            if !current {
+                let route = RipRoute { interface, next_hop: None, metric: HOP_COST, timeout: None, garbage: None, timer: None, changed: true };
+                if self.routes.insert(network, route).is_some_and(|old| old.next_hop.is_some()) {
+                    self.actions.push(RipAction::Remove { network });
+                }
+                self.mark_changed();
+            }
+        }
+
+        let lost: Vec<Ipv4Net> = self.routes
+            .iter()
+            .filter(|(network, route)| {
+                route.metric < INFINITY && match route.next_hop {
+                    None => !connected.iter().any(|&(other, interface)| {
+                        other == **network && interface == route.interface && self.is_up(interface)
+                    }),
+                    Some(_) => !self.is_up(route.interface),
+                }
+            })
+            .map(|(network, _)| *network)
+            .collect();
+        for network in lost {
+            self.invalidate(network, now);
+        }
+    }
+
+    fn is_up(&self, interface: usize) -> bool { self.up.get(interface).copied().unwrap_or(false) }
+
+    fn sending_interfaces(&self) -> Vec<usize> {
+        (0..self.up.len()).filter(|&interface| self.is_up(interface) && !self.is_passive(interface)).collect()
+    }
+
+    // Split horizon with poison reverse: routes go back out of their own interface as unreachable
+    fn entries_for(&self, interface: usize, only_changed: bool) -> Vec<RipEntry> {
+        self.routes
+            .iter()
+            .filter(|(_, route)| !only_changed || route.changed)
+            .map(|(network, route)| RipEntry {
+                network: *network,
+                next_hop: Ipv4Addr::UNSPECIFIED,
+                metric: if route.interface == interface { INFINITY } else { route.metric },
+                tag: 0,
+            })
+            .collect()
+    }
+
+    fn send_table(&mut self, only_changed: bool) {
+        for interface in self.sending_interfaces() {
+            let entries = self.entries_for(interface, only_changed);
+            for chunk in entries.chunks(MAX_ENTRIES) {
+                self.actions.push(RipAction::Send { interface, message: RipMessage::Response(chunk.to_vec()) });
+            }
+        }
+        for route in self.routes.values_mut() {
+            route.changed = false;
+        }
+    }
+
+    // Timers, passive interfaces, then every route with its metric and timer
+    pub fn describe(&self, interface_names: &[String], now: Duration) -> String {
+        let name = |interface: usize| interface_names.get(interface).map_or("?", String::as_str);
+        let mut lines = vec![format!(
+            "RIP: update {} s, timeout {} s, garbage collection {} s",
+            self.timers.update.as_secs_f64(),
+            self.timers.timeout.as_secs_f64(),
+            self.timers.garbage.as_secs_f64()
+        )];
+        if !self.passive.is_empty() {
+            let passive: Vec<&str> = self.passive.iter().map(|&interface| name(interface)).collect();
+            lines.push(format!("Passive: {}", passive.join(", ")));
+        }
+        for (network, route) in &self.routes {
+            let next_hop = route.next_hop.map_or_else(|| "direct".to_string(), |ip| ip.to_string());
+            let timer = match (route.timeout, route.garbage) {
+                (_, Some(garbage)) => format!("deleted in {:.1} s", garbage.saturating_sub(now).as_secs_f64()),
+                (Some(timeout), None) => format!("expires in {:.1} s", timeout.saturating_sub(now).as_secs_f64()),
+                (None, None) => String::new(),
+            };
+            lines.push(format!(
+                "{:<18} {:<15} metric {:<2} {:<8} {timer}",
+                network.to_string(),
+                next_hop,
+                route.metric,
+                name(route.interface)
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::MacAddr;
+
+    const NEIGHBOR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+    fn net(s: &str) -> Ipv4Net { s.parse().unwrap() }
+
+    // A router with a directly connected network on interface 0 and a neighbor on interface 1
+    fn rip() -> Rip {
+        let mut rip = Rip::new(RipTimers::default());
+        rip.start(&[true, true], &[(net("10.0.1.0/24"), 0), (net("10.0.2.0/24"), 1)], Duration::ZERO);
+        rip
+    }
+
+    fn hear(rip: &mut Rip, metric: u32, now: Duration) -> Vec<RipAction> {
+        let entry = RipEntry { network: net("10.0.3.0/24"), next_hop: Ipv4Addr::UNSPECIFIED, metric, tag: 0 };
+        rip.receive_response(1, NEIGHBOR, &[entry], now)
+    }
+
+    fn metric(rip: &Rip, interface: usize, network: &str) -> Option<u32> {
+        rip.answer(interface, &[])
+            .iter()
+            .flat_map(|message| match message {
+                RipMessage::Response(entries) => entries.clone(),
+                RipMessage::Request(_) => Vec::new(),
+            })
+            .find(|entry| entry.network == net(network))
+            .map(|entry| entry.metric)
+    }
+
+    fn expires(actions: &[RipAction]) -> Vec<Duration> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                RipAction::Expire { after, .. } => Some(*after),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn learned_routes_are_poisoned_back_to_their_neighbor() {
+        let mut rip = rip();
+        let actions = hear(&mut rip, 1, Duration::ZERO);
+        assert!(actions.contains(&RipAction::Install { network: net("10.0.3.0/24"), interface: 1, next_hop: NEIGHBOR }));
+        assert_eq!(metric(&rip, 0, "10.0.3.0/24"), Some(2));
+        assert_eq!(metric(&rip, 1, "10.0.3.0/24"), Some(INFINITY));
+        assert_eq!(metric(&rip, 1, "10.0.1.0/24"), Some(1));
+    }
+
+    #[test]
+    fn refreshes_keep_a_single_expiry_timer() {
+        let mut rip = rip();
+        let timeout = RipTimers::default().timeout;
+        assert_eq!(expires(&hear(&mut rip, 1, Duration::ZERO)), vec![timeout]);
+        for second in 1..10 {
+            assert!(expires(&hear(&mut rip, 1, Duration::from_secs(second * 30))).is_empty());
+        }
+        // The first timer finds the route refreshed and waits for the new deadline
+        let actions = rip.expire(net("10.0.3.0/24"), timeout);
+        assert_eq!(expires(&actions), vec![Duration::from_secs(270)]);
+        assert!(!actions.iter().any(|action| matches!(action, RipAction::Remove { .. })));
+    }
+
+    #[test]
+    fn silent_routes_time_out_and_are_garbage_collected() {
+        let mut rip = rip();
+        let RipTimers { timeout, garbage, .. } = RipTimers::default();
+        hear(&mut rip, 1, Duration::ZERO);
+        let actions = rip.expire(net("10.0.3.0/24"), timeout);
+        assert!(actions.contains(&RipAction::Remove { network: net("10.0.3.0/24") }));
+        assert_eq!(expires(&actions), vec![garbage]);
+        assert_eq!(metric(&rip, 0, "10.0.3.0/24"), Some(INFINITY));
+
+        rip.expire(net("10.0.3.0/24"), timeout + garbage);
+        assert_eq!(metric(&rip, 0, "10.0.3.0/24"), None);
+    }
+
+    #[test]
+    fn neighbor_can_withdraw_its_route() {
+        let mut rip = rip();
+        hear(&mut rip, 1, Duration::ZERO);
+        let actions = hear(&mut rip, INFINITY, Duration::from_secs(30));
+        assert!(actions.contains(&RipAction::Remove { network: net("10.0.3.0/24") }));
+        assert_eq!(metric(&rip, 0, "10.0.3.0/24"), Some(INFINITY));
+    }
+
+    #[test]
+    fn rip_update_round_trip() {
+        let message = RipMessage::Response(vec![
+            RipEntry { network: "10.0.0.0/8".parse().unwrap(), next_hop: Ipv4Addr::UNSPECIFIED, metric: 2, tag: 0 },
+            RipEntry { network: "0.0.0.0/0".parse().unwrap(), next_hop: Ipv4Addr::new(10, 0, 0, 2), metric: 16, tag: 7 },
+        ]);
+        let mut packet = Packet::rip(Ipv4Addr::new(10, 0, 0, 1), RIP_GROUP, &message);
+        packet.src_mac = MacAddr::from([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x11]);
+        packet.dest_mac = MacAddr::ipv4_multicast(RIP_GROUP);
+        let bytes = packet.to_bytes();
+        assert_eq!(&bytes[..6], &[0x01, 0x00, 0x5E, 0x00, 0x00, 0x09]);
+        assert_eq!(&bytes[34..38], &[0x02, 0x08, 0x02, 0x08]); // UDP ports 520
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.rip_message(), Some(message));
+
+        // A flipped payload bit fails the UDP checksum
+        let mut corrupted = decoded;
+        corrupted.data[12] ^= 1;
+        assert_eq!(corrupted.rip_message(), None);
+    }
+}
@@ -1,147 +1,781 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::rc::{Rc, Weak};
-use std::cell::RefCell;
-use crate::packet::Packet;
-use crate::switch::Switch;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::rc::Rc;
+use std::time::Duration;
+use crate::address::{Ipv4Net, MacAddr};
+use crate::arp::{self, ArpCache, Resolution, Retry};
+use crate::bgp::{Bgp, BgpAction, BgpNeighbor, BgpTimers};
+use crate::capture::{CaptureRef, Direction};
+use crate::dhcp::{DhcpAction, DhcpMessage, DhcpPool, DhcpServer};
+use crate::icmp::{self, IcmpMessage};
+use crate::link::Attachment;
+use crate::packet::{ArpOp, Packet};
+use crate::ospf::{self, Ospf, OspfAction, OspfMessage, OspfTimers};
+use crate::rip::{self, Rip, RipAction, RipMessage, RipTimers};
+use crate::sim::{Simulator, Timer};
+use crate::tcp::TcpSegment;
+
+// (interface, IP address of next hop)
+pub type RouteEntry = (usize, Option<Ipv4Addr>);
+
+// Where a route came from. Routes from a more trusted source (lower administrative
+// distance) are not replaced by routes from a less trusted one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteSource {
+    Static, // From the topology file, including directly connected networks
+    Ebgp,
+    Ospf,
+    Rip,
+    Ibgp,
+}
+
+impl RouteSource {
+    fn distance(&self) -> u8 {
+        match self {
+            RouteSource::Static => 1,
+            RouteSource::Ebgp => 20,
+            RouteSource::Ospf => 110,
+            RouteSource::Rip => 120,
+            RouteSource::Ibgp => 200,
+        }
+    }
+}
+
+impl fmt::Display for RouteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteSource::Static => write!(f, "static"),
+            RouteSource::Ebgp => write!(f, "ebgp"),
+            RouteSource::Ospf => write!(f, "ospf"),
+            RouteSource::Rip => write!(f, "rip"),
+            RouteSource::Ibgp => write!(f, "ibgp"),
+        }
+    }
+}
+
+// A physical interface, or a VLAN subinterface (e.g. eth0.10) sharing its parent's link and
+// MAC address and sending frames tagged with its VLAN, as used for router-on-a-stick
+#[derive(Debug)]
+struct Interface {
+    name: String,
+    mac_address: MacAddr,
+    link: Option<Attachment>,   // Unused for subinterfaces
+    capture: Option<CaptureRef>, // Unused for subinterfaces
+    parent: Option<usize>,      // Physical interface of a subinterface
+    vlan: Option<u16>,          // Tag of the subinterface's frames
+}
 
 #[derive(Debug)]
 pub struct Router {
-    arp_table: HashMap<String, String>, // IP address -> MAC address
-    // Network address -> (switch, port, IP address of next hop, MAC address of the interface)
-    routing_table: HashMap<String, (Weak<RefCell<Switch>>, usize, String, String)>, 
+    arp_cache: ArpCache,
+    interfaces: Vec<Interface>,
+    routing_table: Vec<(Ipv4Net, RouteEntry, RouteSource)>, // Network prefix -> route entry, longest prefix first
+    standby_routes: Vec<(Ipv4Net, RouteEntry, RouteSource)>, // Routes shadowed by a more trusted source
     incoming_packets: Vec<Rc<Packet>>,
     outgoing_packets: Vec<Rc<Packet>>,
-    ip_address: String,
+    ip_address: Ipv4Addr,
+    rip: Option<Rip>,   // None unless the router runs RIP
+    ospf: Option<Ospf>, // None unless the router runs OSPF
+    bgp: Option<Bgp>,   // None unless the router runs BGP
+    dhcp_server: Option<DhcpServer>, // None unless the router hands out addresses
 }
 
 /*
 Example Routing Tables:
-If string is empty, then the next hop is the final destination. 
+If the next hop is None, then the next hop is the final destination.
 Otherwise, the next hop is the IP address of the next router.
+Lookups use the longest matching prefix, so 0.0.0.0/0 acts as the default route.
+Interfaces are numbered in the order they were added, e.g. R1 has
+eth0 (MAC1/1, switch1), eth1 (MAC2/1, switch2) and eth2 (MAC_MAIN/1, switch_main).
 
 R1:
 {
-    "192.168.1.0": (switch1, 1, "", MAC1/1),
-    "192.168.2.0": (switch2, 1, "", MAC2/1),
-    "192.168.3.0": (switch_main, 1, R2, MAC_MAIN/1),
-    "192.168.4.0": (switch_main, 1, R2, MAC_MAIN/1),
-    "192.168.5.0": (switch_main, 1, R3, MAC_MAIN/1),
+    "192.168.1.0/24": (eth0, None),
+    "192.168.2.0/24": (eth1, None),
+    "192.168.3.0/24": (eth2, Some(R2)),
+    "192.168.4.0/24": (eth2, Some(R2)),
+    "192.168.5.0/24": (eth2, Some(R3)),
 }
 
 R2:
 {
-    "192.168.1.0": (switch_main, 2, R1, MAC_MAIN/2),
-    "192.168.2.0": (switch2, 2, "", MAC2/2),
-    "192.168.3.0": (switch3, 1, "", MAC3/2),
-    "192.168.4.0": (switch4, 1, "", MAC4/2),
-    "192.168.5.0": (switch_main, 2, R3, MAC_MAIN/2),
+    "192.168.1.0/24": (eth0, Some(R1)),
+    "192.168.2.0/24": (eth1, None),
+    "192.168.3.0/24": (eth2, None),
+    "192.168.4.0/24": (eth3, None),
+    "192.168.5.0/24": (eth0, Some(R3)),
 }
 
 R3:
 {
-    "192.168.1.0": (switch_main, 3, R1, MAC_MAIN/3),
-    "192.168.2.0": (switch_main, 3, R2, MAC_MAIN/3),
-    "192.168.3.0": (switch_main, 3, R2, MAC_MAIN/3),
-    "192.168.4.0": (switch_main, 3, R2, MAC_MAIN/3),
-    "192.168.5.0": (switch5, 1, "", MAC5/3),
+    "192.168.1.0/24": (eth0, Some(R1)),
+    "192.168.5.0/24": (eth1, None),
+    "0.0.0.0/0": (eth0, Some(R2)),
 }
 */
 
 impl Router {
-    pub fn new(ip_address: String) -> Self {
+    pub fn new(ip_address: Ipv4Addr) -> Self {
         Self {
-            arp_table: HashMap::new(),
-            routing_table: HashMap::new(),
+            arp_cache: ArpCache::new(),
+            interfaces: Vec::new(),
+            routing_table: Vec::new(),
+            standby_routes: Vec::new(),
             incoming_packets: Vec::new(),
             outgoing_packets: Vec::new(),
             ip_address,
+            rip: None,
+            ospf: None,
+            bgp: None,
+            dhcp_server: None,
+        }
+    }
+
+    // Adds an interface and returns its index, which is also its port number for links
+    pub fn add_interface(&mut self, name: &str, mac_address: MacAddr) -> usize {
+        self.interfaces.push(Interface { name: name.to_string(), mac_address, link: None, capture: None, parent: None, vlan: None });
+        self.interfaces.len() - 1
+    }
+
+    // Adds a subinterface for `vlan` on a physical interface and returns its index.
+    // Subinterfaces are not ports: links attach to the parent.
+    pub fn add_subinterface(&mut self, name: &str, parent: usize, vlan: u16) -> Option<usize> {
+        let mac_address = self.interfaces.get(parent).filter(|iface| iface.parent.is_none())?.mac_address;
+        self.interfaces.push(Interface {
+            name: name.to_string(),
+            mac_address,
+            link: None,
+            capture: None,
+            parent: Some(parent),
+            vlan: Some(vlan),
+        });
+        Some(self.interfaces.len() - 1)
+    }
+
+    // (parent interface, VLAN) of a subinterface, None for physical interfaces
+    pub fn subinterface(&self, interface: usize) -> Option<(usize, u16)> {
+        let iface = self.interfaces.get(interface)?;
+        Some((iface.parent?, iface.vlan?))
+    }
+
+    // Physical interface whose link an interface's frames go through
+    fn physical(&self, interface: usize) -> Option<&Interface> {
+        let iface = self.interfaces.get(interface)?;
+        match iface.parent {
+            Some(parent) => self.interfaces.get(parent),
+            None => Some(iface),
+        }
+    }
+
+    // Interface a frame received on a port belongs to: the port itself when untagged,
+    // else its subinterface for the frame's VLAN
+    fn ingress_interface(&self, port: usize, vlan: Option<u16>) -> Option<usize> {
+        match vlan {
+            None => Some(port),
+            Some(vlan) => self.interfaces.iter().position(|iface| iface.parent == Some(port) && iface.vlan == Some(vlan)),
+        }
+    }
+
+    pub fn interface_index(&self, name: &str) -> Option<usize> {
+        self.interfaces.iter().position(|iface| iface.name == name)
+    }
+
+    // (name, MAC address) of every interface, in port order
+    pub fn interfaces(&self) -> Vec<(String, MacAddr)> {
+        self.interfaces.iter().map(|iface| (iface.name.clone(), iface.mac_address)).collect()
+    }
+
+    pub fn is_interface_connected(&self, port: usize) -> bool {
+        self.interfaces.get(port).is_some_and(|iface| iface.link.is_some())
+    }
+
+    // Starts recording the frames an interface sends and receives, or stops with None.
+    // Subinterfaces are recorded on their parent, tags included.
+    pub fn set_capture(&mut self, port: usize, capture: Option<CaptureRef>) {
+        let port = self.subinterface(port).map_or(port, |(parent, _)| parent);
+        if let Some(interface) = self.interfaces.get_mut(port) {
+            interface.capture = capture;
+        }
+    }
+
+    pub fn attach_link(&mut self, port: usize, attachment: Attachment) {
+        if let Some(interface) = self.interfaces.get_mut(port) {
+            interface.link = Some(attachment);
         }
     }
 
     pub fn populate_routing_table(
         &mut self,
-        network: String,
-        switch: Weak<RefCell<Switch>>,
-        port: usize,
-        router_ip: String, // "" if directly connected
-        local_mac: String
+        network: Ipv4Net,
+        interface: usize,
+        router_ip: Option<Ipv4Addr>, // None if directly connected
     ) {
-        self.routing_table.insert(network, (switch, port, router_ip, local_mac));
+        self.install_route(network, (interface, router_ip), RouteSource::Static);
+    }
+
+    // Adds or replaces the route to a network, unless a more trusted source already has one.
+    // A route that loses out is kept on standby, to be used once the better one is removed.
+    // Returns whether the route is in the table now.
+    fn install_route(&mut self, network: Ipv4Net, entry: RouteEntry, source: RouteSource) -> bool {
+        let network = network.trunc();
+        self.standby_routes.retain(|(net, _, from)| !(*net == network && *from == source));
+        if let Some(route) = self.routing_table.iter_mut().find(|(net, _, _)| *net == network) {
+            if route.2 != source && route.2.distance() < source.distance() {
+                self.standby_routes.push((network, entry, source));
+                return false;
+            }
+            let replaced = std::mem::replace(route, (network, entry, source));
+            if replaced.2 != source {
+                self.standby_routes.push(replaced);
+            }
+            return true;
+        }
+
+        // Keep the table ordered longest prefix first (then by address) so lookups are deterministic
+        self.routing_table.push((network, entry, source));
+        self.routing_table.sort_by_key(|(net, _, _)| (Reverse(net.prefix_len()), net.addr()));
+        true
+    }
+
+    // Removes the route to a network if it came from `source`, falling back to the most
+    // trusted standby route for the network. Returns whether the route was in the table.
+    fn remove_route(&mut self, network: Ipv4Net, source: RouteSource) -> bool {
+        let network = network.trunc();
+        self.standby_routes.retain(|(net, _, from)| !(*net == network && *from == source));
+        let before = self.routing_table.len();
+        self.routing_table.retain(|(net, _, from)| !(*net == network && *from == source));
+        if self.routing_table.len() == before {
+            return false;
+        }
+
+        let standby = self.standby_routes
+            .iter()
+            .enumerate()
+            .filter(|(_, (net, _, _))| *net == network)
+            .min_by_key(|(_, (_, _, from))| from.distance())
+            .map(|(index, _)| index);
+        if let Some(index) = standby {
+            let (network, entry, source) = self.standby_routes.remove(index);
+            self.install_route(network, entry, source);
+        }
+        true
+    }
+
+    // Longest-prefix match over the routing table
+    fn lookup_route(&self, dest_ip: Ipv4Addr) -> Option<&RouteEntry> {
+        self.routing_table
+            .iter()
+            .find(|(network, _, _)| network.contains(dest_ip))
+            .map(|(_, entry, _)| entry)
+    }
+
+    pub fn routing_table(&self) -> &[(Ipv4Net, RouteEntry, RouteSource)] { &self.routing_table }
+
+    pub fn enable_rip(&mut self, timers: RipTimers) {
+        self.rip = Some(Rip::new(timers));
+    }
+
+    pub fn rip(&self) -> Option<&Rip> { self.rip.as_ref() }
+
+    // Passive interfaces still learn routes from RIP updates but send none, e.g. toward hosts
+    pub fn set_rip_passive(&mut self, interface: usize, passive: bool) {
+        if let Some(rip) = &mut self.rip {
+            rip.set_passive(interface, passive);
+        }
+    }
+
+    // Uses the router's address as its OSPF router ID; call after adding the interfaces
+    pub fn enable_ospf(&mut self, timers: OspfTimers) {
+        self.ospf = Some(Ospf::new(self.ip_address, timers, self.interfaces.len()));
+    }
+
+    pub fn ospf(&self) -> Option<&Ospf> { self.ospf.as_ref() }
+
+    // For the per-interface cost, priority and passive settings
+    pub fn ospf_mut(&mut self) -> Option<&mut Ospf> { self.ospf.as_mut() }
+
+    // Uses the router's address as its BGP router ID and as the address neighbors connect to
+    pub fn enable_bgp(&mut self, asn: u16, timers: BgpTimers) {
+        self.bgp = Some(Bgp::new(asn, self.ip_address, timers));
     }
 
-    pub fn get_ip_address(&self) -> String { self.ip_address.clone() }
+    pub fn bgp(&self) -> Option<&Bgp> { self.bgp.as_ref() }
 
-    // Returns an Option<String> that contains the MAC address if successful.
-    pub fn send_arp_request(&mut self, dest_ip: &str, src_mac: &str, switch_ref: Weak<RefCell<Switch>>, port: usize) -> Option<String> {
+    // Announces a network to the BGP neighbors while the routing table has a route to it
+    pub fn add_bgp_network(&mut self, network: Ipv4Net) {
+        if let Some(bgp) = &mut self.bgp {
+            bgp.add_network(network);
+        }
+    }
+
+    pub fn add_bgp_neighbor(&mut self, neighbor: BgpNeighbor) {
+        if let Some(bgp) = &mut self.bgp {
+            bgp.add_neighbor(neighbor);
+        }
+    }
+
+    // Hands out addresses from `pool` to the network on an interface
+    pub fn add_dhcp_pool(&mut self, interface: usize, pool: DhcpPool) {
+        let address = self.ip_address;
+        self.dhcp_server.get_or_insert_with(|| DhcpServer::new(address)).add_pool(interface, pool);
+    }
+
+    pub fn dhcp_server(&self) -> Option<&DhcpServer> { self.dhcp_server.as_ref() }
+
+    // ARP entries from configuration; learned entries come and go
+    pub fn static_arp_entries(&self) -> HashMap<Ipv4Addr, MacAddr> { self.arp_cache.permanent() }
+
+    // Every address the ARP cache knows at `now`
+    pub fn arp_entries(&self, now: Duration) -> HashMap<Ipv4Addr, MacAddr> { self.arp_cache.resolved(now) }
+
+    pub fn add_arp_entry(&mut self, ip: Ipv4Addr, mac: MacAddr) { self.arp_cache.add_permanent(ip, mac); }
+
+    pub fn get_ip_address(&self) -> Ipv4Addr { self.ip_address }
+
+    // Broadcasts a request for the MAC address of `target_ip` out of an interface
+    fn send_arp_request(&mut self, target_ip: Ipv4Addr, interface: usize, sim: &mut Simulator) {
+        let Some(iface) = self.interfaces.get(interface) else {
+            return;
+        };
         println!("==============================================");
         println!("ROUTER: {}", self.ip_address);
-        println!("Sending ARP request for {}", dest_ip);
+        println!("Sending ARP request for {} on {} at {:?}", target_ip, iface.name, sim.now());
         println!("==============================================\n");
-        let request = Packet::new(
-            src_mac,
-            "UNKNOWN",
-            &self.ip_address,
-            dest_ip,
-            Vec::new(),
-            true
+        let request = Packet::arp_request(
+            iface.mac_address,
+            MacAddr::BROADCAST,
+            self.ip_address,
+            target_ip
         );
+        self.send_frame(interface, Rc::new(request), sim);
+    }
 
-        let switch_rc = switch_ref.upgrade();
-        if switch_rc.is_none() {
-            println!("Switch not available");
-            return None;
+    // Answers requests for our address from the interface they arrived on, and learns senders
+    // like a host does (see Host::receive_arp)
+    fn receive_arp(&mut self, packet: Rc<Packet>, interface: usize, sim: &mut Simulator) {
+        let Some(local_mac) = self.interfaces.get(interface).map(|iface| iface.mac_address) else {
+            return;
+        };
+        let is_request = packet.arp.operation == ArpOp::Request;
+        if packet.src_ip == self.ip_address {
+            if !self.interfaces.iter().any(|iface| iface.mac_address == packet.src_mac) {
+                println!("==============================================");
+                println!("ROUTER: {}", self.ip_address);
+                println!("WARNING: duplicate address {} also used by {}", self.ip_address, packet.src_mac);
+                println!("==============================================\n");
+            }
+            return;
+        }
+        if !is_request && packet.dest_mac != local_mac {
+            return;
+        }
+
+        let for_us = packet.dest_ip == self.ip_address;
+        let pending = self.arp_cache.learn(packet.src_ip, packet.src_mac, sim.now(), for_us);
+        if for_us {
+            self.incoming_packets.push(Rc::clone(&packet));
+            println!("==============================================");
+            println!("ROUTER: {}", self.ip_address);
+            println!("Received ARP {} from {}", if is_request { "request" } else { "reply" }, packet.src_ip);
+            println!("==============================================\n");
+        }
+        for waiting in pending {
+            self.transmit_frame(interface, &waiting, packet.src_mac, sim);
+        }
+        if for_us && is_request {
+            let reply = Packet::arp_reply(
+                local_mac,
+                packet.src_mac,
+                self.ip_address,
+                packet.src_ip
+            );
+            self.send_frame(interface, Rc::new(reply), sim);
+        }
+    }
+
+    // No ARP reply came for `ip`: retry, or report the packets waiting for it as undeliverable
+    fn retry_arp(&mut self, ip: Ipv4Addr, sim: &mut Simulator) {
+        match self.arp_cache.retry(ip) {
+            Retry::Resend { interface } => {
+                self.send_arp_request(ip, interface, sim);
+                sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip });
+            }
+            Retry::GiveUp { pending } => {
+                println!("==============================================");
+                println!("ROUTER: {}", self.ip_address);
+                println!("No ARP reply from {ip}, dropping {} packet(s)", pending.len());
+                println!("==============================================\n");
+                for packet in pending {
+                    self.send_icmp_error(IcmpMessage::destination_unreachable(icmp::HOST_UNREACHABLE, &packet), &packet, sim);
+                }
+            }
+            Retry::Done => {}
         }
-        let binding = switch_rc.unwrap();
-        let mut switch = binding.borrow_mut();
+    }
+
+    pub fn start(&mut self, sim: &mut Simulator) {
+        if self.ospf.is_some() {
+            self.ospf_hello(sim);
+            self.ospf_retransmit(sim);
+        }
+        if self.bgp.is_some() {
+            self.bgp_tick(sim);
+        }
+        let up: Vec<bool> = self.link_bandwidths().iter().map(Option::is_some).collect();
+        let connected = self.connected_networks();
+        let Some(rip) = &mut self.rip else {
+            return;
+        };
+        let actions = rip.start(&up, &connected, sim.now());
+        self.apply_rip(actions, sim);
+        self.schedule_rip_update(sim);
+    }
 
-        let response = switch.process_arp_request(Rc::new(request), port);
-        if let Some(ref resp) = response {
-            self.arp_table.insert(dest_ip.to_string(), resp.src_mac.clone());
-            Some(resp.src_mac.clone())
-        } else {
-            None
+    pub fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        match timer {
+            Timer::ArpRetry { ip } => return self.retry_arp(ip, sim),
+            Timer::OspfHello => return self.ospf_hello(sim),
+            Timer::OspfRetransmit => return self.ospf_retransmit(sim),
+            Timer::BgpTick => return self.bgp_tick(sim),
+            Timer::RipUpdate => self.schedule_rip_update(sim),
+            _ => {}
         }
+        let up: Vec<bool> = self.link_bandwidths().iter().map(Option::is_some).collect();
+        let connected = self.connected_networks();
+        let Some(rip) = &mut self.rip else {
+            return;
+        };
+        let actions = match timer {
+            Timer::RipUpdate => rip.periodic_update(&up, &connected, sim.now()),
+            Timer::RipTriggered => rip.triggered_update(&up, &connected, sim.now()),
+            Timer::RipExpire { network } => rip.expire(network, sim.now()),
+            _ => return,
+        };
+        self.apply_rip(actions, sim);
     }
 
-    pub fn receive_arp_request(&mut self, packet: Rc<Packet>) -> Option<Rc<Packet>> {
-        // Only process ARP request if it is intended for this router
-        if packet.dest_ip != self.ip_address {
-            return None;
+    // Bandwidth of each interface's link, None while it has no working link
+    fn link_bandwidths(&self) -> Vec<Option<u64>> {
+        (0..self.interfaces.len())
+            .map(|interface| {
+                let attachment = self.physical(interface).and_then(|iface| iface.link.as_ref())?;
+                let link = attachment.link.borrow();
+                link.is_up().then_some(link.config.bandwidth_bps)
+            })
+            .collect()
+    }
+
+    // Directly connected networks from the routing table, with their interfaces
+    fn connected_networks(&self) -> Vec<(Ipv4Net, usize)> {
+        self.routing_table
+            .iter()
+            .filter(|(_, (_, next_hop), source)| next_hop.is_none() && *source == RouteSource::Static)
+            .map(|(network, (interface, _), _)| (*network, *interface))
+            .collect()
+    }
+
+    // Regular updates are spread out by up to a sixth of the interval either way, so that
+    // routers do not end up sending in step (RFC 2453 section 3.8)
+    fn schedule_rip_update(&self, sim: &mut Simulator) {
+        if let Some(rip) = &self.rip {
+            let offset = (sim.random() - 0.5) / 3.0;
+            sim.schedule_timer(rip.timers().update.mul_f64(1.0 + offset), Timer::RipUpdate);
         }
+    }
 
-        // Find the local MAC address based on the packet's source IP address
-        let mut local_mac : Option<String> = None;
-        for (network, (_, _, _, loc_mac)) in &self.routing_table {
-            if packet.src_ip.get(..9) == network.get(..9) {
-                local_mac = Some(loc_mac.clone());
-                break;
+    fn apply_rip(&mut self, actions: Vec<RipAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                RipAction::Send { interface, message } => {
+                    let Some(iface) = self.interfaces.get(interface) else {
+                        continue;
+                    };
+                    let mut packet = Packet::rip(self.ip_address, rip::RIP_GROUP, &message);
+                    packet.src_mac = iface.mac_address;
+                    packet.dest_mac = MacAddr::ipv4_multicast(rip::RIP_GROUP);
+                    self.send_frame(interface, Rc::new(packet), sim);
+                }
+                RipAction::Install { network, interface, next_hop } => {
+                    if self.install_route(network, (interface, Some(next_hop)), RouteSource::Rip) {
+                        let name = &self.interfaces[interface].name;
+                        self.log_rip(&format!("Route to {network} via {next_hop} ({name}) at {:?}", sim.now()));
+                    }
+                }
+                RipAction::Remove { network } => {
+                    if self.remove_route(network, RouteSource::Rip) {
+                        self.log_rip(&format!("Route to {network} is unreachable at {:?}", sim.now()));
+                    }
+                }
+                // Triggered updates wait 1-5 s so that several changes go out together
+                RipAction::TriggerUpdate => {
+                    let delay = Duration::from_secs_f64(1.0 + 4.0 * sim.random());
+                    sim.schedule_timer(delay, Timer::RipTriggered);
+                }
+                RipAction::Expire { network, after } => sim.schedule_timer(after, Timer::RipExpire { network }),
             }
         }
+    }
 
-        // If the ARP request is intended for this host, return the MAC value
-        if let Some(mac) = local_mac {
-            self.arp_table.insert(packet.src_ip.clone(), packet.src_mac.clone());
-            println!("==============================================");
-            println!("ROUTER: {}", self.ip_address);
-            println!("Received ARP request from {}", packet.src_ip);
-            println!("ARP Table: {:#?}", self.arp_table);
-            println!("==============================================\n");
-            return Some(Rc::new(Packet::new(
-                &mac,
-                &packet.src_mac,
-                &packet.dest_ip,
-                &packet.src_ip,
-                Vec::new(),
-                true
-            )));
+    fn log_rip(&self, message: &str) {
+        println!("==============================================");
+        println!("ROUTER: {}", self.ip_address);
+        println!("RIP: {message}");
+        println!("==============================================\n");
+    }
+
+    fn ospf_hello(&mut self, sim: &mut Simulator) {
+        let links = self.link_bandwidths();
+        let connected = self.connected_networks();
+        let Some(ospf) = &mut self.ospf else {
+            return;
+        };
+        sim.schedule_timer(ospf.timers().hello, Timer::OspfHello);
+        let actions = ospf.tick(&links, &connected, sim.now());
+        self.apply_ospf(actions, sim);
+    }
+
+    fn ospf_retransmit(&mut self, sim: &mut Simulator) {
+        let Some(ospf) = &mut self.ospf else {
+            return;
+        };
+        sim.schedule_timer(ospf.timers().retransmit, Timer::OspfRetransmit);
+        let actions = ospf.retransmit();
+        self.apply_ospf(actions, sim);
+    }
+
+    fn apply_ospf(&mut self, actions: Vec<OspfAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                OspfAction::Send { interface, dest_ip, dest_mac, message } => {
+                    let Some(iface) = self.interfaces.get(interface) else {
+                        continue;
+                    };
+                    let mut packet = Packet::ospf(self.ip_address, dest_ip, &message);
+                    packet.src_mac = iface.mac_address;
+                    packet.dest_mac = dest_mac;
+                    self.send_frame(interface, Rc::new(packet), sim);
+                }
+                OspfAction::Install { network, interface, next_hop } => {
+                    if self.install_route(network, (interface, Some(next_hop)), RouteSource::Ospf) {
+                        let name = &self.interfaces[interface].name;
+                        self.log_ospf(&format!("Route to {network} via {next_hop} ({name}) at {:?}", sim.now()));
+                    }
+                }
+                OspfAction::Remove { network } => {
+                    if self.remove_route(network, RouteSource::Ospf) {
+                        self.log_ospf(&format!("Route to {network} is unreachable at {:?}", sim.now()));
+                    }
+                }
+            }
+        }
+    }
+
+    fn log_ospf(&self, message: &str) {
+        println!("==============================================");
+        println!("ROUTER: {}", self.ip_address);
+        println!("OSPF: {message}");
+        println!("==============================================\n");
+    }
+
+    fn receive_ospf(&mut self, packet: &Packet, message: OspfMessage, interface: usize, sim: &mut Simulator) {
+        let Some(ospf) = &mut self.ospf else {
+            return;
+        };
+        let actions = ospf.receive(interface, packet.src_mac, packet.dest_ip, message, sim.now());
+        self.apply_ospf(actions, sim);
+    }
+
+    // Routes from every source but BGP, which BGP next hops are resolved over
+    fn non_bgp_routes(&self) -> Vec<(Ipv4Net, RouteEntry)> {
+        self.routing_table
+            .iter()
+            .filter(|(_, _, source)| !matches!(source, RouteSource::Ebgp | RouteSource::Ibgp))
+            .map(|(network, entry, _)| (*network, *entry))
+            .collect()
+    }
+
+    fn bgp_tick(&mut self, sim: &mut Simulator) {
+        let up: Vec<bool> = self.link_bandwidths().iter().map(Option::is_some).collect();
+        let routes = self.non_bgp_routes();
+        let Some(bgp) = &mut self.bgp else {
+            return;
+        };
+        sim.schedule_timer(Duration::from_secs(1), Timer::BgpTick);
+        let actions = bgp.tick(&up, &routes, sim.now());
+        self.apply_bgp(actions, sim);
+    }
+
+    fn apply_bgp(&mut self, actions: Vec<BgpAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                // Segments to directly connected neighbors leave with a TTL of 1, so that they
+                // cannot come from further away; other sessions are routed like any traffic
+                BgpAction::Send { peer, interface, segment } => {
+                    let mut packet = Packet::tcp(self.ip_address, peer, &segment);
+                    let _ = match interface {
+                        Some(interface) => {
+                            packet.header.ttl = 1;
+                            self.send_via(&packet, peer, interface, sim)
+                        }
+                        None => self.route_packet(&packet, sim),
+                    };
+                }
+                BgpAction::Install { network, interface, next_hop, external } => {
+                    let source = if external { RouteSource::Ebgp } else { RouteSource::Ibgp };
+                    self.remove_route(network, RouteSource::Ebgp);
+                    self.remove_route(network, RouteSource::Ibgp);
+                    if self.install_route(network, (interface, Some(next_hop)), source) {
+                        let name = &self.interfaces[interface].name;
+                        self.log_bgp(&format!("Route to {network} via {next_hop} ({name}, {source}) at {:?}", sim.now()));
+                    }
+                }
+                BgpAction::Remove { network } => {
+                    if self.remove_route(network, RouteSource::Ebgp) | self.remove_route(network, RouteSource::Ibgp) {
+                        self.log_bgp(&format!("Route to {network} withdrawn at {:?}", sim.now()));
+                    }
+                }
+                BgpAction::Log(message) => self.log_bgp(&format!("{message} at {:?}", sim.now())),
+            }
+        }
+    }
+
+    fn log_bgp(&self, message: &str) {
+        println!("==============================================");
+        println!("ROUTER: {}", self.ip_address);
+        println!("BGP: {message}");
+        println!("==============================================\n");
+    }
+
+    // TCP to the router is for BGP; without it, connections are refused with a reset
+    fn receive_tcp(&mut self, packet: &Packet, segment: TcpSegment, sim: &mut Simulator) {
+        let actions = match &mut self.bgp {
+            Some(bgp) => bgp.receive(packet.src_ip, &segment, sim.now()),
+            None => {
+                if let Some(reset) = segment.reset_for() {
+                    let _ = self.route_packet(&Packet::tcp(self.ip_address, packet.src_ip, &reset), sim);
+                }
+                return;
+            }
+        };
+        self.apply_bgp(actions, sim);
+    }
+
+    // Replies go back out of the interface the request came in on, broadcast unless the client
+    // already has an address
+    fn receive_dhcp(&mut self, message: &DhcpMessage, interface: usize, sim: &mut Simulator) {
+        let Some(server) = &mut self.dhcp_server else {
+            return;
+        };
+        for action in server.receive(interface, message, sim.now()) {
+            match action {
+                DhcpAction::Send { dest_ip, message } if dest_ip == Ipv4Addr::BROADCAST => {
+                    let Some(local_mac) = self.interfaces.get(interface).map(|iface| iface.mac_address) else {
+                        continue;
+                    };
+                    let mut reply = Packet::dhcp(self.ip_address, dest_ip, &message);
+                    reply.src_mac = local_mac;
+                    reply.dest_mac = MacAddr::BROADCAST;
+                    self.send_frame(interface, Rc::new(reply), sim);
+                }
+                DhcpAction::Send { dest_ip, message } => {
+                    let _ = self.route_packet(&Packet::dhcp(self.ip_address, dest_ip, &message), sim);
+                }
+                DhcpAction::Log(message) => {
+                    println!("==============================================");
+                    println!("ROUTER: {}", self.ip_address);
+                    println!("DHCP: {message} at {:?}", sim.now());
+                    println!("==============================================\n");
+                }
+                // Only clients configure themselves
+                DhcpAction::Configure(_) | DhcpAction::Deconfigure | DhcpAction::Wait(_) => {}
+            }
+        }
+    }
+
+    // Learns from responses and answers requests, with a unicast response to the sender
+    fn receive_rip(&mut self, packet: &Packet, message: RipMessage, interface: usize, sim: &mut Simulator) {
+        if packet.src_ip == self.ip_address {
+            return;
+        }
+        let Some(rip) = &mut self.rip else {
+            return;
+        };
+        match message {
+            RipMessage::Response(entries) => {
+                let actions = rip.receive_response(interface, packet.src_ip, &entries, sim.now());
+                self.apply_rip(actions, sim);
+            }
+            RipMessage::Request(entries) => {
+                let Some(local_mac) = self.interfaces.get(interface).map(|iface| iface.mac_address) else {
+                    return;
+                };
+                for message in rip.answer(interface, &entries) {
+                    let mut response = Packet::rip(self.ip_address, packet.src_ip, &message);
+                    response.src_mac = local_mac;
+                    response.dest_mac = packet.src_mac;
+                    self.send_frame(interface, Rc::new(response), sim);
+                }
+            }
         }
-        None
     }
 
-    pub fn forward_packet(&mut self, request: Rc<Packet>) -> Option<Rc<Packet>> {
+    pub fn forward_packet(&mut self, request: Rc<Packet>, port: usize, sim: &mut Simulator) {
+        if let Some(capture) = self.interfaces.get(port).and_then(|iface| iface.capture.as_ref()) {
+            capture.borrow_mut().record(sim.now(), Direction::In, &request);
+        }
+
+        // Frames tagged for a VLAN without a subinterface here are dropped
+        let Some(interface) = self.ingress_interface(port, request.vlan) else {
+            return;
+        };
+
+        if request.is_arp {
+            self.receive_arp(request, interface, sim);
+            return;
+        }
+
+        // RIP updates are multicast to every router on the link; responses to requests are unicast
+        if let Some(message) = request.rip_message()
+            && (request.dest_ip == rip::RIP_GROUP
+                || (request.dest_ip == self.ip_address && request.dest_mac == self.interfaces[interface].mac_address))
+        {
+            self.receive_rip(&request, message, interface, sim);
+            return;
+        }
+
+        // OSPF packets are multicast to the routers (or only the designated routers) on the link,
+        // or unicast to a neighbor
+        if let Some(message) = request.ospf_message()
+            && (request.dest_ip == ospf::ALL_SPF_ROUTERS
+                || request.dest_ip == ospf::ALL_D_ROUTERS
+                || (request.dest_ip == self.ip_address && request.dest_mac == self.interfaces[interface].mac_address))
+        {
+            self.receive_ospf(&request, message, interface, sim);
+            return;
+        }
+
+        // DHCP clients broadcast until they have an address, then renew with the server directly
+        if let Some(message) = request.dhcp_message()
+            && !message.kind.is_reply()
+            && (request.dest_mac == MacAddr::BROADCAST
+                || (request.dest_ip == self.ip_address && request.dest_mac == self.interfaces[interface].mac_address))
+        {
+            self.receive_dhcp(&message, interface, sim);
+            return;
+        }
+
         // Make sure the packet is intended for this router
-        if !self.routing_table.values().any(|(_, _, _, local_mac)| local_mac == &request.dest_mac) {
-            return None;
+        if !self.interfaces.iter().any(|iface| iface.mac_address == request.dest_mac) {
+            return;
+        }
+
+        if request.dest_ip == self.ip_address
+            && let Some(segment) = request.tcp_segment()
+        {
+            self.receive_tcp(&request, segment, sim);
+            return;
         }
 
         // Add to list of incoming packets
@@ -151,78 +785,304 @@ impl Router {
         println!("Received packet: {request:#?}");
         println!("==============================================\n");
 
-        // Get (next_hop_ip, corresponding_switch, port, MAC)
-        let mut hop_info: Option<(String, Weak<RefCell<Switch>>, usize, String)> = None;
-        for (network, (sw, port, router_ip, local_mac)) in &self.routing_table {
-            if request.dest_ip.get(..9) == network.get(..9) {
-                if router_ip == "" {
-                    hop_info = Some((request.dest_ip.clone(), sw.clone(), *port, local_mac.clone()));
-                } else {
-                    hop_info = Some((router_ip.clone(), sw.clone(), *port, local_mac.clone()));
+        // Packets addressed to the router itself: answer pings, refuse anything else
+        if request.dest_ip == self.ip_address {
+            match request.icmp_message() {
+                Some(IcmpMessage::EchoRequest { id, seq, data }) => {
+                    let reply = IcmpMessage::EchoReply { id, seq, data };
+                    let _ = self.route_packet(&Packet::icmp(self.ip_address, request.src_ip, &reply), sim);
+                }
+                Some(_) => {}
+                None if request.udp_datagram().is_some() => {
+                    self.send_icmp_error(IcmpMessage::destination_unreachable(icmp::PORT_UNREACHABLE, &request), &request, sim);
                 }
-                break;
+                None => self.send_icmp_error(IcmpMessage::destination_unreachable(icmp::PROTOCOL_UNREACHABLE, &request), &request, sim),
             }
+            return;
+        }
+
+        // Drop packets whose TTL runs out here and tell the source
+        if request.header.ttl <= 1 {
+            println!("==============================================");
+            println!("ROUTER: {}", self.ip_address);
+            println!("TTL expired for packet from {} to {} at {:?}", request.src_ip, request.dest_ip, sim.now());
+            println!("==============================================\n");
+            self.send_icmp_error(IcmpMessage::time_exceeded(&request), &request, sim);
+            return;
+        }
+
+        let mut forwarded = request.rebuild_l3(request.src_mac, request.dest_mac);
+        forwarded.header.ttl -= 1;
+        if let Err(code) = self.route_packet(&forwarded, sim) {
+            self.send_icmp_error(IcmpMessage::destination_unreachable(code, &request), &request, sim);
+        }
+    }
+
+    // Reports a problem with `packet` to its source, unless it is an ICMP error itself
+    fn send_icmp_error(&mut self, message: IcmpMessage, packet: &Packet, sim: &mut Simulator) {
+        if packet.icmp_message().is_some_and(|message| message.is_error()) {
+            return;
         }
+        let _ = self.route_packet(&Packet::icmp(self.ip_address, packet.src_ip, &message), sim);
+    }
+
+    // Sends a packet toward its destination: route lookup, then next hop ARP and transmit,
+    // possibly once an ARP reply arrives. On failure returns the Destination Unreachable code.
+    fn route_packet(&mut self, packet: &Packet, sim: &mut Simulator) -> Result<(), u8> {
+        // Get (next_hop_ip, outgoing interface)
+        let hop_info = self.lookup_route(packet.dest_ip)
+            .map(|(interface, router_ip)| (router_ip.unwrap_or(packet.dest_ip), *interface));
 
-        let (hop_ip, hop_switch, hop_port, local_mac) = match hop_info {
+        let (hop_ip, hop_interface) = match hop_info {
             Some(info) => info,
             None => {
-                println!("No route to {}", request.dest_ip);
-                return None;
+                println!("No route to {}", packet.dest_ip);
+                return Err(icmp::NET_UNREACHABLE);
             }
         };
+        self.send_via(packet, hop_ip, hop_interface, sim)
+    }
+
+    // Sends a packet to a next hop on an interface, once ARP has resolved its MAC address
+    fn send_via(&mut self, packet: &Packet, hop_ip: Ipv4Addr, hop_interface: usize, sim: &mut Simulator) -> Result<(), u8> {
+        if self.physical(hop_interface).is_none_or(|iface| iface.link.is_none()) {
+            println!("Switch not available");
+            return Err(icmp::HOST_UNREACHABLE);
+        }
 
-        // Obtain next hop's MAC address using the provided switch and port
-        let hop_dest_mac = match self.arp_table.get(&hop_ip) {
-            Some(mac) => mac.clone(),
-            None => match self.send_arp_request(&hop_ip, &local_mac, hop_switch.clone(), hop_port) {
-                Some(mac) => mac,
-                None => {
-                    println!("No route to {}", request.dest_ip);
-                    return None;
+        // Obtain next hop's MAC address through the outgoing interface
+        match self.arp_cache.resolve(hop_ip, packet.clone(), hop_interface, sim.now()) {
+            Resolution::Send { packet, mac, confirm } => {
+                if confirm {
+                    self.send_arp_request(hop_ip, hop_interface, sim);
+                    sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip: hop_ip });
+                }
+                self.transmit_frame(hop_interface, &packet, mac, sim);
+            }
+            Resolution::Queued { request } => {
+                if request {
+                    self.send_arp_request(hop_ip, hop_interface, sim);
+                    sim.schedule_timer(arp::RETRY_INTERVAL, Timer::ArpRetry { ip: hop_ip });
                 }
-            },
+            }
+        }
+        Ok(())
+    }
+
+    // Rebuilds a packet with the interface's and next hop's MAC addresses and sends it
+    fn transmit_frame(&mut self, interface: usize, packet: &Packet, hop_dest_mac: MacAddr, sim: &mut Simulator) {
+        let Some(iface) = self.interfaces.get(interface) else {
+            return;
         };
 
         // Rebuild the packet with updated L3 headers so that the correct switch processes it
-        let modified_packet = request.rebuild_L3(local_mac, hop_dest_mac);
-        let modified_packet = Rc::new(modified_packet);
+        let modified_packet = Rc::new(packet.rebuild_l3(iface.mac_address, hop_dest_mac));
 
-        // Use the switch reference from the routing table entry
-        let switch_rc = hop_switch.upgrade();
-        if switch_rc.is_none() {
-            println!("Switch not available");
-            return None;
-        }
-        let binding = switch_rc.unwrap();
-        let mut switch = binding.borrow_mut();
-
-        // Add to outgoing packets and send the packet through the correct port
+        // Add to outgoing packets and send the packet through the correct interface
         self.outgoing_packets.push(Rc::clone(&modified_packet));
         println!("==============================================");
         println!("ROUTER: {}", self.ip_address);
-        println!("Forwarding packet for {}", request.dest_ip);
+        println!("Forwarding packet for {} at {:?}", packet.dest_ip, sim.now());
         println!("==============================================\n");
-        if let Some(response) = switch.process_packet(Rc::clone(&modified_packet), hop_port) {
-            self.incoming_packets.push(Rc::clone(&response));
-            println!("==============================================");
-            println!("ROUTER: {}", self.ip_address);
-            println!("Forwarding packet for {}", response.dest_ip);
-            println!("==============================================\n");
+        self.send_frame(interface, modified_packet, sim);
+    }
 
-            // make sure to replace the source MAC and destination MAC
-            let modified_response = response.rebuild_L3(request.dest_mac.clone(), request.src_mac.clone());
-            let modified_response = Rc::new(modified_response);
-            self.outgoing_packets.push(Rc::clone(&modified_response));
-            return Some(modified_response);
+    // Puts a frame on the interface's link, tagged with the VLAN of a subinterface
+    fn send_frame(&self, interface: usize, packet: Rc<Packet>, sim: &mut Simulator) {
+        let (Some(iface), Some(physical)) = (self.interfaces.get(interface), self.physical(interface)) else {
+            return;
+        };
+        let Some(link) = &physical.link else {
+            println!("Switch not available");
+            return;
+        };
+        let packet = if packet.vlan == iface.vlan { packet } else { Rc::new(packet.with_vlan(iface.vlan)) };
+        if let Some(capture) = &physical.capture {
+            capture.borrow_mut().record(sim.now(), Direction::Out, &packet);
         }
-        None
+        link.transmit(packet, sim);
     }
 
-    pub fn get_router_info(&self) -> String {
+    pub fn get_router_info(&self, now: Duration) -> String {
+        let names: Vec<String> = self.interfaces.iter().map(|iface| iface.name.clone()).collect();
+        let routes: Vec<String> = self.routing_table
+            .iter()
+            .map(|(network, (interface, next_hop), source)| {
+                let next_hop = next_hop.map_or_else(|| "direct".to_string(), |ip| ip.to_string());
+                format!("{:<18} {:<15} {:<8} {source}", network.to_string(), next_hop, names[*interface])
+            })
+            .collect();
+        let rip = self.rip.as_ref().map_or_else(String::new, |rip| format!("{}\n", rip.describe(&names, now)));
+        let ospf = self.ospf.as_ref().map_or_else(String::new, |ospf| format!("{}\n", ospf.describe(&names, now)));
+        let bgp = self.bgp.as_ref().map_or_else(String::new, |bgp| format!("{}\n", bgp.describe(&names, now)));
+        let dhcp = self.dhcp_server.as_ref().map_or_else(String::new, |server| format!("{}\n", server.describe(&names, now)));
         format!(
-            "======================================\nROUTER: {}\nARP Table: {:#?}\nOutgoing Packets: {:#?}\nIncoming Packets: {:#?}\n======================================\n",
-            self.ip_address, self.arp_table, self.outgoing_packets, self.incoming_packets
+            "======================================\nROUTER: {}\nRouting Table:\n{}\n{bgp}{ospf}{rip}{dhcp}ARP Table:\n{}\nOutgoing Packets: {:#?}\nIncoming Packets: {:#?}\n======================================\n",
+            self.ip_address, routes.join("\n"), self.arp_cache.describe(now), self.outgoing_packets, self.incoming_packets
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use crate::device::{Device, DeviceRef};
+    use crate::link::{Link, LinkConfig};
+
+    fn net(s: &str) -> Ipv4Net { s.parse().unwrap() }
+
+    fn router() -> Router {
+        let mut router = Router::new(Ipv4Addr::new(10, 0, 0, 1));
+        for (index, name) in ["eth0", "eth1", "eth2"].iter().enumerate() {
+            router.add_interface(name, MacAddr::from([0xAA, 0, 0, 0, 0, index as u8]));
+        }
+        router
+    }
+
+    #[test]
+    fn lookup_uses_longest_matching_prefix() {
+        let mut router = router();
+        router.populate_routing_table(net("0.0.0.0/0"), 0, Some(Ipv4Addr::new(192, 168, 0, 1)));
+        router.populate_routing_table(net("10.0.0.0/8"), 1, None);
+        router.populate_routing_table(net("10.1.0.0/16"), 2, None);
+        assert_eq!(router.lookup_route(Ipv4Addr::new(10, 1, 2, 3)), Some(&(2, None)));
+        assert_eq!(router.lookup_route(Ipv4Addr::new(10, 2, 0, 1)), Some(&(1, None)));
+        assert_eq!(router.lookup_route(Ipv4Addr::new(8, 8, 8, 8)), Some(&(0, Some(Ipv4Addr::new(192, 168, 0, 1)))));
+    }
+
+    #[test]
+    fn more_trusted_sources_replace_routes() {
+        let mut router = router();
+        let network = net("10.5.0.0/16");
+        let (rip_hop, ospf_hop) = (Some(Ipv4Addr::new(10, 0, 0, 2)), Some(Ipv4Addr::new(10, 0, 0, 3)));
+        assert!(router.install_route(network, (0, rip_hop), RouteSource::Rip));
+        assert!(router.install_route(network, (1, ospf_hop), RouteSource::Ospf));
+        assert!(!router.install_route(network, (0, rip_hop), RouteSource::Rip));
+        assert!(!router.install_route(network, (2, None), RouteSource::Ibgp));
+        assert_eq!(router.routing_table(), &[(network, (1, ospf_hop), RouteSource::Ospf)]);
+        assert!(router.install_route(network, (2, None), RouteSource::Static));
+        assert_eq!(router.routing_table(), &[(network, (2, None), RouteSource::Static)]);
+    }
+
+    #[test]
+    fn standby_route_takes_over_on_removal() {
+        let mut router = router();
+        let network = net("10.5.0.0/16");
+        let (rip_hop, ospf_hop) = (Some(Ipv4Addr::new(10, 0, 0, 2)), Some(Ipv4Addr::new(10, 0, 0, 3)));
+        router.install_route(network, (0, rip_hop), RouteSource::Rip);
+        router.install_route(network, (2, None), RouteSource::Ibgp);
+        router.install_route(network, (1, ospf_hop), RouteSource::Ospf);
+        assert!(router.remove_route(network, RouteSource::Ospf));
+        assert_eq!(router.routing_table(), &[(network, (0, rip_hop), RouteSource::Rip)]);
+        // Withdrawing a standby route leaves the table alone
+        assert!(!router.remove_route(network, RouteSource::Ibgp));
+        assert!(router.remove_route(network, RouteSource::Rip));
+        assert!(router.routing_table().is_empty());
+    }
+
+    #[test]
+    fn ebgp_outranks_igp_routes_and_ibgp_yields_to_them() {
+        let mut router = router();
+        let network = net("10.9.0.0/24");
+        let hop = |subnet: u8| Some(Ipv4Addr::new(10, 0, subnet, 2));
+        let (ospf_hop, ebgp_hop, ibgp_hop) = (hop(0), hop(1), hop(2));
+        router.install_route(network, (0, ospf_hop), RouteSource::Ospf);
+        assert!(!router.install_route(network, (2, ibgp_hop), RouteSource::Ibgp));
+        assert!(router.install_route(network, (1, ebgp_hop), RouteSource::Ebgp));
+        assert_eq!(router.routing_table(), &[(network, (1, ebgp_hop), RouteSource::Ebgp)]);
+        assert!(router.remove_route(network, RouteSource::Ebgp));
+        assert_eq!(router.routing_table(), &[(network, (0, ospf_hop), RouteSource::Ospf)]);
+        assert!(router.remove_route(network, RouteSource::Ospf));
+        assert_eq!(router.routing_table(), &[(network, (2, ibgp_hop), RouteSource::Ibgp)]);
+    }
+
+    // Remembers the frames that reach it
+    #[derive(Debug, Default)]
+    struct Probe {
+        frames: Vec<Rc<Packet>>,
+    }
+
+    impl Device for Probe {
+        fn attach_link(&mut self, _port: usize, _attachment: Attachment) {}
+        fn receive_packet(&mut self, packet: Rc<Packet>, _port: usize, _sim: &mut Simulator) { self.frames.push(packet); }
+        fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
+        fn get_ip_address(&self) -> Option<Ipv4Addr> { None }
+        fn get_device_info(&self, _now: Duration) -> String { String::new() }
+    }
+
+    #[test]
+    fn subinterfaces_route_between_vlans_on_one_link() {
+        let mut sim = Simulator::new();
+        let mut router = router();
+        let vlan10 = router.add_subinterface("eth0.10", 0, 10).unwrap();
+        let vlan20 = router.add_subinterface("eth0.20", 0, 20).unwrap();
+        assert_eq!(router.add_subinterface("eth0.10.5", vlan10, 5), None);
+        router.populate_routing_table(net("10.0.10.0/24"), vlan10, None);
+        router.populate_routing_table(net("10.0.20.0/24"), vlan20, None);
+        let (a, b) = (MacAddr::from([0xBB, 0, 0, 0, 0, 1]), MacAddr::from([0xBB, 0, 0, 0, 0, 2]));
+        router.add_arp_entry(Ipv4Addr::new(10, 0, 20, 5), b);
+        let router = Rc::new(RefCell::new(router));
+        let trunk = Rc::new(RefCell::new(Probe::default()));
+        let (router_ref, trunk_ref): (DeviceRef, DeviceRef) = (router.clone(), trunk.clone());
+        let _link = Link::connect(&router_ref, 0, &trunk_ref, 0, LinkConfig::default());
+        let mut arrive = |frame: Packet| {
+            router.borrow_mut().forward_packet(Rc::new(frame), 0, &mut sim);
+            while sim.step() {}
+            std::mem::take(&mut trunk.borrow_mut().frames)
+        };
+
+        // A packet from VLAN 10 goes back out of the same link tagged for VLAN 20
+        let eth0 = MacAddr::from([0xAA, 0, 0, 0, 0, 0]);
+        let packet = Packet::new(a, eth0, Ipv4Addr::new(10, 0, 10, 5), Ipv4Addr::new(10, 0, 20, 5), b"hi".to_vec());
+        let out = arrive(packet.with_vlan(Some(10)));
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].vlan, out[0].src_mac, out[0].dest_mac, out[0].header.ttl), (Some(20), eth0, b, 63));
+
+        // ARP on a subinterface is answered in its VLAN; VLANs without one are ignored
+        let request = Packet::arp_request(a, MacAddr::BROADCAST, Ipv4Addr::new(10, 0, 10, 5), Ipv4Addr::new(10, 0, 0, 1));
+        let out = arrive(request.with_vlan(Some(10)));
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].vlan, out[0].arp.operation, out[0].dest_mac), (Some(10), ArpOp::Reply, a));
+        assert!(arrive(request.with_vlan(Some(30))).is_empty());
+        assert!(arrive(packet.with_vlan(Some(30))).is_empty());
+    }
+
+    #[test]
+    fn expiring_ttl_is_reported_to_the_source() {
+        let mut sim = Simulator::new();
+        let mut router = router();
+        router.populate_routing_table(net("10.0.0.0/24"), 0, None);
+        router.populate_routing_table(net("10.1.0.0/24"), 1, None);
+        let (source, dest) = (Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 1, 0, 5));
+        router.add_arp_entry(source, MacAddr::from([0xBB, 0, 0, 0, 0, 1]));
+        router.add_arp_entry(dest, MacAddr::from([0xBB, 0, 0, 0, 0, 2]));
+        let router = Rc::new(RefCell::new(router));
+        let device: DeviceRef = router.clone();
+        let peers: Vec<DeviceRef> = (0..2).map(|_| Rc::new(RefCell::new(Router::new(Ipv4Addr::UNSPECIFIED))) as DeviceRef).collect();
+        let _links: Vec<_> = peers.iter().enumerate().map(|(port, peer)| Link::connect(&device, port, peer, 0, LinkConfig::default())).collect();
+        // Frames from the source to eth0 with the given TTL
+        let frame = |packet: &Packet, ttl: u8| {
+            let mut frame = packet.rebuild_l3(MacAddr::from([0xBB, 0, 0, 0, 0, 1]), MacAddr::from([0xAA, 0, 0, 0, 0, 0]));
+            frame.header.ttl = ttl;
+            frame
+        };
+        let arrive = |frame: Packet, sim: &mut Simulator| {
+            router.borrow_mut().forward_packet(Rc::new(frame), 0, sim);
+            router.borrow_mut().outgoing_packets.pop()
+        };
+
+        // TTL 2 is forwarded with 1 left, TTL 1 runs out here
+        let packet = Packet::new(MacAddr::ZERO, MacAddr::ZERO, source, dest, b"probe".to_vec());
+        let forwarded = arrive(frame(&packet, 2), &mut sim).unwrap();
+        assert_eq!((forwarded.dest_ip, forwarded.header.ttl), (dest, 1));
+        let error = arrive(frame(&packet, 1), &mut sim).unwrap();
+        assert_eq!((error.src_ip, error.dest_ip, error.dest_mac), (Ipv4Addr::new(10, 0, 0, 1), source, MacAddr::from([0xBB, 0, 0, 0, 0, 1])));
+        assert_eq!(error.icmp_message(), Some(IcmpMessage::time_exceeded(&frame(&packet, 1))));
+
+        // An ICMP error whose TTL runs out is dropped without another error about it
+        let error = Packet::icmp(Ipv4Addr::new(10, 0, 0, 1), dest, &IcmpMessage::time_exceeded(&packet));
+        assert!(arrive(frame(&error, 1), &mut sim).is_none());
+        assert!(arrive(frame(&error, 2), &mut sim).is_some());
+    }
+}
@@ -0,0 +1,676 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::address;
+use crate::capture::{Capture, CaptureRef};
+use crate::device::DeviceRef;
+use crate::dns::{self, Lookup, LookupResult, RecordType};
+use crate::host::Host;
+use crate::packet::{DecodeError, Packet};
+use crate::ping::{PingOptions, PingReport};
+use crate::sim::Simulator;
+use crate::traceroute::{TracerouteOptions, TracerouteReport};
+use crate::topology::{DeviceHandle, DnsRecordType, Format, Network, NetworkLink, TopologyError, DEFAULT_TOPOLOGY};
+
+// Once the steps are done, events are processed for at most this much more simulated time
+const DRAIN_LIMIT: Duration = Duration::from_secs(60);
+
+/*
+Scenario files script a headless run over a topology, in the same formats as topology files.
+The topology path is relative to the scenario file; the default network is used without one.
+Steps run in order, and only "wait" and the interval between sent packets advance the clock.
+
+topology = "../topologies/default.toml"
+output = "results.json"
+
+[[steps]]
+action = "send"
+from = "Host A"
+to = "Host I"        # Device name, IP address, or DNS name the sending host looks up
+count = 3
+interval_ms = 10.0
+size = 64            # Payload bytes
+
+[[steps]]
+action = "ping"      # ICMP echo; the step lasts until the last reply or timeout
+from = "Host A"
+to = "Router 3"
+count = 4            # Defaults: 4 probes, 1000 ms apart, 56 bytes, 1000 ms timeout
+interval_ms = 1000.0
+size = 56
+timeout_ms = 1000.0
+
+[[steps]]
+action = "traceroute" # Probes with increasing TTL; the step lasts until the trace is done
+from = "Host A"
+to = "Host I"
+max_hops = 30         # Defaults: 30 hops, 3 probes per hop, 1000 ms timeout per hop
+probes = 3
+timeout_ms = 1000.0
+
+[[steps]]
+action = "link_down" # or "link_up"
+a = "Switch 1"
+b = "Router 1"
+
+[[steps]]
+action = "wait"
+ms = 50.0
+
+[[steps]]
+action = "gratuitous_arp" # Host announces its address; duplicates show up in the host stats
+from = "Host A"
+
+[[steps]]
+action = "lookup"    # DNS query from a host's resolver; the step lasts until it is answered
+from = "Host A"
+name = "www.lab"     # For PTR lookups an IP address stands for its in-addr.arpa name
+type = "A"           # A (default), CNAME or PTR
+
+[[steps]]
+action = "inject"    # Raw Ethernet frame (ARP or IPv4) sent from a host's link
+from = "Host A"
+frame = "ff:ff:ff:ff:ff:ff aa:bb:cc:dd:01:0a 08:06 ..."
+
+Captures record switch ports or router interfaces ("<device>" for all of them) for the
+whole run, and are written as PCAPNG for .pcapng files and PCAP otherwise.
+
+[[captures]]
+point = "Router 3:eth1"
+file = "router3.pcapng"
+*/
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    #[serde(default)]
+    pub topology: Option<String>,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub captures: Vec<CaptureConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub point: String,
+    pub file: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    Send {
+        from: String,
+        to: String,
+        #[serde(default = "default_count")]
+        count: usize,
+        #[serde(default)]
+        interval_ms: f64,
+        #[serde(default)]
+        size: usize,
+    },
+    Ping {
+        from: String,
+        to: String,
+        #[serde(default = "default_ping_count")]
+        count: usize,
+        #[serde(default = "default_ping_ms")]
+        interval_ms: f64,
+        #[serde(default = "default_ping_size")]
+        size: usize,
+        #[serde(default = "default_ping_ms")]
+        timeout_ms: f64,
+    },
+    Traceroute {
+        from: String,
+        to: String,
+        #[serde(default = "default_max_hops")]
+        max_hops: u8,
+        #[serde(default = "default_trace_probes")]
+        probes: usize,
+        #[serde(default = "default_trace_timeout_ms")]
+        timeout_ms: f64,
+    },
+    LinkDown { a: String, b: String },
+    LinkUp { a: String, b: String },
+    Wait { ms: f64 },
+    Inject { from: String, frame: String },
+    GratuitousArp { from: String },
+    Lookup {
+        from: String,
+        name: String,
+        #[serde(rename = "type", default = "default_lookup_type")]
+        kind: DnsRecordType,
+    },
+}
+
+fn default_count() -> usize { 1 }
+
+fn default_ping_count() -> usize { PingOptions::default().count }
+
+fn default_ping_ms() -> f64 { millis(PingOptions::default().interval) }
+
+fn default_ping_size() -> usize { PingOptions::default().size }
+
+fn default_max_hops() -> u8 { TracerouteOptions::default().max_hops }
+
+fn default_trace_probes() -> usize { TracerouteOptions::default().probes }
+
+fn default_trace_timeout_ms() -> f64 { millis(TracerouteOptions::default().timeout) }
+
+fn default_lookup_type() -> DnsRecordType { DnsRecordType::A }
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io { path: String, source: std::io::Error },
+    Parse(String),
+    Serialize(String),
+    Topology(TopologyError),
+    UnknownDevice { step: usize, name: String },
+    NotAHost { step: usize, name: String },
+    UnknownLink { step: usize, a: String, b: String },
+    InvalidFrame { step: usize, reason: String },
+    Unresolved { step: usize, name: String, reason: String },
+    InvalidTime { step: usize, field: String, ms: f64 },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io { path, source } => write!(f, "cannot access {path}: {source}"),
+            ScenarioError::Parse(message) => write!(f, "invalid scenario file: {message}"),
+            ScenarioError::Serialize(message) => write!(f, "cannot write results: {message}"),
+            ScenarioError::Topology(e) => write!(f, "{e}"),
+            ScenarioError::UnknownDevice { step, name } => {
+                write!(f, "step #{}: unknown device or address {name:?}", step + 1)
+            }
+            ScenarioError::NotAHost { step, name } => write!(f, "step #{}: {name} is not a host", step + 1),
+            ScenarioError::UnknownLink { step, a, b } => {
+                write!(f, "step #{}: no link between {a:?} and {b:?}", step + 1)
+            }
+            ScenarioError::InvalidFrame { step, reason } => write!(f, "step #{}: invalid frame: {reason}", step + 1),
+            ScenarioError::Unresolved { step, name, reason } => {
+                write!(f, "step #{}: cannot resolve {name:?}: {reason}", step + 1)
+            }
+            ScenarioError::InvalidTime { step, field, ms } => {
+                write!(f, "step #{}: {field} = {ms:?} is not a valid time", step + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<TopologyError> for ScenarioError {
+    fn from(e: TopologyError) -> Self { ScenarioError::Topology(e) }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepResult {
+    pub time_ms: f64,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<PingReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traceroute: Option<TracerouteReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostStats {
+    pub name: String,
+    pub ip: String,
+    pub sent: usize,
+    pub received: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub address_conflicts: Vec<String>, // MAC addresses of other devices using the host's address
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    pub a: String,
+    pub b: String,
+    pub up: bool,
+    pub delivered: usize,
+    pub dropped: usize,
+}
+
+// Outcome of a scenario run
+#[derive(Debug, Serialize)]
+pub struct ScenarioReport {
+    pub end_time_ms: f64,
+    pub steps: Vec<StepResult>,
+    pub hosts: Vec<HostStats>,
+    pub links: Vec<LinkStats>,
+}
+
+impl ScenarioReport {
+    pub fn save(&self, path: &Path) -> Result<(), ScenarioError> {
+        let format = Format::from_path(path)
+            .ok_or_else(|| ScenarioError::Topology(TopologyError::UnsupportedFormat(path.display().to_string())))?;
+        let contents = match format {
+            Format::Toml => toml::to_string_pretty(self).map_err(|e| ScenarioError::Serialize(e.to_string()))?,
+            Format::Json => serde_json::to_string_pretty(self).map_err(|e| ScenarioError::Serialize(e.to_string()))?,
+            Format::Yaml => serde_yaml::to_string(self).map_err(|e| ScenarioError::Serialize(e.to_string()))?,
+        };
+        std::fs::write(path, contents)
+            .map_err(|source| ScenarioError::Io { path: path.display().to_string(), source })
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "==============================================")?;
+        writeln!(f, "SCENARIO RESULTS ({:.3} ms)", self.end_time_ms)?;
+        for step in &self.steps {
+            writeln!(f, "[{:>10.3} ms] {}", step.time_ms, step.description)?;
+            let details = [step.ping.as_ref().map(ToString::to_string), step.traceroute.as_ref().map(ToString::to_string)];
+            for line in details.iter().flatten().flat_map(|details| details.lines()) {
+                writeln!(f, "               {line}")?;
+            }
+        }
+        writeln!(f, "Hosts:")?;
+        for host in &self.hosts {
+            writeln!(f, "  {:<12} {:<15} sent {:>4}  received {:>4}", host.name, host.ip, host.sent, host.received)?;
+            if !host.address_conflicts.is_empty() {
+                writeln!(f, "    duplicate address, also used by {}", host.address_conflicts.join(", "))?;
+            }
+        }
+        writeln!(f, "Links:")?;
+        for link in &self.links {
+            writeln!(
+                f,
+                "  {:<28} {:<4} delivered {:>4}  dropped {:>4}",
+                format!("{} <-> {}", link.a, link.b),
+                if link.up { "up" } else { "down" },
+                link.delivered,
+                link.dropped
+            )?;
+        }
+        write!(f, "==============================================")
+    }
+}
+
+pub struct Scenario {
+    network: Network,
+    steps: Vec<Step>,
+    pub output: Option<PathBuf>, // Where the report is written, relative paths resolved like the topology
+    captures: Vec<(PathBuf, CaptureRef)>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let format = Format::from_path(path)
+            .ok_or_else(|| ScenarioError::Topology(TopologyError::UnsupportedFormat(path.display().to_string())))?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|source| ScenarioError::Io { path: path.display().to_string(), source })?;
+        Self::parse(&contents, format, path.parent().unwrap_or(Path::new("")))
+    }
+
+    // Topology, capture and output paths are relative to `base`
+    pub fn parse(contents: &str, format: Format, base: &Path) -> Result<Self, ScenarioError> {
+        let file: ScenarioFile = match format {
+            Format::Toml => toml::from_str(contents).map_err(|e| ScenarioError::Parse(e.to_string()))?,
+            Format::Json => serde_json::from_str(contents).map_err(|e| ScenarioError::Parse(e.to_string()))?,
+            Format::Yaml => serde_yaml::from_str(contents).map_err(|e| ScenarioError::Parse(e.to_string()))?,
+        };
+
+        let network = match &file.topology {
+            Some(topology) => Network::load(&base.join(topology))?,
+            None => Network::parse(DEFAULT_TOPOLOGY, Format::Toml)?,
+        };
+        let mut captures = Vec::new();
+        for config in &file.captures {
+            let capture = Capture::new(&config.point);
+            network.attach_capture(&config.point, &capture)?;
+            captures.push((base.join(&config.file), capture));
+        }
+
+        Ok(Self {
+            network,
+            steps: file.steps,
+            output: file.output.map(|output| base.join(output)),
+            captures,
+        })
+    }
+
+    // Runs every step, then lets in-flight traffic settle
+    pub fn run(&self, sim: &mut Simulator) -> Result<ScenarioReport, ScenarioError> {
+        self.network.start(sim);
+        let mut results = Vec::new();
+        for (index, step) in self.steps.iter().enumerate() {
+            let start = sim.now();
+            let mut ping = None;
+            let mut traceroute = None;
+            let description = match step {
+                Step::Send { from, to, count, interval_ms, size } => {
+                    let source = self.find_host(from, index)?;
+                    let dest_ip = self.resolve_address(to, &source, index, sim)?;
+                    let interval = duration(index, "interval_ms", *interval_ms)?;
+                    let device: DeviceRef = source.clone();
+                    for i in 0..*count {
+                        if i > 0 {
+                            sim.run_until(later(sim, interval, index, "interval_ms", *interval_ms)?);
+                        }
+                        sim.with_device(&device, |sim| source.borrow_mut().send_packet(dest_ip, vec![0; *size], sim));
+                    }
+                    format!("{from} sent {count} packet(s) of {size} bytes to {to} ({dest_ip})")
+                }
+                Step::Ping { from, to, count, interval_ms, size, timeout_ms } => {
+                    let source = self.find_host(from, index)?;
+                    let dest_ip = self.resolve_address(to, &source, index, sim)?;
+                    let options = PingOptions {
+                        count: *count,
+                        interval: duration(index, "interval_ms", *interval_ms)?,
+                        size: *size,
+                        timeout: duration(index, "timeout_ms", *timeout_ms)?,
+                    };
+                    let device: DeviceRef = source.clone();
+                    let id = sim.with_device(&device, |sim| source.borrow_mut().start_ping(dest_ip, options, sim));
+                    while !source.borrow().ping_report(id).is_some_and(|report| report.finished) && sim.step() {}
+                    let report = source.borrow().ping_report(id);
+                    let description = match &report {
+                        Some(report) => format!(
+                            "{from} pinged {to} ({dest_ip}): {}/{} replies",
+                            report.received, report.transmitted
+                        ),
+                        None => format!("{from} pinged {to} ({dest_ip})"),
+                    };
+                    ping = report;
+                    description
+                }
+                Step::Traceroute { from, to, max_hops, probes, timeout_ms } => {
+                    let source = self.find_host(from, index)?;
+                    let dest_ip = self.resolve_address(to, &source, index, sim)?;
+                    let options = TracerouteOptions {
+                        max_hops: *max_hops,
+                        probes: *probes,
+                        timeout: duration(index, "timeout_ms", *timeout_ms)?,
+                    };
+                    let device: DeviceRef = source.clone();
+                    let id = sim.with_device(&device, |sim| source.borrow_mut().start_traceroute(dest_ip, options, sim));
+                    while !source.borrow().traceroute_report(id).is_some_and(|report| report.finished) && sim.step() {}
+                    let report = source.borrow().traceroute_report(id);
+                    let description = match &report {
+                        Some(report) => format!(
+                            "{from} traced the route to {to} ({dest_ip}): {} hop(s), {}",
+                            report.hops.len(),
+                            if report.reached { "reached" } else { "not reached" }
+                        ),
+                        None => format!("{from} traced the route to {to} ({dest_ip})"),
+                    };
+                    traceroute = report;
+                    description
+                }
+                Step::LinkDown { a, b } => {
+                    self.find_link(a, b, index)?.link.borrow_mut().set_up(false);
+                    format!("Link {a} <-> {b} down")
+                }
+                Step::LinkUp { a, b } => {
+                    self.find_link(a, b, index)?.link.borrow_mut().set_up(true);
+                    format!("Link {a} <-> {b} up")
+                }
+                Step::Inject { from, frame } => {
+                    let source = self.find_host(from, index)?;
+                    let bytes = parse_hex(frame).ok_or_else(|| ScenarioError::InvalidFrame {
+                        step: index,
+                        reason: "expected hex bytes".to_string(),
+                    })?;
+                    let packet = Packet::from_bytes(&bytes).map_err(|e: DecodeError| ScenarioError::InvalidFrame {
+                        step: index,
+                        reason: e.to_string(),
+                    })?;
+                    let description = format!(
+                        "{from} injected a {} byte {} frame for {}",
+                        bytes.len(),
+                        if packet.is_arp { "ARP" } else if packet.is_bpdu() { "BPDU" } else { "IPv4" },
+                        packet.dest_ip
+                    );
+                    let device: DeviceRef = source.clone();
+                    sim.with_device(&device, |sim| source.borrow_mut().inject_frame(Rc::new(packet), sim));
+                    description
+                }
+                Step::GratuitousArp { from } => {
+                    let source = self.find_host(from, index)?;
+                    let device: DeviceRef = source.clone();
+                    sim.with_device(&device, |sim| source.borrow_mut().send_gratuitous_arp(sim));
+                    format!("{from} sent a gratuitous ARP for {}", source.borrow().get_ip_address())
+                }
+                Step::Lookup { from, name, kind } => {
+                    let source = self.find_host(from, index)?;
+                    let kind = match kind {
+                        DnsRecordType::A => RecordType::A,
+                        DnsRecordType::Cname => RecordType::Cname,
+                        DnsRecordType::Ptr => RecordType::Ptr,
+                    };
+                    let query = match address::parse_ipv4(name) {
+                        Ok(ip) if kind == RecordType::Ptr => dns::reverse_name(ip),
+                        _ => name.clone(),
+                    };
+                    let lookup = run_lookup(&source, &query, kind, sim);
+                    let result = lookup.result.unwrap_or_else(|| LookupResult::Failed("not finished".to_string()));
+                    let origin = match lookup.server {
+                        Some(server) => format!("from {server} in {:.3} ms", millis(lookup.finished.unwrap_or(lookup.started) - lookup.started)),
+                        None if matches!(result, LookupResult::Failed(_)) => "failed".to_string(),
+                        None => "from cache".to_string(),
+                    };
+                    format!("{from} looked up {query} {kind} {origin}: {result}")
+                }
+                Step::Wait { ms } => {
+                    let wait = duration(index, "ms", *ms)?;
+                    sim.run_until(later(sim, wait, index, "ms", *ms)?);
+                    format!("Waited {ms} ms")
+                }
+            };
+            results.push(StepResult { time_ms: millis(start), description, ping, traceroute });
+        }
+
+        let deadline = sim.now().saturating_add(DRAIN_LIMIT);
+        while sim.next_event_time().is_some_and(|time| time <= deadline) {
+            sim.step();
+        }
+
+        Ok(ScenarioReport {
+            end_time_ms: millis(sim.now()),
+            steps: results,
+            hosts: self.host_stats(),
+            links: self.network.links
+                .iter()
+                .map(|link| {
+                    let state = link.link.borrow();
+                    LinkStats {
+                        a: link.a.clone(),
+                        b: link.b.clone(),
+                        up: state.is_up(),
+                        delivered: state.frames_delivered,
+                        dropped: state.frames_dropped,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    // Writes the frames recorded by every capture point
+    pub fn save_captures(&self) -> Result<(), ScenarioError> {
+        for (path, capture) in &self.captures {
+            capture.borrow().save(path)
+                .map_err(|source| ScenarioError::Io { path: path.display().to_string(), source })?;
+            println!("Wrote {} frames to {}", capture.borrow().frame_count(), path.display());
+        }
+        Ok(())
+    }
+
+    fn find_host(&self, name: &str, step: usize) -> Result<Rc<RefCell<Host>>, ScenarioError> {
+        match self.network.find(name).map(|node| &node.device) {
+            Some(DeviceHandle::Host(host)) => Ok(Rc::clone(host)),
+            Some(_) => Err(ScenarioError::NotAHost { step, name: name.to_string() }),
+            None => Err(ScenarioError::UnknownDevice { step, name: name.to_string() }),
+        }
+    }
+
+    // A device name stands for the device's address, an IPv4 address for itself, and anything
+    // else is a DNS name for `source` to look up, which advances the clock until it is answered
+    fn resolve_address(&self, target: &str, source: &Rc<RefCell<Host>>, step: usize, sim: &mut Simulator) -> Result<Ipv4Addr, ScenarioError> {
+        if let Some(node) = self.network.find(target) {
+            return node.device
+                .device()
+                .borrow()
+                .get_ip_address()
+                .ok_or_else(|| ScenarioError::UnknownDevice { step, name: target.to_string() });
+        }
+        if let Ok(ip) = address::parse_ipv4(target) {
+            return Ok(ip);
+        }
+        if !dns::is_valid_name(target) {
+            return Err(ScenarioError::UnknownDevice { step, name: target.to_string() });
+        }
+        let unresolved = |reason: String| ScenarioError::Unresolved { step, name: target.to_string(), reason };
+        match run_lookup(source, target, RecordType::A, sim).result {
+            Some(result) => result.address().ok_or_else(|| unresolved(result.to_string())),
+            None => Err(unresolved("no answer".to_string())),
+        }
+    }
+
+    fn find_link(&self, a: &str, b: &str, step: usize) -> Result<&NetworkLink, ScenarioError> {
+        self.network.links
+            .iter()
+            .find(|link| (link.a == a && link.b == b) || (link.a == b && link.b == a))
+            .ok_or_else(|| ScenarioError::UnknownLink { step, a: a.to_string(), b: b.to_string() })
+    }
+
+    fn host_stats(&self) -> Vec<HostStats> {
+        self.network.nodes
+            .iter()
+            .filter_map(|node| match &node.device {
+                DeviceHandle::Host(host) => {
+                    let host = host.borrow();
+                    let (sent, received) = host.packet_counts();
+                    Some(HostStats {
+                        name: node.name.clone(),
+                        ip: host.get_ip_address().to_string(),
+                        sent,
+                        received,
+                        address_conflicts: host.address_conflicts().iter().map(ToString::to_string).collect(),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn millis(time: Duration) -> f64 { time.as_secs_f64() * 1e3 }
+
+// Milliseconds from a step's `field`, which must be a finite, non-negative time
+fn duration(step: usize, field: &str, ms: f64) -> Result<Duration, ScenarioError> {
+    Duration::try_from_secs_f64(ms / 1e3).map_err(|_| ScenarioError::InvalidTime { step, field: field.to_string(), ms })
+}
+
+// The simulated time `delay` from now, unless that is past the end of time
+fn later(sim: &Simulator, delay: Duration, step: usize, field: &str, ms: f64) -> Result<Duration, ScenarioError> {
+    sim.now().checked_add(delay).ok_or_else(|| ScenarioError::InvalidTime { step, field: field.to_string(), ms })
+}
+
+// Looks `name` up with the host's resolver, running the simulation until the lookup is done
+fn run_lookup(host: &Rc<RefCell<Host>>, name: &str, kind: RecordType, sim: &mut Simulator) -> Lookup {
+    let device: DeviceRef = host.clone();
+    let id = sim.with_device(&device, |sim| host.borrow_mut().start_lookup(name, kind, sim));
+    while host.borrow().lookup(id).is_none_or(|lookup| lookup.finished.is_none()) && sim.step() {}
+    host.borrow().lookup(id).cloned().expect("lookups are kept once started")
+}
+
+// Hex bytes, optionally separated by whitespace, ':' or '-'
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace() && *c != ':' && *c != '-').collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(contents: &str) -> Result<ScenarioReport, ScenarioError> {
+        Scenario::parse(contents, Format::Toml, Path::new(""))?.run(&mut Simulator::new())
+    }
+
+    #[test]
+    fn steps_are_reported_in_order() {
+        let report = run(r#"
+            [[steps]]
+            action = "ping"
+            from = "Host A"
+            to = "Host I"
+            count = 2
+            interval_ms = 100.0
+
+            [[steps]]
+            action = "wait"
+            ms = 50.0
+
+            [[steps]]
+            action = "send"
+            from = "Host A"
+            to = "Host I"
+            count = 3
+            interval_ms = 10.0
+        "#).unwrap();
+        assert_eq!(report.steps.len(), 3);
+        let ping = report.steps[0].ping.as_ref().unwrap();
+        assert_eq!((ping.transmitted, ping.received), (2, 2));
+        assert_eq!(report.steps[2].time_ms - report.steps[1].time_ms, 50.0);
+        assert!(report.steps[2].description.starts_with("Host A sent 3 packet(s) of 0 bytes to Host I"));
+        let host = |name: &str| report.hosts.iter().find(|host| host.name == name).unwrap();
+        assert_eq!(host("Host I").received, 5); // Both echo requests and the three packets
+        assert!(report.end_time_ms >= report.steps[2].time_ms + 20.0);
+    }
+
+    #[test]
+    fn unanswered_arp_requests_are_retried() {
+        // The first request for Host B is lost on the way to the switch; the retry a second later gets through
+        let report = run(r#"
+            [[steps]]
+            action = "link_down"
+            a = "Switch 1"
+            b = "Host A"
+
+            [[steps]]
+            action = "send"
+            from = "Host A"
+            to = "Host B"
+
+            [[steps]]
+            action = "link_up"
+            a = "Switch 1"
+            b = "Host A"
+        "#).unwrap();
+        let link = report.links.iter().find(|link| link.a == "Switch 1" && link.b == "Host A").unwrap();
+        assert_eq!(link.dropped, 1);
+        assert_eq!(report.hosts.iter().find(|host| host.name == "Host B").unwrap().received, 1);
+        assert!(report.end_time_ms >= 1000.0);
+    }
+
+    #[test]
+    fn invalid_times_are_rejected() {
+        let wait = |ms: &str| format!("[[steps]]\naction = \"wait\"\nms = {ms}\n");
+        for (ms, shown) in [("-1.0", "-1.0"), ("nan", "NaN"), ("inf", "inf"), ("1e300", "1e300")] {
+            let error = run(&wait(ms)).unwrap_err();
+            assert_eq!(error.to_string(), format!("step #1: ms = {shown} is not a valid time"));
+        }
+        // Each wait is a valid time, but together they run past the end of time
+        let error = run(&(wait("1e22") + &wait("1e22"))).unwrap_err();
+        assert_eq!(error.to_string(), "step #2: ms = 1e22 is not a valid time");
+    }
+}
@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::net::Ipv4Addr;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+use crate::address::Ipv4Net;
+use crate::device::{Device, DeviceRef};
+use crate::link::Link;
+use crate::packet::Packet;
+
+// Seed for the simulator's random number generator, so runs are reproducible
+const DEFAULT_SEED: u64 = 0x5EED;
+
+#[derive(Debug)]
+pub enum Event {
+    // Frame arriving at the given side of a link
+    Deliver {
+        link: Rc<RefCell<Link>>,
+        side: usize,
+        packet: Rc<Packet>,
+        outages: u64, // The link's outage count when the frame was sent
+    },
+    // Timer set by a device for itself
+    Timer {
+        device: Weak<RefCell<dyn Device>>,
+        timer: Timer,
+    },
+}
+
+// What a device asked to be woken up for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    ArpRetry { ip: Ipv4Addr },          // Ask again for an address that has not answered
+    PingProbe { id: u16 },              // Send the next echo request of a ping
+    PingTimeout { id: u16 },            // Stop waiting for replies to a ping
+    TracerouteHop { id: u16, ttl: u8 }, // Stop waiting for answers to one TTL of a traceroute
+    StpHello,                           // Send spanning tree BPDUs and age out old information
+    StpForwardDelay { port: usize },    // Move a switch port to its next spanning tree state
+    RipUpdate,                          // Send the whole RIP table to the neighbors
+    RipTriggered,                       // Send the RIP routes that changed
+    RipExpire { network: Ipv4Net },     // Check whether a RIP route timed out or can be deleted
+    OspfHello,                          // Send OSPF hellos and drop neighbors that went quiet
+    OspfRetransmit,                     // Resend unacknowledged OSPF LSAs
+    BgpTick,                            // Run BGP sessions' timers and the BGP decision process
+    DhcpClient,                         // Resend a DHCP message or renew the lease
+    DnsTimeout { id: u16 },             // Resend a DNS query or try the next server
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    time: Duration,
+    seq: u64, // Keeps events with the same timestamp in FIFO order
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    // Reversed so that the BinaryHeap pops the earliest event first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+#[derive(Debug)]
+pub struct Simulator {
+    now: Duration,
+    queue: BinaryHeap<Scheduled>,
+    next_seq: u64,
+    rng_state: u64,
+    current: Option<Weak<RefCell<dyn Device>>>, // Device handling the current event, which timers are set for
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self {
+            now: Duration::ZERO,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            rng_state: DEFAULT_SEED,
+            current: None,
+        }
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn now(&self) -> Duration { self.now }
+
+    pub fn pending_events(&self) -> usize { self.queue.len() }
+
+    pub fn next_event_time(&self) -> Option<Duration> { self.queue.peek().map(|next| next.time) }
+
+    // Schedules an event at an absolute virtual time, never earlier than now
+    pub fn schedule_at(&mut self, time: Duration, event: Event) {
+        self.queue.push(Scheduled {
+            time: time.max(self.now),
+            seq: self.next_seq,
+            event,
+        });
+        self.next_seq += 1;
+    }
+
+    // Sets a timer for the device handling the current event (see with_device)
+    pub fn schedule_timer(&mut self, delay: Duration, timer: Timer) {
+        let Some(device) = self.current.clone() else {
+            println!("No device to set {timer:?} for");
+            return;
+        };
+        self.schedule_at(self.now.saturating_add(delay), Event::Timer { device, timer });
+    }
+
+    // Runs `f` on behalf of `device`, so that timers it sets go to that device.
+    // Events set this up themselves; this is for actions started from outside, like the GUI.
+    pub fn with_device<R>(&mut self, device: &DeviceRef, f: impl FnOnce(&mut Simulator) -> R) -> R {
+        let previous = self.current.replace(Rc::downgrade(device));
+        let result = f(self);
+        self.current = previous;
+        result
+    }
+
+    // Uniformly distributed value in [0, 1) from a deterministic SplitMix64 generator
+    pub fn random(&mut self) -> f64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Processes the next event, advancing the clock to its timestamp.
+    // Returns false if there was nothing to process.
+    pub fn step(&mut self) -> bool {
+        let Some(scheduled) = self.queue.pop() else {
+            return false;
+        };
+        self.now = scheduled.time;
+
+        match scheduled.event {
+            Event::Deliver { link, side, packet, outages } => {
+                // Frames still on the wire when the link went down are lost with it
+                if !link.borrow().carries(outages) {
+                    let mut link = link.borrow_mut();
+                    link.frames_dropped += 1;
+                    println!("Link down, dropping packet for {}", packet.dest_ip);
+                    return true;
+                }
+                link.borrow_mut().frames_delivered += 1;
+                let end = link.borrow().end(side);
+                let Some((device, port)) = end else {
+                    return true;
+                };
+                self.with_device(&device, |sim| device.borrow_mut().receive_packet(packet, port, sim));
+            }
+            Event::Timer { device, timer } => {
+                if let Some(device) = device.upgrade() {
+                    self.with_device(&device, |sim| device.borrow_mut().handle_timer(timer, sim));
+                }
+            }
+        }
+        true
+    }
+
+    // Processes every event up to and including `time`, then moves the clock to `time`
+    pub fn run_until(&mut self, time: Duration) {
+        while self.queue.peek().is_some_and(|next| next.time <= time) {
+            self.step();
+        }
+        self.now = self.now.max(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::Attachment;
+
+    // Remembers the timers it was woken up for
+    #[derive(Debug, Default)]
+    struct Sleeper {
+        fired: Vec<(Duration, Timer)>,
+    }
+
+    impl Device for Sleeper {
+        fn attach_link(&mut self, _port: usize, _attachment: Attachment) {}
+        fn receive_packet(&mut self, _packet: Rc<Packet>, _port: usize, _sim: &mut Simulator) {}
+        fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
+        fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) { self.fired.push((sim.now(), timer)); }
+        fn get_ip_address(&self) -> Option<Ipv4Addr> { None }
+        fn get_device_info(&self, _now: Duration) -> String { String::new() }
+    }
+
+    fn sleeper(sim: &mut Simulator, timers: &[(u64, u16)]) -> (Rc<RefCell<Sleeper>>, DeviceRef) {
+        let sleeper = Rc::new(RefCell::new(Sleeper::default()));
+        let device: DeviceRef = sleeper.clone();
+        sim.with_device(&device, |sim| {
+            for &(ms, id) in timers {
+                sim.schedule_timer(Duration::from_millis(ms), Timer::PingProbe { id });
+            }
+        });
+        (sleeper, device)
+    }
+
+    fn fired(sleeper: &RefCell<Sleeper>) -> Vec<(u64, u16)> {
+        let sleeper = sleeper.borrow();
+        let ids = sleeper.fired.iter().map(|(time, timer)| match timer {
+            Timer::PingProbe { id } => (time.as_millis() as u64, *id),
+            other => panic!("unexpected {other:?}"),
+        });
+        ids.collect()
+    }
+
+    #[test]
+    fn events_run_in_time_order_and_ties_in_scheduling_order() {
+        let mut sim = Simulator::new();
+        let (sleeper, _device) = sleeper(&mut sim, &[(3, 1), (1, 2), (2, 3), (1, 4), (1, 5)]);
+        while sim.step() {}
+        assert_eq!(fired(&sleeper), [(1, 2), (1, 4), (1, 5), (2, 3), (3, 1)]);
+        assert_eq!(sim.now(), Duration::from_millis(3));
+    }
+
+    #[test]
+    fn run_until_stops_at_its_limit() {
+        let mut sim = Simulator::new();
+        let (sleeper, _device) = sleeper(&mut sim, &[(10, 1), (20, 2), (30, 3)]);
+        sim.run_until(Duration::from_millis(20));
+        assert_eq!(fired(&sleeper), [(10, 1), (20, 2)]);
+        assert_eq!((sim.now(), sim.pending_events()), (Duration::from_millis(20), 1));
+        assert_eq!(sim.next_event_time(), Some(Duration::from_millis(30)));
+
+        // The clock moves on to the limit without events, and never back
+        sim.run_until(Duration::from_millis(25));
+        assert_eq!(sim.now(), Duration::from_millis(25));
+        sim.run_until(Duration::from_millis(5));
+        assert_eq!(sim.now(), Duration::from_millis(25));
+        assert_eq!(fired(&sleeper).len(), 2);
+    }
+
+    #[test]
+    fn timers_need_a_device() {
+        let mut sim = Simulator::new();
+        sim.schedule_timer(Duration::from_millis(1), Timer::StpHello);
+        assert_eq!(sim.pending_events(), 0);
+
+        // Timers of devices that are gone are skipped
+        let (_, device) = sleeper(&mut sim, &[(1, 1)]);
+        drop(device);
+        assert!(sim.step());
+        assert_eq!(sim.pending_events(), 0);
+    }
+}
@@ -0,0 +1,626 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::MacAddr;
+use crate::packet::Packet;
+
+// Bridge priority when not configured (IEEE 802.1D default)
+pub const DEFAULT_PRIORITY: u16 = 32768;
+// Priority part of every port identifier
+const PORT_PRIORITY: u16 = 128;
+// Added to the message age of root information at every bridge it passes
+const MESSAGE_AGE_INCREMENT: Duration = Duration::from_secs(1);
+
+const BPDU_CONFIG: u8 = 0x00;
+const BPDU_TCN: u8 = 0x80;
+const CONFIG_LEN: usize = 35;
+const FLAG_TOPOLOGY_CHANGE: u8 = 0x01;
+const FLAG_TOPOLOGY_CHANGE_ACK: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StpTimers {
+    pub hello_time: Duration,    // Between the BPDUs a bridge sends on its designated ports
+    pub max_age: Duration,       // How long a port keeps information it stops hearing
+    pub forward_delay: Duration, // Time spent listening, then learning, before forwarding
+}
+
+impl Default for StpTimers {
+    fn default() -> Self {
+        Self {
+            hello_time: Duration::from_secs(2),
+            max_age: Duration::from_secs(20),
+            forward_delay: Duration::from_secs(15),
+        }
+    }
+}
+
+// Lower is better: the bridge with the lowest ID becomes the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BridgeId {
+    pub priority: u16,
+    pub mac: MacAddr,
+}
+
+impl fmt::Display for BridgeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.priority, self.mac)
+    }
+}
+
+// What a BPDU says about the path to the root, compared field by field; lower is better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PriorityVector {
+    pub root: BridgeId,
+    pub cost: u32,          // Root path cost of the sending bridge
+    pub bridge: BridgeId,   // Sending bridge
+    pub port: u16,          // Sending port identifier
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bpdu {
+    Config {
+        vector: PriorityVector,
+        message_age: Duration, // Time since the root sent the information
+        timers: StpTimers,
+        topology_change: bool, // Set by the root while bridges should flush learned addresses
+        topology_change_ack: bool,
+    },
+    // Sent toward the root by a bridge that saw one of its ports change state
+    TopologyChange,
+}
+
+impl Bpdu {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Bpdu::TopologyChange => vec![0, 0, 0, BPDU_TCN],
+            Bpdu::Config { vector, message_age, timers, topology_change, topology_change_ack } => {
+                let mut flags = 0;
+                if *topology_change {
+                    flags |= FLAG_TOPOLOGY_CHANGE;
+                }
+                if *topology_change_ack {
+                    flags |= FLAG_TOPOLOGY_CHANGE_ACK;
+                }
+                let mut bytes = vec![0, 0, 0, BPDU_CONFIG, flags]; // Protocol 0, version 0 (802.1D)
+                push_bridge_id(&mut bytes, vector.root);
+                bytes.extend_from_slice(&vector.cost.to_be_bytes());
+                push_bridge_id(&mut bytes, vector.bridge);
+                bytes.extend_from_slice(&vector.port.to_be_bytes());
+                for time in [*message_age, timers.max_age, timers.hello_time, timers.forward_delay] {
+                    bytes.extend_from_slice(&encode_time(time).to_be_bytes());
+                }
+                bytes
+            }
+        }
+    }
+
+    // None for other protocols, versions or BPDU types, and truncated BPDUs
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[..2] != [0, 0] {
+            return None;
+        }
+        match bytes[3] {
+            BPDU_TCN => Some(Bpdu::TopologyChange),
+            BPDU_CONFIG if bytes.len() >= CONFIG_LEN => {
+                let u16_at = |offset: usize| u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+                let time_at = |offset: usize| decode_time(u16_at(offset));
+                Some(Bpdu::Config {
+                    vector: PriorityVector {
+                        root: bridge_id_at(bytes, 5),
+                        cost: u32::from_be_bytes([bytes[13], bytes[14], bytes[15], bytes[16]]),
+                        bridge: bridge_id_at(bytes, 17),
+                        port: u16_at(25),
+                    },
+                    message_age: time_at(27),
+                    timers: StpTimers { max_age: time_at(29), hello_time: time_at(31), forward_delay: time_at(33) },
+                    topology_change: bytes[4] & FLAG_TOPOLOGY_CHANGE != 0,
+                    topology_change_ack: bytes[4] & FLAG_TOPOLOGY_CHANGE_ACK != 0,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Packet {
+    // BPDU frame sent by a bridge port
+    pub fn bpdu(src_mac: MacAddr, bpdu: &Bpdu) -> Self {
+        Packet::new(src_mac, MacAddr::BRIDGE_GROUP, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, bpdu.to_bytes())
+    }
+
+    // The BPDU this frame carries, if any
+    pub fn bpdu_message(&self) -> Option<Bpdu> {
+        if !self.is_bpdu() {
+            return None;
+        }
+        Bpdu::from_bytes(&self.data)
+    }
+}
+
+// Port path cost for a link speed (IEEE 802.1D-2004), e.g. 20000 for 1 Gb/s
+pub fn path_cost(bandwidth_bps: u64) -> u32 {
+    (20_000_000_000_000 / bandwidth_bps.max(1)).clamp(1, 200_000_000) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortRole {
+    Root,       // Best path toward the root
+    Designated, // Best bridge toward the root for the link on this port
+    Alternate,  // Another path to the root, kept blocked
+    Backup,     // Second port of this bridge on the same link, kept blocked
+    Disabled,   // No working link
+}
+
+impl fmt::Display for PortRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortRole::Root => write!(f, "root"),
+            PortRole::Designated => write!(f, "designated"),
+            PortRole::Alternate => write!(f, "alternate"),
+            PortRole::Backup => write!(f, "backup"),
+            PortRole::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Disabled,
+    Blocking,   // Only BPDUs are received
+    Listening,  // Waiting for the tree to settle before learning
+    Learning,   // Learning addresses, not forwarding yet
+    Forwarding,
+}
+
+impl PortState {
+    pub fn learns(&self) -> bool { matches!(self, PortState::Learning | PortState::Forwarding) }
+
+    pub fn forwards(&self) -> bool { *self == PortState::Forwarding }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortState::Disabled => write!(f, "disabled"),
+            PortState::Blocking => write!(f, "blocking"),
+            PortState::Listening => write!(f, "listening"),
+            PortState::Learning => write!(f, "learning"),
+            PortState::Forwarding => write!(f, "forwarding"),
+        }
+    }
+}
+
+// What the bridge owning a spanning tree has to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum StpAction {
+    Send { port: usize, bpdu: Bpdu },
+    FlushMacTable,                // The topology changed, so learned addresses may point the wrong way
+    ForwardDelay { port: usize }, // Call forward_delay_expired for the port after the forward delay
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReceivedInfo {
+    vector: PriorityVector,
+    message_age: Duration,
+    expires: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Port {
+    cost: Option<u32>, // None while the port has no working link
+    edge: bool,        // Leads to end stations only: forwards at once, cleared when a BPDU arrives
+    role: PortRole,
+    state: PortState,
+    since: Duration,   // When the state last changed
+    info: Option<ReceivedInfo>, // Latest configuration BPDU from the other end
+}
+
+// IEEE 802.1D spanning tree state of one bridge: root election, port roles and the
+// blocking/listening/learning/forwarding state machine, with topology change notification.
+// Edge ports and the immediate switch to an alternate port when the root port's link
+// fails are borrowed from RSTP.
+#[derive(Debug, Clone)]
+pub struct SpanningTree {
+    bridge: BridgeId,
+    timers: StpTimers,
+    ports: Vec<Port>,
+    root: BridgeId,
+    root_cost: u32,
+    root_port: Option<usize>,
+    topology_change_until: Duration, // While the root sets the topology change flag
+    topology_change: bool,           // Flag in the last BPDU from the root port
+    actions: Vec<StpAction>,
+}
+
+impl SpanningTree {
+    pub fn new(bridge: BridgeId, timers: StpTimers, port_count: usize) -> Self {
+        let port = Port {
+            cost: None,
+            edge: false,
+            role: PortRole::Disabled,
+            state: PortState::Disabled,
+            since: Duration::ZERO,
+            info: None,
+        };
+        Self {
+            bridge,
+            timers,
+            ports: vec![port; port_count],
+            root: bridge,
+            root_cost: 0,
+            root_port: None,
+            topology_change_until: Duration::ZERO,
+            topology_change: false,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn bridge_id(&self) -> BridgeId { self.bridge }
+
+    pub fn timers(&self) -> StpTimers { self.timers }
+
+    pub fn is_edge(&self, port: usize) -> bool { self.ports.get(port).is_some_and(|port| port.edge) }
+
+    pub fn set_edge(&mut self, port: usize, edge: bool) {
+        if let Some(port) = self.ports.get_mut(port) {
+            port.edge = edge;
+        }
+    }
+
+    pub fn port_role(&self, port: usize) -> Option<PortRole> { self.ports.get(port).map(|port| port.role) }
+
+    pub fn port_state(&self, port: usize) -> Option<PortState> { self.ports.get(port).map(|port| port.state) }
+
+    // Called every hello time with the path cost of each port, None for ports without a working
+    // link. Ages out information that stopped arriving and sends BPDUs on designated ports.
+    pub fn tick(&mut self, links: &[Option<u32>], now: Duration) -> Vec<StpAction> {
+        for (port, cost) in self.ports.iter_mut().zip(links) {
+            if cost.is_none() {
+                port.info = None;
+            }
+            port.cost = *cost;
+            if port.info.is_some_and(|info| info.expires <= now) {
+                port.info = None;
+            }
+        }
+        self.update(now);
+        for port in 0..self.ports.len() {
+            if self.ports[port].role == PortRole::Designated {
+                self.send_config(port, now);
+            }
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    pub fn receive(&mut self, port: usize, bpdu: Bpdu, now: Duration) -> Vec<StpAction> {
+        if self.ports.get(port).is_none_or(|port| port.cost.is_none()) {
+            return Vec::new();
+        }
+        if self.ports[port].edge {
+            // Another bridge is there after all
+            println!("STP {}: BPDU on edge port {port}, treating it as a bridge port", self.bridge);
+            self.ports[port].edge = false;
+            self.set_state(port, PortState::Blocking, now);
+        }
+
+        match bpdu {
+            Bpdu::Config { vector, message_age, topology_change, .. } => {
+                if message_age >= self.timers.max_age {
+                    return Vec::new();
+                }
+                let expires = now + self.timers.max_age - message_age;
+                self.ports[port].info = Some(ReceivedInfo { vector, message_age, expires });
+                self.update(now);
+
+                if self.root_port == Some(port) {
+                    if topology_change && !self.topology_change {
+                        self.actions.push(StpAction::FlushMacTable);
+                    }
+                    self.topology_change = topology_change;
+                }
+                // Answer a neighbor that claims a worse path right away instead of at the next hello
+                if self.ports[port].role == PortRole::Designated && self.designated_vector(port) < vector {
+                    self.send_config(port, now);
+                }
+            }
+            Bpdu::TopologyChange => {
+                if self.ports[port].role == PortRole::Designated {
+                    self.topology_changed(now);
+                }
+            }
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    // Moves a listening port to learning, or a learning port to forwarding, once it has
+    // spent the forward delay in that state
+    pub fn forward_delay_expired(&mut self, port: usize, now: Duration) -> Vec<StpAction> {
+        let Some(current) = self.ports.get(port) else {
+            return Vec::new();
+        };
+        if now < current.since + self.timers.forward_delay {
+            return Vec::new(); // Left over from an earlier state
+        }
+        match current.state {
+            PortState::Listening => self.set_state(port, PortState::Learning, now),
+            PortState::Learning => self.set_state(port, PortState::Forwarding, now),
+            _ => {}
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    fn port_id(port: usize) -> u16 { (PORT_PRIORITY << 8) | (port as u16 + 1) }
+
+    // What this bridge would send on a port as its designated bridge
+    fn designated_vector(&self, port: usize) -> PriorityVector {
+        PriorityVector { root: self.root, cost: self.root_cost, bridge: self.bridge, port: Self::port_id(port) }
+    }
+
+    // Elects the root and root port from the information on every port, then gives each port its role
+    fn update(&mut self, now: Duration) {
+        let old_root = (self.root, self.root_cost, self.root_port);
+        let best = self.ports
+            .iter()
+            .enumerate()
+            .filter_map(|(index, port)| {
+                let info = port.info?;
+                let cost = info.vector.cost.saturating_add(port.cost?);
+                Some(((info.vector.root, cost, info.vector.bridge, info.vector.port, Self::port_id(index)), index))
+            })
+            .min();
+        match best {
+            Some(((root, cost, ..), port)) if root < self.bridge => {
+                self.root = root;
+                self.root_cost = cost;
+                self.root_port = Some(port);
+            }
+            _ => {
+                self.root = self.bridge;
+                self.root_cost = 0;
+                self.root_port = None;
+            }
+        }
+        if self.root != old_root.0 {
+            println!("STP {}: root bridge is now {}", self.bridge, self.root);
+        }
+
+        for index in 0..self.ports.len() {
+            let port = &self.ports[index];
+            let role = if port.cost.is_none() {
+                PortRole::Disabled
+            } else if self.root_port == Some(index) {
+                PortRole::Root
+            } else {
+                match port.info {
+                    Some(info) if info.vector < self.designated_vector(index) => {
+                        if info.vector.bridge == self.bridge { PortRole::Backup } else { PortRole::Alternate }
+                    }
+                    _ => PortRole::Designated,
+                }
+            };
+            // A blocked alternate port can take over from a root port whose link failed at once,
+            // as nothing else forwards toward the root
+            let old_root_port_down = old_root.2.is_none_or(|old| self.ports[old].cost.is_none());
+            let rapid = role == PortRole::Root && self.ports[index].role == PortRole::Alternate && old_root_port_down;
+            self.set_role(index, role, rapid, now);
+        }
+
+        // Tell the neighbors about a new root or root path cost without waiting for the next hello
+        if (self.root, self.root_cost) != (old_root.0, old_root.1) {
+            for port in 0..self.ports.len() {
+                if self.ports[port].role == PortRole::Designated {
+                    self.send_config(port, now);
+                }
+            }
+        }
+    }
+
+    fn set_role(&mut self, index: usize, role: PortRole, rapid: bool, now: Duration) {
+        let port = &mut self.ports[index];
+        if port.role != role {
+            println!("STP {}: port {index} is now {role}", self.bridge);
+        }
+        port.role = role;
+        let state = match role {
+            PortRole::Disabled => PortState::Disabled,
+            PortRole::Alternate | PortRole::Backup => PortState::Blocking,
+            PortRole::Root | PortRole::Designated => match port.state {
+                PortState::Disabled | PortState::Blocking if rapid || (port.edge && role == PortRole::Designated) => {
+                    PortState::Forwarding
+                }
+                PortState::Disabled | PortState::Blocking => PortState::Listening,
+                state => state,
+            },
+        };
+        self.set_state(index, state, now);
+    }
+
+    fn set_state(&mut self, index: usize, state: PortState, now: Duration) {
+        let port = &mut self.ports[index];
+        let old = port.state;
+        if old == state {
+            return;
+        }
+        port.state = state;
+        port.since = now;
+        let edge = port.edge;
+        println!("STP {}: port {index} {old} -> {state} at {now:?}", self.bridge);
+        if matches!(state, PortState::Listening | PortState::Learning) {
+            self.actions.push(StpAction::ForwardDelay { port: index });
+        }
+        // Ports toward end stations coming and going do not change the tree
+        if !edge && (state == PortState::Forwarding || old == PortState::Forwarding) {
+            self.topology_changed(now);
+        }
+    }
+
+    // Flushes the local table and makes sure every bridge does the same: the root sets the
+    // topology change flag in its BPDUs for a while, other bridges notify it through their root port
+    fn topology_changed(&mut self, now: Duration) {
+        self.actions.push(StpAction::FlushMacTable);
+        match self.root_port {
+            None => self.topology_change_until = now + self.timers.max_age + self.timers.forward_delay,
+            Some(root_port) => self.actions.push(StpAction::Send { port: root_port, bpdu: Bpdu::TopologyChange }),
+        }
+    }
+
+    fn send_config(&mut self, port: usize, now: Duration) {
+        let (message_age, topology_change) = match self.root_port.and_then(|root_port| self.ports[root_port].info) {
+            Some(info) => (info.message_age + MESSAGE_AGE_INCREMENT, self.topology_change),
+            None => (Duration::ZERO, now < self.topology_change_until),
+        };
+        let bpdu = Bpdu::Config {
+            vector: self.designated_vector(port),
+            message_age,
+            timers: self.timers,
+            topology_change,
+            topology_change_ack: false,
+        };
+        self.actions.push(StpAction::Send { port, bpdu });
+    }
+
+    // Bridge and root IDs, then the role, state and cost of every port
+    pub fn describe(&self) -> String {
+        let root = match self.root_port {
+            None => "this bridge is the root".to_string(),
+            Some(port) => format!("root {} at cost {} through port {port}", self.root, self.root_cost),
+        };
+        let ports: Vec<String> = self.ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| {
+                let cost = port.cost.map_or_else(|| "-".to_string(), |cost| cost.to_string());
+                let edge = if port.edge { " edge" } else { "" };
+                format!("port {index:<3} {:<10} {:<10} cost {cost}{edge}", port.role.to_string(), port.state.to_string())
+            })
+            .collect();
+        format!("Spanning tree: bridge {}, {root}\n{}", self.bridge, ports.join("\n"))
+    }
+}
+
+fn push_bridge_id(bytes: &mut Vec<u8>, id: BridgeId) {
+    bytes.extend_from_slice(&id.priority.to_be_bytes());
+    bytes.extend_from_slice(&id.mac.octets());
+}
+
+fn bridge_id_at(bytes: &[u8], offset: usize) -> BridgeId {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&bytes[offset + 2..offset + 8]);
+    BridgeId { priority: u16::from_be_bytes([bytes[offset], bytes[offset + 1]]), mac: MacAddr::from(mac) }
+}
+
+// BPDU times are in 1/256 s
+fn encode_time(time: Duration) -> u16 {
+    (time.as_secs_f64() * 256.0).round().min(f64::from(u16::MAX)) as u16
+}
+
+fn decode_time(value: u16) -> Duration {
+    Duration::from_secs_f64(f64::from(value) / 256.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINKS: [Option<u32>; 2] = [Some(19), Some(19)];
+
+    fn id(n: u8) -> BridgeId { BridgeId { priority: DEFAULT_PRIORITY, mac: MacAddr::from([0x02, 0, 0, 0, 0, n]) } }
+
+    fn secs(n: u64) -> Duration { Duration::from_secs(n) }
+
+    // Configuration BPDU sent by `bridge` from its `port`, fresh from the root
+    fn config(root: u8, cost: u32, bridge: u8, port: usize) -> Bpdu {
+        Bpdu::Config {
+            vector: PriorityVector { root: id(root), cost, bridge: id(bridge), port: SpanningTree::port_id(port) },
+            message_age: Duration::ZERO,
+            timers: StpTimers::default(),
+            topology_change: false,
+            topology_change_ack: false,
+        }
+    }
+
+    // Bridge 3 with the root (bridge 1) on port 0 and bridge 2, one hop closer, on port 1
+    fn redundant() -> SpanningTree {
+        let mut stp = SpanningTree::new(id(3), StpTimers::default(), 2);
+        stp.tick(&LINKS, Duration::ZERO);
+        stp.receive(0, config(1, 0, 1, 0), Duration::ZERO);
+        stp.receive(1, config(1, 19, 2, 1), Duration::ZERO);
+        stp
+    }
+
+    fn roles(stp: &SpanningTree) -> Vec<(PortRole, PortState)> {
+        (0..stp.ports.len()).map(|port| (stp.port_role(port).unwrap(), stp.port_state(port).unwrap())).collect()
+    }
+
+    #[test]
+    fn lowest_bridge_id_becomes_root() {
+        let mut stp = SpanningTree::new(id(2), StpTimers::default(), 2);
+        stp.tick(&LINKS, Duration::ZERO);
+        assert_eq!(stp.root_port, None);
+        // A worse root claim is answered with our own information at once
+        let actions = stp.receive(1, config(3, 0, 3, 0), Duration::ZERO);
+        assert_eq!(stp.root, id(2));
+        assert!(actions.iter().any(|action| matches!(action, StpAction::Send { port: 1, .. })));
+        stp.receive(0, config(1, 0, 1, 0), Duration::ZERO);
+        assert_eq!((stp.root, stp.root_cost, stp.root_port), (id(1), 19, Some(0)));
+        assert_eq!(roles(&stp), [(PortRole::Root, PortState::Listening), (PortRole::Designated, PortState::Listening)]);
+    }
+
+    #[test]
+    fn redundant_port_is_blocked_as_alternate() {
+        let stp = redundant();
+        assert_eq!(roles(&stp), [(PortRole::Root, PortState::Listening), (PortRole::Alternate, PortState::Blocking)]);
+    }
+
+    #[test]
+    fn ports_listen_then_learn_for_a_forward_delay_each() {
+        let mut stp = SpanningTree::new(id(1), StpTimers::default(), 1);
+        let actions = stp.tick(&LINKS[..1], Duration::ZERO);
+        assert!(actions.contains(&StpAction::ForwardDelay { port: 0 }));
+        assert_eq!(stp.port_state(0), Some(PortState::Listening));
+        assert!(stp.forward_delay_expired(0, secs(14)).is_empty());
+        assert_eq!(stp.forward_delay_expired(0, secs(15)), [StpAction::ForwardDelay { port: 0 }]);
+        assert_eq!(stp.port_state(0), Some(PortState::Learning));
+        // Forwarding changes the topology, so the root flushes its table
+        assert_eq!(stp.forward_delay_expired(0, secs(30)), [StpAction::FlushMacTable]);
+        assert_eq!(stp.port_state(0), Some(PortState::Forwarding));
+        assert!(stp.forward_delay_expired(0, secs(45)).is_empty());
+    }
+
+    #[test]
+    fn alternate_port_forwards_at_once_when_the_root_port_fails() {
+        let mut stp = redundant();
+        stp.forward_delay_expired(0, secs(15));
+        stp.forward_delay_expired(0, secs(30));
+        assert_eq!(stp.port_state(0), Some(PortState::Forwarding));
+        stp.receive(0, config(1, 0, 1, 0), secs(30));
+        stp.receive(1, config(1, 19, 2, 1), secs(30));
+        stp.tick(&[None, Some(19)], secs(31));
+        assert_eq!((stp.root, stp.root_cost, stp.root_port), (id(1), 38, Some(1)));
+        assert_eq!(roles(&stp), [(PortRole::Disabled, PortState::Disabled), (PortRole::Root, PortState::Forwarding)]);
+    }
+
+    #[test]
+    fn information_expires_after_max_age() {
+        let mut stp = SpanningTree::new(id(2), StpTimers::default(), 2);
+        stp.tick(&LINKS, Duration::ZERO);
+        stp.receive(0, config(1, 0, 1, 0), Duration::ZERO);
+        stp.tick(&LINKS, secs(19));
+        assert_eq!(stp.root_port, Some(0));
+        // The root stopped talking, so this bridge takes over
+        stp.tick(&LINKS, secs(20));
+        assert_eq!((stp.root, stp.root_port), (id(2), None));
+        assert_eq!(stp.port_role(0), Some(PortRole::Designated));
+    }
+
+    #[test]
+    fn bpdu_on_an_edge_port_makes_it_a_bridge_port() {
+        let mut stp = SpanningTree::new(id(1), StpTimers::default(), 2);
+        stp.set_edge(1, true);
+        stp.tick(&LINKS, Duration::ZERO);
+        assert_eq!(roles(&stp)[1], (PortRole::Designated, PortState::Forwarding));
+        stp.receive(1, config(2, 0, 2, 0), secs(1));
+        assert!(!stp.is_edge(1));
+        assert_eq!(roles(&stp)[1], (PortRole::Designated, PortState::Listening));
+    }
+}
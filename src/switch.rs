@@ -1,94 +1,585 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::time::Duration;
+use crate::address::MacAddr;
+use crate::capture::{CaptureRef, Direction};
+use crate::link::Attachment;
 use crate::packet::Packet;
-use crate::device::Device;
+use crate::sim::{Simulator, Timer};
+use crate::stp::{self, BridgeId, PortState, SpanningTree, StpAction, StpTimers};
+
+// Learned MAC addresses are forgotten after this long without a frame from them (IEEE 802.1D default)
+pub const DEFAULT_AGING_TIME: Duration = Duration::from_secs(300);
+// Learned entries the MAC table holds; frames for unknown addresses are flooded once it is full
+pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+// VLAN of every port until configured otherwise
+pub const DEFAULT_VLAN: u16 = 1;
+
+// How a port carries VLANs (IEEE 802.1Q)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortMode {
+    Access { vlan: u16 },                     // A single VLAN, sent and received untagged
+    Trunk { native: u16, allowed: Vec<u16> }, // Tagged frames of the allowed VLANs (all if empty), untagged native VLAN
+}
+
+impl Default for PortMode {
+    fn default() -> Self { PortMode::Access { vlan: DEFAULT_VLAN } }
+}
+
+impl PortMode {
+    pub fn carries(&self, vlan: u16) -> bool {
+        match self {
+            PortMode::Access { vlan: access } => *access == vlan,
+            PortMode::Trunk { native, allowed } => *native == vlan || allowed.is_empty() || allowed.contains(&vlan),
+        }
+    }
+
+    // VLAN a frame received on the port belongs to, None if the port does not accept it
+    fn ingress_vlan(&self, tag: Option<u16>) -> Option<u16> {
+        let vlan = match (self, tag) {
+            (PortMode::Access { vlan }, None) => *vlan,
+            (PortMode::Access { .. }, Some(_)) => return None,
+            (PortMode::Trunk { native, .. }, None) => *native,
+            (PortMode::Trunk { .. }, Some(vlan)) => vlan,
+        };
+        self.carries(vlan).then_some(vlan)
+    }
+
+    // Tag a frame of `vlan` carries when it leaves through the port
+    fn egress_tag(&self, vlan: u16) -> Option<u16> {
+        match self {
+            PortMode::Trunk { native, .. } if *native != vlan => Some(vlan),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PortMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortMode::Access { vlan } => write!(f, "access VLAN {vlan}"),
+            PortMode::Trunk { native, allowed } if allowed.is_empty() => write!(f, "trunk, native VLAN {native}, all VLANs"),
+            PortMode::Trunk { native, allowed } => {
+                let allowed: Vec<String> = allowed.iter().map(u16::to_string).collect();
+                write!(f, "trunk, native VLAN {native}, VLANs {}", allowed.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MacEntry {
+    port: usize,
+    last_seen: Duration, // Time of the last frame from the address
+    is_static: bool,     // Configured rather than learned; never ages or moves
+}
 
 #[derive(Debug)]
 pub struct Switch {
-    mac_table: HashMap<String, usize>, // MAC -> port
-    ports: Vec<Option<Rc<RefCell<dyn Device>>>>,
-    packets: Vec<Rc<Packet>>, // Will be used in multithreading
+    mac_table: HashMap<(u16, MacAddr), MacEntry>, // (VLAN, MAC) -> port
+    aging_time: Duration,
+    max_entries: usize,
+    ports: Vec<Option<Attachment>>,
+    modes: Vec<PortMode>, // One per port
+    captures: HashMap<usize, CaptureRef>, // Port -> capture recording its traffic
+    stp: Option<SpanningTree>, // None if spanning tree is off and every port forwards
 }
 
 impl Switch {
     pub fn new(port_count: usize) -> Self {
         Self {
             mac_table: HashMap::new(),
+            aging_time: DEFAULT_AGING_TIME,
+            max_entries: DEFAULT_MAX_ENTRIES,
             ports: vec![None; port_count],
-            packets: Vec::new(),
+            modes: vec![PortMode::default(); port_count],
+            captures: HashMap::new(),
+            stp: None,
+        }
+    }
+
+    // Returns the first port without a link
+    pub fn free_port(&self) -> Option<usize> {
+        self.ports.iter().position(|port| port.is_none())
+    }
+
+    // Some(true) if the port exists and has no link, None if there is no such port
+    pub fn is_port_free(&self, port: usize) -> Option<bool> {
+        self.ports.get(port).map(|slot| slot.is_none())
+    }
+
+    pub fn port_count(&self) -> usize { self.ports.len() }
+
+    pub fn aging_time(&self) -> Duration { self.aging_time }
+
+    pub fn set_aging_time(&mut self, aging_time: Duration) { self.aging_time = aging_time; }
+
+    pub fn max_entries(&self) -> usize { self.max_entries }
+
+    pub fn set_max_entries(&mut self, max_entries: usize) { self.max_entries = max_entries; }
+
+    pub fn port_mode(&self, port: usize) -> Option<&PortMode> { self.modes.get(port) }
+
+    pub fn set_port_mode(&mut self, port: usize, mode: PortMode) {
+        if let Some(slot) = self.modes.get_mut(port) {
+            *slot = mode;
+        }
+    }
+
+    // Runs spanning tree on every port, starting with the ports blocked
+    pub fn enable_stp(&mut self, bridge: BridgeId, timers: StpTimers) {
+        self.stp = Some(SpanningTree::new(bridge, timers, self.ports.len()));
+    }
+
+    pub fn spanning_tree(&self) -> Option<&SpanningTree> { self.stp.as_ref() }
+
+    // Edge ports lead to hosts and routers only, so they forward without waiting
+    pub fn set_edge_port(&mut self, port: usize, edge: bool) {
+        if let Some(stp) = &mut self.stp {
+            stp.set_edge(port, edge);
         }
     }
 
-    pub fn add_device(&mut self, device: Rc<RefCell<dyn Device>>) -> Option<usize> {
-        for (i, port) in self.ports.iter_mut().enumerate() {
-            if port.is_none() {
-                *port = Some(device);
-                return Some(i);
+    // Spanning tree state of a port; every port forwards while spanning tree is off
+    pub fn port_state(&self, port: usize) -> PortState {
+        match &self.stp {
+            Some(stp) => stp.port_state(port).unwrap_or(PortState::Disabled),
+            None => PortState::Forwarding,
+        }
+    }
+
+    pub fn start(&mut self, sim: &mut Simulator) {
+        if self.stp.is_some() {
+            self.stp_hello(sim);
+        }
+    }
+
+    pub fn handle_timer(&mut self, timer: Timer, sim: &mut Simulator) {
+        match timer {
+            Timer::StpHello => self.stp_hello(sim),
+            Timer::StpForwardDelay { port } => {
+                if let Some(stp) = &mut self.stp {
+                    let actions = stp.forward_delay_expired(port, sim.now());
+                    self.apply_stp(actions, sim);
+                }
             }
+            _ => {}
         }
-        None
     }
 
-    pub fn process_arp_request(&mut self, packet: Rc<Packet>, port: usize) -> Option<Rc<Packet>> {
-        // Add to MAC table
-        self.mac_table.entry(packet.src_mac.clone()).or_insert(port);
-        println!("==============================================");
-        println!("SWITCH");
-        println!("Received ARP request from {}", packet.src_ip);
-        println!("ARP Request: {packet:#?}");
-        println!("==============================================\n");
+    // Checks which ports have a working link (as a real switch sees the carrier), then lets
+    // spanning tree age its information and send BPDUs
+    fn stp_hello(&mut self, sim: &mut Simulator) {
+        let links: Vec<Option<u32>> = self.ports
+            .iter()
+            .map(|slot| {
+                let link = slot.as_ref()?.link.borrow();
+                link.is_up().then(|| stp::path_cost(link.config.bandwidth_bps))
+            })
+            .collect();
+        let Some(stp) = &mut self.stp else {
+            return;
+        };
+        let actions = stp.tick(&links, sim.now());
+        let hello_time = stp.timers().hello_time;
+        self.apply_stp(actions, sim);
+        sim.schedule_timer(hello_time, Timer::StpHello);
+    }
 
-        // For all other occupied ports except the one that sent the request, send the request
-        for (i, device) in self.ports.iter().enumerate() {
-            if let Some(dev) = device {
-                if i != port {
-                    if let Some(response) = dev.borrow_mut().receive_arp_request(Rc::clone(&packet)) {
-                        self.mac_table.entry(response.src_mac.clone()).or_insert(i);
-                        return Some(response);
+    fn apply_stp(&mut self, actions: Vec<StpAction>, sim: &mut Simulator) {
+        for action in actions {
+            match action {
+                StpAction::Send { port, bpdu } => {
+                    let Some(stp) = &self.stp else {
+                        return;
+                    };
+                    let packet = Rc::new(Packet::bpdu(stp.bridge_id().mac, &bpdu));
+                    self.transmit_frame(port, packet, sim);
+                }
+                StpAction::FlushMacTable => self.flush_mac_table(),
+                StpAction::ForwardDelay { port } => {
+                    if let Some(stp) = &self.stp {
+                        sim.schedule_timer(stp.timers().forward_delay, Timer::StpForwardDelay { port });
                     }
                 }
             }
         }
+    }
+
+    // MAC table entries from configuration; learned entries come and go
+    pub fn static_mac_entries(&self) -> HashMap<(u16, MacAddr), usize> {
+        self.mac_table
+            .iter()
+            .filter(|(_, entry)| entry.is_static)
+            .map(|(key, entry)| (*key, entry.port))
+            .collect()
+    }
+
+    // Every entry in the MAC table at `now`, learned or static
+    pub fn mac_entries(&self, now: Duration) -> HashMap<(u16, MacAddr), usize> {
+        self.mac_table
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .map(|(key, entry)| (*key, entry.port))
+            .collect()
+    }
+
+    // Static entry in a VLAN, which does not count toward the table size
+    pub fn add_mac_entry(&mut self, vlan: u16, mac: MacAddr, port: usize) {
+        self.mac_table.insert((vlan, mac), MacEntry { port, last_seen: Duration::ZERO, is_static: true });
+    }
+
+    // Forgets every learned entry, so traffic floods until addresses are learned again
+    pub fn flush_mac_table(&mut self) {
+        self.mac_table.retain(|_, entry| entry.is_static);
+    }
+
+    fn is_expired(&self, entry: &MacEntry, now: Duration) -> bool {
+        !entry.is_static && now.saturating_sub(entry.last_seen) >= self.aging_time
+    }
 
-        // None of them worked
-        None
+    // Port a frame for `mac` in `vlan` goes out of, None if it has to be flooded
+    fn lookup(&self, vlan: u16, mac: MacAddr, now: Duration) -> Option<usize> {
+        self.mac_table
+            .get(&(vlan, mac))
+            .filter(|entry| !self.is_expired(entry, now))
+            .map(|entry| entry.port)
     }
 
-    pub fn process_packet(&mut self, packet: Rc<Packet>, port: usize) -> Option<Rc<Packet>> {
+    // Records that `mac` is reachable in `vlan` through `port`, following stations that move
+    // between ports. Each VLAN learns separately. New addresses are not learned while the table is full.
+    fn learn(&mut self, vlan: u16, mac: MacAddr, port: usize, now: Duration) {
+        // Group addresses are never a frame's source, so a frame claiming one teaches nothing
+        if mac.is_group() {
+            return;
+        }
+        if let Some(entry) = self.mac_table.get(&(vlan, mac)).copied()
+            && !self.is_expired(&entry, now)
+        {
+            if entry.is_static {
+                return;
+            }
+            if entry.port != port {
+                println!("SWITCH: {mac} moved from port {} to port {port} in VLAN {vlan}", entry.port);
+            }
+            self.mac_table.insert((vlan, mac), MacEntry { port, last_seen: now, is_static: false });
+            return;
+        }
+
+        let aging_time = self.aging_time;
+        self.mac_table.retain(|_, entry| entry.is_static || now.saturating_sub(entry.last_seen) < aging_time);
+        let learned = self.mac_table.values().filter(|entry| !entry.is_static).count();
+        if learned >= self.max_entries {
+            println!("SWITCH: MAC table full, not learning {mac}");
+            return;
+        }
+        self.mac_table.insert((vlan, mac), MacEntry { port, last_seen: now, is_static: false });
+    }
+
+    // Starts recording the frames a port sends and receives, or stops with None
+    pub fn set_capture(&mut self, port: usize, capture: Option<CaptureRef>) {
+        match capture {
+            Some(capture) => self.captures.insert(port, capture),
+            None => self.captures.remove(&port),
+        };
+    }
+
+    pub fn attach_link(&mut self, port: usize, attachment: Attachment) {
+        if let Some(slot) = self.ports.get_mut(port) {
+            *slot = Some(attachment);
+        }
+    }
+
+    // Learns the source MAC and sends the frame out of the matching port, or floods it,
+    // within the VLAN the frame belongs to
+    pub fn process_packet(&mut self, packet: Rc<Packet>, port: usize, sim: &mut Simulator) {
+        if let Some(capture) = self.captures.get(&port) {
+            capture.borrow_mut().record(sim.now(), Direction::In, &packet);
+        }
+
+        // BPDUs are for the switch itself and never forwarded. Without spanning tree they are
+        // flooded like any other multicast, so that bridges on either side still see each other.
+        if packet.is_bpdu()
+            && let Some(stp) = &mut self.stp
+        {
+            if let Some(bpdu) = packet.bpdu_message() {
+                let actions = stp.receive(port, bpdu, sim.now());
+                self.apply_stp(actions, sim);
+            }
+            return;
+        }
+
+        // Blocked ports drop everything else; learning ports only learn
+        let state = self.port_state(port);
+        if !state.learns() {
+            return;
+        }
+
+        // Untagged frames belong to the port's access or native VLAN
+        let Some(vlan) = self.modes.get(port).and_then(|mode| mode.ingress_vlan(packet.vlan)) else {
+            println!("SWITCH: dropping frame from {} on port {port}, VLAN {:?} not allowed", packet.src_mac, packet.vlan);
+            return;
+        };
+
         // Add to MAC table
-        self.mac_table.entry(packet.src_mac.clone()).or_insert(port);
+        self.learn(vlan, packet.src_mac, port, sim.now());
+        if !state.forwards() {
+            return;
+        }
         println!("==============================================");
         println!("SWITCH");
-        println!("Received Packet from {}", packet.src_ip);
+        println!("Received Packet from {} in VLAN {vlan}", packet.src_ip);
         println!("Packet: {packet:#?}");
         println!("==============================================\n");
 
-        // Check if destination is in MAC table
-        if let Some(&target_port) = self.mac_table.get(&packet.dest_mac) {
-            if let Some(device) = &self.ports[target_port] {
-                return device.borrow_mut().receive_packet(Rc::clone(&packet));
+        // Check if destination is in MAC table; broadcast and multicast frames always flood
+        if !packet.dest_mac.is_group()
+            && let Some(target_port) = self.lookup(vlan, packet.dest_mac, sim.now())
+        {
+            if target_port != port {
+                self.transmit(target_port, vlan, packet, sim);
             }
-        } else {
-            // Flood to all ports except the incoming one
-            for (i, device) in self.ports.iter().enumerate() {
-                if i != port {
-                    if let Some(dev) = device {
-                        if let Some(response) = dev.borrow_mut().receive_packet(Rc::clone(&packet)) {
-                            return Some(response);
-                        }
-                    }
-                }
+            return;
+        }
+
+        // Flood a copy to all other ports in the VLAN; every device on them receives it
+        for i in 0..self.ports.len() {
+            if i != port {
+                self.transmit(i, vlan, Rc::clone(&packet), sim);
             }
         }
-        
-        None
     }
 
-    pub fn get_switch_info(&self) -> String {
+    // Sends a frame of `vlan` out of a port, if it forwards and carries the VLAN,
+    // tagging it for trunks and untagging it for access ports and the native VLAN
+    fn transmit(&self, port: usize, vlan: u16, packet: Rc<Packet>, sim: &mut Simulator) {
+        let Some(mode) = self.modes.get(port) else {
+            return;
+        };
+        if !mode.carries(vlan) || !self.port_state(port).forwards() {
+            return;
+        }
+        let tag = mode.egress_tag(vlan);
+        let packet = if packet.vlan == tag { packet } else { Rc::new(packet.with_vlan(tag)) };
+        self.transmit_frame(port, packet, sim);
+    }
+
+    // Puts a frame on a port's link as is, if it has one
+    fn transmit_frame(&self, port: usize, packet: Rc<Packet>, sim: &mut Simulator) {
+        let Some(Some(attachment)) = self.ports.get(port) else {
+            return;
+        };
+        if let Some(capture) = self.captures.get(&port) {
+            capture.borrow_mut().record(sim.now(), Direction::Out, &packet);
+        }
+        attachment.transmit(packet, sim);
+    }
+
+    // Port modes that are not the default, then the MAC table sorted by port, VLAN and address,
+    // with the age of learned entries
+    pub fn get_switch_info(&self, now: Duration) -> String {
+        let modes: Vec<String> = self.modes
+            .iter()
+            .enumerate()
+            .filter(|(_, mode)| **mode != PortMode::default())
+            .map(|(port, mode)| format!("port {port:<3} {mode}"))
+            .collect();
+        let mut entries: Vec<(&(u16, MacAddr), &MacEntry)> = self.mac_table
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .collect();
+        entries.sort_by_key(|((vlan, mac), entry)| (entry.port, *vlan, *mac));
+        let rows: Vec<String> = entries
+            .iter()
+            .map(|((vlan, mac), entry)| {
+                let kind = if entry.is_static {
+                    "static".to_string()
+                } else {
+                    format!("learned {:.1} s ago", now.saturating_sub(entry.last_seen).as_secs_f64())
+                };
+                format!("{mac}  port {:<3} VLAN {vlan:<4} {kind}", entry.port)
+            })
+            .collect();
+        let modes = if modes.is_empty() { String::new() } else { format!("Ports:\n{}\n", modes.join("\n")) };
+        let stp = self.stp.as_ref().map_or_else(String::new, |stp| format!("{}\n", stp.describe()));
         format!(
-            "======================================\nSWITCH\nMAC Table: {:#?}\n======================================\n",
-            self.mac_table
+            "======================================\nSWITCH\n{modes}{stp}MAC Table ({} entries, {} max, aging {} s):\n{}\n======================================\n",
+            rows.len(),
+            self.max_entries,
+            self.aging_time.as_secs_f64(),
+            rows.join("\n")
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+    use crate::device::{Device, DeviceRef};
+    use crate::link::{Link, LinkConfig};
+
+    // Remembers the frames that reach it
+    #[derive(Debug, Default)]
+    struct Probe {
+        frames: Vec<Rc<Packet>>,
+    }
+
+    impl Device for Probe {
+        fn attach_link(&mut self, _port: usize, _attachment: Attachment) {}
+        fn receive_packet(&mut self, packet: Rc<Packet>, _port: usize, _sim: &mut Simulator) { self.frames.push(packet); }
+        fn send_packet(&mut self, _dest_ip: Ipv4Addr, _data: Vec<u8>, _sim: &mut Simulator) {}
+        fn get_ip_address(&self) -> Option<Ipv4Addr> { None }
+        fn get_device_info(&self, _now: Duration) -> String { String::new() }
+    }
+
+    // A switch with a probe on every port
+    struct Lan {
+        switch: Rc<RefCell<Switch>>,
+        probes: Vec<Rc<RefCell<Probe>>>,
+        _links: Vec<Rc<RefCell<Link>>>,
+    }
+
+    impl Lan {
+        fn new(switch: Switch) -> Self {
+            let probes: Vec<_> = (0..switch.port_count()).map(|_| Rc::new(RefCell::new(Probe::default()))).collect();
+            let switch = Rc::new(RefCell::new(switch));
+            let switch_ref: DeviceRef = switch.clone();
+            let _links = probes
+                .iter()
+                .enumerate()
+                .map(|(port, probe)| {
+                    let probe: DeviceRef = probe.clone();
+                    Link::connect(&switch_ref, port, &probe, 0, LinkConfig::default())
+                })
+                .collect();
+            Self { switch, probes, _links }
+        }
+
+        // Ports the frame from `src` to `dest` comes out of when it arrives on `port`
+        fn send(&self, port: usize, src: MacAddr, dest: MacAddr, sim: &mut Simulator) -> Vec<usize> {
+            self.send_tagged(port, None, src, dest, sim).into_iter().map(|(port, _)| port).collect()
+        }
+
+        // Like send, with the tag the frame arrives with and those it leaves with
+        fn send_tagged(&self, port: usize, vlan: Option<u16>, src: MacAddr, dest: MacAddr, sim: &mut Simulator) -> Vec<(usize, Option<u16>)> {
+            let frame = Packet::new(src, dest, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, Vec::new()).with_vlan(vlan);
+            self.switch.borrow_mut().process_packet(Rc::new(frame), port, sim);
+            while sim.step() {}
+            self.probes
+                .iter()
+                .enumerate()
+                .flat_map(|(port, probe)| probe.borrow_mut().frames.drain(..).map(|frame| (port, frame.vlan)).collect::<Vec<_>>())
+                .collect()
+        }
+    }
+
+    fn mac(n: u8) -> MacAddr { MacAddr::from([0xAA, 0, 0, 0, 0, n]) }
+
+    fn seconds(n: u64) -> Duration { Duration::from_secs(n) }
+
+    #[test]
+    fn vlan_tag_round_trip() {
+        let packet = Packet::new(mac(1), mac(2), Ipv4Addr::new(10, 0, 20, 1), Ipv4Addr::new(10, 0, 20, 2), b"tagged".to_vec()).with_vlan(Some(20));
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), packet.size());
+        assert_eq!(&bytes[12..18], &[0x81, 0x00, 0x00, 20, 0x08, 0x00]);
+        assert_eq!(Packet::from_bytes(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn access_ports_only_reach_their_own_vlan() {
+        let mut sim = Simulator::new();
+        let mut switch = Switch::new(5);
+        switch.set_port_mode(0, PortMode::Access { vlan: 10 });
+        switch.set_port_mode(1, PortMode::Access { vlan: 10 });
+        switch.set_port_mode(2, PortMode::Access { vlan: 20 });
+        switch.set_port_mode(3, PortMode::Trunk { native: 1, allowed: vec![10, 20] });
+        let lan = Lan::new(switch);
+        // Port 4 stays in VLAN 1, the trunk's native VLAN
+        assert_eq!(lan.send_tagged(0, None, mac(1), MacAddr::BROADCAST, &mut sim), [(1, None), (3, Some(10))]);
+        assert_eq!(lan.send_tagged(2, None, mac(2), MacAddr::BROADCAST, &mut sim), [(3, Some(20))]);
+        assert_eq!(lan.send_tagged(4, None, mac(3), MacAddr::BROADCAST, &mut sim), [(3, None)]);
+        // Learned addresses stay in their VLAN, so mac(1) is unknown in VLAN 20
+        assert_eq!(lan.send_tagged(3, Some(10), mac(4), mac(1), &mut sim), [(0, None)]);
+        assert_eq!(lan.send_tagged(3, Some(20), mac(4), mac(1), &mut sim), [(2, None)]);
+        assert_eq!(lan.send_tagged(3, None, mac(4), mac(3), &mut sim), [(4, None)]);
+    }
+
+    #[test]
+    fn frames_outside_a_ports_vlans_are_dropped() {
+        let mut sim = Simulator::new();
+        let mut switch = Switch::new(3);
+        switch.set_port_mode(0, PortMode::Access { vlan: 10 });
+        switch.set_port_mode(1, PortMode::Trunk { native: 1, allowed: vec![10] });
+        switch.set_port_mode(2, PortMode::Trunk { native: 1, allowed: Vec::new() });
+        let lan = Lan::new(switch);
+        assert!(lan.send_tagged(0, Some(10), mac(1), MacAddr::BROADCAST, &mut sim).is_empty());
+        assert!(lan.send_tagged(1, Some(30), mac(1), MacAddr::BROADCAST, &mut sim).is_empty());
+        // A trunk allowing every VLAN passes VLAN 30 on, but not to a trunk without it
+        assert!(lan.send_tagged(2, Some(30), mac(1), MacAddr::BROADCAST, &mut sim).is_empty());
+        assert_eq!(lan.send_tagged(2, Some(10), mac(1), MacAddr::BROADCAST, &mut sim), [(0, None), (1, Some(10))]);
+    }
+
+    #[test]
+    fn learned_addresses_age_out_and_fall_back_to_flooding() {
+        let mut sim = Simulator::new();
+        let mut switch = Switch::new(3);
+        switch.set_aging_time(seconds(10));
+        let lan = Lan::new(switch);
+        assert_eq!(lan.send(0, mac(1), MacAddr::BROADCAST, &mut sim), [1, 2]);
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0]);
+        sim.run_until(seconds(9));
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0]);
+        // Nothing more from mac(1), so its entry ages out
+        sim.run_until(seconds(11));
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0, 2]);
+        assert!(!lan.switch.borrow().mac_entries(sim.now()).contains_key(&(DEFAULT_VLAN, mac(1))));
+    }
+
+    #[test]
+    fn stations_that_move_are_followed() {
+        let mut sim = Simulator::new();
+        let lan = Lan::new(Switch::new(3));
+        lan.send(0, mac(1), MacAddr::BROADCAST, &mut sim);
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0]);
+        lan.send(2, mac(1), mac(2), &mut sim);
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [2]);
+    }
+
+    #[test]
+    fn full_table_floods_unknown_addresses() {
+        let mut sim = Simulator::new();
+        let mut switch = Switch::new(3);
+        switch.set_max_entries(1);
+        switch.set_aging_time(seconds(10));
+        let lan = Lan::new(switch);
+        lan.send(0, mac(1), MacAddr::BROADCAST, &mut sim);
+        // mac(2) is not learned, so frames for it keep flooding
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0]);
+        assert_eq!(lan.send(0, mac(1), mac(2), &mut sim), [1, 2]);
+        // Room is made once mac(1) ages out
+        sim.run_until(seconds(20));
+        lan.send(1, mac(2), MacAddr::BROADCAST, &mut sim);
+        assert_eq!(lan.send(0, mac(1), mac(2), &mut sim), [1]);
+    }
+
+    #[test]
+    fn static_entries_never_age_or_move() {
+        let mut sim = Simulator::new();
+        let mut switch = Switch::new(3);
+        switch.set_aging_time(seconds(10));
+        switch.set_max_entries(1);
+        switch.add_mac_entry(DEFAULT_VLAN, mac(1), 0);
+        let lan = Lan::new(switch);
+        // Static entries do not count toward the table size
+        lan.send(1, mac(2), MacAddr::BROADCAST, &mut sim);
+        assert_eq!(lan.send(0, mac(3), mac(2), &mut sim), [1]);
+        // Frames from mac(1) on another port are not a move
+        lan.send(2, mac(1), MacAddr::BROADCAST, &mut sim);
+        sim.run_until(seconds(60));
+        assert_eq!(lan.send(1, mac(2), mac(1), &mut sim), [0]);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::address::MacAddr;
+use crate::packet::{internet_checksum, Packet};
+use crate::udp::with_pseudo_header;
+
+pub const PROTOCOL_TCP: u8 = 6;
+
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+
+const HEADER_LEN: usize = 20;
+const WINDOW: u16 = 65535;
+// First resend after this long, doubling with every further one
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+// Connections that get no acknowledgement after this many resends are given up
+const MAX_RETRANSMISSIONS: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSegment {
+    pub src_port: u16,
+    pub dest_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub payload: Vec<u8>,
+}
+
+impl TcpSegment {
+    pub fn has(&self, flag: u8) -> bool { self.flags & flag != 0 }
+
+    // Sequence space the segment takes up: SYN and FIN count as one byte each
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + u32::from(self.has(SYN)) + u32::from(self.has(FIN))
+    }
+
+    // The reset a closed port answers with (RFC 793 page 36); never one for a reset
+    pub fn reset_for(&self) -> Option<Self> {
+        if self.has(RST) {
+            return None;
+        }
+        let (seq, ack, flags) = if self.has(ACK) { (self.ack, 0, RST) } else { (0, self.seq.wrapping_add(self.len()), RST | ACK) };
+        Some(Self { src_port: self.dest_port, dest_port: self.src_port, seq, ack, flags, window: 0, payload: Vec::new() })
+    }
+
+    // The checksum covers a pseudo-header, as for UDP
+    pub fn to_bytes(&self, src_ip: Ipv4Addr, dest_ip: Ipv4Addr) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend_from_slice(&self.src_port.to_be_bytes());
+        bytes.extend_from_slice(&self.dest_port.to_be_bytes());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        bytes.extend_from_slice(&self.ack.to_be_bytes());
+        bytes.extend_from_slice(&[(HEADER_LEN as u8 / 4) << 4, self.flags]);
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Checksum, urgent pointer
+        bytes.extend_from_slice(&self.payload);
+        let checksum = internet_checksum(&with_pseudo_header(&bytes, src_ip, dest_ip, PROTOCOL_TCP));
+        bytes[16..18].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // None for truncated segments or a bad checksum; options are skipped
+    pub fn from_bytes(bytes: &[u8], src_ip: Ipv4Addr, dest_ip: Ipv4Addr) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || internet_checksum(&with_pseudo_header(bytes, src_ip, dest_ip, PROTOCOL_TCP)) != 0 {
+            return None;
+        }
+        let header_len = usize::from(bytes[12] >> 4) * 4;
+        if header_len < HEADER_LEN || header_len > bytes.len() {
+            return None;
+        }
+        Some(Self {
+            src_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            dest_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            seq: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ack: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            flags: bytes[13],
+            window: u16::from_be_bytes([bytes[14], bytes[15]]),
+            payload: bytes[header_len..].to_vec(),
+        })
+    }
+}
+
+impl Packet {
+    // TCP segment carried in an IPv4 packet; MAC addresses are filled in when it is routed
+    pub fn tcp(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, segment: &TcpSegment) -> Self {
+        let mut packet = Packet::new(MacAddr::ZERO, MacAddr::ZERO, src_ip, dest_ip, segment.to_bytes(src_ip, dest_ip));
+        packet.header.protocol = PROTOCOL_TCP;
+        packet
+    }
+
+    // The TCP segment this packet carries, if any
+    pub fn tcp_segment(&self) -> Option<TcpSegment> {
+        if self.is_arp || self.header.protocol != PROTOCOL_TCP {
+            return None;
+        }
+        TcpSegment::from_bytes(&self.data, self.src_ip, self.dest_ip)
+    }
+}
+
+// Whether sequence number `a` comes before `b`, allowing for wraparound
+fn before(a: u32, b: u32) -> bool { (a.wrapping_sub(b) as i32) < 0 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    SynSent,     // Connecting: our SYN is not answered yet
+    SynReceived, // Accepting: our SYN-ACK is not acknowledged yet
+    Established,
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpEvent {
+    Send(TcpSegment),
+    Connected,
+    Data(Vec<u8>), // Received in order
+    Closed,        // Reset by the peer, or given up after unanswered resends
+}
+
+// Just enough TCP to carry a byte stream between two endpoints of the simulation: the
+// three-way handshake, cumulative acknowledgements and resending of unacknowledged
+// segments with exponential backoff. Segments that arrive out of order are dropped and
+// resent by the peer; there is no flow or congestion control, and connections end
+// with a reset rather than FIN.
+#[derive(Debug, Clone)]
+pub struct TcpConnection {
+    pub local_port: u16,
+    pub remote_port: u16,
+    state: TcpState,
+    snd_nxt: u32, // Next sequence number to send
+    rcv_nxt: u32, // Next sequence number expected from the peer
+    unacked: VecDeque<TcpSegment>,
+    resend_at: Duration,
+    retransmissions: u32,
+}
+
+impl TcpConnection {
+    // Opens a connection with a SYN, starting at sequence number `iss`
+    pub fn connect(local_port: u16, remote_port: u16, iss: u32, now: Duration) -> (Self, TcpEvent) {
+        let mut connection = Self {
+            local_port,
+            remote_port,
+            state: TcpState::SynSent,
+            snd_nxt: iss,
+            rcv_nxt: 0,
+            unacked: VecDeque::new(),
+            resend_at: Duration::ZERO,
+            retransmissions: 0,
+        };
+        let syn = connection.segment(SYN, Vec::new(), now);
+        (connection, TcpEvent::Send(syn))
+    }
+
+    // Answers a SYN to a listening port with a SYN-ACK
+    pub fn accept(syn: &TcpSegment, iss: u32, now: Duration) -> Option<(Self, TcpEvent)> {
+        if !syn.has(SYN) || syn.has(ACK) || syn.has(RST) {
+            return None;
+        }
+        let mut connection = Self {
+            local_port: syn.dest_port,
+            remote_port: syn.src_port,
+            state: TcpState::SynReceived,
+            snd_nxt: iss,
+            rcv_nxt: syn.seq.wrapping_add(1),
+            unacked: VecDeque::new(),
+            resend_at: Duration::ZERO,
+            retransmissions: 0,
+        };
+        let syn_ack = connection.segment(SYN | ACK, Vec::new(), now);
+        Some((connection, TcpEvent::Send(syn_ack)))
+    }
+
+    pub fn state(&self) -> TcpState { self.state }
+
+    // Whether a segment belongs to this connection
+    pub fn matches(&self, segment: &TcpSegment) -> bool {
+        segment.dest_port == self.local_port && segment.src_port == self.remote_port
+    }
+
+    pub fn receive(&mut self, segment: &TcpSegment, now: Duration) -> Vec<TcpEvent> {
+        let mut events = Vec::new();
+        if self.state == TcpState::Closed {
+            return events;
+        }
+        if segment.has(RST) {
+            self.state = TcpState::Closed;
+            events.push(TcpEvent::Closed);
+            return events;
+        }
+        match self.state {
+            TcpState::SynSent => {
+                if !(segment.has(SYN) && segment.has(ACK) && segment.ack == self.snd_nxt) {
+                    return events;
+                }
+                self.rcv_nxt = segment.seq.wrapping_add(1);
+                self.acknowledge(segment.ack, now);
+                self.state = TcpState::Established;
+                events.push(TcpEvent::Send(self.ack_segment()));
+                events.push(TcpEvent::Connected);
+                return events;
+            }
+            TcpState::SynReceived => {
+                // The peer resent its SYN: our SYN-ACK is resent on the timer
+                if segment.has(SYN) || !segment.has(ACK) || segment.ack != self.snd_nxt {
+                    return events;
+                }
+                self.acknowledge(segment.ack, now);
+                self.state = TcpState::Established;
+                events.push(TcpEvent::Connected);
+            }
+            TcpState::Established => {
+                // A resent SYN-ACK means our ACK of it was lost
+                if segment.has(SYN) {
+                    events.push(TcpEvent::Send(self.ack_segment()));
+                    return events;
+                }
+                if segment.has(ACK) {
+                    self.acknowledge(segment.ack, now);
+                }
+            }
+            TcpState::Closed => {}
+        }
+        if !segment.payload.is_empty() {
+            if segment.seq == self.rcv_nxt {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(segment.payload.len() as u32);
+                events.push(TcpEvent::Data(segment.payload.clone()));
+            }
+            // Acknowledge in-order data, and repeat the last acknowledgement for anything else
+            events.push(TcpEvent::Send(self.ack_segment()));
+        }
+        events
+    }
+
+    // Sends data in one segment once the connection is established
+    pub fn send(&mut self, data: Vec<u8>, now: Duration) -> Option<TcpEvent> {
+        if self.state != TcpState::Established || data.is_empty() {
+            return None;
+        }
+        Some(TcpEvent::Send(self.segment(PSH | ACK, data, now)))
+    }
+
+    // Resends unacknowledged segments once their time is up, backing off each time
+    pub fn tick(&mut self, now: Duration) -> Vec<TcpEvent> {
+        if self.unacked.is_empty() || now < self.resend_at || self.state == TcpState::Closed {
+            return Vec::new();
+        }
+        if self.retransmissions == MAX_RETRANSMISSIONS {
+            self.state = TcpState::Closed;
+            return vec![TcpEvent::Closed];
+        }
+        self.retransmissions += 1;
+        self.resend_at = now + RETRANSMIT_TIMEOUT * (1 << self.retransmissions);
+        let rcv_nxt = self.rcv_nxt;
+        let established = self.state == TcpState::Established;
+        self.unacked
+            .iter()
+            .map(|segment| {
+                let mut segment = segment.clone();
+                if established {
+                    segment.ack = rcv_nxt;
+                }
+                TcpEvent::Send(segment)
+            })
+            .collect()
+    }
+
+    // Aborts the connection, returning the reset to tell the peer
+    pub fn close(&mut self) -> Option<TcpSegment> {
+        if self.state == TcpState::Closed {
+            return None;
+        }
+        self.state = TcpState::Closed;
+        self.unacked.clear();
+        Some(TcpSegment {
+            src_port: self.local_port,
+            dest_port: self.remote_port,
+            seq: self.snd_nxt,
+            ack: 0,
+            flags: RST,
+            window: 0,
+            payload: Vec::new(),
+        })
+    }
+
+    // Forgets the segments the peer has acknowledged
+    fn acknowledge(&mut self, ack: u32, now: Duration) {
+        let before_len = self.unacked.len();
+        while let Some(segment) = self.unacked.front()
+            && !before(ack, segment.seq.wrapping_add(segment.len()))
+        {
+            self.unacked.pop_front();
+        }
+        if self.unacked.len() != before_len {
+            self.retransmissions = 0;
+            self.resend_at = now + RETRANSMIT_TIMEOUT;
+        }
+    }
+
+    // A new segment, kept until acknowledged
+    fn segment(&mut self, flags: u8, payload: Vec<u8>, now: Duration) -> TcpSegment {
+        let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+        let segment = TcpSegment { src_port: self.local_port, dest_port: self.remote_port, seq: self.snd_nxt, ack, flags, window: WINDOW, payload };
+        self.snd_nxt = self.snd_nxt.wrapping_add(segment.len());
+        if self.unacked.is_empty() {
+            self.resend_at = now + RETRANSMIT_TIMEOUT;
+        }
+        self.unacked.push_back(segment.clone());
+        segment
+    }
+
+    fn ack_segment(&self) -> TcpSegment {
+        TcpSegment {
+            src_port: self.local_port,
+            dest_port: self.remote_port,
+            seq: self.snd_nxt,
+            ack: self.rcv_nxt,
+            flags: ACK,
+            window: WINDOW,
+            payload: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,1574 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::address::{self, AddrParseError, Ipv4Net, MacAddr};
+use crate::bgp::{BgpNeighbor, BgpTimers, PolicyRule};
+use crate::capture::CaptureRef;
+use crate::device::DeviceRef;
+use crate::dhcp::{self, DhcpPool};
+use crate::dns::{self, DnsRecord, RecordData};
+use crate::host::Host;
+use crate::link::{Link, LinkConfig};
+use crate::ospf::{OspfTimers, DEFAULT_PRIORITY};
+use crate::rip::RipTimers;
+use crate::router::{RouteSource, Router};
+use crate::sim::Simulator;
+use crate::stp::{self, BridgeId, StpTimers};
+use crate::switch::{PortMode, Switch, DEFAULT_AGING_TIME, DEFAULT_MAX_ENTRIES, DEFAULT_VLAN};
+
+/*
+Topology files describe the devices of a network and the links between them.
+Every device has a unique name, which links and GUI labels refer to.
+Link endpoints are written as "<device>" or "<device>:<port>", where the port is
+an interface name for routers, an optional port number for switches (the next
+free port is used otherwise) and omitted for hosts.
+Saved topologies also record links that are down, and under `state` the routing, ARP and
+MAC tables each device had at the time; loading a topology skips `state`.
+Switch ports are access ports in VLAN 1 unless `vlans` says otherwise; routers
+reach tagged VLANs through subinterfaces of the interface linked to a trunk port.
+Switches linked into loops need `stp`, which runs spanning tree with the given bridge
+address; ports leading only to hosts and routers can be listed as edge ports.
+Routers with `rip` or `ospf` learn the routes to networks they have no static route for;
+OSPF routes are preferred over RIP ones. Routers with `bgp` exchange routes with the
+neighbors listed, in their own or other autonomous systems; routes from other ASes are
+preferred over OSPF and RIP ones, routes from the router's own AS are not.
+Hosts with `dhcp` lease their address from a router or host with `dhcp_pools` on their network.
+Hosts with `dns_records` answer DNS queries for them; hosts look names up with the `dns` servers.
+
+[[switches]]
+name = "Switch 1"
+ports = 3
+position = [200.0, 450.0]
+# Optional: static MAC entries, learned entry lifetime (default 300 s) and table size (default 1024)
+mac_table = [{ mac = "AA:BB:CC:DD:01:0A", port = 0, vlan = 10 }]
+mac_aging_s = 60.0
+max_mac_entries = 2
+# Optional: port VLANs; trunks carry every VLAN unless `allowed` is given, native VLAN 1 untagged
+vlans = [
+    { port = 0, mode = "access", vlan = 10 },
+    { port = 2, mode = "trunk", native = 1, allowed = [10, 20] },
+]
+# Optional: spanning tree, with priority 32768 and 2/20/15 s hello/max age/forward delay by default
+stp = { mac = "02:00:00:00:00:01", priority = 4096, edge_ports = [0, 1] }
+
+[[routers]]
+name = "Router 1"
+ip = "192.168.1.1"
+interfaces = [{ name = "eth0", mac = "AA:BB:CC:DD:EE:11" }]
+# Optional: VLAN subinterfaces, which use the parent's MAC address and link
+subinterfaces = [{ name = "eth0.10", parent = "eth0", vlan = 10 }]
+routes = [{ network = "192.168.1.0/24", interface = "eth0.10" }]
+# Optional: RIPv2, advertising the directly connected routes (no next hop) and installing the
+# routes it learns; 30/180/120 s update/timeout/garbage collection by default
+rip = { update_s = 10.0, passive = ["eth0.10"] }
+# Optional: OSPF in a single area, advertising the directly connected routes; 10/40/5 s
+# hello/dead/retransmit by default, interface cost 100 Mb/s divided by the link bandwidth
+# (at least 1) and priority 1 unless set per interface
+ospf = { hello_s = 5.0, interfaces = [{ name = "eth0", cost = 10, priority = 0, passive = true }] }
+# Optional: BGP in AS `asn`, announcing `networks` while the routing table has a route to them;
+# 90/30/30 s hold/keepalive/connect retry by default. Neighbors reached over `interface` are
+# directly connected, others are reached over the routing table. Import and export rules apply
+# in order, the first one matching a route (by `prefix` and AS in `as_path`) denies it or sets
+# its attributes; unmatched routes are accepted
+bgp = { asn = 65001, networks = ["192.168.1.0/24"], hold_s = 9.0, neighbors = [
+    { ip = "10.0.0.2", remote_as = 65002, interface = "eth0", import = [{ as_path = 65003, local_pref = 50 }], export = [
+        { prefix = "192.168.0.0/16", prepend = 2 },
+        { deny = true },
+    ] },
+    { ip = "192.168.1.2", remote_as = 65001, next_hop_self = true },
+] }
+# Optional: DHCP pools, each inside a directly connected network of its interface; /24 and
+# 3600 s leases by default
+dhcp_pools = [{ interface = "eth0.10", first = "192.168.1.100", last = "192.168.1.199", gateway = "192.168.1.1", lease_s = 600.0 }]
+
+[[hosts]]
+name = "Host A"
+ip = "192.168.1.10/24"
+mac = "AA:BB:CC:DD:01:0A"
+# Optional: default gateway, and static routes to networks reached through other routers
+gateway = "192.168.1.1"
+routes = [{ network = "192.168.5.0/24", next_hop = "192.168.1.2" }]
+# Optional: DNS servers, tried in order
+dns = ["192.168.1.2"]
+
+[[hosts]]
+name = "Host B"
+mac = "AA:BB:CC:DD:01:0B"
+# Leases its address, default gateway and DNS servers, so has no `ip` or `gateway`
+dhcp = true
+
+[[hosts]]
+name = "Server"
+ip = "192.168.1.2/24"
+mac = "AA:BB:CC:DD:01:0C"
+# Optional: DHCP pool inside the host's network, as for routers but without `interface`
+dhcp_pools = [{ first = "192.168.1.50", last = "192.168.1.59", gateway = "192.168.1.1", dns = ["192.168.1.2"] }]
+# Optional: DNS zone served on UDP port 53, with TTLs of 3600 s unless given
+dns_records = [
+    { name = "web.lab", type = "A", value = "192.168.1.10", ttl = 60 },
+    { name = "www.lab", type = "CNAME", value = "web.lab" },
+    { name = "10.1.168.192.in-addr.arpa", type = "PTR", value = "web.lab" },
+]
+
+[[links]]
+a = "Host A"
+b = "Switch 1"
+delay_ms = 1.0
+*/
+
+// Network used when no topology file is given
+pub const DEFAULT_TOPOLOGY: &str = include_str!("../topologies/default.toml");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopologyFile {
+    #[serde(default)]
+    pub switches: Vec<SwitchConfig>,
+    #[serde(default)]
+    pub routers: Vec<RouterConfig>,
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+    #[serde(default)]
+    pub links: Vec<LinkSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state: Vec<DeviceState>, // Written on save, skipped on load
+}
+
+// Tables a device had built up when the network was saved, for reference: devices start
+// with only their configured entries when a topology is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceState {
+    pub device: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arp: Vec<ArpEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mac_table: Vec<MacEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteState {
+    pub network: String,
+    pub interface: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_hop: Option<String>,
+    pub source: String, // static, ebgp, ospf, rip or ibgp
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwitchConfig {
+    pub name: String,
+    pub ports: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mac_table: Vec<MacEntry>, // Static entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_aging_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_mac_entries: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vlans: Vec<PortVlanConfig>, // Ports that are not access ports in VLAN 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stp: Option<StpConfig>, // Spanning tree is off if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StpConfig {
+    pub mac: String, // Bridge address, which breaks ties between equal priorities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hello_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_delay_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edge_ports: Vec<usize>,
+}
+
+impl StpConfig {
+    fn timers(&self, device: &str) -> Result<StpTimers, TopologyError> {
+        let default = StpTimers::default();
+        let seconds = |field: &str, value: Option<f64>, default: Duration| value.map_or(Ok(default), |s| parse_seconds(device, field, s));
+        Ok(StpTimers {
+            hello_time: seconds("hello_s", self.hello_s, default.hello_time)?,
+            max_age: seconds("max_age_s", self.max_age_s, default.max_age)?,
+            forward_delay: seconds("forward_delay_s", self.forward_delay_s, default.forward_delay)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacEntry {
+    pub mac: String,
+    pub port: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>, // VLAN 1 if omitted
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VlanMode {
+    Access,
+    Trunk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortVlanConfig {
+    pub port: usize,
+    pub mode: VlanMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>,   // Access VLAN, 1 if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub native: Option<u16>, // Untagged VLAN of a trunk, 1 if omitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<u16>,   // VLANs a trunk carries, all if empty
+}
+
+impl PortVlanConfig {
+    fn port_mode(&self) -> PortMode {
+        match self.mode {
+            VlanMode::Access => PortMode::Access { vlan: self.vlan.unwrap_or(DEFAULT_VLAN) },
+            VlanMode::Trunk => PortMode::Trunk {
+                native: self.native.unwrap_or(DEFAULT_VLAN),
+                allowed: self.allowed.clone(),
+            },
+        }
+    }
+
+    fn from_mode(port: usize, mode: &PortMode) -> Self {
+        match mode {
+            PortMode::Access { vlan } => Self { port, mode: VlanMode::Access, vlan: Some(*vlan), native: None, allowed: Vec::new() },
+            PortMode::Trunk { native, allowed } => Self {
+                port,
+                mode: VlanMode::Trunk,
+                vlan: None,
+                native: (*native != DEFAULT_VLAN).then_some(*native),
+                allowed: allowed.clone(),
+            },
+        }
+    }
+
+    // Every VLAN ID the entry mentions
+    fn vlan_ids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.vlan.into_iter().chain(self.native).chain(self.allowed.iter().copied())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArpEntry {
+    pub ip: String,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterConfig {
+    pub name: String,
+    pub ip: String,
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subinterfaces: Vec<SubinterfaceConfig>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>, // Static routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rip: Option<RipConfig>, // RIP is off if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ospf: Option<OspfConfig>, // OSPF is off if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bgp: Option<BgpConfig>, // BGP is off if omitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dhcp_pools: Vec<DhcpPoolConfig>, // Addresses handed out to DHCP clients
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arp: Vec<ArpEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RipConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub garbage_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passive: Vec<String>, // Interfaces that send no updates
+}
+
+impl RipConfig {
+    fn timers(&self, device: &str) -> Result<RipTimers, TopologyError> {
+        let default = RipTimers::default();
+        let seconds = |field: &str, value: Option<f64>, default: Duration| value.map_or(Ok(default), |s| parse_seconds(device, field, s));
+        Ok(RipTimers {
+            update: seconds("update_s", self.update_s, default.update)?,
+            timeout: seconds("timeout_s", self.timeout_s, default.timeout)?,
+            garbage: seconds("garbage_s", self.garbage_s, default.garbage)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OspfConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hello_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retransmit_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<OspfInterfaceConfig>, // Only interfaces with non-default settings
+}
+
+impl OspfConfig {
+    // Timers under a second are raised to one
+    fn timers(&self, device: &str) -> Result<OspfTimers, TopologyError> {
+        let default = OspfTimers::default();
+        let seconds = |field: &str, value: Option<f64>, default: Duration| {
+            value.map_or(Ok(default), |s| Ok(parse_seconds(device, field, s)?.max(Duration::from_secs(1))))
+        };
+        Ok(OspfTimers {
+            hello: seconds("hello_s", self.hello_s, default.hello)?,
+            dead: seconds("dead_s", self.dead_s, default.dead)?,
+            retransmit: seconds("retransmit_s", self.retransmit_s, default.retransmit)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OspfInterfaceConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<u16>, // From the link bandwidth if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>, // For DR election; 0 never becomes DR
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passive: Option<bool>, // Advertised, but no hellos are sent or accepted
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BgpConfig {
+    pub asn: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<String>, // Announced to the neighbors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_retry_s: Option<f64>,
+    #[serde(default)]
+    pub neighbors: Vec<BgpNeighborConfig>,
+}
+
+impl BgpConfig {
+    fn timers(&self, device: &str) -> Result<BgpTimers, TopologyError> {
+        let default = BgpTimers::default();
+        let seconds = |field: &str, value: Option<f64>, default: Duration| value.map_or(Ok(default), |s| parse_seconds(device, field, s));
+        Ok(BgpTimers {
+            hold: seconds("hold_s", self.hold_s, default.hold)?,
+            keepalive: seconds("keepalive_s", self.keepalive_s, default.keepalive)?,
+            connect_retry: seconds("connect_retry_s", self.connect_retry_s, default.connect_retry)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BgpNeighborConfig {
+    pub ip: String,
+    pub remote_as: u16, // The router's own AS for iBGP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>, // Omitted if reached over the routing table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_hop_self: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub import: Vec<PolicyRuleConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub export: Vec<PolicyRuleConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRuleConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>, // Matches this network and the networks inside it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_path: Option<u16>, // Matches paths through this AS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deny: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_pref: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub med: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prepend: Option<u8>, // Copies of the router's AS added to exported paths
+}
+
+impl PolicyRuleConfig {
+    fn rule(&self) -> Result<PolicyRule, AddrParseError> {
+        Ok(PolicyRule {
+            prefix: self.prefix.as_deref().map(str::parse).transpose()?,
+            as_path: self.as_path,
+            deny: self.deny.unwrap_or(false),
+            local_pref: self.local_pref,
+            med: self.med,
+            prepend: self.prepend.unwrap_or(0),
+        })
+    }
+
+    fn from_rule(rule: &PolicyRule) -> Self {
+        Self {
+            prefix: rule.prefix.map(|prefix| prefix.to_string()),
+            as_path: rule.as_path,
+            deny: rule.deny.then_some(true),
+            local_pref: rule.local_pref,
+            med: rule.med,
+            prepend: Some(rule.prepend).filter(|prepend| *prepend != 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpPoolConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>, // Routers only: the interface whose network the pool serves
+    pub first: String,
+    pub last: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_len: Option<u8>, // /24 if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease_s: Option<f64>,
+}
+
+impl DhcpPoolConfig {
+    // The pool has to lie inside one of `networks`, those of the interface it serves
+    fn pool(&self, device: &str, networks: &[Ipv4Net]) -> Result<DhcpPool, TopologyError> {
+        let prefix_len = self.prefix_len.unwrap_or(address::DEFAULT_PREFIX_LEN);
+        let parse = |s: &str| address::parse_ipv4(s).map_err(|source| invalid_address(device, source));
+        Ipv4Net::new(Ipv4Addr::UNSPECIFIED, prefix_len).map_err(|source| invalid_address(device, source))?;
+        let (first, last) = (parse(&self.first)?, parse(&self.last)?);
+        if first > last {
+            return Err(TopologyError::Parse(format!("{device}: DHCP pool {first}-{last} ends before it starts")));
+        }
+        if !networks.iter().any(|network| network.contains(first) && network.contains(last)) {
+            return Err(TopologyError::Parse(format!("{device}: DHCP pool {first}-{last} is outside the network it serves")));
+        }
+        // Leases under a second are raised to one
+        let lease_time = self.lease_s.map_or(Ok(dhcp::DEFAULT_LEASE_TIME), |s| parse_seconds(device, "lease_s", s))?;
+        Ok(DhcpPool {
+            first,
+            last,
+            prefix_len,
+            gateway: self.gateway.as_deref().map(parse).transpose()?,
+            dns_servers: self.dns.iter().map(|server| parse(server)).collect::<Result<_, _>>()?,
+            lease_time: lease_time.max(Duration::from_secs(1)),
+        })
+    }
+
+    fn from_pool(interface: Option<String>, pool: &DhcpPool) -> Self {
+        Self {
+            interface,
+            first: pool.first.to_string(),
+            last: pool.last.to_string(),
+            prefix_len: Some(pool.prefix_len).filter(|len| *len != address::DEFAULT_PREFIX_LEN),
+            gateway: pool.gateway.map(|gateway| gateway.to_string()),
+            dns: pool.dns_servers.iter().map(ToString::to_string).collect(),
+            lease_s: (pool.lease_time != dhcp::DEFAULT_LEASE_TIME).then_some(pool.lease_time.as_secs_f64()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceConfig {
+    pub name: String,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubinterfaceConfig {
+    pub name: String,
+    pub parent: String, // Physical interface whose link it uses
+    pub vlan: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    pub network: String,
+    pub interface: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_hop: Option<String>, // Omitted if directly connected
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>, // With the subnet's prefix length, e.g. "192.168.1.10/24"; /24 if omitted
+    pub mac: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<bool>, // Lease the address, gateway and DNS servers instead of `ip` and `gateway`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>, // Default gateway
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<HostRouteConfig>, // Static routes, preferred over the default gateway
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>, // DNS servers, tried in order; DHCP clients get theirs from the lease
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dhcp_pools: Vec<DhcpPoolConfig>, // Addresses handed out to DHCP clients
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_records: Vec<DnsRecordConfig>, // Zone the host serves on port 53
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arp: Vec<ArpEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsRecordType {
+    A,
+    Cname,
+    Ptr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsRecordConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: DnsRecordType,
+    pub value: String, // An address for A records, a name for CNAME and PTR records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>, // Seconds, 3600 if omitted
+}
+
+impl DnsRecordConfig {
+    fn record(&self, device: &str) -> Result<DnsRecord, TopologyError> {
+        let invalid_name = |name: &str| TopologyError::Parse(format!("{device}: invalid DNS name {name:?}"));
+        if !dns::is_valid_name(&self.name) {
+            return Err(invalid_name(&self.name));
+        }
+        let data = match self.kind {
+            DnsRecordType::A => RecordData::A(address::parse_ipv4(&self.value).map_err(|source| invalid_address(device, source))?),
+            DnsRecordType::Cname | DnsRecordType::Ptr if !dns::is_valid_name(&self.value) => return Err(invalid_name(&self.value)),
+            DnsRecordType::Cname => RecordData::Cname(self.value.clone()),
+            DnsRecordType::Ptr => RecordData::Ptr(self.value.clone()),
+        };
+        Ok(DnsRecord { name: self.name.clone(), ttl: self.ttl.unwrap_or(dns::DEFAULT_TTL), data })
+    }
+
+    fn from_record(record: &DnsRecord) -> Self {
+        let kind = match record.data {
+            RecordData::A(_) => DnsRecordType::A,
+            RecordData::Cname(_) => DnsRecordType::Cname,
+            RecordData::Ptr(_) => DnsRecordType::Ptr,
+        };
+        Self {
+            name: record.name.clone(),
+            kind,
+            value: record.data.to_string(),
+            ttl: Some(record.ttl).filter(|ttl| *ttl != dns::DEFAULT_TTL),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostRouteConfig {
+    pub network: String,
+    pub next_hop: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkSpec {
+    pub a: String,
+    pub b: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up: Option<bool>, // Links are up unless this is false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_mbps: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loss: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_error_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<usize>,
+}
+
+impl LinkSpec {
+    // Link settings, falling back to the defaults for anything not in the file
+    fn config(&self, link: usize) -> Result<LinkConfig, TopologyError> {
+        let invalid = |reason: String| TopologyError::InvalidLink { link, reason: format!("{} <-> {}: {reason}", self.a, self.b) };
+        let default = LinkConfig::default();
+        let bandwidth_bps = match self.bandwidth_mbps {
+            // Anything that rounds to 0 b/s would make every frame take forever to send
+            Some(mbps) if !(mbps * 1e6 >= 1.0 && mbps.is_finite()) => {
+                return Err(invalid(format!("bandwidth_mbps = {mbps} is not a bandwidth of at least 1 b/s")));
+            }
+            Some(mbps) => (mbps * 1e6) as u64,
+            None => default.bandwidth_bps,
+        };
+        let millis = |field: &str, value: Option<f64>, default: Duration| match value {
+            Some(ms) => Duration::try_from_secs_f64(ms / 1e3).map_err(|_| invalid(format!("{field} = {ms} is not a valid time"))),
+            None => Ok(default),
+        };
+        let probability = |field: &str, value: Option<f64>, default: f64| match value {
+            Some(p) if !(0.0..=1.0).contains(&p) => Err(invalid(format!("{field} = {p} is not a probability from 0 to 1"))),
+            value => Ok(value.unwrap_or(default)),
+        };
+        if self.mtu == Some(0) {
+            return Err(invalid("mtu = 0 leaves no room for any frame".to_string()));
+        }
+        Ok(LinkConfig {
+            bandwidth_bps,
+            delay: millis("delay_ms", self.delay_ms, default.delay)?,
+            jitter: millis("jitter_ms", self.jitter_ms, default.jitter)?,
+            loss: probability("loss", self.loss, default.loss)?,
+            bit_error_rate: probability("bit_error_rate", self.bit_error_rate, default.bit_error_rate)?,
+            mtu: self.mtu.unwrap_or(default.mtu),
+        })
+    }
+
+    // Spec for an existing link, only writing the settings that differ from the defaults
+    fn from_link(a: String, b: String, link: &Link) -> Self {
+        let config = link.config;
+        let default = LinkConfig::default();
+        let changed = |differs: bool, value: f64| differs.then_some(value);
+        Self {
+            a,
+            b,
+            up: (!link.is_up()).then_some(false),
+            bandwidth_mbps: changed(config.bandwidth_bps != default.bandwidth_bps, config.bandwidth_bps as f64 / 1e6),
+            delay_ms: changed(config.delay != default.delay, config.delay.as_secs_f64() * 1e3),
+            jitter_ms: changed(config.jitter != default.jitter, config.jitter.as_secs_f64() * 1e3),
+            loss: changed(config.loss != default.loss, config.loss),
+            bit_error_rate: changed(config.bit_error_rate != default.bit_error_rate, config.bit_error_rate),
+            mtu: (config.mtu != default.mtu).then_some(config.mtu),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TopologyError {
+    Io { path: String, source: std::io::Error },
+    UnsupportedFormat(String),
+    Parse(String),
+    Serialize(String),
+    InvalidAddress { device: String, source: AddrParseError },
+    InvalidLink { link: usize, reason: String },
+    DuplicateName(String),
+    UnknownDevice { context: String, name: String },
+    UnknownInterface { device: String, interface: String },
+    InvalidPort { device: String, port: String },
+    PortInUse { device: String, port: String },
+    InvalidVlan { device: String, vlan: u16 },
+    NoCapturePoint(String),
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::Io { path, source } => write!(f, "cannot read {path}: {source}"),
+            TopologyError::UnsupportedFormat(path) => {
+                write!(f, "unsupported topology format for {path} (expected .toml, .json, .yaml or .yml)")
+            }
+            TopologyError::Parse(message) => write!(f, "invalid topology file: {message}"),
+            TopologyError::Serialize(message) => write!(f, "cannot write topology: {message}"),
+            TopologyError::InvalidAddress { device, source } => write!(f, "{device}: {source}"),
+            TopologyError::InvalidLink { link, reason } => write!(f, "link #{}: {reason}", link + 1),
+            TopologyError::DuplicateName(name) => write!(f, "device name {name:?} is used more than once"),
+            TopologyError::UnknownDevice { context, name } => {
+                write!(f, "{context} refers to unknown device {name:?}")
+            }
+            TopologyError::UnknownInterface { device, interface } => {
+                write!(f, "{device} has no interface named {interface:?}")
+            }
+            TopologyError::InvalidPort { device, port } => write!(f, "{device} has no port {port:?}"),
+            TopologyError::PortInUse { device, port } => {
+                write!(f, "port {port:?} of {device} is already connected")
+            }
+            TopologyError::InvalidVlan { device, vlan } => {
+                write!(f, "{device}: VLAN {vlan} is outside the valid range 1-4094")
+            }
+            TopologyError::NoCapturePoint(device) => {
+                write!(f, "{device} is not a switch or router, so it has no ports to capture on")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[derive(Debug, Clone)]
+pub enum DeviceHandle {
+    Host(Rc<RefCell<Host>>),
+    Switch(Rc<RefCell<Switch>>),
+    Router(Rc<RefCell<Router>>),
+}
+
+impl DeviceHandle {
+    pub fn device(&self) -> DeviceRef {
+        match self {
+            DeviceHandle::Host(host) => host.clone(),
+            DeviceHandle::Switch(switch) => switch.clone(),
+            DeviceHandle::Router(router) => router.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NetworkNode {
+    pub name: String,
+    pub position: Option<[f32; 2]>,
+    pub device: DeviceHandle,
+}
+
+#[derive(Debug)]
+pub struct NetworkLink {
+    pub a: String, // Device names of both ends
+    pub b: String,
+    pub link: Rc<RefCell<Link>>,
+}
+
+// A running network built from a topology file
+#[derive(Debug, Default)]
+pub struct Network {
+    pub nodes: Vec<NetworkNode>,
+    pub links: Vec<NetworkLink>,
+}
+
+impl Network {
+    pub fn load(path: &Path) -> Result<Self, TopologyError> {
+        let format = Format::from_path(path)
+            .ok_or_else(|| TopologyError::UnsupportedFormat(path.display().to_string()))?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|source| TopologyError::Io { path: path.display().to_string(), source })?;
+        Self::parse(&contents, format)
+    }
+
+    pub fn parse(contents: &str, format: Format) -> Result<Self, TopologyError> {
+        let file: TopologyFile = match format {
+            Format::Toml => toml::from_str(contents).map_err(|e| TopologyError::Parse(e.to_string()))?,
+            Format::Json => serde_json::from_str(contents).map_err(|e| TopologyError::Parse(e.to_string()))?,
+            Format::Yaml => serde_yaml::from_str(contents).map_err(|e| TopologyError::Parse(e.to_string()))?,
+        };
+        Self::build(&file)
+    }
+
+    // Lets devices that run protocols on their own, like spanning tree, get going
+    pub fn start(&self, sim: &mut Simulator) {
+        for node in &self.nodes {
+            let device = node.device.device();
+            sim.with_device(&device, |sim| device.borrow_mut().start(sim));
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&NetworkNode> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    pub fn build(file: &TopologyFile) -> Result<Self, TopologyError> {
+        let mut network = Network::default();
+
+        // Create all devices
+        for config in &file.switches {
+            let mut switch = Switch::new(config.ports);
+            for entry in &config.mac_table {
+                let mac: MacAddr = entry.mac.parse().map_err(|source| invalid_address(&config.name, source))?;
+                if entry.port >= config.ports {
+                    return Err(TopologyError::InvalidPort { device: config.name.clone(), port: entry.port.to_string() });
+                }
+                let vlan = check_vlan(&config.name, entry.vlan.unwrap_or(DEFAULT_VLAN))?;
+                switch.add_mac_entry(vlan, mac, entry.port);
+            }
+            for entry in &config.vlans {
+                if entry.port >= config.ports {
+                    return Err(TopologyError::InvalidPort { device: config.name.clone(), port: entry.port.to_string() });
+                }
+                for vlan in entry.vlan_ids() {
+                    check_vlan(&config.name, vlan)?;
+                }
+                switch.set_port_mode(entry.port, entry.port_mode());
+            }
+            if let Some(stp) = &config.stp {
+                let mac: MacAddr = stp.mac.parse().map_err(|source| invalid_address(&config.name, source))?;
+                let priority = stp.priority.unwrap_or(stp::DEFAULT_PRIORITY);
+                switch.enable_stp(BridgeId { priority, mac }, stp.timers(&config.name)?);
+                for &port in &stp.edge_ports {
+                    if port >= config.ports {
+                        return Err(TopologyError::InvalidPort { device: config.name.clone(), port: port.to_string() });
+                    }
+                    switch.set_edge_port(port, true);
+                }
+            }
+            if let Some(aging_s) = config.mac_aging_s {
+                switch.set_aging_time(parse_seconds(&config.name, "mac_aging_s", aging_s)?);
+            }
+            if let Some(max_entries) = config.max_mac_entries {
+                switch.set_max_entries(max_entries);
+            }
+            let switch = Rc::new(RefCell::new(switch));
+            network.add_node(&config.name, config.position, DeviceHandle::Switch(switch))?;
+        }
+
+        for config in &file.routers {
+            let ip = address::parse_ipv4(&config.ip).map_err(|source| invalid_address(&config.name, source))?;
+            let mut router = Router::new(ip);
+            let mut names = HashSet::new();
+            for name in config.interfaces.iter().map(|interface| &interface.name).chain(config.subinterfaces.iter().map(|sub| &sub.name)) {
+                if !names.insert(name) {
+                    return Err(TopologyError::Parse(format!("{}: interface name {name:?} is used more than once", config.name)));
+                }
+            }
+            for interface in &config.interfaces {
+                let mac: MacAddr = interface.mac.parse().map_err(|source| invalid_address(&config.name, source))?;
+                router.add_interface(&interface.name, mac);
+            }
+            for subinterface in &config.subinterfaces {
+                let unknown_parent = || TopologyError::UnknownInterface {
+                    device: config.name.clone(),
+                    interface: subinterface.parent.clone(),
+                };
+                let parent = router.interface_index(&subinterface.parent).ok_or_else(unknown_parent)?;
+                let vlan = check_vlan(&config.name, subinterface.vlan)?;
+                router.add_subinterface(&subinterface.name, parent, vlan).ok_or_else(unknown_parent)?;
+            }
+            for (ip, mac) in parse_arp_entries(&config.name, &config.arp)? {
+                router.add_arp_entry(ip, mac);
+            }
+            if let Some(rip) = &config.rip {
+                router.enable_rip(rip.timers(&config.name)?);
+                for name in &rip.passive {
+                    let interface = router.interface_index(name).ok_or_else(|| TopologyError::UnknownInterface {
+                        device: config.name.clone(),
+                        interface: name.clone(),
+                    })?;
+                    router.set_rip_passive(interface, true);
+                }
+            }
+            if let Some(ospf) = &config.ospf {
+                router.enable_ospf(ospf.timers(&config.name)?);
+                for settings in &ospf.interfaces {
+                    let interface = router.interface_index(&settings.name).ok_or_else(|| TopologyError::UnknownInterface {
+                        device: config.name.clone(),
+                        interface: settings.name.clone(),
+                    })?;
+                    if let Some(ospf) = router.ospf_mut() {
+                        ospf.set_cost(interface, settings.cost);
+                        ospf.set_priority(interface, settings.priority.unwrap_or(DEFAULT_PRIORITY));
+                        ospf.set_passive(interface, settings.passive.unwrap_or(false));
+                    }
+                }
+            }
+            if let Some(bgp) = &config.bgp {
+                router.enable_bgp(bgp.asn, bgp.timers(&config.name)?);
+                for network in &bgp.networks {
+                    let network: Ipv4Net = network.parse().map_err(|source| invalid_address(&config.name, source))?;
+                    router.add_bgp_network(network);
+                }
+                for neighbor in &bgp.neighbors {
+                    let address = address::parse_ipv4(&neighbor.ip).map_err(|source| invalid_address(&config.name, source))?;
+                    let interface = neighbor.interface
+                        .as_ref()
+                        .map(|name| {
+                            router.interface_index(name).ok_or_else(|| TopologyError::UnknownInterface {
+                                device: config.name.clone(),
+                                interface: name.clone(),
+                            })
+                        })
+                        .transpose()?;
+                    let rules = |rules: &[PolicyRuleConfig]| {
+                        rules
+                            .iter()
+                            .map(PolicyRuleConfig::rule)
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|source| invalid_address(&config.name, source))
+                    };
+                    router.add_bgp_neighbor(BgpNeighbor {
+                        address,
+                        remote_as: neighbor.remote_as,
+                        interface,
+                        next_hop_self: neighbor.next_hop_self.unwrap_or(false),
+                        import: rules(&neighbor.import)?,
+                        export: rules(&neighbor.export)?,
+                    });
+                }
+            }
+            for pool in &config.dhcp_pools {
+                let name = pool.interface.clone().unwrap_or_default();
+                let interface = router.interface_index(&name).ok_or_else(|| TopologyError::UnknownInterface {
+                    device: config.name.clone(),
+                    interface: name.clone(),
+                })?;
+                // The directly connected networks on the interface
+                let networks: Vec<Ipv4Net> = config.routes
+                    .iter()
+                    .filter(|route| route.interface == name && route.next_hop.is_none())
+                    .filter_map(|route| route.network.parse().ok())
+                    .collect();
+                router.add_dhcp_pool(interface, pool.pool(&config.name, &networks)?);
+            }
+            let router = Rc::new(RefCell::new(router));
+            network.add_node(&config.name, config.position, DeviceHandle::Router(router))?;
+        }
+
+        for config in &file.hosts {
+            let dhcp = config.dhcp.unwrap_or(false);
+            let address = match (&config.ip, dhcp) {
+                (Some(ip), _) => parse_host_address(ip).map_err(|source| invalid_address(&config.name, source))?,
+                (None, true) => Ipv4Net::new(Ipv4Addr::UNSPECIFIED, 32).map_err(|source| invalid_address(&config.name, source))?,
+                (None, false) => return Err(TopologyError::Parse(format!("{} needs an ip unless it uses dhcp", config.name))),
+            };
+            let mac: MacAddr = config.mac.parse().map_err(|source| invalid_address(&config.name, source))?;
+            let mut host = Host::new(address, mac);
+            let gateway = config.gateway.as_deref().map(address::parse_ipv4).transpose();
+            host.set_default_gateway(gateway.map_err(|source| invalid_address(&config.name, source))?);
+            let dns_servers = config.dns.iter().map(|server| address::parse_ipv4(server)).collect::<Result<_, _>>();
+            host.set_dns_servers(dns_servers.map_err(|source| invalid_address(&config.name, source))?);
+            for record in &config.dns_records {
+                host.add_dns_record(record.record(&config.name)?);
+            }
+            if dhcp {
+                host.enable_dhcp_client();
+            }
+            for pool in &config.dhcp_pools {
+                // A host serves the one network it is on
+                if let Some(interface) = &pool.interface {
+                    return Err(TopologyError::UnknownInterface { device: config.name.clone(), interface: interface.clone() });
+                }
+                let pool = pool.pool(&config.name, &[host.subnet()])?;
+                host.add_dhcp_pool(pool);
+            }
+            for route in &config.routes {
+                let network: Ipv4Net = route.network.parse().map_err(|source| invalid_address(&config.name, source))?;
+                let next_hop = address::parse_ipv4(&route.next_hop).map_err(|source| invalid_address(&config.name, source))?;
+                host.add_route(network, next_hop);
+            }
+            for (ip, mac) in parse_arp_entries(&config.name, &config.arp)? {
+                host.add_arp_entry(ip, mac);
+            }
+            let host = Rc::new(RefCell::new(host));
+            network.add_node(&config.name, config.position, DeviceHandle::Host(host))?;
+        }
+
+        // Connect devices
+        for (index, spec) in file.links.iter().enumerate() {
+            let (a_name, a_device, a_port) = network.resolve_endpoint(&spec.a, index)?;
+            let (b_name, b_device, b_port) = network.resolve_endpoint(&spec.b, index)?;
+            if a_name == b_name {
+                return Err(TopologyError::InvalidLink { link: index, reason: format!("{a_name} is linked to itself") });
+            }
+            let link = Link::connect(&a_device, a_port, &b_device, b_port, spec.config(index)?);
+            link.borrow_mut().set_up(spec.up.unwrap_or(true));
+            network.links.push(NetworkLink { a: a_name, b: b_name, link });
+        }
+
+        // Populate routing tables
+        for config in &file.routers {
+            let Some(DeviceHandle::Router(router)) = network.find(&config.name).map(|node| node.device.clone()) else {
+                continue;
+            };
+            for route in &config.routes {
+                let network_prefix: Ipv4Net = route.network.parse().map_err(|source| invalid_address(&config.name, source))?;
+                let interface = router.borrow().interface_index(&route.interface).ok_or_else(|| {
+                    TopologyError::UnknownInterface { device: config.name.clone(), interface: route.interface.clone() }
+                })?;
+                let next_hop = route.next_hop
+                    .as_deref()
+                    .map(address::parse_ipv4)
+                    .transpose()
+                    .map_err(|source| invalid_address(&config.name, source))?;
+                router.borrow_mut().populate_routing_table(network_prefix, interface, next_hop);
+            }
+        }
+
+        Ok(network)
+    }
+
+    // Describes the network as it is now, so that loading the result rebuilds it.
+    // `positions` overrides the positions the network was loaded with (e.g. from the GUI).
+    // The configuration of the running network, plus the tables it has built up by `now`
+    pub fn to_file(&self, positions: &HashMap<String, [f32; 2]>, now: Duration) -> TopologyFile {
+        let mut file = TopologyFile::default();
+        for node in &self.nodes {
+            let name = node.name.clone();
+            let position = positions.get(&node.name).copied().or(node.position);
+            let mut state = DeviceState { device: name.clone(), routes: Vec::new(), arp: Vec::new(), mac_table: Vec::new() };
+            match &node.device {
+                DeviceHandle::Switch(switch) => {
+                    let switch = switch.borrow();
+                    state.mac_table = mac_entries(&switch.mac_entries(now));
+                    file.switches.push(SwitchConfig {
+                        name,
+                        ports: switch.port_count(),
+                        mac_table: mac_entries(&switch.static_mac_entries()),
+                        mac_aging_s: (switch.aging_time() != DEFAULT_AGING_TIME).then(|| switch.aging_time().as_secs_f64()),
+                        max_mac_entries: (switch.max_entries() != DEFAULT_MAX_ENTRIES).then(|| switch.max_entries()),
+                        vlans: (0..switch.port_count())
+                            .filter_map(|port| Some((port, switch.port_mode(port)?)))
+                            .filter(|(_, mode)| **mode != PortMode::default())
+                            .map(|(port, mode)| PortVlanConfig::from_mode(port, mode))
+                            .collect(),
+                        stp: switch.spanning_tree().map(|stp| {
+                            let bridge = stp.bridge_id();
+                            let (timers, default) = (stp.timers(), StpTimers::default());
+                            let changed = |value: Duration, default: Duration| (value != default).then_some(value.as_secs_f64());
+                            StpConfig {
+                                mac: bridge.mac.to_string(),
+                                priority: (bridge.priority != stp::DEFAULT_PRIORITY).then_some(bridge.priority),
+                                hello_s: changed(timers.hello_time, default.hello_time),
+                                max_age_s: changed(timers.max_age, default.max_age),
+                                forward_delay_s: changed(timers.forward_delay, default.forward_delay),
+                                edge_ports: (0..switch.port_count()).filter(|port| stp.is_edge(*port)).collect(),
+                            }
+                        }),
+                        position,
+                    });
+                }
+                DeviceHandle::Router(router) => {
+                    let router = router.borrow();
+                    let interfaces = router.interfaces();
+                    state.routes = router
+                        .routing_table()
+                        .iter()
+                        .map(|(network, (interface, next_hop), source)| RouteState {
+                            network: network.to_string(),
+                            interface: interfaces[*interface].0.clone(),
+                            next_hop: next_hop.map(|ip| ip.to_string()),
+                            source: source.to_string(),
+                        })
+                        .collect();
+                    state.arp = arp_entries(&router.arp_entries(now));
+                    file.routers.push(RouterConfig {
+                        name,
+                        ip: router.get_ip_address().to_string(),
+                        routes: router.routing_table()
+                            .iter()
+                            .filter(|(_, _, source)| *source == RouteSource::Static)
+                            .map(|(network, (interface, next_hop), _)| RouteConfig {
+                                network: network.to_string(),
+                                interface: interfaces[*interface].0.clone(),
+                                next_hop: next_hop.map(|ip| ip.to_string()),
+                            })
+                            .collect(),
+                        interfaces: interfaces
+                            .iter()
+                            .enumerate()
+                            .filter(|(index, _)| router.subinterface(*index).is_none())
+                            .map(|(_, (name, mac))| InterfaceConfig { name: name.clone(), mac: mac.to_string() })
+                            .collect(),
+                        subinterfaces: interfaces
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(index, (name, _))| {
+                                let (parent, vlan) = router.subinterface(index)?;
+                                Some(SubinterfaceConfig { name: name.clone(), parent: interfaces[parent].0.clone(), vlan })
+                            })
+                            .collect(),
+                        rip: router.rip().map(|rip| {
+                            let (timers, default) = (rip.timers(), RipTimers::default());
+                            let changed = |value: Duration, default: Duration| (value != default).then_some(value.as_secs_f64());
+                            RipConfig {
+                                update_s: changed(timers.update, default.update),
+                                timeout_s: changed(timers.timeout, default.timeout),
+                                garbage_s: changed(timers.garbage, default.garbage),
+                                passive: interfaces
+                                    .iter()
+                                    .enumerate()
+                                    .filter(|(index, _)| rip.is_passive(*index))
+                                    .map(|(_, (name, _))| name.clone())
+                                    .collect(),
+                            }
+                        }),
+                        ospf: router.ospf().map(|ospf| {
+                            let (timers, default) = (ospf.timers(), OspfTimers::default());
+                            let changed = |value: Duration, default: Duration| (value != default).then_some(value.as_secs_f64());
+                            OspfConfig {
+                                hello_s: changed(timers.hello, default.hello),
+                                dead_s: changed(timers.dead, default.dead),
+                                retransmit_s: changed(timers.retransmit, default.retransmit),
+                                interfaces: interfaces
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(index, (name, _))| OspfInterfaceConfig {
+                                        name: name.clone(),
+                                        cost: ospf.configured_cost(index),
+                                        priority: Some(ospf.priority(index)).filter(|priority| *priority != DEFAULT_PRIORITY),
+                                        passive: ospf.is_passive(index).then_some(true),
+                                    })
+                                    .filter(|settings| settings.cost.is_some() || settings.priority.is_some() || settings.passive.is_some())
+                                    .collect(),
+                            }
+                        }),
+                        bgp: router.bgp().map(|bgp| {
+                            let (timers, default) = (bgp.timers(), BgpTimers::default());
+                            let changed = |value: Duration, default: Duration| (value != default).then_some(value.as_secs_f64());
+                            BgpConfig {
+                                asn: bgp.asn(),
+                                networks: bgp.networks().iter().map(ToString::to_string).collect(),
+                                hold_s: changed(timers.hold, default.hold),
+                                keepalive_s: changed(timers.keepalive, default.keepalive),
+                                connect_retry_s: changed(timers.connect_retry, default.connect_retry),
+                                neighbors: bgp
+                                    .neighbors()
+                                    .into_iter()
+                                    .map(|neighbor| BgpNeighborConfig {
+                                        ip: neighbor.address.to_string(),
+                                        remote_as: neighbor.remote_as,
+                                        interface: neighbor.interface.map(|interface| interfaces[interface].0.clone()),
+                                        next_hop_self: neighbor.next_hop_self.then_some(true),
+                                        import: neighbor.import.iter().map(PolicyRuleConfig::from_rule).collect(),
+                                        export: neighbor.export.iter().map(PolicyRuleConfig::from_rule).collect(),
+                                    })
+                                    .collect(),
+                            }
+                        }),
+                        dhcp_pools: router.dhcp_server().map_or_else(Vec::new, |server| {
+                            server
+                                .pools()
+                                .iter()
+                                .map(|(interface, pool)| DhcpPoolConfig::from_pool(Some(interfaces[*interface].0.clone()), pool))
+                                .collect()
+                        }),
+                        arp: arp_entries(&router.static_arp_entries()),
+                        position,
+                    });
+                }
+                DeviceHandle::Host(host) => {
+                    let host = host.borrow();
+                    state.arp = arp_entries(&host.arp_entries(now));
+                    // The address and gateway of DHCP clients come from their lease
+                    let dhcp = host.dhcp_client().is_some();
+                    file.hosts.push(HostConfig {
+                        name,
+                        ip: (!dhcp).then(|| format!("{}/{}", host.get_ip_address(), host.subnet().prefix_len())),
+                        mac: host.get_mac_address().to_string(),
+                        dhcp: dhcp.then_some(true),
+                        gateway: host.default_gateway().filter(|_| !dhcp).map(|gateway| gateway.to_string()),
+                        routes: host.routes()
+                            .iter()
+                            .map(|(network, next_hop)| HostRouteConfig { network: network.to_string(), next_hop: next_hop.to_string() })
+                            .collect(),
+                        dns: if dhcp { Vec::new() } else { host.dns_servers().iter().map(ToString::to_string).collect() },
+                        dhcp_pools: host.dhcp_server().map_or_else(Vec::new, |server| {
+                            server.pools().iter().map(|(_, pool)| DhcpPoolConfig::from_pool(None, pool)).collect()
+                        }),
+                        dns_records: host.dns_server().map_or_else(Vec::new, |server| {
+                            server.records().iter().map(DnsRecordConfig::from_record).collect()
+                        }),
+                        arp: arp_entries(&host.static_arp_entries()),
+                        position,
+                    });
+                }
+            }
+            if !(state.routes.is_empty() && state.arp.is_empty() && state.mac_table.is_empty()) {
+                file.state.push(state);
+            }
+        }
+
+        for link in &self.links {
+            let a = self.endpoint_name(&link.a, &link.link, 0);
+            let b = self.endpoint_name(&link.b, &link.link, 1);
+            file.links.push(LinkSpec::from_link(a, b, &link.link.borrow()));
+        }
+        file
+    }
+
+    // Writes the network to `path`, in the format given by its extension
+    pub fn save(&self, path: &Path, positions: &HashMap<String, [f32; 2]>, now: Duration) -> Result<(), TopologyError> {
+        let format = Format::from_path(path)
+            .ok_or_else(|| TopologyError::UnsupportedFormat(path.display().to_string()))?;
+        let file = self.to_file(positions, now);
+        let contents = match format {
+            Format::Toml => toml::to_string_pretty(&file).map_err(|e| TopologyError::Serialize(e.to_string()))?,
+            Format::Json => serde_json::to_string_pretty(&file).map_err(|e| TopologyError::Serialize(e.to_string()))?,
+            Format::Yaml => serde_yaml::to_string(&file).map_err(|e| TopologyError::Serialize(e.to_string()))?,
+        };
+        std::fs::write(path, contents)
+            .map_err(|source| TopologyError::Io { path: path.display().to_string(), source })
+    }
+
+    fn add_node(&mut self, name: &str, position: Option<[f32; 2]>, device: DeviceHandle) -> Result<(), TopologyError> {
+        if self.find(name).is_some() {
+            return Err(TopologyError::DuplicateName(name.to_string()));
+        }
+        self.nodes.push(NetworkNode { name: name.to_string(), position, device });
+        Ok(())
+    }
+
+    // Records the traffic of a switch port or router interface ("<device>:<port>"),
+    // or of every port of the device if no port is given
+    pub fn attach_capture(&self, endpoint: &str, capture: &CaptureRef) -> Result<(), TopologyError> {
+        let (name, port) = self.split_endpoint(endpoint);
+        let node = self.find(name).ok_or_else(|| TopologyError::UnknownDevice {
+            context: "capture".to_string(),
+            name: name.to_string(),
+        })?;
+        let invalid_port = |port: &str| TopologyError::InvalidPort { device: name.to_string(), port: port.to_string() };
+
+        match &node.device {
+            DeviceHandle::Switch(switch) => {
+                let mut switch = switch.borrow_mut();
+                let ports = match port {
+                    Some(port) => {
+                        let number: usize = port.parse().map_err(|_| invalid_port(port))?;
+                        if number >= switch.port_count() {
+                            return Err(invalid_port(port));
+                        }
+                        number..number + 1
+                    }
+                    None => 0..switch.port_count(),
+                };
+                for port in ports {
+                    switch.set_capture(port, Some(Rc::clone(capture)));
+                }
+            }
+            DeviceHandle::Router(router) => {
+                let mut router = router.borrow_mut();
+                let ports = match port {
+                    Some(interface) => {
+                        let index = router.interface_index(interface).ok_or_else(|| {
+                            TopologyError::UnknownInterface { device: name.to_string(), interface: interface.to_string() }
+                        })?;
+                        index..index + 1
+                    }
+                    None => 0..router.interfaces().len(),
+                };
+                for port in ports {
+                    router.set_capture(port, Some(Rc::clone(capture)));
+                }
+            }
+            DeviceHandle::Host(_) => return Err(TopologyError::NoCapturePoint(name.to_string())),
+        }
+        Ok(())
+    }
+
+    // Splits "<device>[:<port>]", preferring the whole string as a device name so names may contain ':'
+    fn split_endpoint<'a>(&self, endpoint: &'a str) -> (&'a str, Option<&'a str>) {
+        if self.find(endpoint).is_some() {
+            return (endpoint, None);
+        }
+        match endpoint.rsplit_once(':') {
+            Some((name, port)) => (name.trim(), Some(port.trim())),
+            None => (endpoint, None),
+        }
+    }
+
+    // Turns "<device>[:<port>]" into the device and the port number the link attaches to
+    fn resolve_endpoint(&self, endpoint: &str, link: usize) -> Result<(String, DeviceRef, usize), TopologyError> {
+        let (name, port) = self.split_endpoint(endpoint);
+        let node = self.find(name).ok_or_else(|| TopologyError::UnknownDevice {
+            context: format!("link #{}", link + 1),
+            name: name.to_string(),
+        })?;
+        let invalid_port = |port: &str| TopologyError::InvalidPort { device: name.to_string(), port: port.to_string() };
+        let port_in_use = |port: &str| TopologyError::PortInUse { device: name.to_string(), port: port.to_string() };
+
+        let port = match (&node.device, port) {
+            (DeviceHandle::Host(host), None) => {
+                if host.borrow().is_connected() {
+                    return Err(port_in_use("0"));
+                }
+                0
+            }
+            (DeviceHandle::Host(_), Some(port)) => return Err(invalid_port(port)),
+            (DeviceHandle::Switch(switch), None) => switch.borrow().free_port().ok_or_else(|| {
+                TopologyError::InvalidLink { link, reason: format!("{name} has no free ports") }
+            })?,
+            (DeviceHandle::Switch(switch), Some(port)) => {
+                let number: usize = port.parse().map_err(|_| invalid_port(port))?;
+                match switch.borrow().is_port_free(number) {
+                    Some(true) => number,
+                    Some(false) => return Err(port_in_use(port)),
+                    None => return Err(invalid_port(port)),
+                }
+            }
+            (DeviceHandle::Router(_), None) => {
+                return Err(TopologyError::InvalidLink {
+                    link,
+                    reason: format!("{name} needs an interface, e.g. \"{name}:eth0\""),
+                });
+            }
+            (DeviceHandle::Router(router), Some(interface)) => {
+                let router = router.borrow();
+                let port = router.interface_index(interface).ok_or_else(|| {
+                    TopologyError::UnknownInterface { device: name.to_string(), interface: interface.to_string() }
+                })?;
+                if router.subinterface(port).is_some() {
+                    return Err(TopologyError::InvalidLink {
+                        link,
+                        reason: format!("{interface} of {name} is a VLAN subinterface; link its parent interface"),
+                    });
+                }
+                if router.is_interface_connected(port) {
+                    return Err(port_in_use(interface));
+                }
+                port
+            }
+        };
+        Ok((name.to_string(), node.device.device(), port))
+    }
+
+    // The inverse of resolve_endpoint, always naming the port so that it is kept on reload
+    fn endpoint_name(&self, name: &str, link: &Rc<RefCell<Link>>, side: usize) -> String {
+        let port = link.borrow().end(side).map(|(_, port)| port);
+        match (self.find(name).map(|node| &node.device), port) {
+            (Some(DeviceHandle::Switch(_)), Some(port)) => format!("{name}:{port}"),
+            (Some(DeviceHandle::Router(router)), Some(port)) => match router.borrow().interfaces().get(port) {
+                Some((interface, _)) => format!("{name}:{interface}"),
+                None => name.to_string(),
+            },
+            _ => name.to_string(),
+        }
+    }
+}
+
+// A time from the file in seconds; negative, infinite and NaN values are rejected
+fn parse_seconds(device: &str, field: &str, seconds: f64) -> Result<Duration, TopologyError> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| TopologyError::Parse(format!("{device}: {field} = {seconds} is not a valid time")))
+}
+
+fn invalid_address(device: &str, source: AddrParseError) -> TopologyError {
+    TopologyError::InvalidAddress { device: device.to_string(), source }
+}
+
+// A host's interface address, "192.168.1.10/24" or just "192.168.1.10" for a /24 subnet
+fn parse_host_address(s: &str) -> Result<Ipv4Net, AddrParseError> {
+    if s.contains('/') {
+        return s.parse();
+    }
+    Ipv4Net::new(address::parse_ipv4(s)?, address::DEFAULT_PREFIX_LEN)
+}
+
+fn check_vlan(device: &str, vlan: u16) -> Result<u16, TopologyError> {
+    if !(1..=4094).contains(&vlan) {
+        return Err(TopologyError::InvalidVlan { device: device.to_string(), vlan });
+    }
+    Ok(vlan)
+}
+
+fn parse_arp_entries(device: &str, entries: &[ArpEntry]) -> Result<Vec<(Ipv4Addr, MacAddr)>, TopologyError> {
+    entries
+        .iter()
+        .map(|entry| Ok((address::parse_ipv4(&entry.ip)?, entry.mac.parse()?)))
+        .collect::<Result<_, AddrParseError>>()
+        .map_err(|source| invalid_address(device, source))
+}
+
+// ARP table entries sorted by IP address, so saved files are stable
+fn mac_entries(table: &HashMap<(u16, MacAddr), usize>) -> Vec<MacEntry> {
+    let mut entries: Vec<_> = table.iter().collect();
+    entries.sort();
+    entries
+        .into_iter()
+        .map(|((vlan, mac), port)| MacEntry { mac: mac.to_string(), port: *port, vlan: (*vlan != DEFAULT_VLAN).then_some(*vlan) })
+        .collect()
+}
+
+fn arp_entries(table: &HashMap<Ipv4Addr, MacAddr>) -> Vec<ArpEntry> {
+    let mut entries: Vec<_> = table.iter().collect();
+    entries.sort();
+    entries
+        .into_iter()
+        .map(|(ip, mac)| ArpEntry { ip: ip.to_string(), mac: mac.to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two hosts on a switch, with a router on the same switch
+    const LAN: &str = r#"
+[[switches]]
+name = "Switch"
+ports = 3
+
+[[routers]]
+name = "Router"
+ip = "10.0.0.1"
+interfaces = [{ name = "eth0", mac = "AA:00:00:00:00:01" }]
+routes = [{ network = "10.0.0.0/24", interface = "eth0" }]
+
+[[hosts]]
+name = "Host A"
+ip = "10.0.0.10/24"
+mac = "AA:00:00:00:00:0A"
+gateway = "10.0.0.1"
+
+[[hosts]]
+name = "Host B"
+ip = "10.0.0.11/24"
+mac = "AA:00:00:00:00:0B"
+
+[[links]]
+a = "Switch"
+b = "Router:eth0"
+
+[[links]]
+a = "Switch:1"
+b = "Host A"
+delay_ms = 5.0
+
+[[links]]
+a = "Switch"
+b = "Host B"
+"#;
+
+    fn names(network: &Network) -> (Vec<&str>, Vec<(&str, &str)>) {
+        let nodes = network.nodes.iter().map(|node| node.name.as_str()).collect();
+        let links = network.links.iter().map(|link| (link.a.as_str(), link.b.as_str())).collect();
+        (nodes, links)
+    }
+
+    // The error from loading LAN with `find` replaced by `replace`
+    fn error(find: &str, replace: &str) -> String {
+        assert!(LAN.contains(find), "{find:?}");
+        Network::parse(&LAN.replacen(find, replace, 1), Format::Toml).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_format_loads_the_same_network() {
+        let file: TopologyFile = toml::from_str(LAN).unwrap();
+        let toml = Network::parse(LAN, Format::Toml).unwrap();
+        let json = Network::parse(&serde_json::to_string(&file).unwrap(), Format::Json).unwrap();
+        let yaml = Network::parse(&serde_yaml::to_string(&file).unwrap(), Format::Yaml).unwrap();
+        let expected = (
+            vec!["Switch", "Router", "Host A", "Host B"],
+            vec![("Switch", "Router"), ("Switch", "Host A"), ("Switch", "Host B")],
+        );
+        for network in [&toml, &json, &yaml] {
+            assert_eq!(names(network), expected);
+            assert_eq!(network.links[1].link.borrow().config.delay, Duration::from_millis(5));
+        }
+        assert_eq!(Format::from_path(Path::new("lab.YML")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("lab.txt")), None);
+    }
+
+    #[test]
+    fn dangling_references_name_the_link() {
+        assert_eq!(error("b = \"Host B\"", "b = \"Host C\""), "link #3 refers to unknown device \"Host C\"");
+        assert_eq!(error("b = \"Router:eth0\"", "b = \"Router:eth1\""), "Router has no interface named \"eth1\"");
+        assert_eq!(error("b = \"Router:eth0\"", "b = \"Router\""), "link #1: Router needs an interface, e.g. \"Router:eth0\"");
+        assert_eq!(error("a = \"Switch:1\"", "a = \"Switch:3\""), "Switch has no port \"3\"");
+        assert_eq!(error("a = \"Switch:1\"", "a = \"Switch:0\""), "port \"0\" of Switch is already connected");
+        assert_eq!(error("b = \"Host B\"", "b = \"Host A\""), "port \"0\" of Host A is already connected");
+        assert_eq!(error("a = \"Switch\"\nb = \"Host B\"", "a = \"Host B\"\nb = \"Host B\""), "link #3: Host B is linked to itself");
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(error("name = \"Host B\"", "name = \"Host A\""), "device name \"Host A\" is used more than once");
+        let interfaces = "interfaces = [{ name = \"eth0\", mac = \"AA:00:00:00:00:01\" }";
+        assert_eq!(
+            error(interfaces, &format!("{interfaces}, {{ name = \"eth0\", mac = \"AA:00:00:00:00:02\" }}")),
+            "invalid topology file: Router: interface name \"eth0\" is used more than once"
+        );
+        assert_eq!(error("10.0.0.10/24", "10.0.0.300/24"), "Host A: invalid CIDR prefix: \"10.0.0.300/24\"");
+        assert_eq!(error("gateway = \"10.0.0.1\"", "gateway = \"10.0.0\""), "Host A: invalid IPv4 address: \"10.0.0\"");
+        assert_eq!(error("AA:00:00:00:00:0B", "AA:00:00:00:0B"), "Host B: invalid MAC address: \"AA:00:00:00:0B\"");
+        assert!(error("ports = 3", "ports = \"three\"").starts_with("invalid topology file: "));
+    }
+
+    #[test]
+    fn invalid_link_settings_are_rejected() {
+        let link = |setting: &str| error("delay_ms = 5.0", setting);
+        assert_eq!(link("delay_ms = -1.0"), "link #2: Switch:1 <-> Host A: delay_ms = -1 is not a valid time");
+        assert_eq!(link("jitter_ms = inf"), "link #2: Switch:1 <-> Host A: jitter_ms = inf is not a valid time");
+        assert_eq!(link("bandwidth_mbps = 0.0"), "link #2: Switch:1 <-> Host A: bandwidth_mbps = 0 is not a bandwidth of at least 1 b/s");
+        assert_eq!(link("loss = 1.5"), "link #2: Switch:1 <-> Host A: loss = 1.5 is not a probability from 0 to 1");
+        assert_eq!(link("loss = -0.1"), "link #2: Switch:1 <-> Host A: loss = -0.1 is not a probability from 0 to 1");
+        assert_eq!(link("bit_error_rate = nan"), "link #2: Switch:1 <-> Host A: bit_error_rate = NaN is not a probability from 0 to 1");
+        assert_eq!(link("mtu = 0"), "link #2: Switch:1 <-> Host A: mtu = 0 leaves no room for any frame");
+        assert!(Network::parse(&LAN.replacen("delay_ms = 5.0", "loss = 1.0\nmtu = 68", 1), Format::Toml).is_ok());
+    }
+
+    #[test]
+    fn saved_networks_load_back_with_their_tables_recorded() {
+        let network = Network::parse(LAN, Format::Toml).unwrap();
+        let mut sim = Simulator::new();
+        network.start(&mut sim);
+        let Some(DeviceHandle::Host(host)) = network.find("Host A").map(|node| node.device.clone()) else {
+            panic!("no Host A");
+        };
+        let device: DeviceRef = host.clone();
+        sim.with_device(&device, |sim| host.borrow_mut().send_packet(Ipv4Addr::new(10, 0, 0, 11), b"hello".to_vec(), sim));
+        sim.run_until(Duration::from_secs(1));
+
+        let file = network.to_file(&HashMap::new(), sim.now());
+        let state = |device: &str| file.state.iter().find(|state| state.device == device).unwrap();
+        let arp: Vec<(&str, &str)> = state("Host A").arp.iter().map(|entry| (entry.ip.as_str(), entry.mac.as_str())).collect();
+        assert_eq!(arp, [("10.0.0.11", "AA:00:00:00:00:0B")]);
+        let macs: Vec<(&str, usize)> = state("Switch").mac_table.iter().map(|entry| (entry.mac.as_str(), entry.port)).collect();
+        assert_eq!(macs, [("AA:00:00:00:00:0A", 1), ("AA:00:00:00:00:0B", 2)]);
+        let route = &state("Router").routes[0];
+        assert_eq!((route.network.as_str(), route.interface.as_str(), route.source.as_str()), ("10.0.0.0/24", "eth0", "static"));
+
+        // Loading skips the tables; the configuration comes back unchanged
+        let saved = toml::to_string_pretty(&file).unwrap();
+        let reloaded = Network::parse(&saved, Format::Toml).unwrap();
+        let mut resaved = reloaded.to_file(&HashMap::new(), Duration::ZERO);
+        assert_eq!(resaved.state.len(), 1);
+        let without_state = |file: &mut TopologyFile| {
+            file.state.clear();
+            toml::to_string_pretty(file).unwrap()
+        };
+        assert_eq!(without_state(&mut resaved), without_state(&mut file.clone()));
+        assert_eq!(toml::to_string_pretty(&toml::from_str::<TopologyFile>(&saved).unwrap()).unwrap(), saved);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TracerouteOptions {
+    pub max_hops: u8,
+    pub probes: usize,     // Echo requests sent with each TTL
+    pub timeout: Duration, // How long to wait for the answers to one TTL
+}
+
+impl Default for TracerouteOptions {
+    fn default() -> Self {
+        Self {
+            max_hops: 30,
+            probes: 3,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+// How a probe was answered
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeAnswer {
+    TimeExceeded,        // By a router on the way
+    Reached,             // By the destination itself
+    Unreachable(String), // By a router that cannot go any further
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hop {
+    pub ttl: u8,
+    pub address: Option<String>,   // Who answered, None if nobody did
+    pub rtt_ms: Vec<Option<f64>>,  // One entry per probe, None if it timed out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,     // Destination Unreachable received instead
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TracerouteReport {
+    pub destination: String,
+    pub reached: bool,
+    pub finished: bool,
+    pub hops: Vec<Hop>,
+}
+
+impl TracerouteReport {
+    // Addresses of the devices that answered, in path order
+    pub fn path(&self) -> Vec<Ipv4Addr> {
+        self.hops
+            .iter()
+            .filter_map(|hop| hop.address.as_deref()?.parse().ok())
+            .collect()
+    }
+}
+
+// State of one traceroute run by a host, identified by its ICMP echo identifier.
+// Probes are numbered from 1 across all hops, so (seq - 1) / probes is the hop index.
+#[derive(Debug, Clone)]
+pub struct TracerouteSession {
+    pub dest_ip: Ipv4Addr,
+    pub options: TracerouteOptions,
+    sent: Vec<Duration>, // Send time of each probe
+    hops: Vec<Hop>,
+    reached: bool,
+    pub finished: bool,
+}
+
+impl TracerouteSession {
+    pub fn new(dest_ip: Ipv4Addr, options: TracerouteOptions) -> Self {
+        Self {
+            dest_ip,
+            options,
+            sent: Vec::new(),
+            hops: Vec::new(),
+            reached: false,
+            finished: false,
+        }
+    }
+
+    // TTL of the hop being probed, 0 before the first one
+    pub fn current_ttl(&self) -> u8 { self.hops.len() as u8 }
+
+    // Whether another hop should be probed once the current one is answered or times out
+    pub fn has_next_hop(&self) -> bool {
+        !self.finished
+            && !self.reached
+            && self.hops.last().is_none_or(|hop| hop.error.is_none())
+            && self.current_ttl() < self.options.max_hops
+    }
+
+    // Starts probing the next hop; returns its TTL and the sequence numbers of its probes
+    pub fn start_hop(&mut self, now: Duration) -> (u8, Vec<u16>) {
+        let ttl = self.current_ttl() + 1;
+        let first = self.sent.len() as u16 + 1;
+        let probes = self.options.probes.max(1);
+        self.sent.extend(std::iter::repeat_n(now, probes));
+        self.hops.push(Hop { ttl, address: None, rtt_ms: vec![None; probes], error: None });
+        (ttl, (first..first + probes as u16).collect())
+    }
+
+    // Records the answer to a probe; returns true once every probe of the current hop is answered
+    pub fn record_answer(&mut self, seq: u16, from: Ipv4Addr, answer: ProbeAnswer, now: Duration) -> bool {
+        let probes = self.options.probes.max(1);
+        let Some(index) = usize::from(seq).checked_sub(1) else {
+            return false;
+        };
+        let (Some(&sent), Some(hop)) = (self.sent.get(index), self.hops.get_mut(index / probes)) else {
+            return false;
+        };
+        let Some(rtt) = now.checked_sub(sent).filter(|rtt| *rtt <= self.options.timeout) else {
+            return false;
+        };
+        hop.address.get_or_insert_with(|| from.to_string());
+        hop.rtt_ms[index % probes] = Some(rtt.as_secs_f64() * 1e3);
+        match answer {
+            ProbeAnswer::TimeExceeded => {}
+            ProbeAnswer::Reached => self.reached = true,
+            ProbeAnswer::Unreachable(error) => hop.error = Some(error),
+        }
+        let current = self.hops.len() - 1;
+        index / probes == current && self.hops[current].rtt_ms.iter().all(Option::is_some)
+    }
+
+    pub fn report(&self) -> TracerouteReport {
+        TracerouteReport {
+            destination: self.dest_ip.to_string(),
+            reached: self.reached,
+            finished: self.finished,
+            hops: self.hops.clone(),
+        }
+    }
+}
+
+impl fmt::Display for TracerouteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TRACEROUTE {}", self.destination)?;
+        for hop in &self.hops {
+            write!(f, "\n{:>3}  {:<15}", hop.ttl, hop.address.as_deref().unwrap_or("*"))?;
+            for rtt in &hop.rtt_ms {
+                match rtt {
+                    Some(rtt) => write!(f, "  {rtt:.3} ms")?,
+                    None => write!(f, "  *")?,
+                }
+            }
+            if let Some(error) = &hop.error {
+                write!(f, "  {error}")?;
+            }
+        }
+        if self.finished && !self.reached {
+            write!(f, "\nDestination not reached")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceRef;
+    use crate::sim::Simulator;
+    use crate::topology::{DeviceHandle, Format, Network, DEFAULT_TOPOLOGY};
+
+    #[test]
+    fn hops_are_reported_in_order_up_to_the_destination() {
+        let mut sim = Simulator::new();
+        let network = Network::parse(DEFAULT_TOPOLOGY, Format::Toml).unwrap();
+        network.start(&mut sim);
+        let Some(DeviceHandle::Host(host)) = network.find("Host A").map(|node| &node.device) else {
+            panic!("Host A is a host");
+        };
+        let device: DeviceRef = host.clone();
+        let dest_ip = Ipv4Addr::new(192, 168, 5, 18); // Host I, behind Router 1 and Router 3
+        let id = sim.with_device(&device, |sim| host.borrow_mut().start_traceroute(dest_ip, TracerouteOptions::default(), sim));
+        while sim.step() {}
+
+        let report = host.borrow().traceroute_report(id).unwrap();
+        assert!(report.finished && report.reached);
+        assert_eq!(report.path(), [Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(192, 168, 3, 1), dest_ip]);
+        assert_eq!(report.hops.iter().map(|hop| hop.ttl).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(report.hops.iter().all(|hop| hop.error.is_none() && hop.rtt_ms.iter().all(Option::is_some)));
+    }
+
+    #[test]
+    fn echo_reply_from_the_destination_ends_the_trace() {
+        let dest_ip = Ipv4Addr::new(10, 0, 2, 5);
+        let mut session = TracerouteSession::new(dest_ip, TracerouteOptions { probes: 2, ..TracerouteOptions::default() });
+        let millis = Duration::from_millis;
+        assert_eq!(session.start_hop(millis(0)), (1, vec![1, 2]));
+        assert!(!session.record_answer(2, Ipv4Addr::new(10, 0, 0, 1), ProbeAnswer::TimeExceeded, millis(4)));
+        assert!(session.record_answer(1, Ipv4Addr::new(10, 0, 0, 1), ProbeAnswer::TimeExceeded, millis(5)));
+        assert!(session.has_next_hop());
+        assert_eq!(session.start_hop(millis(5)), (2, vec![3, 4]));
+        session.record_answer(3, dest_ip, ProbeAnswer::Reached, millis(9));
+        // An answer for the first hop does not complete the second
+        assert!(!session.record_answer(1, Ipv4Addr::new(10, 0, 0, 9), ProbeAnswer::TimeExceeded, millis(10)));
+        assert!(session.record_answer(4, dest_ip, ProbeAnswer::Reached, millis(10)));
+        assert!(!session.has_next_hop());
+        let report = session.report();
+        assert!(report.reached);
+        assert_eq!(report.path(), [Ipv4Addr::new(10, 0, 0, 1), dest_ip]);
+        assert_eq!(report.hops[1].rtt_ms, [Some(4.0), Some(5.0)]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::net::Ipv4Addr;
+use crate::address::MacAddr;
+use crate::packet::{internet_checksum, Packet};
+
+pub const PROTOCOL_UDP: u8 = 17;
+
+const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpDatagram {
+    pub src_port: u16,
+    pub dest_port: u16,
+    pub payload: Vec<u8>,
+}
+
+impl UdpDatagram {
+    // The checksum covers a pseudo-header with the addresses of the IPv4 packet carrying the datagram
+    pub fn to_bytes(&self, src_ip: Ipv4Addr, dest_ip: Ipv4Addr) -> Vec<u8> {
+        let length = (HEADER_LEN + self.payload.len()) as u16;
+        let mut bytes = Vec::with_capacity(usize::from(length));
+        bytes.extend_from_slice(&self.src_port.to_be_bytes());
+        bytes.extend_from_slice(&self.dest_port.to_be_bytes());
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.payload);
+        let checksum = match internet_checksum(&with_pseudo_header(&bytes, src_ip, dest_ip, PROTOCOL_UDP)) {
+            0 => 0xFFFF, // Zero means "no checksum"
+            checksum => checksum,
+        };
+        bytes[6..8].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // None for truncated datagrams or a bad checksum; a zero checksum is not checked
+    pub fn from_bytes(bytes: &[u8], src_ip: Ipv4Addr, dest_ip: Ipv4Addr) -> Option<Self> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+        let length = usize::from(u16::from_be_bytes([bytes[4], bytes[5]]));
+        if length < HEADER_LEN || length > bytes.len() {
+            return None;
+        }
+        let bytes = &bytes[..length];
+        if bytes[6..8] != [0, 0] && internet_checksum(&with_pseudo_header(bytes, src_ip, dest_ip, PROTOCOL_UDP)) != 0 {
+            return None;
+        }
+        Some(Self {
+            src_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            dest_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            payload: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+// What UDP and TCP checksums are computed over
+pub fn with_pseudo_header(datagram: &[u8], src_ip: Ipv4Addr, dest_ip: Ipv4Addr, protocol: u8) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(12 + datagram.len());
+    bytes.extend_from_slice(&src_ip.octets());
+    bytes.extend_from_slice(&dest_ip.octets());
+    bytes.extend_from_slice(&[0, protocol]);
+    bytes.extend_from_slice(&(datagram.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(datagram);
+    bytes
+}
+
+impl Packet {
+    // UDP datagram carried in an IPv4 packet; MAC addresses are filled in when it is routed
+    pub fn udp(src_ip: Ipv4Addr, dest_ip: Ipv4Addr, datagram: &UdpDatagram) -> Self {
+        let mut packet = Packet::new(
+            MacAddr::ZERO,
+            MacAddr::ZERO,
+            src_ip,
+            dest_ip,
+            datagram.to_bytes(src_ip, dest_ip),
+        );
+        packet.header.protocol = PROTOCOL_UDP;
+        packet
+    }
+
+    // The UDP datagram this packet carries, if any
+    pub fn udp_datagram(&self) -> Option<UdpDatagram> {
+        if self.is_arp || self.header.protocol != PROTOCOL_UDP {
+            return None;
+        }
+        UdpDatagram::from_bytes(&self.data, self.src_ip, self.dest_ip)
+    }
+}